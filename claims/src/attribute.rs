@@ -0,0 +1,473 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use failure::{bail, ensure, err_msg, format_err, Fallible};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::model::{content_id, AttributeMap, ContentId};
+use keyvault::encryption::SymmetricKey;
+
+pub type AttributeId = String;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub struct GpsLocation {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+/// Owned and serializable counterpart of `storage::meta::AttributeValue`, the value of a profile attribute.
+/// Plain strings are serialized as they used to be before attributes were typed,
+/// so existing profiles, signatures and clients keep working. Other values are tagged with their kind.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(from = "AttributeValueRepr", into = "AttributeValueRepr")]
+pub enum AttributeValue {
+    Boolean(bool),
+    Integer(i64),
+    Float(f64),
+    Timestamp(SystemTime),
+    Location(GpsLocation),
+    String(String),
+    Blob(Vec<u8>),
+    Link(String),
+    Array(Vec<AttributeValue>),
+    Object(BTreeMap<AttributeId, AttributeValue>),
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+enum AttributeValueRepr {
+    Plain(String),
+    Typed(TypedAttributeValue),
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+enum TypedAttributeValue {
+    Boolean(bool),
+    Integer(i64),
+    Float(f64),
+    Timestamp(SystemTime),
+    Location(GpsLocation),
+    Blob(Vec<u8>),
+    Link(String),
+    Array(Vec<AttributeValue>),
+    Object(BTreeMap<AttributeId, AttributeValue>),
+}
+
+impl From<AttributeValueRepr> for AttributeValue {
+    fn from(repr: AttributeValueRepr) -> Self {
+        use TypedAttributeValue::*;
+        match repr {
+            AttributeValueRepr::Plain(v) => AttributeValue::String(v),
+            AttributeValueRepr::Typed(typed) => match typed {
+                Boolean(v) => AttributeValue::Boolean(v),
+                Integer(v) => AttributeValue::Integer(v),
+                Float(v) => AttributeValue::Float(v),
+                Timestamp(v) => AttributeValue::Timestamp(v),
+                Location(v) => AttributeValue::Location(v),
+                Blob(v) => AttributeValue::Blob(v),
+                Link(v) => AttributeValue::Link(v),
+                Array(v) => AttributeValue::Array(v),
+                Object(v) => AttributeValue::Object(v),
+            },
+        }
+    }
+}
+
+impl From<AttributeValue> for AttributeValueRepr {
+    fn from(value: AttributeValue) -> Self {
+        use TypedAttributeValue::*;
+        let typed = match value {
+            AttributeValue::String(v) => return AttributeValueRepr::Plain(v),
+            AttributeValue::Boolean(v) => Boolean(v),
+            AttributeValue::Integer(v) => Integer(v),
+            AttributeValue::Float(v) => Float(v),
+            AttributeValue::Timestamp(v) => Timestamp(v),
+            AttributeValue::Location(v) => Location(v),
+            AttributeValue::Blob(v) => Blob(v),
+            AttributeValue::Link(v) => Link(v),
+            AttributeValue::Array(v) => Array(v),
+            AttributeValue::Object(v) => Object(v),
+        };
+        AttributeValueRepr::Typed(typed)
+    }
+}
+
+impl From<String> for AttributeValue {
+    fn from(value: String) -> Self {
+        AttributeValue::String(value)
+    }
+}
+
+impl From<&str> for AttributeValue {
+    fn from(value: &str) -> Self {
+        AttributeValue::String(value.to_owned())
+    }
+}
+
+impl fmt::Display for AttributeValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AttributeValue::String(v) | AttributeValue::Link(v) => write!(f, "{}", v),
+            AttributeValue::Boolean(v) => write!(f, "{}", v),
+            AttributeValue::Integer(v) => write!(f, "{}", v),
+            AttributeValue::Float(v) => write!(f, "{}", v),
+            AttributeValue::Timestamp(v) => write!(f, "{:?}", v),
+            AttributeValue::Location(v) => write!(f, "{},{}", v.latitude, v.longitude),
+            AttributeValue::Blob(v) => write!(f, "{}", multibase::encode(multibase::Base64url, v)),
+            AttributeValue::Array(_) | AttributeValue::Object(_) => {
+                let json = self.to_json().map_err(|_| fmt::Error)?;
+                write!(f, "{}", json)
+            }
+        }
+    }
+}
+
+impl AttributeValue {
+    pub fn kind(&self) -> AttributeKind {
+        match self {
+            AttributeValue::Boolean(_) => AttributeKind::Boolean,
+            AttributeValue::Integer(_) => AttributeKind::Integer,
+            AttributeValue::Float(_) => AttributeKind::Float,
+            AttributeValue::Timestamp(_) => AttributeKind::Timestamp,
+            AttributeValue::Location(_) => AttributeKind::Location,
+            AttributeValue::String(_) => AttributeKind::String,
+            AttributeValue::Blob(_) => AttributeKind::Blob,
+            AttributeValue::Link(_) => AttributeKind::Link,
+            AttributeValue::Array(_) => AttributeKind::Array,
+            AttributeValue::Object(_) => AttributeKind::Object,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            AttributeValue::String(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// Parses a textual value of the given kind, e.g. typed on the command line.
+    /// Timestamps are given in seconds since the Unix epoch, locations as "latitude,longitude",
+    /// blobs multibase-encoded, arrays and objects as JSON.
+    pub fn parse(kind: AttributeKind, src: &str) -> Fallible<Self> {
+        let value = match kind {
+            AttributeKind::Boolean => AttributeValue::Boolean(src.parse()?),
+            AttributeKind::Integer => AttributeValue::Integer(src.parse()?),
+            AttributeKind::Float => AttributeValue::Float(src.parse()?),
+            AttributeKind::Timestamp => {
+                AttributeValue::Timestamp(UNIX_EPOCH + Duration::from_secs(src.parse()?))
+            }
+            AttributeKind::Location => {
+                let mut parts = src.splitn(2, ',');
+                let (latitude, longitude) = match (parts.next(), parts.next()) {
+                    (Some(latitude), Some(longitude)) => (latitude, longitude),
+                    _ => bail!("Location must be given as latitude,longitude"),
+                };
+                AttributeValue::Location(GpsLocation {
+                    latitude: latitude.trim().parse()?,
+                    longitude: longitude.trim().parse()?,
+                })
+            }
+            AttributeKind::String => AttributeValue::String(src.to_owned()),
+            AttributeKind::Blob => AttributeValue::Blob(multibase::decode(src)?.1),
+            AttributeKind::Link => AttributeValue::Link(src.to_owned()),
+            AttributeKind::Array | AttributeKind::Object => {
+                let value = Self::from_json(serde_json::from_str(src)?)?;
+                if value.kind() != kind {
+                    bail!("Expected {} value, found {}", kind, value.kind());
+                }
+                value
+            }
+        };
+        Ok(value)
+    }
+
+    /// Converts plain JSON, numbers become integers if possible and floats otherwise.
+    pub fn from_json(json: serde_json::Value) -> Fallible<Self> {
+        use serde_json::Value;
+        let value = match json {
+            Value::Null => bail!("Attributes cannot have null values"),
+            Value::Bool(v) => AttributeValue::Boolean(v),
+            Value::Number(v) => match v.as_i64() {
+                Some(v) => AttributeValue::Integer(v),
+                None => AttributeValue::Float(
+                    v.as_f64().ok_or_else(|| format_err!("Number {} is out of range", v))?,
+                ),
+            },
+            Value::String(v) => AttributeValue::String(v),
+            Value::Array(v) => {
+                let items: Fallible<Vec<_>> = v.into_iter().map(Self::from_json).collect();
+                AttributeValue::Array(items?)
+            }
+            Value::Object(v) => {
+                let fields: Fallible<BTreeMap<_, _>> =
+                    v.into_iter().map(|(key, val)| Ok((key, Self::from_json(val)?))).collect();
+                AttributeValue::Object(fields?)
+            }
+        };
+        Ok(value)
+    }
+
+    /// Converts into plain JSON, losing the distinction of some kinds, e.g. links become strings.
+    pub fn to_json(&self) -> Fallible<serde_json::Value> {
+        use serde_json::Value;
+        let json = match self {
+            AttributeValue::Boolean(v) => Value::Bool(*v),
+            AttributeValue::Integer(v) => Value::from(*v),
+            AttributeValue::Float(v) => serde_json::Number::from_f64(*v)
+                .map(Value::Number)
+                .ok_or_else(|| format_err!("Float {} cannot be represented in JSON", v))?,
+            AttributeValue::Timestamp(v) => Value::from(v.duration_since(UNIX_EPOCH)?.as_secs()),
+            AttributeValue::Location(v) => serde_json::to_value(v)?,
+            AttributeValue::String(v) | AttributeValue::Link(v) => Value::String(v.to_owned()),
+            AttributeValue::Blob(v) => Value::from(v.to_owned()),
+            AttributeValue::Array(v) => {
+                let items: Fallible<Vec<_>> = v.iter().map(|item| item.to_json()).collect();
+                Value::Array(items?)
+            }
+            AttributeValue::Object(v) => {
+                let mut fields = serde_json::Map::new();
+                for (key, val) in v {
+                    fields.insert(key.to_owned(), val.to_json()?);
+                }
+                Value::Object(fields)
+            }
+        };
+        Ok(json)
+    }
+
+    /// Stores a structured value as a typed attribute instead of a serialized string.
+    pub fn from_serializable<T: Serialize>(value: &T) -> Fallible<Self> {
+        Self::from_json(serde_json::to_value(value)?)
+    }
+
+    /// Restores a structured value, also accepting the serialized string form of older profiles.
+    pub fn to_deserializable<T: DeserializeOwned>(&self) -> Fallible<T> {
+        match self {
+            AttributeValue::String(v) => Ok(serde_json::from_str(v)?),
+            _ => Ok(serde_json::from_value(self.to_json()?)?),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum AttributeKind {
+    Boolean,
+    Integer,
+    Float,
+    Timestamp,
+    Location,
+    String,
+    Blob,
+    Link,
+    Array,
+    Object,
+}
+
+impl fmt::Display for AttributeKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            AttributeKind::Boolean => "boolean",
+            AttributeKind::Integer => "integer",
+            AttributeKind::Float => "float",
+            AttributeKind::Timestamp => "timestamp",
+            AttributeKind::Location => "location",
+            AttributeKind::String => "string",
+            AttributeKind::Blob => "blob",
+            AttributeKind::Link => "link",
+            AttributeKind::Array => "array",
+            AttributeKind::Object => "object",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for AttributeKind {
+    type Err = failure::Error;
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        match src {
+            "boolean" => Ok(AttributeKind::Boolean),
+            "integer" => Ok(AttributeKind::Integer),
+            "float" => Ok(AttributeKind::Float),
+            "timestamp" => Ok(AttributeKind::Timestamp),
+            "location" => Ok(AttributeKind::Location),
+            "string" => Ok(AttributeKind::String),
+            "blob" => Ok(AttributeKind::Blob),
+            "link" => Ok(AttributeKind::Link),
+            "array" => Ok(AttributeKind::Array),
+            "object" => Ok(AttributeKind::Object),
+            _ => Err(err_msg("Invalid attribute kind")),
+        }
+    }
+}
+
+/// Who can read an attribute of a profile.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub enum AttributeVisibility {
+    /// Published in plaintext for everyone
+    Public,
+    /// Published sealed separately for each contact of the profile
+    Contacts,
+    /// Never published, kept only in the private part of the profile
+    Private,
+}
+
+impl Default for AttributeVisibility {
+    fn default() -> Self {
+        AttributeVisibility::Public
+    }
+}
+
+impl fmt::Display for AttributeVisibility {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            AttributeVisibility::Public => "public",
+            AttributeVisibility::Contacts => "contacts",
+            AttributeVisibility::Private => "private",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for AttributeVisibility {
+    type Err = failure::Error;
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        match src {
+            "public" => Ok(AttributeVisibility::Public),
+            "contacts" => Ok(AttributeVisibility::Contacts),
+            "private" => Ok(AttributeVisibility::Private),
+            _ => Err(err_msg("Invalid attribute visibility, use public, contacts or private")),
+        }
+    }
+}
+
+/// Contacts-only attributes of a profile encrypted for a single contact with a key derived from
+/// the secret shared by the profile and the contact. Only the digest is covered by the profile signature,
+/// so the ciphertext can be withheld from anyone else without invalidating the signature.
+/// Entries are found by their contact tag, which does not reveal who the contact is.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SealedAttributes {
+    digest: ContentId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ciphertext: Option<String>,
+}
+
+impl SealedAttributes {
+    const KEY_CONTEXT: &'static str = "mercury contact attributes";
+    const TAG_CONTEXT: &'static str = "mercury contact attributes tag";
+
+    /// Identifies the entry sealed with the shared secret. Only the profile and the contact
+    /// can calculate it, so others cannot tell which profiles are contacts.
+    pub fn contact_tag(shared_secret: &[u8]) -> String {
+        let secret = multibase::encode(multibase::Base64url, shared_secret);
+        content_id(&(Self::TAG_CONTEXT, secret))
+    }
+
+    pub fn seal(attributes: &AttributeMap, shared_secret: &[u8]) -> Fallible<Self> {
+        let key = SymmetricKey::derive(shared_secret, Self::KEY_CONTEXT);
+        let sealed = key.seal(&serde_json::to_vec(attributes)?)?;
+        let ciphertext = multibase::encode(multibase::Base64url, &sealed);
+        Ok(Self { digest: content_id(&ciphertext), ciphertext: Some(ciphertext) })
+    }
+
+    pub fn open(&self, shared_secret: &[u8]) -> Fallible<AttributeMap> {
+        self.validate()?;
+        let ciphertext = match &self.ciphertext {
+            Some(ciphertext) => ciphertext,
+            None => bail!("Sealed attributes were withheld"),
+        };
+        let (_base, sealed) = multibase::decode(ciphertext)?;
+        let key = SymmetricKey::derive(shared_secret, Self::KEY_CONTEXT);
+        Ok(serde_json::from_slice(&key.open(&sealed)?)?)
+    }
+
+    pub fn digest(&self) -> &ContentId {
+        &self.digest
+    }
+
+    pub fn is_withheld(&self) -> bool {
+        self.ciphertext.is_none()
+    }
+
+    /// The same entry without the ciphertext, for those the attributes were not sealed for.
+    pub fn withheld(&self) -> Self {
+        Self { digest: self.digest.clone(), ciphertext: None }
+    }
+
+    pub fn validate(&self) -> Fallible<()> {
+        if let Some(ciphertext) = &self.ciphertext {
+            ensure!(content_id(ciphertext) == self.digest, "Sealed attributes do not match digest");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serialization() -> Fallible<()> {
+        // Attributes stored before they were typed are still plain strings
+        let legacy: AttributeValue = serde_json::from_str(r#""some text""#)?;
+        assert_eq!(legacy, AttributeValue::String("some text".to_owned()));
+        assert_eq!(serde_json::to_string(&legacy)?, r#""some text""#);
+
+        let mut fields = BTreeMap::new();
+        fields.insert("answer".to_owned(), AttributeValue::Integer(42));
+        fields.insert(
+            "location".to_owned(),
+            AttributeValue::parse(AttributeKind::Location, "47.5, 19.04")?,
+        );
+        fields.insert("tags".to_owned(), AttributeValue::Array(vec!["a".into(), "b".into()]));
+        let object = AttributeValue::Object(fields);
+        let object_str = serde_json::to_string(&object)?;
+        assert_eq!(
+            object_str,
+            r#"{"Object":{"answer":{"Integer":42},"location":{"Location":{"latitude":47.5,"longitude":19.04}},"tags":{"Array":["a","b"]}}}"#
+        );
+        assert_eq!(serde_json::from_str::<AttributeValue>(&object_str)?, object);
+        Ok(())
+    }
+
+    #[test]
+    fn parsing() -> Fallible<()> {
+        assert_eq!(
+            AttributeValue::parse(AttributeKind::Boolean, "true")?,
+            AttributeValue::Boolean(true)
+        );
+        assert_eq!(
+            AttributeValue::parse(AttributeKind::Integer, "-7")?,
+            AttributeValue::Integer(-7)
+        );
+        assert_eq!(
+            AttributeValue::parse(AttributeKind::Timestamp, "60")?,
+            AttributeValue::Timestamp(UNIX_EPOCH + Duration::from_secs(60))
+        );
+        assert!(AttributeValue::parse(AttributeKind::Integer, "seven").is_err());
+        assert!(AttributeValue::parse(AttributeKind::Object, "[1, 2]").is_err());
+        assert_eq!(
+            AttributeValue::parse(AttributeKind::Array, "[1, 2.5]")?,
+            AttributeValue::Array(vec![AttributeValue::Integer(1), AttributeValue::Float(2.5)])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn structured_values() -> Fallible<()> {
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        struct Facet {
+            addrs: Vec<String>,
+            data: Vec<u8>,
+        }
+        let facet = Facet { addrs: vec!["/ip4/127.0.0.1/tcp/2077".to_owned()], data: vec![1, 2] };
+        let value = AttributeValue::from_serializable(&facet)?;
+        assert_eq!(value.kind(), AttributeKind::Object);
+        assert_eq!(value.to_deserializable::<Facet>()?, facet);
+
+        let legacy = AttributeValue::String(serde_json::to_string(&facet)?);
+        assert_eq!(legacy.to_deserializable::<Facet>()?, facet);
+        Ok(())
+    }
+}
@@ -1,14 +1,23 @@
 use serde_json::json;
 
-use super::SchemaVersion;
+use super::{SchemaId, SchemaVersion};
 
 pub fn get() -> Vec<SchemaVersion> {
     vec![age_over(), email_address(), full_name()]
 }
 
+/// Maps ids used before content-addressing to the current ids of the same schemas,
+/// so claims created with older versions can still be resolved.
+pub fn legacy_ids() -> Vec<(SchemaId, SchemaId)> {
+    vec![
+        ("McL9746fWtE9EXV5".to_owned(), age_over().id().to_owned()),
+        ("McL9746fWtE9EXVb".to_owned(), email_address().id().to_owned()),
+        ("McL9746fWtE9EXVa".to_owned(), full_name().id().to_owned()),
+    ]
+}
+
 fn age_over() -> SchemaVersion {
     SchemaVersion::new(
-        "McL9746fWtE9EXV5",
         "iop",
         "age-over",
         0,
@@ -29,7 +38,6 @@ fn age_over() -> SchemaVersion {
 
 fn email_address() -> SchemaVersion {
     SchemaVersion::new(
-        "McL9746fWtE9EXVb",
         "iop",
         "email-address",
         0,
@@ -49,7 +57,6 @@ fn email_address() -> SchemaVersion {
 
 fn full_name() -> SchemaVersion {
     SchemaVersion::new_with_order(
-        "McL9746fWtE9EXVa",
         "iop",
         "full-name",
         0,
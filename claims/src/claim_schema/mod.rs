@@ -4,11 +4,12 @@ use std::collections::HashMap;
 use std::fs;
 use std::path::Path;
 
-use failure::{err_msg, Fallible};
+use failure::{bail, ensure, err_msg, format_err, Fallible};
 use log::*;
 use serde_derive::{Deserialize, Serialize};
 
-use did::model::ContentId;
+use crate::model::{content_id, PublicProfileData};
+use did::model::{ContentId, ProfileId, SignedMessage, Signer};
 
 pub type SchemaId = ContentId;
 
@@ -16,35 +17,60 @@ const EMPTY_ORDERING: [String; 0] = [];
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct SchemaVersion {
+    /// Content id of the canonical form of all other fields except the signature
     id: SchemaId,
+    /// Profile id of the author for published schemas
     author: String,
     name: String,
     version: u32,
     content: serde_json::Value,
     #[serde(skip_serializing_if = "Option::is_none")]
     ordering: Option<Vec<String>>,
+    /// Schema id signed by the author. Only built-in schemas are allowed to be unsigned.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    signature: Option<SignedMessage>,
+}
+
+/// Fields hashed into the schema id
+#[derive(Serialize)]
+struct SchemaContent<'a> {
+    author: &'a str,
+    name: &'a str,
+    version: u32,
+    content: serde_json::Value,
+    ordering: &'a Option<Vec<String>>,
+}
+
+/// Object keys are sorted recursively, so the same schema content always has the same id,
+/// regardless of how it was written or which serde_json features are enabled.
+fn canonical_json(value: &serde_json::Value) -> serde_json::Value {
+    use serde_json::Value;
+    match value {
+        Value::Array(items) => Value::Array(items.iter().map(canonical_json).collect()),
+        Value::Object(map) => {
+            let mut keys = map.keys().collect::<Vec<_>>();
+            keys.sort();
+            Value::Object(
+                keys.into_iter().map(|k| (k.to_owned(), canonical_json(&map[k]))).collect(),
+            )
+        }
+        _ => value.to_owned(),
+    }
 }
 
 impl SchemaVersion {
+    const ATTRIBUTE_PREFIX: &'static str = "osg_claim_schema_";
+
     pub fn new(
-        id: impl ToString,
         author: impl ToString,
         name: impl ToString,
         version: u32,
         content: serde_json::Value,
     ) -> Self {
-        Self {
-            id: id.to_string(),
-            author: author.to_string(),
-            name: name.to_string(),
-            version,
-            content,
-            ordering: None,
-        }
+        Self::create(author.to_string(), name.to_string(), version, content, None)
     }
 
     pub fn new_with_order<T>(
-        id: impl ToString,
         author: impl ToString,
         name: impl ToString,
         version: u32,
@@ -54,14 +80,111 @@ impl SchemaVersion {
     where
         T: ToString,
     {
-        Self {
-            id: id.to_string(),
-            author: author.to_string(),
-            name: name.to_string(),
+        let ordering = ordering.into_iter().map(|s| s.to_string()).collect();
+        Self::create(author.to_string(), name.to_string(), version, content, Some(ordering))
+    }
+
+    fn create(
+        author: String,
+        name: String,
+        version: u32,
+        content: serde_json::Value,
+        ordering: Option<Vec<String>>,
+    ) -> Self {
+        let mut this = Self {
+            id: Default::default(),
+            author,
+            name,
             version,
             content,
-            ordering: Some(ordering.into_iter().map(|s| s.to_string()).collect()),
+            ordering,
+            signature: None,
+        };
+        this.id = this.content_id();
+        this
+    }
+
+    fn content_id(&self) -> SchemaId {
+        content_id(&SchemaContent {
+            author: &self.author,
+            name: &self.name,
+            version: self.version,
+            content: canonical_json(&self.content),
+            ordering: &self.ordering,
+        })
+    }
+
+    pub fn sign(&mut self, signer: &dyn Signer) -> Fallible<()> {
+        ensure!(
+            self.author == signer.profile_id().to_string(),
+            "Schema {} can be signed only by its author {}",
+            self.id,
+            self.author
+        );
+        let message = self.id.as_bytes().to_owned();
+        let signature = signer.sign(&message)?;
+        self.signature = Some(SignedMessage::new(signer.public_key(), message, signature));
+        Ok(())
+    }
+
+    pub fn is_signed(&self) -> bool {
+        self.signature.is_some()
+    }
+
+    /// Checks that the id matches the content and the signature of the author, if present.
+    pub fn validate(&self) -> Fallible<()> {
+        ensure!(self.id == self.content_id(), "Schema id {} does not match its content", self.id);
+        if let Some(signed_id) = &self.signature {
+            let author: ProfileId = self.author.parse()?;
+            ensure!(
+                signed_id.public_key().validate_id(&author),
+                "Schema {} was signed with another key than its author",
+                self.id
+            );
+            ensure!(
+                signed_id.message() == self.id.as_bytes(),
+                "Schema signature is for another id"
+            );
+            ensure!(signed_id.validate(), "Invalid schema signature");
         }
+        Ok(())
+    }
+
+    /// Adds the schema to the public profile of its author, so others can look it up by id.
+    pub fn publish_to(&self, profile: &mut PublicProfileData) -> Fallible<()> {
+        ensure!(self.is_signed(), "Only signed schemas can be published");
+        ensure!(
+            self.author == profile.id().to_string(),
+            "Schema {} can be published only by its author {}",
+            self.id,
+            self.author
+        );
+        self.validate()?;
+        let schema_str = serde_json::to_string(self)?;
+        profile.set_attribute(format!("{}{}", Self::ATTRIBUTE_PREFIX, self.id), schema_str.into());
+        Ok(())
+    }
+
+    /// Looks up a schema published by the owner of the profile and checks its signature.
+    pub fn published_by(profile: &PublicProfileData, id: &SchemaId) -> Fallible<Option<Self>> {
+        let attribute_id = format!("{}{}", Self::ATTRIBUTE_PREFIX, id);
+        let schema_str = match profile.attributes().get(&attribute_id) {
+            Some(value) => value
+                .as_str()
+                .ok_or_else(|| format_err!("Published schema {} is not a string attribute", id))?,
+            None => return Ok(None),
+        };
+        let schema: Self = serde_json::from_str(schema_str)?;
+        ensure!(schema.id == *id, "Schema was published with a different id {}", id);
+        ensure!(schema.is_signed(), "Published schema {} is not signed", id);
+        ensure!(
+            schema.author == profile.id().to_string(),
+            "Schema {} was published by {} instead of its author",
+            id,
+            profile.id()
+        );
+        schema.validate()?;
+        Ok(Some(schema))
     }
 
     pub fn id(&self) -> &str {
@@ -95,25 +218,48 @@ impl SchemaVersion {
 pub trait ClaimSchemas {
     fn iter<'a>(&'a self) -> Box<dyn Iterator<Item = &SchemaVersion> + 'a>;
     fn get(&self, id: &SchemaId) -> Fallible<SchemaVersion>;
+
+    /// All known versions of a schema, ordered by version number.
+    fn versions(&self, author: &str, name: &str) -> Vec<SchemaVersion> {
+        let mut versions = self
+            .iter()
+            .filter(|schema| schema.author() == author && schema.name() == name)
+            .cloned()
+            .collect::<Vec<_>>();
+        versions.sort_by_key(|schema| schema.version());
+        versions
+    }
 }
 
 pub struct ClaimSchemaRegistry {
     schemas: HashMap<SchemaId, SchemaVersion>,
+    // Hand-written ids used before ids were derived from the content
+    legacy_ids: HashMap<SchemaId, SchemaId>,
 }
 
 impl ClaimSchemaRegistry {
     pub fn populate_folder(path: &Path) -> Fallible<()> {
         for schema in defaults::get() {
-            let file_name =
-                format!("{}_{}_{}.schema.json", schema.author, schema.name, schema.version);
-            let file = std::fs::File::create(&path.join(file_name))?;
-            serde_json::to_writer_pretty(file, &schema)?;
+            Self::export_file(path, &schema)?;
         }
         Ok(())
     }
 
+    pub fn export_file(path: &Path, schema: &SchemaVersion) -> Fallible<()> {
+        let file_name = format!("{}_{}_{}.schema.json", schema.author, schema.name, schema.version);
+        let file = std::fs::File::create(&path.join(file_name))?;
+        serde_json::to_writer_pretty(file, &schema)?;
+        Ok(())
+    }
+
     pub fn import_folder(path: &Path) -> Fallible<Self> {
-        let mut root = ClaimSchemaRegistry { schemas: Default::default() };
+        let mut root = ClaimSchemaRegistry {
+            schemas: Default::default(),
+            legacy_ids: defaults::legacy_ids().into_iter().collect(),
+        };
+        for schema in defaults::get() {
+            root.add(schema)?;
+        }
         for entry in path.read_dir()? {
             // Iterator.next() might fail and then iteration should stop
             let entry = entry?.path();
@@ -147,9 +293,38 @@ impl ClaimSchemaRegistry {
 
     fn import_content(&mut self, content: &str) -> Fallible<()> {
         let schema_version = serde_json::from_str::<SchemaVersion>(&content)?;
-        self.schemas.insert(schema_version.id.clone(), schema_version);
+        self.add(schema_version)
+    }
+
+    /// Adds a schema after validating it. Schemas other than the built-in ones must be signed.
+    pub fn add(&mut self, schema: SchemaVersion) -> Fallible<()> {
+        schema.validate()?;
+        ensure!(
+            schema.is_signed() || defaults::get().iter().any(|builtin| builtin.id == schema.id),
+            "Schema {} is not signed by its author {}",
+            schema.id,
+            schema.author
+        );
+        let versions = self.versions(&schema.author, &schema.name);
+        let conflict =
+            versions.iter().find(|old| old.version == schema.version && old.id != schema.id);
+        if let Some(old) = conflict {
+            bail!(
+                "Schema {} conflicts with {}, both are version {} of {}",
+                schema.id,
+                old.id,
+                schema.version,
+                schema.name
+            );
+        }
+        self.schemas.insert(schema.id.clone(), schema);
         Ok(())
     }
+
+    /// The next version number for a schema with the given author and name.
+    pub fn next_version(&self, author: &str, name: &str) -> u32 {
+        self.versions(author, name).last().map(|latest| latest.version() + 1).unwrap_or_default()
+    }
 }
 
 impl ClaimSchemas for ClaimSchemaRegistry {
@@ -158,9 +333,64 @@ impl ClaimSchemas for ClaimSchemaRegistry {
     }
 
     fn get(&self, id: &SchemaId) -> Fallible<SchemaVersion> {
+        let id = self.legacy_ids.get(id).unwrap_or(id);
         self.schemas
             .get(id)
             .map(|val| val.to_owned())
             .ok_or_else(|| err_msg(format!("Schema id {} not found in registry", id)))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serde_json::json;
+
+    use super::*;
+    use did::vault::{HdProfileVault, ProfileVault};
+    use keyvault::PublicKey as KeyVaultPublicKey;
+
+    #[test]
+    fn signed_schema_publishing() -> Fallible<()> {
+        let phrase = keyvault::Seed::generate_bip39();
+        let mut vault = HdProfileVault::create(keyvault::Seed::from_bip39(&phrase)?);
+        let author_key = vault.create_key(None)?;
+        let vault = Arc::new(vault);
+        let signer = vault.clone().signer(&author_key.key_id())?;
+
+        let content = json!({ "b": 1, "a": { "d": 2, "c": 3 } });
+        let reordered: serde_json::Value = serde_json::from_str(r#"{"a":{"c":3,"d":2},"b":1}"#)?;
+        let mut schema = SchemaVersion::new(author_key.key_id(), "test", 0, content);
+        assert_eq!(schema.id(), SchemaVersion::new(author_key.key_id(), "test", 0, reordered).id());
+        assert_ne!(schema.id(), SchemaVersion::new(author_key.key_id(), "test", 1, json!({})).id());
+
+        let mut profile = PublicProfileData::empty(&author_key);
+        assert!(schema.publish_to(&mut profile).is_err());
+        schema.sign(signer.as_ref())?;
+        schema.validate()?;
+        schema.publish_to(&mut profile)?;
+
+        let id = schema.id().to_owned();
+        let published = SchemaVersion::published_by(&profile, &id)?.unwrap();
+        assert_eq!(published.id(), schema.id());
+        assert!(SchemaVersion::published_by(&profile, &"unknown".to_owned())?.is_none());
+
+        let mut registry = ClaimSchemaRegistry {
+            schemas: Default::default(),
+            legacy_ids: defaults::legacy_ids().into_iter().collect(),
+        };
+        registry.add(published)?;
+        assert_eq!(registry.next_version(&author_key.key_id().to_string(), "test"), 1);
+
+        let unsigned = SchemaVersion::new(author_key.key_id(), "unsigned", 0, json!({}));
+        assert!(registry.add(unsigned).is_err());
+        registry.add(defaults::get().remove(0))?;
+
+        let mut tampered = schema.clone();
+        tampered.content = json!({ "a": 1 });
+        assert!(tampered.validate().is_err());
+        assert!(registry.add(tampered).is_err());
+        Ok(())
+    }
+}
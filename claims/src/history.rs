@@ -0,0 +1,208 @@
+use std::collections::BTreeSet;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::model::*;
+
+/// Number of a snapshot in the history of a profile, increased with every stored change.
+/// NOTE changes of private data only do not increase the public version of the profile.
+pub type Revision = u64;
+
+/// A retained version of a profile in a repository
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ProfileSnapshot {
+    /// When this version was stored into the repository
+    pub timestamp: TimeStamp,
+    pub profile: PrivateProfileData,
+}
+
+impl ProfileSnapshot {
+    pub fn new(profile: PrivateProfileData) -> Self {
+        Self { timestamp: TimeStamp::now(), profile }
+    }
+
+    pub fn info(&self, revision: Revision) -> ProfileVersionInfo {
+        ProfileVersionInfo { revision, version: self.profile.version(), timestamp: self.timestamp }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ProfileVersionInfo {
+    pub revision: Revision,
+    pub version: Version,
+    pub timestamp: TimeStamp,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct AttributeChange {
+    pub attribute_id: AttributeId,
+    /// Visibility in the newer version, or in the older one if the attribute was removed
+    #[serde(default)]
+    pub visibility: AttributeVisibility,
+    /// None if the attribute was added
+    pub old_value: Option<AttributeValue>,
+    /// None if the attribute was removed
+    pub new_value: Option<AttributeValue>,
+}
+
+/// Structured changes between two versions of the same profile
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ProfileDiff {
+    pub from_version: Version,
+    pub to_version: Version,
+    pub attributes: Vec<AttributeChange>,
+    pub added_links: Vec<ProfileId>,
+    pub removed_links: Vec<ProfileId>,
+    #[serde(default)]
+    pub added_contacts: Vec<ProfileId>,
+    #[serde(default)]
+    pub removed_contacts: Vec<ProfileId>,
+    pub added_claims: Vec<ClaimId>,
+    pub removed_claims: Vec<ClaimId>,
+    /// Claims present in both versions, but with different proofs
+    pub changed_claims: Vec<ClaimId>,
+}
+
+impl ProfileDiff {
+    pub fn between(from: &PrivateProfileData, to: &PrivateProfileData) -> Self {
+        let (from_public, to_public) = (from.public_data(), to.public_data());
+
+        let attribute_ids: BTreeSet<&AttributeId> = ATTRIBUTE_VISIBILITIES
+            .iter()
+            .flat_map(|visibility| {
+                from.attributes(*visibility).keys().chain(to.attributes(*visibility).keys())
+            })
+            .collect();
+        let attributes = attribute_ids
+            .into_iter()
+            .filter_map(|id| {
+                let old_attribute = from.attribute(id);
+                let new_attribute = to.attribute(id);
+                if old_attribute == new_attribute {
+                    return None;
+                }
+                let visibility = new_attribute.or(old_attribute).map(|(visibility, _)| visibility);
+                Some(AttributeChange {
+                    attribute_id: id.to_owned(),
+                    visibility: visibility.unwrap_or_default(),
+                    old_value: old_attribute.map(|(_, value)| value.to_owned()),
+                    new_value: new_attribute.map(|(_, value)| value.to_owned()),
+                })
+            })
+            .collect();
+
+        let missing_links = |links: &[Link], others: &[Link]| -> Vec<ProfileId> {
+            links
+                .iter()
+                .filter(|link| !others.contains(link))
+                .map(|link| link.peer_profile.to_owned())
+                .collect()
+        };
+
+        let missing_contacts = |contacts: &[Contact], others: &[Contact]| -> Vec<ProfileId> {
+            contacts
+                .iter()
+                .filter(|contact| !others.iter().any(|other| other.id() == contact.id()))
+                .map(|contact| contact.id())
+                .collect()
+        };
+
+        let (from_claims, to_claims) = (from.claims(), to.claims());
+        let missing_claims = |claims: &[Claim], others: &[Claim]| -> Vec<ClaimId> {
+            claims
+                .iter()
+                .filter(|claim| !others.iter().any(|other| other.id() == claim.id()))
+                .map(|claim| claim.id())
+                .collect()
+        };
+        let changed_claims = to_claims
+            .iter()
+            .filter(|claim| {
+                from_claims
+                    .iter()
+                    .any(|old| old.id() == claim.id() && old.proofs() != claim.proofs())
+            })
+            .map(|claim| claim.id())
+            .collect();
+
+        Self {
+            from_version: from.version(),
+            to_version: to.version(),
+            attributes,
+            added_links: missing_links(to_public.links(), from_public.links()),
+            removed_links: missing_links(from_public.links(), to_public.links()),
+            added_contacts: missing_contacts(to.contacts(), from.contacts()),
+            removed_contacts: missing_contacts(from.contacts(), to.contacts()),
+            added_claims: missing_claims(&to_claims, &from_claims),
+            removed_claims: missing_claims(&from_claims, &to_claims),
+            changed_claims,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.attributes.is_empty()
+            && self.added_links.is_empty()
+            && self.removed_links.is_empty()
+            && self.added_contacts.is_empty()
+            && self.removed_contacts.is_empty()
+            && self.added_claims.is_empty()
+            && self.removed_claims.is_empty()
+            && self.changed_claims.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+    use keyvault::PublicKey as KeyVaultPublicKey;
+
+    #[test]
+    fn profile_diff() -> failure::Fallible<()> {
+        let my_key = PublicKey::from_str("pezAgmjPHe5Qs4VakvXHGnd6NsYjaxt4suMUtf39TayrSfb")?;
+        let peer_key = PublicKey::from_str("pezFVen3X669xLzsi6N2V91DoiyzHzg1uAgqiT8jZ9nS96Z")?;
+
+        let mut old = PrivateProfileData::empty(&my_key);
+        old.mut_public_data().set_attribute("kept".to_owned(), "same".into());
+        old.mut_public_data().set_attribute("changed".to_owned(), "old".into());
+        old.mut_public_data().set_attribute("removed".to_owned(), "gone".into());
+        let removed_claim = Claim::unproven(my_key.key_id(), "schema", serde_json::json!(1));
+        old.mut_claims().push(removed_claim.clone());
+
+        let mut new = old.clone();
+        new.mut_public_data().set_attribute("changed".to_owned(), "new".into());
+        new.mut_public_data().clear_attribute(&"removed".to_owned());
+        new.mut_public_data().set_attribute("added".to_owned(), "fresh".into());
+        new.mut_public_data().create_link(&peer_key.key_id());
+        new.mut_public_data().increase_version();
+        new.mut_claims().clear();
+        let added_claim = Claim::unproven(my_key.key_id(), "schema", serde_json::json!(2));
+        new.mut_claims().push(added_claim.clone());
+
+        let diff = ProfileDiff::between(&old, &new);
+        assert_eq!((diff.from_version, diff.to_version), (1, 2));
+        let change = |id: &str, old: Option<&str>, new: Option<&str>| AttributeChange {
+            attribute_id: id.to_owned(),
+            visibility: AttributeVisibility::Public,
+            old_value: old.map(|v| v.into()),
+            new_value: new.map(|v| v.into()),
+        };
+        assert_eq!(
+            diff.attributes,
+            vec![
+                change("added", None, Some("fresh")),
+                change("changed", Some("old"), Some("new")),
+                change("removed", Some("gone"), None),
+            ]
+        );
+        assert_eq!(diff.added_links, vec![peer_key.key_id()]);
+        assert!(diff.removed_links.is_empty());
+        assert_eq!(diff.added_claims, vec![added_claim.id()]);
+        assert_eq!(diff.removed_claims, vec![removed_claim.id()]);
+        assert!(diff.changed_claims.is_empty());
+
+        assert!(ProfileDiff::between(&new, &new).is_empty());
+        Ok(())
+    }
+}
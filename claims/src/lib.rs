@@ -1,4 +1,7 @@
+pub mod attribute;
 pub mod claim_schema;
+pub mod history;
 pub mod journal;
+pub mod merge;
 pub mod model;
 pub mod repo;
@@ -0,0 +1,312 @@
+use std::collections::{BTreeSet, HashMap};
+use std::str::FromStr;
+
+use failure::{ensure, Fallible};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::history::ProfileDiff;
+use crate::model::*;
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum MergeSide {
+    Local,
+    Remote,
+}
+
+impl FromStr for MergeSide {
+    type Err = failure::Error;
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        match src {
+            "local" => Ok(MergeSide::Local),
+            "remote" => Ok(MergeSide::Remote),
+            _ => Err(failure::err_msg("Invalid merge side, use local or remote")),
+        }
+    }
+}
+
+/// A part of the profile changed differently both in the local and the remote version
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum ProfileConflict {
+    Attribute {
+        attribute_id: AttributeId,
+        base: Option<AttributeValue>,
+        local: Option<AttributeValue>,
+        remote: Option<AttributeValue>,
+    },
+    PrivateData,
+}
+
+impl ProfileConflict {
+    pub fn key(&self) -> String {
+        match self {
+            ProfileConflict::Attribute { attribute_id, .. } => {
+                format!("attribute {}", attribute_id)
+            }
+            ProfileConflict::PrivateData => "private data".to_owned(),
+        }
+    }
+}
+
+/// Choices of the user for conflicts reported by a previous merge attempt
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct MergeResolutions {
+    /// Value of a conflicting attribute in the merged profile, None removes the attribute
+    #[serde(default)]
+    pub attributes: HashMap<AttributeId, Option<AttributeValue>>,
+    #[serde(default)]
+    pub private_data: Option<MergeSide>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ProfileMerge {
+    /// The merged profile. Conflicting parts keep their local values until resolved.
+    pub profile: PrivateProfileData,
+    pub conflicts: Vec<ProfileConflict>,
+}
+
+impl ProfileMerge {
+    /// Three-way merge of two divergent versions of a profile with their last common version as
+    /// ancestor. Attributes and private data are merged as single values, links, contacts and claims as sets
+    /// keeping additions and removals of both sides, proofs of claims present on both sides are united.
+    pub fn merge(
+        base: Option<&PrivateProfileData>,
+        local: &PrivateProfileData,
+        remote: &PrivateProfileData,
+        resolutions: &MergeResolutions,
+    ) -> Fallible<Self> {
+        ensure!(local.id() == remote.id(), "Cannot merge versions of different profiles");
+        let empty_base = PrivateProfileData::empty(&local.public_key());
+        let base = base.unwrap_or(&empty_base);
+
+        // Fast-forward if only one side changed
+        if same_content(local, base) || same_content(local, remote) {
+            return Ok(Self { profile: remote.to_owned(), conflicts: vec![] });
+        }
+        if same_content(remote, base) {
+            return Ok(Self { profile: local.to_owned(), conflicts: vec![] });
+        }
+
+        let mut conflicts = vec![];
+        let (base_public, local_public, remote_public) =
+            (base.public_data(), local.public_data(), remote.public_data());
+
+        let mut merged_public = PublicProfileData::new(
+            local.public_key(),
+            local.version().max(remote.version()) + 1,
+            merge_sets(base_public.links(), local_public.links(), remote_public.links()),
+            Default::default(),
+        );
+        // Attributes are merged together with their visibility, a resolved value keeps the local visibility
+        let attribute_ids: BTreeSet<&AttributeId> = ATTRIBUTE_VISIBILITIES
+            .iter()
+            .flat_map(|visibility| {
+                local
+                    .attributes(*visibility)
+                    .keys()
+                    .chain(remote.attributes(*visibility).keys())
+                    .chain(base.attributes(*visibility).keys())
+            })
+            .collect();
+        let mut merged_attributes = vec![];
+        for attribute_id in attribute_ids {
+            let base_attribute = base.attribute(attribute_id);
+            let local_attribute = local.attribute(attribute_id);
+            let remote_attribute = remote.attribute(attribute_id);
+            let merged_attribute = match merge_values(
+                base_attribute.as_ref(),
+                local_attribute.as_ref(),
+                remote_attribute.as_ref(),
+            ) {
+                Some(attribute) => attribute.cloned(),
+                None => match resolutions.attributes.get(attribute_id) {
+                    Some(resolved) => resolved.as_ref().map(|value| {
+                        let visibility = local_attribute
+                            .or(remote_attribute)
+                            .map(|(visibility, _)| visibility)
+                            .unwrap_or_default();
+                        (visibility, value)
+                    }),
+                    None => {
+                        let value = |attribute: Option<(_, &AttributeValue)>| {
+                            attribute.map(|(_, value)| value.to_owned())
+                        };
+                        conflicts.push(ProfileConflict::Attribute {
+                            attribute_id: attribute_id.to_owned(),
+                            base: value(base_attribute),
+                            local: value(local_attribute),
+                            remote: value(remote_attribute),
+                        });
+                        local_attribute
+                    }
+                },
+            };
+            if let Some((visibility, value)) = merged_attribute {
+                match visibility {
+                    AttributeVisibility::Public => {
+                        merged_public.set_attribute(attribute_id.to_owned(), value.to_owned())
+                    }
+                    _ => merged_attributes.push((attribute_id, visibility, value)),
+                }
+            }
+        }
+
+        let (base_private, local_private, remote_private) =
+            (base.private_data(), local.private_data(), remote.private_data());
+        let merged_private =
+            match merge_values(Some(&base_private), Some(&local_private), Some(&remote_private)) {
+                Some(value) => value.cloned().unwrap_or_default(),
+                None => match resolutions.private_data {
+                    Some(MergeSide::Remote) => remote_private.to_owned(),
+                    Some(MergeSide::Local) => local_private.to_owned(),
+                    None => {
+                        conflicts.push(ProfileConflict::PrivateData);
+                        local_private.to_owned()
+                    }
+                },
+            };
+
+        let merged_claims = merge_claims(&base.claims(), &local.claims(), &remote.claims());
+        let mut profile = PrivateProfileData::new(merged_public, merged_private, merged_claims);
+        for (attribute_id, visibility, value) in merged_attributes {
+            profile.set_attribute(attribute_id.to_owned(), value.to_owned(), visibility);
+        }
+        for contact in merge_sets(base.contacts(), local.contacts(), remote.contacts()) {
+            profile.add_contact(contact);
+        }
+        Ok(Self { profile, conflicts })
+    }
+}
+
+/// Versions and signatures are ignored, only the profile content is compared.
+fn same_content(left: &PrivateProfileData, right: &PrivateProfileData) -> bool {
+    left.private_data() == right.private_data() && ProfileDiff::between(left, right).is_empty()
+}
+
+/// Returns None on conflict, otherwise the merged value, where None means a missing value.
+fn merge_values<'a, T: PartialEq>(
+    base: Option<&'a T>,
+    local: Option<&'a T>,
+    remote: Option<&'a T>,
+) -> Option<Option<&'a T>> {
+    if local == remote || remote == base {
+        Some(local)
+    } else if local == base {
+        Some(remote)
+    } else {
+        None
+    }
+}
+
+/// Items added on any side are kept, items removed on any side are dropped.
+fn merge_sets<T: Clone + PartialEq>(base: &[T], local: &[T], remote: &[T]) -> Vec<T> {
+    let mut merged: Vec<T> = local
+        .iter()
+        .filter(|item| remote.contains(item) || !base.contains(item))
+        .cloned()
+        .collect();
+    for item in remote {
+        if !base.contains(item) && !merged.contains(item) {
+            merged.push(item.to_owned());
+        }
+    }
+    merged
+}
+
+fn merge_claims(base: &[Claim], local: &[Claim], remote: &[Claim]) -> Vec<Claim> {
+    let ids = |claims: &[Claim]| claims.iter().map(|claim| claim.id()).collect::<Vec<_>>();
+    let merged_ids = merge_sets(&ids(base), &ids(local), &ids(remote));
+    merged_ids
+        .iter()
+        .filter_map(|id| {
+            let local_claim = local.iter().find(|claim| claim.id() == *id);
+            let remote_claim = remote.iter().find(|claim| claim.id() == *id);
+            match (local_claim, remote_claim) {
+                (Some(local_claim), Some(remote_claim)) => {
+                    let mut claim = local_claim.to_owned();
+                    for proof in remote_claim.proofs() {
+                        if !claim.proofs().contains(proof) {
+                            claim.add_proof(proof.to_owned());
+                        }
+                    }
+                    Some(claim)
+                }
+                (claim, None) | (None, claim) => claim.cloned(),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use keyvault::PublicKey as KeyVaultPublicKey;
+
+    fn keys() -> Fallible<(PublicKey, PublicKey, PublicKey)> {
+        Ok((
+            PublicKey::from_str("pezAgmjPHe5Qs4VakvXHGnd6NsYjaxt4suMUtf39TayrSfb")?,
+            PublicKey::from_str("pezFVen3X669xLzsi6N2V91DoiyzHzg1uAgqiT8jZ9nS96Z")?,
+            PublicKey::from_str("pez7aYuvoDPM5i7xedjwjsWaFVzL3qRKPv4sBLv3E3pAGi6")?,
+        ))
+    }
+
+    #[test]
+    fn three_way_merge() -> Fallible<()> {
+        let (my_key, peer1_key, peer2_key) = keys()?;
+        let mut base = PrivateProfileData::empty(&my_key);
+        base.mut_public_data().set_attribute("kept".to_owned(), "base".into());
+        base.mut_public_data().set_attribute("local".to_owned(), "base".into());
+        base.mut_public_data().set_attribute("remote".to_owned(), "base".into());
+        base.mut_public_data().set_attribute("both".to_owned(), "base".into());
+        base.mut_public_data().create_link(&peer1_key.key_id());
+        let claim = Claim::unproven(my_key.key_id(), "schema", serde_json::json!(1));
+        base.mut_claims().push(claim.clone());
+
+        let mut local = base.clone();
+        local.mut_public_data().set_attribute("local".to_owned(), "local".into());
+        local.mut_public_data().set_attribute("both".to_owned(), "local".into());
+        local.mut_public_data().remove_link(&peer1_key.key_id());
+        local.mut_public_data().increase_version();
+        *local.mut_private_data() = b"local".to_vec();
+
+        let mut remote = base.clone();
+        remote.mut_public_data().clear_attribute(&"remote".to_owned());
+        remote.mut_public_data().set_attribute("both".to_owned(), "remote".into());
+        remote.mut_public_data().create_link(&peer2_key.key_id());
+        remote.mut_public_data().increase_version();
+        remote.mut_public_data().increase_version();
+        let new_claim = Claim::unproven(my_key.key_id(), "schema", serde_json::json!(2));
+        remote.mut_claims().push(new_claim.clone());
+
+        let merge = ProfileMerge::merge(Some(&base), &local, &remote, &Default::default())?;
+        assert_eq!(
+            merge.conflicts,
+            vec![ProfileConflict::Attribute {
+                attribute_id: "both".to_owned(),
+                base: Some("base".into()),
+                local: Some("local".into()),
+                remote: Some("remote".into()),
+            }]
+        );
+        let merged = merge.profile.public_data();
+        assert_eq!(merged.version(), 4);
+        assert_eq!(merged.attributes().get("kept"), Some(&"base".into()));
+        assert_eq!(merged.attributes().get("local"), Some(&"local".into()));
+        assert_eq!(merged.attributes().get("remote"), None);
+        assert_eq!(merged.attributes().get("both"), Some(&"local".into()));
+        assert_eq!(merged.links(), &vec![Link { peer_profile: peer2_key.key_id() }]);
+        assert_eq!(merge.profile.private_data(), b"local".to_vec());
+        assert_eq!(merge.profile.claims(), vec![claim, new_claim]);
+
+        let mut resolutions = MergeResolutions::default();
+        resolutions.attributes.insert("both".to_owned(), Some("resolved".into()));
+        let merge = ProfileMerge::merge(Some(&base), &local, &remote, &resolutions)?;
+        assert!(merge.conflicts.is_empty());
+        let merged = merge.profile.public_data();
+        assert_eq!(merged.attributes().get("both"), Some(&"resolved".into()));
+
+        // Unchanged local version is simply replaced by the remote one
+        let merge = ProfileMerge::merge(Some(&base), &base, &remote, &Default::default())?;
+        assert_eq!(merge.profile, remote);
+        Ok(())
+    }
+}
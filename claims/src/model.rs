@@ -1,19 +1,20 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::str::FromStr;
 use std::string::ToString;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
-use failure::{ensure, Fallible};
+use failure::{bail, ensure, Fallible};
 use serde::{Deserialize, Serialize};
 
 use crate::claim_schema::SchemaId;
 pub use did::model::*;
-use keyvault::PublicKey as KeyVaultPublicKey;
+use keyvault::{encryption::SymmetricKey, PublicKey as KeyVaultPublicKey};
 
 // TODO this overlaps with JournalState, maybe they could be merged
 pub type Version = u64; // monotonically increasing, e.g. normal version, unix timestamp or block height
-pub type AttributeId = String;
-pub type AttributeValue = String;
+pub use crate::attribute::{
+    AttributeId, AttributeKind, AttributeValue, AttributeVisibility, GpsLocation, SealedAttributes,
+};
 pub type AttributeMap = HashMap<AttributeId, AttributeValue>;
 pub type ClaimId = ContentId;
 pub type ClaimLicenseId = ContentId;
@@ -129,6 +130,10 @@ impl ClaimProof {
         self.valid_until.clone()
     }
 
+    pub fn is_expired_at(&self, time: TimeStamp) -> bool {
+        self.valid_until <= time
+    }
+
     // NOTE Useful only for testing, shouldn't be here otherwise
     pub fn mut_signer_id(&mut self) -> &mut ProfileId {
         &mut self.signer_id
@@ -171,6 +176,32 @@ impl FromStr for ClaimProof {
     }
 }
 
+/// Validity state of a claim derived from the expiry of its proofs.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ClaimExpiryStatus {
+    /// No witness has signed the claim yet, so there's nothing to expire
+    Unproven,
+    Valid,
+    /// Still has a valid proof, but all of them expire within the warning period
+    ExpiringSoon,
+    /// All proofs have expired, witnesses have to be asked again to sign the claim
+    Expired,
+}
+
+impl FromStr for ClaimExpiryStatus {
+    type Err = failure::Error;
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        match src {
+            "unproven" => Ok(ClaimExpiryStatus::Unproven),
+            "valid" => Ok(ClaimExpiryStatus::Valid),
+            "expiring-soon" => Ok(ClaimExpiryStatus::ExpiringSoon),
+            "expired" => Ok(ClaimExpiryStatus::Expired),
+            _ => Err(failure::err_msg("Invalid claim expiry status")),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct Claim {
     signable: SignableClaimPart,
@@ -216,6 +247,33 @@ impl Claim {
     pub fn add_proof(&mut self, proof: ClaimProof) {
         self.proofs.push(proof);
     }
+
+    /// The claim stays valid until its last proof expires.
+    pub fn valid_until(&self) -> Option<TimeStamp> {
+        self.proofs.iter().map(|proof| proof.valid_until()).max()
+    }
+
+    pub fn expiry_status(&self, now: TimeStamp, warning_period: Duration) -> ClaimExpiryStatus {
+        match self.valid_until() {
+            None => ClaimExpiryStatus::Unproven,
+            Some(valid_until) if valid_until <= now => ClaimExpiryStatus::Expired,
+            Some(valid_until) if valid_until <= now + warning_period => {
+                ClaimExpiryStatus::ExpiringSoon
+            }
+            Some(_valid_until) => ClaimExpiryStatus::Valid,
+        }
+    }
+
+    /// Distinct profiles that witnessed this claim so far.
+    pub fn witnesses(&self) -> Vec<ProfileId> {
+        let mut witnesses: Vec<ProfileId> = Vec::new();
+        for proof in &self.proofs {
+            if !witnesses.contains(proof.signer_id()) {
+                witnesses.push(proof.signer_id().to_owned());
+            }
+        }
+        witnesses
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
@@ -268,7 +326,28 @@ pub struct PublicProfileData {
     attributes: AttributeMap,
     // TODO remove this, links/contacts should be a special case of claims and filtered from them
     links: Vec<Link>,
-    // TODO consider adding a signature of the profile data here
+    /// Contacts-only attributes sealed separately for each contact, keyed by the contact tag,
+    /// see `SealedAttributes::contact_tag()`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    sealed_attributes: BTreeMap<String, SealedAttributes>,
+    /// Signature of the profile key over the canonical form of all fields above.
+    /// Any modification of the profile invalidates and therefore clears it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    signature: Option<Signature>,
+}
+
+/// Fields of PublicProfileData covered by its signature. Attributes are ordered by key
+/// so the serialized form does not depend on the iteration order of the attribute map.
+#[derive(Serialize)]
+struct SignablePublicProfile<'a> {
+    public_key: &'a PublicKey,
+    version: Version,
+    attributes: BTreeMap<&'a AttributeId, &'a AttributeValue>,
+    links: &'a [Link],
+    /// Only digests are signed, so ciphertexts sealed for others can be withheld from a contact.
+    /// Skipped when empty to keep signatures of profiles without contacts-only attributes valid.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    sealed_attributes: BTreeMap<&'a String, &'a ContentId>,
 }
 
 impl PublicProfileData {
@@ -278,7 +357,14 @@ impl PublicProfileData {
         links: Vec<Link>,
         attributes: AttributeMap,
     ) -> Self {
-        Self { public_key, version, links, attributes }
+        Self {
+            public_key,
+            version,
+            links,
+            attributes,
+            sealed_attributes: Default::default(),
+            signature: None,
+        }
     }
 
     pub fn empty(public_key: &PublicKey) -> Self {
@@ -291,6 +377,8 @@ impl PublicProfileData {
             version: last_version + 1,
             links: Default::default(),
             attributes: Default::default(),
+            sealed_attributes: Default::default(),
+            signature: None,
         }
     }
 
@@ -298,6 +386,60 @@ impl PublicProfileData {
         self.public_key.key_id()
     }
 
+    /// Canonical serialization of the profile content that is signed by the profile key.
+    pub fn signable_bytes(&self) -> Vec<u8> {
+        let signable = SignablePublicProfile {
+            public_key: &self.public_key,
+            version: self.version,
+            attributes: self.attributes.iter().collect(),
+            links: &self.links,
+            sealed_attributes: self
+                .sealed_attributes
+                .iter()
+                .map(|(tag, sealed)| (tag, sealed.digest()))
+                .collect(),
+        };
+        // NOTE serializing a struct with string keys only must never fail
+        serde_json::to_vec(&signable).unwrap()
+    }
+
+    pub fn signature(&self) -> Option<&Signature> {
+        self.signature.as_ref()
+    }
+
+    pub fn is_signed(&self) -> bool {
+        self.signature.is_some()
+    }
+
+    pub fn sign(&mut self, signer: &dyn Signer) -> Fallible<()> {
+        ensure!(
+            *signer.profile_id() == self.id(),
+            "Profile {} can be signed only by its own key, not by {}",
+            self.id(),
+            signer.profile_id()
+        );
+        self.signature = Some(signer.sign(&self.signable_bytes())?);
+        Ok(())
+    }
+
+    /// Checks that the profile content is signed by its own key.
+    pub fn validate(&self) -> Fallible<()> {
+        for sealed in self.sealed_attributes.values() {
+            sealed.validate()?;
+        }
+        let signature = match &self.signature {
+            Some(signature) => signature,
+            None => bail!("Profile {} version {} is not signed", self.id(), self.version),
+        };
+        ensure!(
+            self.public_key.verify(&self.signable_bytes(), signature),
+            "Invalid signature on profile {} version {}",
+            self.id(),
+            self.version
+        );
+        Ok(())
+    }
+
     pub fn public_key(&self) -> PublicKey {
         self.public_key.clone() // TODO in the dev branches this is already Copy, remove cloning after it's merged
     }
@@ -308,9 +450,11 @@ impl PublicProfileData {
 
     pub fn increase_version(&mut self) {
         self.version += 1;
+        self.signature = None;
     }
     pub fn set_version(&mut self, version: Version) {
         self.version = version;
+        self.signature = None;
     }
 
     pub fn links(&self) -> &Vec<Link> {
@@ -321,12 +465,14 @@ impl PublicProfileData {
         let link = Link { peer_profile: with_id.to_owned() };
         if !self.links.contains(&link) {
             self.links.push(link.clone());
+            self.signature = None;
         }
         link
     }
 
     pub fn remove_link(&mut self, with_id: &ProfileId) {
-        self.links.retain(|link| link.peer_profile != *with_id)
+        self.links.retain(|link| link.peer_profile != *with_id);
+        self.signature = None;
     }
 
     pub fn attributes(&self) -> &AttributeMap {
@@ -334,15 +480,79 @@ impl PublicProfileData {
     }
 
     pub fn mut_attributes(&mut self) -> &mut AttributeMap {
+        self.signature = None;
         &mut self.attributes
     }
 
     pub fn set_attribute(&mut self, key: AttributeId, value: AttributeValue) {
         self.attributes.insert(key, value);
+        self.signature = None;
     }
 
     pub fn clear_attribute(&mut self, key: &AttributeId) {
         self.attributes.remove(key);
+        self.signature = None;
+    }
+
+    pub fn sealed_attributes(&self) -> &BTreeMap<String, SealedAttributes> {
+        &self.sealed_attributes
+    }
+
+    /// Seals attributes for the contact sharing the given secret with the profile.
+    pub fn seal_attributes_for(
+        &mut self,
+        attributes: &AttributeMap,
+        shared_secret: &[u8],
+    ) -> Fallible<()> {
+        let sealed = SealedAttributes::seal(attributes, shared_secret)?;
+        self.sealed_attributes.insert(SealedAttributes::contact_tag(shared_secret), sealed);
+        self.signature = None;
+        Ok(())
+    }
+
+    pub fn clear_sealed_attributes(&mut self) {
+        self.sealed_attributes.clear();
+        self.signature = None;
+    }
+
+    /// Contacts-only attributes sealed for the contact sharing the given secret with the profile,
+    /// None if nothing was sealed for them.
+    pub fn open_attributes_for(&self, shared_secret: &[u8]) -> Fallible<Option<AttributeMap>> {
+        match self.sealed_attributes.get(&SealedAttributes::contact_tag(shared_secret)) {
+            Some(sealed) => Ok(Some(sealed.open(shared_secret)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// The profile as it should be served to the given requester: sealed attributes are
+    /// withheld from anonymous requesters. Entries cannot be matched to contacts without
+    /// the shared secret, so authenticated requesters get all of them and can open only their own.
+    /// The signature stays valid as it covers only their digests.
+    pub fn view_for(&self, requester_id: Option<&ProfileId>) -> Self {
+        let mut view = self.clone();
+        if requester_id.is_none() {
+            for sealed in view.sealed_attributes.values_mut() {
+                *sealed = sealed.withheld();
+            }
+        }
+        view
+    }
+}
+
+/// A paired peer of a profile that contacts-only attributes are sealed for on publishing.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Contact {
+    pub public_key: PublicKey,
+    pub relation_type: String,
+}
+
+impl Contact {
+    pub fn new(public_key: PublicKey, relation_type: impl ToString) -> Self {
+        Self { public_key, relation_type: relation_type.to_string() }
+    }
+
+    pub fn id(&self) -> ProfileId {
+        self.public_key.key_id()
     }
 }
 
@@ -352,11 +562,40 @@ pub struct PrivateProfileData {
     // TODO consider storing claims in a map for easier search by id
     claims: Vec<Claim>,
     private_data: Vec<u8>,
+    /// Attributes readable only by contacts, published sealed for each of them.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    contact_attributes: AttributeMap,
+    /// Attributes that are never published.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    private_attributes: AttributeMap,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    contacts: Vec<Contact>,
+    /// All private parts above encrypted by the owner, see `seal_private_parts()`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sealed_private_parts: Option<String>,
+}
+
+/// Parts of PrivateProfileData sealed together into its backup.
+#[derive(Deserialize, Serialize)]
+struct PrivateParts {
+    claims: Vec<Claim>,
+    private_data: Vec<u8>,
+    contact_attributes: AttributeMap,
+    private_attributes: AttributeMap,
+    contacts: Vec<Contact>,
 }
 
 impl PrivateProfileData {
     pub fn new(public_data: PublicProfileData, private_data: Vec<u8>, claims: Vec<Claim>) -> Self {
-        Self { public_data, private_data, claims }
+        Self {
+            public_data,
+            private_data,
+            claims,
+            contact_attributes: Default::default(),
+            private_attributes: Default::default(),
+            contacts: Default::default(),
+            sealed_private_parts: None,
+        }
     }
 
     // TODO The lower-level Mercury network layer should not be aware of claims and the
@@ -376,11 +615,11 @@ impl PrivateProfileData {
     }
 
     pub fn tombstone(public_key: &PublicKey, last_version: Version) -> Self {
-        Self {
-            public_data: PublicProfileData::tombstone(public_key, last_version),
-            claims: Default::default(),
-            private_data: Default::default(),
-        }
+        Self::new(
+            PublicProfileData::tombstone(public_key, last_version),
+            Default::default(),
+            Default::default(),
+        )
     }
 
     pub fn public_data(&self) -> PublicProfileData {
@@ -409,6 +648,117 @@ impl PrivateProfileData {
         &mut self.private_data
     }
 
+    pub fn attributes(&self, visibility: AttributeVisibility) -> &AttributeMap {
+        match visibility {
+            AttributeVisibility::Public => self.public_data.attributes(),
+            AttributeVisibility::Contacts => &self.contact_attributes,
+            AttributeVisibility::Private => &self.private_attributes,
+        }
+    }
+
+    pub fn attribute(&self, key: &str) -> Option<(AttributeVisibility, &AttributeValue)> {
+        ATTRIBUTE_VISIBILITIES.iter().find_map(|visibility| {
+            self.attributes(*visibility).get(key).map(|value| (*visibility, value))
+        })
+    }
+
+    /// Sets the attribute with the given visibility, removing it from the other visibilities.
+    pub fn set_attribute(
+        &mut self,
+        key: AttributeId,
+        value: AttributeValue,
+        visibility: AttributeVisibility,
+    ) {
+        self.clear_attribute(&key);
+        match visibility {
+            AttributeVisibility::Public => self.public_data.set_attribute(key, value),
+            AttributeVisibility::Contacts => {
+                self.contact_attributes.insert(key, value);
+            }
+            AttributeVisibility::Private => {
+                self.private_attributes.insert(key, value);
+            }
+        }
+    }
+
+    pub fn clear_attribute(&mut self, key: &AttributeId) {
+        if self.public_data.attributes().contains_key(key) {
+            self.public_data.clear_attribute(key);
+        }
+        self.contact_attributes.remove(key);
+        self.private_attributes.remove(key);
+    }
+
+    pub fn contacts(&self) -> &[Contact] {
+        &self.contacts
+    }
+
+    pub fn add_contact(&mut self, contact: Contact) {
+        self.remove_contact(&contact.id());
+        self.contacts.push(contact);
+    }
+
+    pub fn remove_contact(&mut self, contact_id: &ProfileId) {
+        self.contacts.retain(|contact| contact.id() != *contact_id);
+    }
+
+    pub fn is_sealed(&self) -> bool {
+        self.sealed_private_parts.is_some()
+    }
+
+    /// Backup form of the profile where only the public data is readable, all private parts are
+    /// encrypted with the given key. Storage holding the backup, e.g. a home node, cannot read them.
+    pub fn seal_private_parts(&self, key: &SymmetricKey) -> Fallible<Self> {
+        ensure!(!self.is_sealed(), "Private parts of profile {} are already sealed", self.id());
+        let parts = PrivateParts {
+            claims: self.claims.clone(),
+            private_data: self.private_data.clone(),
+            contact_attributes: self.contact_attributes.clone(),
+            private_attributes: self.private_attributes.clone(),
+            contacts: self.contacts.clone(),
+        };
+        let sealed = key.seal(&serde_json::to_vec(&parts)?)?;
+        let mut backup = Self::from_public(self.public_data.clone());
+        backup.sealed_private_parts = Some(multibase::encode(multibase::Base64url, &sealed));
+        Ok(backup)
+    }
+
+    /// Reverts `seal_private_parts()`. Backups made before sealing was introduced are returned as they are.
+    pub fn open_private_parts(&self, key: &SymmetricKey) -> Fallible<Self> {
+        let sealed = match &self.sealed_private_parts {
+            Some(sealed) => sealed,
+            None => return Ok(self.clone()),
+        };
+        let (_base, sealed) = multibase::decode(sealed)?;
+        let parts: PrivateParts = serde_json::from_slice(&key.open(&sealed)?)?;
+        Ok(Self {
+            public_data: self.public_data.clone(),
+            claims: parts.claims,
+            private_data: parts.private_data,
+            contact_attributes: parts.contact_attributes,
+            private_attributes: parts.private_attributes,
+            contacts: parts.contacts,
+            sealed_private_parts: None,
+        })
+    }
+
+    /// Replaces sealed attributes of the public data with contacts-only attributes sealed
+    /// for each contact using the secret shared with the contact.
+    pub fn seal_contact_attributes(
+        &mut self,
+        shared_secret: impl Fn(&PublicKey) -> Fallible<Vec<u8>>,
+    ) -> Fallible<()> {
+        self.public_data.clear_sealed_attributes();
+        if self.contact_attributes.is_empty() {
+            return Ok(());
+        }
+        for contact in &self.contacts {
+            let secret = shared_secret(&contact.public_key)?;
+            self.public_data.seal_attributes_for(&self.contact_attributes, &secret)?;
+        }
+        Ok(())
+    }
+
     pub fn id(&self) -> ProfileId {
         self.public_data.id()
     }
@@ -420,6 +770,9 @@ impl PrivateProfileData {
     }
 }
 
+pub const ATTRIBUTE_VISIBILITIES: [AttributeVisibility; 3] =
+    [AttributeVisibility::Public, AttributeVisibility::Contacts, AttributeVisibility::Private];
+
 #[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub enum Grant {
     Impersonate,
@@ -477,6 +830,74 @@ impl ProfileAuthData {
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use did::vault::{HdProfileVault, ProfileVault};
+
     #[test]
     fn it_works() {}
+
+    #[test]
+    fn claim_expiry_status() -> Fallible<()> {
+        let seed = keyvault::Seed::generate_bip39();
+        let mut vault = HdProfileVault::create(keyvault::Seed::from_bip39(&seed)?);
+        let witness_id = vault.create_key(None)?.key_id();
+
+        let mut claim = Claim::unproven(witness_id.clone(), "schema", serde_json::Value::Null);
+        let now = SystemTime::now();
+        let day = Duration::from_secs(24 * 60 * 60);
+        assert_eq!(claim.expiry_status(now, 30 * day), ClaimExpiryStatus::Unproven);
+
+        let message = serde_json::to_vec(claim.signable_part())?;
+        let signed_message = vault.sign(&witness_id, &message)?;
+        claim.add_proof(ClaimProof::new(
+            witness_id.clone(),
+            signed_message.clone(),
+            now - 2 * day,
+            now - day,
+        ));
+        assert_eq!(claim.expiry_status(now, 30 * day), ClaimExpiryStatus::Expired);
+
+        claim.add_proof(ClaimProof::new(witness_id.clone(), signed_message, now, now + 10 * day));
+        assert_eq!(claim.valid_until(), Some(now + 10 * day));
+        assert_eq!(claim.expiry_status(now, 30 * day), ClaimExpiryStatus::ExpiringSoon);
+        assert_eq!(claim.expiry_status(now, 5 * day), ClaimExpiryStatus::Valid);
+        assert_eq!(claim.witnesses(), vec![witness_id]);
+
+        let status_json = serde_json::to_string(&ClaimExpiryStatus::ExpiringSoon)?;
+        assert_eq!(status_json, "\"expiring-soon\"");
+        assert_eq!(
+            serde_json::from_str::<ClaimExpiryStatus>(&status_json)?,
+            "expiring-soon".parse()?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn sealed_backup() -> Fallible<()> {
+        let seed = keyvault::Seed::generate_bip39();
+        let mut vault = HdProfileVault::create(keyvault::Seed::from_bip39(&seed)?);
+        let my_key = vault.create_key(None)?;
+        let contact_key = vault.create_key(None)?;
+
+        let mut profile = PrivateProfileData::empty(&my_key);
+        profile.set_attribute("name".to_owned(), "Me".into(), AttributeVisibility::Public);
+        profile.set_attribute("phone".to_owned(), "+36123".into(), AttributeVisibility::Contacts);
+        profile.add_contact(Contact::new(contact_key, "friend"));
+        profile.mut_claims().push(Claim::unproven(my_key.key_id(), "schema", "secret".into()));
+        *profile.mut_private_data() = b"private".to_vec();
+
+        let key = vault.backup_key(&my_key.key_id())?;
+        let backup = profile.seal_private_parts(&key)?;
+        assert!(backup.is_sealed());
+        assert_eq!(backup.public_data(), profile.public_data());
+        let backup_json = serde_json::to_string(&backup)?;
+        assert!(!backup_json.contains("+36123") && !backup_json.contains("secret"));
+        assert!(backup.seal_private_parts(&key).is_err());
+
+        assert_eq!(backup.open_private_parts(&key)?, profile);
+        assert_eq!(profile.open_private_parts(&key)?, profile);
+        let other_key = vault.backup_key(&profile.contacts()[0].id())?;
+        assert!(backup.open_private_parts(&other_key).is_err());
+        Ok(())
+    }
 }
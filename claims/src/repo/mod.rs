@@ -0,0 +1,644 @@
+mod sqlite;
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::File;
+use std::path::PathBuf;
+
+use failure::{bail, format_err, Fallible};
+use futures::prelude::*;
+use log::*;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::history::{ProfileSnapshot, ProfileVersionInfo, Revision};
+use crate::model::*;
+use keyvault::PublicKey as KeyVaultPublicKey;
+pub use sqlite::SqliteProfileRepository;
+
+/// A whole network of storage nodes, potentially with internal routing and sharding.
+/// Storage nodes are not trusted, so implementations must accept and return only public profiles
+/// properly signed by their own key, see PublicProfileData::validate().
+pub trait DistributedPublicProfileRepository {
+    fn get_public(&self, id: &ProfileId) -> AsyncFallible<PublicProfileData>;
+    fn set_public(&mut self, profile: PublicProfileData) -> AsyncFallible<()>;
+    fn clear_public_local(&mut self, key: &PublicKey) -> AsyncFallible<()>;
+    /// Forgets previous versions of the profile stored locally, only the latest one is kept.
+    fn clear_history_local(&mut self, id: &ProfileId) -> AsyncFallible<()>;
+
+    // TODO implement efficient loading based on hints
+    // /// Same as load(), but also contains hints for resolution, therefore it's more efficient than load(id)
+    // ///
+    // /// The `url` may contain
+    // /// * ProfileID (mandatory)
+    // /// * some profile metadata (for user experience enhancement) (big fat warning should be thrown if it does not match the latest info)
+    // /// * ProfileID of its home server
+    // /// * last known multiaddress(es) of its home server
+    // fn resolve(&self, url: &str) -> AsyncResult<Profile, Error>;
+
+    // TODO notifications on profile updates should be possible
+}
+
+// TODO consider authorization: should we require signatures here or leave it to a different layer?
+pub trait PrivateProfileRepository {
+    fn get(&self, id: &ProfileId) -> AsyncFallible<PrivateProfileData>;
+    fn set(&mut self, profile: PrivateProfileData) -> AsyncFallible<()>;
+    fn clear(&mut self, key: &PublicKey) -> AsyncFallible<()>;
+    /// Forgets previous versions of the profile, only the latest one is kept.
+    /// Cleared profiles of a service must not be retained, e.g. after they left a home.
+    fn clear_history(&mut self, id: &ProfileId) -> AsyncFallible<()>;
+}
+
+pub trait LocalProfileRepository: PrivateProfileRepository {
+    // NOTE similar to set() but without version check, must be able to revert to a previous version
+    fn restore(&mut self, profile: PrivateProfileData) -> Fallible<()>;
+
+    /// All versions of the profile retained by the repository, oldest first.
+    fn history(&self, id: &ProfileId) -> Fallible<Vec<ProfileVersionInfo>>;
+    fn get_revision(&self, id: &ProfileId, revision: Revision) -> Fallible<PrivateProfileData>;
+}
+
+pub trait ProfileExplorer {
+    /// Implementations serving authenticated requesters should return the view of the profile
+    /// for them, see `PublicProfileData::view_for()`.
+    fn fetch(&self, id: &ProfileId) -> AsyncFallible<PublicProfileData>;
+    /// Profiles publicly linking to the given one, skipping `offset` entries and returning
+    /// at most `limit` of them in a stable order, so results can be paged through.
+    fn followers(&self, id: &ProfileId, offset: usize, limit: usize) -> AsyncFallible<Vec<Link>>;
+    // fn list(&self, /* TODO what filter criteria should we have here? */ ) -> AsyncFallible<Profile>;
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct InMemoryProfileRepository {
+    profiles: HashMap<String, PrivateProfileData>,
+    /// Reverse index of public links: followed profile id -> ids of profiles linking to it
+    #[serde(default)]
+    followers: HashMap<String, BTreeSet<String>>,
+    /// Every version stored for each profile id by revision
+    #[serde(default)]
+    history: HashMap<String, BTreeMap<Revision, ProfileSnapshot>>,
+}
+
+impl InMemoryProfileRepository {
+    pub fn new() -> Self {
+        Self {
+            profiles: Default::default(),
+            followers: Default::default(),
+            history: Default::default(),
+        }
+    }
+
+    fn list_history(&self, id: &ProfileId) -> Fallible<Vec<ProfileVersionInfo>> {
+        let versions = self
+            .history
+            .get(&id.to_string())
+            .ok_or_else(|| format_err!("Profile not found: {}", id))?;
+        Ok(versions.iter().map(|(revision, snapshot)| snapshot.info(*revision)).collect())
+    }
+
+    fn get_profile_revision(
+        &self,
+        id: &ProfileId,
+        revision: Revision,
+    ) -> Fallible<PrivateProfileData> {
+        self.history
+            .get(&id.to_string())
+            .and_then(|versions| versions.get(&revision))
+            .map(|snapshot| snapshot.profile.to_owned())
+            .ok_or_else(|| format_err!("Revision {} of profile {} not found", revision, id))
+    }
+
+    fn prune_history(&mut self, id: &ProfileId) {
+        if let Some(versions) = self.history.get_mut(&id.to_string()) {
+            if let Some(&last_revision) = versions.keys().next_back() {
+                *versions = versions.split_off(&last_revision);
+            }
+        }
+    }
+
+    fn index_links(&mut self, profile: &PrivateProfileData) {
+        Self::add_links_to(&mut self.followers, profile);
+    }
+
+    fn add_links_to(
+        followers: &mut HashMap<String, BTreeSet<String>>,
+        profile: &PrivateProfileData,
+    ) {
+        let follower_id = profile.id().to_string();
+        for link in profile.public_data().links() {
+            followers.entry(link.peer_profile.to_string()).or_default().insert(follower_id.clone());
+        }
+    }
+
+    fn unindex_links(&mut self, profile: &PrivateProfileData) {
+        let follower_id = profile.id().to_string();
+        for link in profile.public_data().links() {
+            let peer_id = link.peer_profile.to_string();
+            if let Some(followers) = self.followers.get_mut(&peer_id) {
+                followers.remove(&follower_id);
+                if followers.is_empty() {
+                    self.followers.remove(&peer_id);
+                }
+            }
+        }
+    }
+
+    /// Builds the follower index for repositories persisted before it was introduced.
+    fn reindex_followers(&mut self) {
+        let mut followers = HashMap::new();
+        for profile in self.profiles.values() {
+            Self::add_links_to(&mut followers, profile);
+        }
+        self.followers = followers;
+    }
+
+    fn list_followers(&self, id: &ProfileId, offset: usize, limit: usize) -> Fallible<Vec<Link>> {
+        let followers = match self.followers.get(&id.to_string()) {
+            None => return Ok(vec![]),
+            Some(followers) => followers,
+        };
+        followers
+            .iter()
+            .skip(offset)
+            .take(limit)
+            .map(|follower_id| Ok(Link { peer_profile: follower_id.parse()? }))
+            .collect()
+    }
+
+    fn put(&mut self, profile: PrivateProfileData) -> Fallible<()> {
+        if let Some(old_profile) = self.profiles.get(&profile.id().to_string()) {
+            ensure_version_update(old_profile, &profile)?;
+        }
+        if let Some(old_profile) = self.profiles.remove(&profile.id().to_string()) {
+            self.unindex_links(&old_profile);
+        }
+        self.index_links(&profile);
+        let versions = self.history.entry(profile.id().to_string()).or_default();
+        let next_revision = match versions.iter().next_back() {
+            Some((_revision, snapshot)) if snapshot.profile == profile => None,
+            Some((revision, _snapshot)) => Some(revision + 1),
+            None => Some(1),
+        };
+        if let Some(revision) = next_revision {
+            versions.insert(revision, ProfileSnapshot::new(profile.clone()));
+        }
+        self.profiles.insert(profile.id().to_string(), profile);
+        Ok(())
+    }
+
+    fn remove(&mut self, key: &PublicKey) -> Fallible<()> {
+        let id = key.key_id();
+        let profile_version = self
+            .profiles
+            .get(&id.to_string())
+            .ok_or_else(|| format_err!("Profile not found: {}", key))?
+            .version();
+        self.put(PrivateProfileData::tombstone(key, profile_version))
+    }
+}
+
+fn ensure_version_update(
+    old_profile: &PrivateProfileData,
+    new_profile: &PrivateProfileData,
+) -> Fallible<()> {
+    if old_profile.version() > new_profile.version()
+        || (old_profile.version() == new_profile.version()
+            && old_profile.public_data() != new_profile.public_data())
+    {
+        bail!("Version must increase on profile change");
+    }
+    Ok(())
+}
+
+impl Default for InMemoryProfileRepository {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// NOTE normally public and private repositories should not be mixed.
+//      We do it here because InMemoryProfileRepository is created for testing, not real usage.
+impl DistributedPublicProfileRepository for InMemoryProfileRepository {
+    fn get_public(&self, id: &ProfileId) -> AsyncFallible<PublicProfileData> {
+        let res = (self as &dyn PrivateProfileRepository).get(id).and_then(|prof_ref| {
+            let profile = prof_ref.public_data();
+            profile.validate()?;
+            Ok(profile)
+        });
+        Box::new(res)
+    }
+
+    fn set_public(&mut self, profile: PublicProfileData) -> AsyncFallible<()> {
+        if let Err(e) = profile.validate() {
+            return Box::new(Err(e).into_future());
+        }
+        let private_profile = PrivateProfileData::from_public(profile);
+        let res = (self as &mut dyn PrivateProfileRepository).set(private_profile);
+        Box::new(res)
+    }
+
+    fn clear_public_local(&mut self, key: &PublicKey) -> AsyncFallible<()> {
+        let res = (self as &mut dyn PrivateProfileRepository).clear(key);
+        Box::new(res)
+    }
+
+    fn clear_history_local(&mut self, id: &ProfileId) -> AsyncFallible<()> {
+        (self as &mut dyn PrivateProfileRepository).clear_history(id)
+    }
+}
+
+impl PrivateProfileRepository for InMemoryProfileRepository {
+    fn get(&self, id: &ProfileId) -> AsyncFallible<PrivateProfileData> {
+        let res = self
+            .profiles
+            .get(&id.to_string())
+            .map(|prof_ref| prof_ref.to_owned())
+            .ok_or_else(|| format_err!("Profile not found: {}", id));
+        Box::new(res.into_future())
+    }
+
+    fn set(&mut self, profile: PrivateProfileData) -> AsyncFallible<()> {
+        let res = self.put(profile);
+        Box::new(res.into_future())
+    }
+
+    fn clear(&mut self, key: &PublicKey) -> AsyncFallible<()> {
+        let res = self.remove(key);
+        Box::new(res.into_future())
+    }
+
+    fn clear_history(&mut self, id: &ProfileId) -> AsyncFallible<()> {
+        self.prune_history(id);
+        Box::new(Ok(()).into_future())
+    }
+}
+
+impl LocalProfileRepository for InMemoryProfileRepository {
+    fn restore(&mut self, profile: PrivateProfileData) -> Fallible<()> {
+        self.put(profile)
+    }
+
+    fn history(&self, id: &ProfileId) -> Fallible<Vec<ProfileVersionInfo>> {
+        self.list_history(id)
+    }
+
+    fn get_revision(&self, id: &ProfileId, revision: Revision) -> Fallible<PrivateProfileData> {
+        self.get_profile_revision(id, revision)
+    }
+}
+
+impl ProfileExplorer for InMemoryProfileRepository {
+    fn fetch(&self, id: &ProfileId) -> AsyncFallible<PublicProfileData> {
+        (self as &dyn DistributedPublicProfileRepository).get_public(id)
+    }
+    fn followers(&self, id: &ProfileId, offset: usize, limit: usize) -> AsyncFallible<Vec<Link>> {
+        Box::new(self.list_followers(id, offset, limit).into_future())
+    }
+}
+
+#[derive(Debug)]
+pub struct FileProfileRepository {
+    filename: PathBuf,
+}
+
+impl FileProfileRepository {
+    // TODO clean up the name chaos here for new(), from, load, store, save
+    pub fn new(filename: &PathBuf) -> Fallible<Self> {
+        if let Err(_e) = Self::from(filename) {
+            debug!("Failed to load profile repository from {:?}, initializing one there", filename);
+            Self::store(filename, InMemoryProfileRepository::default())?;
+        }
+
+        Ok(Self { filename: filename.to_owned() })
+    }
+
+    fn from(filename: &PathBuf) -> Fallible<InMemoryProfileRepository> {
+        trace!("Loading profile repository from {:?}", filename);
+        let repo_file = File::open(filename)?;
+        //let repo: InMemoryProfileRepository = bincode::deserialize_from(repo_file)?;
+        let mut repo: InMemoryProfileRepository = serde_json::from_reader(repo_file)?;
+        if repo.followers.is_empty() && !repo.profiles.is_empty() {
+            repo.reindex_followers();
+        }
+        Ok(repo)
+    }
+
+    fn store(filename: &PathBuf, mem_repo: InMemoryProfileRepository) -> Fallible<()> {
+        trace!("Saving profile repository to {:?}", filename);
+        if let Some(repo_dir) = filename.parent() {
+            // TODO should we check here first if it already exists?
+            trace!("Recursively Creating directory {:?}", repo_dir);
+            std::fs::create_dir_all(repo_dir)?;
+        }
+
+        // NOTE write a temporary file first and rename it in place, so a crash while saving
+        //      leaves the previous version of the repository intact
+        let mut tmp_filename = filename.clone().into_os_string();
+        tmp_filename.push(".tmp");
+        let tmp_filename = PathBuf::from(tmp_filename);
+        let mut repo_file = File::create(&tmp_filename)?;
+        //bincode::serialize_into(repo_file, &mem_repo)?;
+        serde_json::to_writer(&mut repo_file, &mem_repo)?;
+        repo_file.sync_all()?;
+        std::fs::rename(&tmp_filename, filename)?;
+        Ok(())
+    }
+
+    fn load(&self) -> Fallible<InMemoryProfileRepository> {
+        Self::from(&self.filename)
+    }
+
+    fn save(&self, mem_repo: InMemoryProfileRepository) -> Fallible<()> {
+        Self::store(&self.filename, mem_repo)
+    }
+}
+
+impl DistributedPublicProfileRepository for FileProfileRepository {
+    fn get_public(&self, id: &ProfileId) -> AsyncFallible<PublicProfileData> {
+        let res = self.load().and_then(|mem_repo| mem_repo.get_public(id).wait());
+        Box::new(res.into_future())
+    }
+
+    fn set_public(&mut self, profile: PublicProfileData) -> AsyncFallible<()> {
+        let res = self.load().and_then(|mut mem_repo| {
+            mem_repo.set_public(profile).wait()?;
+            self.save(mem_repo)
+        });
+        Box::new(res.into_future())
+    }
+
+    fn clear_public_local(&mut self, key: &PublicKey) -> AsyncFallible<()> {
+        let res = self.load().and_then(|mut mem_repo| {
+            mem_repo.clear_public_local(key).wait()?;
+            self.save(mem_repo)
+        });
+        Box::new(res.into_future())
+    }
+
+    fn clear_history_local(&mut self, id: &ProfileId) -> AsyncFallible<()> {
+        (self as &mut dyn PrivateProfileRepository).clear_history(id)
+    }
+}
+
+impl PrivateProfileRepository for FileProfileRepository {
+    fn get(&self, id: &ProfileId) -> AsyncFallible<PrivateProfileData> {
+        let res = self.load().and_then(|mem_repo| mem_repo.get(id).wait());
+        Box::new(res.into_future())
+    }
+
+    fn set(&mut self, profile: PrivateProfileData) -> AsyncFallible<()> {
+        let res = self.load().and_then(|mut mem_repo| {
+            mem_repo.set(profile).wait()?;
+            self.save(mem_repo)
+        });
+        Box::new(res.into_future())
+    }
+
+    fn clear(&mut self, key: &PublicKey) -> AsyncFallible<()> {
+        let res = self.load().and_then(|mut mem_repo| {
+            mem_repo.clear(key).wait()?;
+            self.save(mem_repo)
+        });
+        Box::new(res.into_future())
+    }
+
+    fn clear_history(&mut self, id: &ProfileId) -> AsyncFallible<()> {
+        let res = self.load().and_then(|mut mem_repo| {
+            mem_repo.prune_history(id);
+            self.save(mem_repo)
+        });
+        Box::new(res.into_future())
+    }
+}
+
+impl LocalProfileRepository for FileProfileRepository {
+    fn restore(&mut self, profile: PrivateProfileData) -> Fallible<()> {
+        let mut mem_repo = self.load()?;
+        mem_repo.put(profile)?;
+        self.save(mem_repo)
+    }
+
+    fn history(&self, id: &ProfileId) -> Fallible<Vec<ProfileVersionInfo>> {
+        self.load()?.list_history(id)
+    }
+
+    fn get_revision(&self, id: &ProfileId, revision: Revision) -> Fallible<PrivateProfileData> {
+        self.load()?.get_profile_revision(id, revision)
+    }
+}
+
+impl ProfileExplorer for FileProfileRepository {
+    fn fetch(&self, id: &ProfileId) -> AsyncFallible<PublicProfileData> {
+        let res = self.load().and_then(move |mem_repo| mem_repo.fetch(id).wait());
+        Box::new(res.into_future())
+    }
+    fn followers(&self, id: &ProfileId, offset: usize, limit: usize) -> AsyncFallible<Vec<Link>> {
+        let res = self.load().and_then(|mem_repo| mem_repo.list_followers(id, offset, limit));
+        Box::new(res.into_future())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use serde_json::json;
+
+    use super::*;
+    use keyvault::PublicKey as KeyVaultPublicKey;
+
+    #[test]
+    fn test_local_repository() -> Fallible<()> {
+        let tmp_file = std::env::temp_dir().join("local_repo_test.dat");
+        let mut repo = FileProfileRepository::new(&tmp_file)?;
+
+        let my_pubkey = PublicKey::from_str("pezAgmjPHe5Qs4VakvXHGnd6NsYjaxt4suMUtf39TayrSfb")?;
+        //let my_id = ProfileId::from_str("iezbeWGSY2dqcUBqT8K7R14xr")?;
+        let my_id = my_pubkey.key_id();
+        let mut my_data = PrivateProfileData::empty(&my_pubkey);
+        repo.set(my_data.clone()).wait()?;
+
+        let peer_pubkey = PublicKey::from_str("pezFVen3X669xLzsi6N2V91DoiyzHzg1uAgqiT8jZ9nS96Z")?;
+        //let peer_id = ProfileId::from_str("iez25N5WZ1Q6TQpgpyYgiu9gTX")?;
+        let peer_id = peer_pubkey.key_id();
+        let peer_data = PrivateProfileData::empty(&peer_pubkey);
+        repo.set(peer_data.clone()).wait()?;
+
+        let mut me = repo.get(&my_id).wait()?;
+        let peer = repo.get(&peer_id).wait()?;
+        assert_eq!(me, my_data);
+        assert_eq!(peer, peer_data);
+
+        let attr_id = "1 2 3".to_owned();
+        let attr_val = "one two three".into();
+        my_data.mut_public_data().set_attribute(attr_id, attr_val);
+        let _link = my_data.mut_public_data().create_link(&peer_id);
+        my_data.mut_public_data().increase_version();
+        repo.set(my_data.clone()).wait()?;
+        me = repo.get(&my_id).wait()?;
+        assert_eq!(me, my_data);
+        assert_eq!(me.version(), 2);
+        assert_eq!(me.public_data().attributes().len(), 1);
+        assert_eq!(me.public_data().links().len(), 1);
+
+        // Private changes keep the public version, but are still retained in the history
+        let with_public_changes = my_data.clone();
+        my_data.mut_claims().push(Claim::unproven(my_id.clone(), "test", json!({ "a": 1 })));
+        repo.set(my_data.clone()).wait()?;
+        repo.set(my_data.clone()).wait()?;
+
+        let history = repo.history(&my_id)?;
+        assert_eq!(history.iter().map(|entry| entry.revision).collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert_eq!(history.iter().map(|entry| entry.version).collect::<Vec<_>>(), vec![1, 2, 2]);
+        assert!(history[0].timestamp <= history[1].timestamp);
+        assert_eq!(repo.get_revision(&my_id, 1)?, PrivateProfileData::empty(&my_pubkey));
+        assert_eq!(repo.get_revision(&my_id, 2)?, with_public_changes);
+        assert_eq!(repo.get_revision(&my_id, 3)?, my_data);
+        assert!(repo.get_revision(&my_id, 4).is_err());
+
+        repo.clear(&my_pubkey).wait()?;
+        me = repo.get(&my_id).wait()?;
+        assert_eq!(
+            me,
+            PrivateProfileData::from_public(PublicProfileData::new(
+                my_pubkey,
+                3,
+                Default::default(),
+                Default::default()
+            ),)
+        );
+        repo.clear_history(&my_id).wait()?;
+        let history = repo.history(&my_id)?;
+        assert_eq!(history.iter().map(|entry| entry.revision).collect::<Vec<_>>(), vec![4]);
+        assert_eq!(repo.get_revision(&my_id, 4)?, me);
+
+        std::fs::remove_file(&tmp_file)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_followers() -> Fallible<()> {
+        let mut repo = InMemoryProfileRepository::new();
+
+        let followed_key = PublicKey::from_str("pezAgmjPHe5Qs4VakvXHGnd6NsYjaxt4suMUtf39TayrSfb")?;
+        let followed_id = followed_key.key_id();
+        repo.set(PrivateProfileData::empty(&followed_key)).wait()?;
+
+        let follower_keys = vec![
+            PublicKey::from_str("pezFVen3X669xLzsi6N2V91DoiyzHzg1uAgqiT8jZ9nS96Z")?,
+            PublicKey::from_str("pez7aYuvoDPM5i7xedjwjsWaFVzL3qRKPv4sBLv3E3pAGi6")?,
+        ];
+        for key in &follower_keys {
+            let mut follower = PrivateProfileData::empty(key);
+            follower.mut_public_data().create_link(&followed_id);
+            follower.mut_public_data().increase_version();
+            repo.set(follower).wait()?;
+        }
+
+        let all = repo.followers(&followed_id, 0, 10).wait()?;
+        assert_eq!(all.len(), 2);
+        let first_page = repo.followers(&followed_id, 0, 1).wait()?;
+        let second_page = repo.followers(&followed_id, 1, 1).wait()?;
+        assert_eq!(vec![first_page[0].clone(), second_page[0].clone()], all);
+        assert!(repo.followers(&followed_id, 2, 1).wait()?.is_empty());
+        assert!(repo.followers(&follower_keys[0].key_id(), 0, 10).wait()?.is_empty());
+
+        let unfollowing_id = all[0].peer_profile.clone();
+        let mut unfollowing = repo.get(&unfollowing_id).wait()?;
+        unfollowing.mut_public_data().remove_link(&followed_id);
+        unfollowing.mut_public_data().increase_version();
+        repo.set(unfollowing).wait()?;
+        assert_eq!(repo.followers(&followed_id, 0, 10).wait()?, vec![all[1].clone()]);
+
+        let remaining_key = follower_keys.iter().find(|key| key.key_id() == all[1].peer_profile);
+        repo.clear(remaining_key.unwrap()).wait()?;
+        assert!(repo.followers(&followed_id, 0, 10).wait()?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_signed_public_profiles() -> Fallible<()> {
+        use did::vault::{HdProfileVault, ProfileVault};
+        use std::sync::Arc;
+
+        let phrase = keyvault::Seed::generate_bip39();
+        let mut vault = HdProfileVault::create(keyvault::Seed::from_bip39(&phrase)?);
+        let my_pubkey = vault.create_key(None)?;
+        let other_pubkey = vault.create_key(None)?;
+        let vault = Arc::new(vault);
+        let my_signer = vault.clone().signer(&my_pubkey.key_id())?;
+        let other_signer = vault.clone().signer(&other_pubkey.key_id())?;
+
+        let mut repo = InMemoryProfileRepository::new();
+        let mut my_profile = PublicProfileData::empty(&my_pubkey);
+        assert!(repo.set_public(my_profile.clone()).wait().is_err());
+        assert!(my_profile.sign(&*other_signer).is_err());
+
+        my_profile.sign(&*my_signer)?;
+        repo.set_public(my_profile.clone()).wait()?;
+        assert_eq!(repo.get_public(&my_pubkey.key_id()).wait()?, my_profile);
+        assert_eq!(repo.fetch(&my_pubkey.key_id()).wait()?, my_profile);
+
+        // A storage node tampering with the profile cannot produce a valid signature
+        let mut forged = my_profile.clone();
+        forged.set_attribute("forged".to_owned(), "value".into());
+        forged.increase_version();
+        assert!(!forged.is_signed());
+        let mut forged_json = serde_json::to_value(&PrivateProfileData::from_public(forged))?;
+        forged_json["public_data"]["signature"] = serde_json::to_value(my_profile.signature())?;
+        repo.set(serde_json::from_value(forged_json)?).wait()?;
+        assert!(repo.get_public(&my_pubkey.key_id()).wait().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_contact_attributes() -> Fallible<()> {
+        use did::vault::{HdProfileVault, ProfileVault};
+        use std::sync::Arc;
+
+        let phrase = keyvault::Seed::generate_bip39();
+        let mut vault = HdProfileVault::create(keyvault::Seed::from_bip39(&phrase)?);
+        let my_pubkey = vault.create_key(None)?;
+        let contact_pubkey = vault.create_key(None)?;
+        let stranger_pubkey = vault.create_key(None)?;
+        let (my_id, contact_id) = (my_pubkey.key_id(), contact_pubkey.key_id());
+
+        let mut profile = PrivateProfileData::empty(&my_pubkey);
+        profile.set_attribute("name".to_owned(), "Me".into(), AttributeVisibility::Public);
+        profile.set_attribute("phone".to_owned(), "+36123".into(), AttributeVisibility::Contacts);
+        profile.set_attribute("notes".to_owned(), "secret".into(), AttributeVisibility::Private);
+        profile.add_contact(Contact::new(contact_pubkey.clone(), "friend"));
+        profile.seal_contact_attributes(|peer_key| vault.shared_secret(&my_id, peer_key))?;
+        assert_eq!(profile.attribute("phone").map(|(v, _)| v), Some(AttributeVisibility::Contacts));
+        assert!(profile.public_data().attributes().get("phone").is_none());
+
+        let vault = Arc::new(vault);
+        let mut public = profile.public_data();
+        public.sign(&*vault.clone().signer(&my_id)?)?;
+        let mut repo = InMemoryProfileRepository::new();
+        repo.set_public(public.clone()).wait()?;
+
+        // Only the contact can open its sealed attributes, entries do not reveal who it is
+        let contact_view = repo.get_public(&my_id).wait()?.view_for(Some(&contact_id));
+        contact_view.validate()?;
+        let contact_secret = vault.shared_secret(&contact_id, &my_pubkey)?;
+        let opened = contact_view.open_attributes_for(&contact_secret)?;
+        assert_eq!(opened, Some(profile.attributes(AttributeVisibility::Contacts).to_owned()));
+        assert!(!serde_json::to_string(&public)?.contains(&contact_id.to_string()));
+
+        let stranger_id = stranger_pubkey.key_id();
+        let stranger_view = public.view_for(Some(&stranger_id));
+        stranger_view.validate()?;
+        let stranger_secret = vault.shared_secret(&stranger_id, &my_pubkey)?;
+        assert_eq!(stranger_view.open_attributes_for(&stranger_secret)?, None);
+        assert!(!serde_json::to_string(&stranger_view)?.contains("secret"));
+
+        // Anonymous requesters only see digests
+        let anonymous_view = public.view_for(None);
+        anonymous_view.validate()?;
+        assert!(anonymous_view.open_attributes_for(&contact_secret).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,502 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, UNIX_EPOCH};
+
+use failure::{bail, format_err, Fallible};
+use futures::prelude::*;
+use log::*;
+use rusqlite::{params, Connection, OptionalExtension};
+
+use super::*;
+
+const SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS profiles (
+    id      TEXT PRIMARY KEY NOT NULL,
+    version INTEGER NOT NULL,
+    data    TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS links (
+    followed TEXT NOT NULL,
+    follower TEXT NOT NULL,
+    PRIMARY KEY (followed, follower)
+);
+CREATE INDEX IF NOT EXISTS links_by_follower ON links (follower);
+CREATE TABLE IF NOT EXISTS profile_revisions (
+    id        TEXT NOT NULL,
+    revision  INTEGER NOT NULL,
+    version   INTEGER NOT NULL,
+    timestamp INTEGER NOT NULL,
+    data      TEXT NOT NULL,
+    PRIMARY KEY (id, revision)
+);
+"#;
+
+/// Profile repository in an embedded SQLite database. Operations touch only the rows of the
+/// affected profile and each of them runs in a single transaction, so a crash never leaves
+/// a partially written repository behind.
+#[derive(Debug)]
+pub struct SqliteProfileRepository {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteProfileRepository {
+    pub fn open(path: &Path) -> Fallible<Self> {
+        if let Some(db_dir) = path.parent() {
+            std::fs::create_dir_all(db_dir)?;
+        }
+        trace!("Opening profile database {:?}", path);
+        Self::init(Connection::open(path)?)
+    }
+
+    pub fn in_memory() -> Fallible<Self> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    /// Opens the database, importing profiles from a legacy JSON repository file
+    /// (see FileProfileRepository) when the database is created.
+    pub fn open_or_import(path: &Path, json_path: &PathBuf) -> Fallible<Self> {
+        let db_exists = path.exists();
+        let repo = Self::open(path)?;
+        if !db_exists && json_path.exists() {
+            let count = repo.import_json(json_path)?;
+            info!("Imported {} profiles from {:?} into {:?}", count, json_path, path);
+        }
+        Ok(repo)
+    }
+
+    fn init(connection: Connection) -> Fallible<Self> {
+        // NOTE in WAL mode readers do not block writers and a commit is durable once returned
+        connection.execute_batch("PRAGMA journal_mode = WAL; PRAGMA synchronous = FULL;")?;
+        connection.execute_batch(SCHEMA)?;
+        Ok(Self { connection: Mutex::new(connection) })
+    }
+
+    /// Imports all profiles of a JSON repository file in a single transaction. Profiles already
+    /// stored with the same or a newer version are kept. Returns the number of imported profiles.
+    pub fn import_json(&self, json_path: &PathBuf) -> Fallible<usize> {
+        let mem_repo = FileProfileRepository::from(json_path)?;
+        let mut connection = self.lock()?;
+        let tx = connection.transaction()?;
+        let mut count = 0;
+        for (id, versions) in &mem_repo.history {
+            for (revision, snapshot) in versions {
+                Self::store_snapshot(&tx, id, *revision, snapshot)?;
+            }
+        }
+        for profile in mem_repo.profiles.values() {
+            if let Some(stored_profile) = Self::load(&tx, &profile.id())? {
+                if stored_profile.version() >= profile.version() {
+                    continue;
+                }
+            }
+            Self::store(&tx, profile)?;
+            count += 1;
+        }
+        tx.commit()?;
+        Ok(count)
+    }
+
+    fn lock(&self) -> Fallible<MutexGuard<Connection>> {
+        self.connection.lock().map_err(|e| format_err!("Failed to lock profile database: {}", e))
+    }
+
+    fn load(connection: &Connection, id: &ProfileId) -> Fallible<Option<PrivateProfileData>> {
+        let data: Option<String> = connection
+            .query_row("SELECT data FROM profiles WHERE id = ?1", params![id.to_string()], |row| {
+                row.get(0)
+            })
+            .optional()?;
+        match data {
+            None => Ok(None),
+            Some(data) => Ok(Some(serde_json::from_str(&data)?)),
+        }
+    }
+
+    fn store(connection: &Connection, profile: &PrivateProfileData) -> Fallible<()> {
+        let id = profile.id().to_string();
+        let data = serde_json::to_string(profile)?;
+        connection.execute(
+            "INSERT OR REPLACE INTO profiles (id, version, data) VALUES (?1, ?2, ?3)",
+            params![id, profile.version() as i64, data],
+        )?;
+        Self::append_snapshot(connection, profile)?;
+        connection.execute("DELETE FROM links WHERE follower = ?1", params![id])?;
+        for link in profile.public_data().links() {
+            connection.execute(
+                "INSERT OR IGNORE INTO links (followed, follower) VALUES (?1, ?2)",
+                params![link.peer_profile.to_string(), id],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Stores the profile as the next revision in its history unless it is the same as the last one
+    fn append_snapshot(connection: &Connection, profile: &PrivateProfileData) -> Fallible<()> {
+        let id = profile.id().to_string();
+        let last: Option<(i64, String)> = connection
+            .query_row(
+                "SELECT revision, data FROM profile_revisions WHERE id = ?1 \
+                 ORDER BY revision DESC LIMIT 1",
+                params![id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        let revision = match last {
+            None => 1,
+            Some((revision, data)) => {
+                if serde_json::from_str::<PrivateProfileData>(&data)? == *profile {
+                    return Ok(());
+                }
+                revision as Revision + 1
+            }
+        };
+        Self::store_snapshot(connection, &id, revision, &ProfileSnapshot::new(profile.to_owned()))
+    }
+
+    fn store_snapshot(
+        connection: &Connection,
+        id: &str,
+        revision: Revision,
+        snapshot: &ProfileSnapshot,
+    ) -> Fallible<()> {
+        let timestamp = snapshot.timestamp.duration_since(UNIX_EPOCH)?.as_nanos() as i64;
+        connection.execute(
+            "INSERT OR REPLACE INTO profile_revisions (id, revision, version, timestamp, data) \
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                id,
+                revision as i64,
+                snapshot.profile.version() as i64,
+                timestamp,
+                serde_json::to_string(&snapshot.profile)?
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn list_history(&self, id: &ProfileId) -> Fallible<Vec<ProfileVersionInfo>> {
+        let connection = self.lock()?;
+        let mut statement = connection.prepare(
+            "SELECT revision, version, timestamp FROM profile_revisions WHERE id = ?1 \
+             ORDER BY revision",
+        )?;
+        let rows = statement.query_map(params![id.to_string()], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?, row.get::<_, i64>(2)?))
+        })?;
+        let mut history = Vec::new();
+        for row in rows {
+            let (revision, version, timestamp) = row?;
+            history.push(ProfileVersionInfo {
+                revision: revision as Revision,
+                version: version as Version,
+                timestamp: UNIX_EPOCH + Duration::from_nanos(timestamp as u64),
+            });
+        }
+        if history.is_empty() {
+            bail!("Profile not found: {}", id);
+        }
+        Ok(history)
+    }
+
+    fn get_profile_revision(
+        &self,
+        id: &ProfileId,
+        revision: Revision,
+    ) -> Fallible<PrivateProfileData> {
+        let connection = self.lock()?;
+        let data: Option<String> = connection
+            .query_row(
+                "SELECT data FROM profile_revisions WHERE id = ?1 AND revision = ?2",
+                params![id.to_string(), revision as i64],
+                |row| row.get(0),
+            )
+            .optional()?;
+        match data {
+            None => bail!("Revision {} of profile {} not found", revision, id),
+            Some(data) => Ok(serde_json::from_str(&data)?),
+        }
+    }
+
+    fn get_profile(&self, id: &ProfileId) -> Fallible<PrivateProfileData> {
+        let connection = self.lock()?;
+        Self::load(&connection, id)?.ok_or_else(|| format_err!("Profile not found: {}", id))
+    }
+
+    fn put(&self, profile: PrivateProfileData) -> Fallible<()> {
+        let mut connection = self.lock()?;
+        let tx = connection.transaction()?;
+        if let Some(old_profile) = Self::load(&tx, &profile.id())? {
+            ensure_version_update(&old_profile, &profile)?;
+        }
+        Self::store(&tx, &profile)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn remove(&self, key: &PublicKey) -> Fallible<()> {
+        let id = key.key_id();
+        let mut connection = self.lock()?;
+        let tx = connection.transaction()?;
+        let profile_version = Self::load(&tx, &id)?
+            .ok_or_else(|| format_err!("Profile not found: {}", key))?
+            .version();
+        Self::store(&tx, &PrivateProfileData::tombstone(key, profile_version))?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn prune_history(&self, id: &ProfileId) -> Fallible<()> {
+        let connection = self.lock()?;
+        connection.execute(
+            "DELETE FROM profile_revisions WHERE id = ?1 AND revision < \
+             (SELECT MAX(revision) FROM profile_revisions WHERE id = ?1)",
+            params![id.to_string()],
+        )?;
+        Ok(())
+    }
+
+    fn list_followers(&self, id: &ProfileId, offset: usize, limit: usize) -> Fallible<Vec<Link>> {
+        let connection = self.lock()?;
+        let mut statement = connection.prepare(
+            "SELECT follower FROM links WHERE followed = ?1 ORDER BY follower LIMIT ?2 OFFSET ?3",
+        )?;
+        let rows = statement
+            .query_map(params![id.to_string(), limit as i64, offset as i64], |row| {
+                row.get::<_, String>(0)
+            })?;
+        let mut followers = Vec::new();
+        for follower_id in rows {
+            followers.push(Link { peer_profile: follower_id?.parse()? });
+        }
+        Ok(followers)
+    }
+}
+
+impl DistributedPublicProfileRepository for SqliteProfileRepository {
+    fn get_public(&self, id: &ProfileId) -> AsyncFallible<PublicProfileData> {
+        let res = self.get_profile(id).and_then(|profile| {
+            let public_profile = profile.public_data();
+            public_profile.validate()?;
+            Ok(public_profile)
+        });
+        Box::new(res.into_future())
+    }
+
+    fn set_public(&mut self, profile: PublicProfileData) -> AsyncFallible<()> {
+        let res =
+            profile.validate().and_then(|()| self.put(PrivateProfileData::from_public(profile)));
+        Box::new(res.into_future())
+    }
+
+    fn clear_public_local(&mut self, key: &PublicKey) -> AsyncFallible<()> {
+        Box::new(self.remove(key).into_future())
+    }
+
+    fn clear_history_local(&mut self, id: &ProfileId) -> AsyncFallible<()> {
+        Box::new(self.prune_history(id).into_future())
+    }
+}
+
+impl PrivateProfileRepository for SqliteProfileRepository {
+    fn get(&self, id: &ProfileId) -> AsyncFallible<PrivateProfileData> {
+        Box::new(self.get_profile(id).into_future())
+    }
+
+    fn set(&mut self, profile: PrivateProfileData) -> AsyncFallible<()> {
+        Box::new(self.put(profile).into_future())
+    }
+
+    fn clear(&mut self, key: &PublicKey) -> AsyncFallible<()> {
+        Box::new(self.remove(key).into_future())
+    }
+
+    fn clear_history(&mut self, id: &ProfileId) -> AsyncFallible<()> {
+        Box::new(self.prune_history(id).into_future())
+    }
+}
+
+impl LocalProfileRepository for SqliteProfileRepository {
+    fn restore(&mut self, profile: PrivateProfileData) -> Fallible<()> {
+        self.put(profile)
+    }
+
+    fn history(&self, id: &ProfileId) -> Fallible<Vec<ProfileVersionInfo>> {
+        self.list_history(id)
+    }
+
+    fn get_revision(&self, id: &ProfileId, revision: Revision) -> Fallible<PrivateProfileData> {
+        self.get_profile_revision(id, revision)
+    }
+}
+
+impl ProfileExplorer for SqliteProfileRepository {
+    fn fetch(&self, id: &ProfileId) -> AsyncFallible<PublicProfileData> {
+        (self as &dyn DistributedPublicProfileRepository).get_public(id)
+    }
+    fn followers(&self, id: &ProfileId, offset: usize, limit: usize) -> AsyncFallible<Vec<Link>> {
+        Box::new(self.list_followers(id, offset, limit).into_future())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use super::*;
+
+    fn test_dir(name: &str) -> Fallible<PathBuf> {
+        let dir = std::env::temp_dir().join(format!("sqlite_repo_test_{}", name));
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir)?;
+        }
+        std::fs::create_dir_all(&dir)?;
+        Ok(dir)
+    }
+
+    /// Copies the database files as they are on disk at this moment, like after a power loss.
+    fn snapshot_files(db_path: &Path, target_path: &Path) -> Fallible<()> {
+        std::fs::copy(db_path, target_path)?;
+        for suffix in &["-wal", "-journal"] {
+            let mut source = db_path.as_os_str().to_owned();
+            source.push(suffix);
+            let mut target = target_path.as_os_str().to_owned();
+            target.push(suffix);
+            if Path::new(&source).exists() {
+                std::fs::copy(&source, &target)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn followed_and_follower() -> Fallible<(PublicKey, PublicKey)> {
+        Ok((
+            PublicKey::from_str("pezAgmjPHe5Qs4VakvXHGnd6NsYjaxt4suMUtf39TayrSfb")?,
+            PublicKey::from_str("pezFVen3X669xLzsi6N2V91DoiyzHzg1uAgqiT8jZ9nS96Z")?,
+        ))
+    }
+
+    #[test]
+    fn test_profile_operations() -> Fallible<()> {
+        let mut repo = SqliteProfileRepository::in_memory()?;
+        let (followed_key, follower_key) = followed_and_follower()?;
+        let followed_id = followed_key.key_id();
+
+        let followed = PrivateProfileData::empty(&followed_key);
+        repo.set(followed.clone()).wait()?;
+        assert_eq!(repo.get(&followed_id).wait()?, followed);
+
+        let mut follower = PrivateProfileData::empty(&follower_key);
+        follower.mut_public_data().create_link(&followed_id);
+        follower.mut_public_data().increase_version();
+        repo.set(follower.clone()).wait()?;
+        assert_eq!(repo.get(&follower_key.key_id()).wait()?, follower);
+        assert_eq!(
+            repo.followers(&followed_id, 0, 10).wait()?,
+            vec![Link { peer_profile: follower_key.key_id() }]
+        );
+        assert!(repo.followers(&followed_id, 1, 10).wait()?.is_empty());
+
+        let mut outdated = follower.clone();
+        outdated.mut_public_data().remove_link(&followed_id);
+        assert!(repo.set(outdated).wait().is_err());
+        assert_eq!(repo.get(&follower_key.key_id()).wait()?, follower);
+        assert_eq!(repo.followers(&followed_id, 0, 10).wait()?.len(), 1);
+
+        repo.clear(&follower_key).wait()?;
+        let tombstone = repo.get(&follower_key.key_id()).wait()?;
+        assert_eq!(tombstone.version(), 3);
+
+        let history = repo.history(&follower_key.key_id())?;
+        assert_eq!(history.iter().map(|entry| entry.revision).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(history.iter().map(|entry| entry.version).collect::<Vec<_>>(), vec![2, 3]);
+        assert_eq!(repo.get_revision(&follower_key.key_id(), 1)?, follower);
+        assert!(repo.get_revision(&follower_key.key_id(), 3).is_err());
+        assert!(repo.followers(&followed_id, 0, 10).wait()?.is_empty());
+        repo.clear_history(&follower_key.key_id()).wait()?;
+        let history = repo.history(&follower_key.key_id())?;
+        assert_eq!(history.iter().map(|entry| entry.revision).collect::<Vec<_>>(), vec![2]);
+        assert_eq!(repo.get_revision(&follower_key.key_id(), 2)?, tombstone);
+
+        // Unsigned profiles are never served as public ones
+        assert!(repo.get_public(&followed_id).wait().is_err());
+        assert!(repo.set_public(followed.public_data()).wait().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_json() -> Fallible<()> {
+        let dir = test_dir("import")?;
+        let json_path = dir.join("profiles.dat");
+        let db_path = dir.join("profiles.db");
+        let (followed_key, follower_key) = followed_and_follower()?;
+
+        let mut json_repo = FileProfileRepository::new(&json_path)?;
+        json_repo.set(PrivateProfileData::empty(&followed_key)).wait()?;
+        let mut follower = PrivateProfileData::empty(&follower_key);
+        follower.mut_public_data().create_link(&followed_key.key_id());
+        json_repo.set(follower.clone()).wait()?;
+
+        let repo = SqliteProfileRepository::open_or_import(&db_path, &json_path)?;
+        assert_eq!(repo.get(&follower_key.key_id()).wait()?, follower);
+        assert_eq!(repo.followers(&followed_key.key_id(), 0, 10).wait()?.len(), 1);
+        assert_eq!(repo.import_json(&json_path)?, 0);
+        drop(repo);
+
+        // The import happens only once, when the database is created
+        json_repo.clear(&follower_key).wait()?;
+        let repo = SqliteProfileRepository::open_or_import(&db_path, &json_path)?;
+        assert_eq!(repo.get(&follower_key.key_id()).wait()?, follower);
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_crash_safety() -> Fallible<()> {
+        let dir = test_dir("crash")?;
+        let db_path = dir.join("profiles.db");
+        let (followed_key, follower_key) = followed_and_follower()?;
+
+        let mut repo = SqliteProfileRepository::open(&db_path)?;
+        let mut follower = PrivateProfileData::empty(&follower_key);
+        follower.mut_public_data().create_link(&followed_key.key_id());
+        repo.set(follower.clone()).wait()?;
+
+        // Committed changes are durable even if the process dies without closing the database
+        let committed_path = dir.join("committed.db");
+        snapshot_files(&db_path, &committed_path)?;
+        let recovered = SqliteProfileRepository::open(&committed_path)?;
+        assert_eq!(recovered.get(&follower_key.key_id()).wait()?, follower);
+        drop(recovered);
+
+        // Crash in the middle of an update: changes of the unfinished transaction are lost,
+        // but the previous version is left intact, including its links
+        let writer = Connection::open(&db_path)?;
+        writer.execute_batch(
+            r#"BEGIN IMMEDIATE;
+            UPDATE profiles SET version = 2, data = '{"broken":' ;
+            DELETE FROM links;"#,
+        )?;
+        let crashed_path = dir.join("crashed.db");
+        snapshot_files(&db_path, &crashed_path)?;
+        writer.execute_batch("ROLLBACK;")?;
+        drop(writer);
+
+        let recovered = SqliteProfileRepository::open(&crashed_path)?;
+        assert_eq!(recovered.get(&follower_key.key_id()).wait()?, follower);
+        assert_eq!(
+            recovered.followers(&followed_key.key_id(), 0, 10).wait()?,
+            vec![Link { peer_profile: follower_key.key_id() }]
+        );
+
+        // A failed operation does not leave partial changes behind either
+        let mut outdated = PrivateProfileData::empty(&follower_key);
+        outdated.mut_public_data().set_attribute("key".to_owned(), "value".into());
+        assert!(repo.set(outdated).wait().is_err());
+        assert_eq!(repo.get(&follower_key.key_id()).wait()?, follower);
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
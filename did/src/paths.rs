@@ -24,6 +24,16 @@ pub fn base_repo_path(parent_dir: Option<PathBuf>) -> Fallible<PathBuf> {
     parent_dir.map(|base| base.join("bases.dat")).ok_or_else(|| err_msg(BASEDIR_DETECTION_ERROR))
 }
 
+pub fn profile_db_path(parent_dir: Option<PathBuf>) -> Fallible<PathBuf> {
+    let parent_dir = parent_dir.or_else(default_dir);
+    parent_dir.map(|base| base.join("profiles.db")).ok_or_else(|| err_msg(BASEDIR_DETECTION_ERROR))
+}
+
+pub fn base_db_path(parent_dir: Option<PathBuf>) -> Fallible<PathBuf> {
+    let parent_dir = parent_dir.or_else(default_dir);
+    parent_dir.map(|base| base.join("bases.db")).ok_or_else(|| err_msg(BASEDIR_DETECTION_ERROR))
+}
+
 pub fn schemas_path(schemas_dir: Option<PathBuf>) -> Fallible<PathBuf> {
     schemas_dir
         .or_else(|| default_dir().map(|base| base.join("schemas")))
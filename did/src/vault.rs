@@ -10,6 +10,7 @@ use serde_derive::{Deserialize, Serialize};
 use crate::model::*;
 use keyvault::{
     ed25519::{Ed25519, EdExtPrivateKey},
+    encryption::SymmetricKey,
     ExtendedPrivateKey, ExtendedPublicKey, KeyDerivationCrypto, PrivateKey as KeyVaultPrivateKey,
     PublicKey as KeyVaultPublicKey, Seed, BIP43_PURPOSE_MERCURY,
 };
@@ -93,8 +94,16 @@ pub trait ProfileVault {
     fn profile(&self, id: &ProfileId) -> Fallible<ProfileVaultRecord>;
 
     fn signer(self: Arc<Self>, profile_id: &ProfileId) -> Fallible<Rc<dyn Signer>>;
+    /// Like `signer()`, but also for profiles not restored yet within `GAP` after the last one,
+    /// e.g. to look up their backups while restoring the vault.
+    fn lookahead_signer(self: Arc<Self>, profile_id: &ProfileId) -> Fallible<Rc<dyn Signer>>;
     // TODO sign() should be removed and done only via signer(), but that requires using Arc<> or finding another solution
     fn sign(&self, id: &ProfileId, message: &[u8]) -> Fallible<SignedMessage>;
+    /// Secret shared between a profile of the vault and a peer, see `MPrivateKey::diffie_hellman`.
+    fn shared_secret(&self, id: &ProfileId, peer_key: &PublicKey) -> Fallible<Vec<u8>>;
+    /// Key sealing private parts of a profile backed up to storage not trusted with their content.
+    /// It is derived from the seed, so backups can be opened after restoring the vault.
+    fn backup_key(&self, id: &ProfileId) -> Fallible<SymmetricKey>;
     fn validate(&self, signer_id: Option<ProfileId>, signed_msg: &SignedMessage) -> bool;
 
     // TODO these probably should not be here on the long run, list() is enough in most cases.
@@ -144,8 +153,12 @@ impl HdProfileVault {
     }
 
     fn index_of_id(&self, id: &ProfileId) -> Option<usize> {
+        self.index_of_id_before(id, self.next_idx)
+    }
+
+    fn index_of_id_before(&self, id: &ProfileId, end_idx: i32) -> Option<usize> {
         let profiles = self.keys().ok()?;
-        for idx in 0..self.next_idx {
+        for idx in 0..end_idx {
             // TODO consider if we should really validate() here or should directly check id equality only
             if profiles.public_key(idx).ok()?.validate_id(id) {
                 return Some(idx as usize);
@@ -188,6 +201,20 @@ impl HdProfileVault {
     fn get_public_key(&self, idx: i32) -> Fallible<PublicKey> {
         self.keys()?.public_key(idx)
     }
+
+    fn signer_of_idx(
+        self: Arc<Self>,
+        profile_id: &ProfileId,
+        idx: i32,
+    ) -> Fallible<Rc<dyn Signer>> {
+        let public_key = self.get_public_key(idx)?;
+        Ok(Rc::new(VaultSigner {
+            vault: Arc::downgrade(&self),
+            profile_id: profile_id.to_owned(),
+            idx,
+            public_key,
+        }))
+    }
 }
 
 impl ProfileVault for HdProfileVault {
@@ -286,13 +313,14 @@ impl ProfileVault for HdProfileVault {
 
     fn signer(self: Arc<Self>, profile_id: &ProfileId) -> Fallible<Rc<dyn Signer>> {
         let idx = Self::get_bip32_idx(self.as_ref(), profile_id)?;
-        let public_key = self.get_public_key(idx)?;
-        Ok(Rc::new(VaultSigner {
-            vault: Arc::downgrade(&self),
-            profile_id: profile_id.to_owned(),
-            idx,
-            public_key,
-        }))
+        self.signer_of_idx(profile_id, idx)
+    }
+
+    fn lookahead_signer(self: Arc<Self>, profile_id: &ProfileId) -> Fallible<Rc<dyn Signer>> {
+        let idx = self
+            .index_of_id_before(profile_id, self.next_idx + GAP as i32)
+            .ok_or_else(|| format_err!("Profile {} is not found in vault", profile_id))?;
+        self.signer_of_idx(profile_id, idx as i32)
     }
 
     fn sign(&self, id: &ProfileId, message: &[u8]) -> Fallible<SignedMessage> {
@@ -302,6 +330,17 @@ impl ProfileVault for HdProfileVault {
         Ok(SignedMessage::new(private_key.public_key(), message.to_owned(), signature))
     }
 
+    fn shared_secret(&self, id: &ProfileId, peer_key: &PublicKey) -> Fallible<Vec<u8>> {
+        let idx = self.get_bip32_idx(id)?;
+        let private_key = self.secrets()?.private_key(idx)?;
+        private_key.diffie_hellman(peer_key)
+    }
+
+    fn backup_key(&self, id: &ProfileId) -> Fallible<SymmetricKey> {
+        let context = format!("mercury profile backup {}", id);
+        Ok(SymmetricKey::derive(self.seed.as_bytes(), &context))
+    }
+
     fn validate(&self, signer_id: Option<ProfileId>, signed_msg: &SignedMessage) -> bool {
         let id_ok = match signer_id {
             Some(id) => signed_msg.public_key().validate_id(&id),
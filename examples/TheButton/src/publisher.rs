@@ -56,6 +56,9 @@ impl IntoFuture for Server {
 
                             DAppEvent::PairingResponse(response) => Ok( debug!(
                                 "Got incoming pairing response. We do not send such requests, ignoring it {:?}", response.proof()) ),
+
+                            DAppEvent::Message(message) => Ok( debug!(
+                                "Got incoming message. We do not expect messages, ignoring it {:?}", message.payload) ),
                         }
                     } )
             } );
@@ -0,0 +1,98 @@
+use std::path::PathBuf;
+
+use failure::{bail, format_err, Fallible};
+use futures::Future;
+use rand::{distributions::Alphanumeric, Rng};
+use structopt::StructOpt;
+
+use mercury_home_node::config::load_signer;
+use mercury_home_node::registration::{validate_voucher, InvitationStatus};
+use mercury_home_protocol::*;
+use mercury_storage::asynch::fs::FileStore;
+use mercury_storage::asynch::KeyValueStore;
+
+const VOUCHER_LENGTH: usize = 16;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "mercury-home-invite",
+    about = "Issue and revoke invitations to register on a Mercury Home Node",
+    setting = structopt::clap::AppSettings::ColoredHelp
+)]
+struct Options {
+    #[structopt(long = "keyvault-dir", value_name = "DIR", parse(from_os_str))]
+    /// Configuration directory to load keyvault from.
+    /// Default: OS-specific app_cfg_dir/prometheus
+    keyvault_dir: Option<PathBuf>,
+
+    #[structopt(long = "profileid", value_name = "ID")]
+    /// Key ID within keyvault used by the home node to sign invitations.
+    profile_id: Option<ProfileId>,
+
+    #[structopt(
+        long = "invitations",
+        default_value = "/tmp/mercury/home/invitations",
+        parse(from_os_str),
+        value_name = "PATH"
+    )]
+    /// Directory path to store invitations issued by the home, must match the one of the node
+    invitation_path: PathBuf,
+
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    #[structopt(name = "create")]
+    /// Sign a new invitation and print it as JSON to be handed over to the invitee
+    Create {
+        #[structopt(long)]
+        /// Unique identifier of the invitation, a random one is generated if not given
+        voucher: Option<String>,
+    },
+
+    #[structopt(name = "revoke")]
+    /// Revoke an invitation that was not used yet
+    Revoke {
+        #[structopt()]
+        /// Voucher of the invitation
+        voucher: String,
+    },
+}
+
+fn main() -> Fallible<()> {
+    let options = Options::from_args();
+    let mut store = FileStore::new(&options.invitation_path)?;
+
+    match options.command {
+        Command::Create { voucher } => {
+            let voucher = voucher.unwrap_or_else(|| {
+                rand::thread_rng().sample_iter(&Alphanumeric).take(VOUCHER_LENGTH).collect()
+            });
+            validate_voucher(&voucher)?;
+            let existing: Fallible<InvitationStatus> = store.get(voucher.clone()).wait();
+            if existing.is_ok() {
+                bail!("Invitation {} was already issued", voucher);
+            }
+
+            let (_vault, signer) = load_signer(options.keyvault_dir, options.profile_id);
+            let invitation = HomeInvitation::new(&voucher, signer.as_ref())?;
+            store.set(voucher, InvitationStatus::Issued).wait()?;
+            println!("{}", serde_json::to_string_pretty(&invitation)?);
+        }
+        Command::Revoke { voucher } => {
+            validate_voucher(&voucher)?;
+            let status: InvitationStatus = store
+                .get(voucher.clone())
+                .wait()
+                .map_err(|e| format_err!("Invitation {} was not found: {}", voucher, e))?;
+            if let InvitationStatus::Used(profile_id) = status {
+                bail!("Invitation {} was already used by profile {}", voucher, profile_id);
+            }
+            store.set(voucher.clone(), InvitationStatus::Revoked).wait()?;
+            println!("Revoked invitation {}", voucher);
+        }
+    }
+    Ok(())
+}
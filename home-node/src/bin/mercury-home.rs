@@ -1,15 +1,18 @@
 use std::cell::RefCell;
 use std::rc::Rc;
 
-use futures::{Future, Stream};
+use futures::{Future, IntoFuture, Stream};
 use log::*;
+use multiaddr::{AddrComponent, ToMultiaddr};
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::tcp::TcpListener;
+use tokio::timer::Interval;
 use tokio_current_thread as reactor;
 
 use claims::repo::{DistributedPublicProfileRepository, FileProfileRepository};
 use mercury_home_node::{config::*, server::*};
 use mercury_home_protocol::{
-    crypto::*, handshake, mercury_capnp::server_dispatcher::HomeDispatcherCapnProto, *,
+    crypto::*, handshake, mercury_capnp::server_dispatcher::HomeDispatcherCapnProto, websocket, *,
 };
 use mercury_storage::asynch::fs::FileStore;
 use mercury_storage::asynch::KeyAdapter;
@@ -30,23 +33,114 @@ fn main() {
     // TODO use some kind of real distributed storage here on the long run
     let mut distributed_storage =
         FileProfileRepository::new(&std::path::PathBuf::from("/tmp/cuccos")).unwrap();
+    let mut home_multiaddrs = vec![config.listen_socket().to_multiaddr().unwrap()];
+    if let Some(websocket_socket) = config.websocket_socket() {
+        let mut websocket_multiaddr = websocket_socket.to_multiaddr().unwrap();
+        websocket_multiaddr.append(AddrComponent::WS);
+        home_multiaddrs.push(websocket_multiaddr);
+    }
+    // NOTE the addresses may change with the configuration, the published profile must follow them
     let avail_prof_res = reactor.block_on(distributed_storage.get_public(&signer.profile_id()));
-    if avail_prof_res.is_err() {
-        info!("Home node profile is not found on distributed public storage, saving node profile");
-        use multiaddr::ToMultiaddr;
-        let home_multiaddr = config.listen_socket().to_multiaddr().unwrap();
-        let home_attrs = HomeFacet::new(vec![home_multiaddr], vec![]).to_attribute_map();
-        let home_profile = Profile::new(signer.public_key(), 1, vec![], home_attrs);
+    let home_profile = match avail_prof_res {
+        Err(_e) => {
+            info!(
+                "Home node profile is not found on distributed public storage, saving node profile"
+            );
+            let home_attrs = HomeFacet::new(home_multiaddrs, vec![]).to_attribute_map();
+            Some(Profile::new(signer.public_key(), 1, vec![], home_attrs))
+        }
+        Ok(mut home_profile) => {
+            let mut home_facet = home_profile.to_home().unwrap_or_default();
+            if home_facet.addrs == home_multiaddrs {
+                info!("Home node profile is already available on distributed public storage");
+                None
+            } else {
+                info!(
+                    "Home node addresses changed, updating node profile to {:?}",
+                    home_multiaddrs
+                );
+                home_facet.addrs = home_multiaddrs;
+                home_profile.set_home(&home_facet);
+                home_profile.increase_version();
+                Some(home_profile)
+            }
+        }
+    };
+    if let Some(mut home_profile) = home_profile {
+        home_profile.sign(&*signer).expect("Failed to sign home node profile");
         reactor.block_on(distributed_storage.set_public(home_profile)).unwrap();
-    } else {
-        info!("Home node profile is already available on distributed public storage");
     }
 
-    let host_db = Rc::new(RefCell::new(KeyAdapter::new(
-        FileStore::new(config.host_relations_path()).unwrap(),
-    )));
     let distributed_storage = Rc::new(RefCell::new(distributed_storage));
-    let server = Rc::new(HomeServer::new(validator, distributed_storage, local_storage, host_db));
+    let stores = HomeStores {
+        public_dht: distributed_storage.clone(),
+        public_explorer: distributed_storage,
+        private_db: local_storage,
+        host_relations_db: Rc::new(RefCell::new(KeyAdapter::new(
+            FileStore::new(config.host_relations_path()).unwrap(),
+        ))),
+        mailbox_db: Rc::new(RefCell::new(KeyAdapter::new(
+            FileStore::new(config.mailbox_path()).unwrap(),
+        ))),
+        ban_list_db: Rc::new(RefCell::new(KeyAdapter::new(
+            FileStore::new(config.ban_list_path()).unwrap(),
+        ))),
+        invitation_db: Rc::new(RefCell::new(FileStore::new(config.invitation_path()).unwrap())),
+        revocation_db: Rc::new(RefCell::new(KeyAdapter::new(
+            FileStore::new(config.revocation_path()).unwrap(),
+        ))),
+        redirect_db: Rc::new(RefCell::new(KeyAdapter::new(
+            FileStore::new(config.redirect_path()).unwrap(),
+        ))),
+    };
+    let server = Rc::new(HomeServer::new(
+        validator,
+        stores,
+        config.mailbox_limits(),
+        config.registration_policy().to_owned(),
+        config.redirect_period(),
+    ));
+
+    // NOTE sessions are checked every heartbeat period, so they expire at most this late
+    let session_timeout = config.session_timeout();
+    let expiry_fut = Interval::new_interval(HEARTBEAT_INTERVAL)
+        .map_err(|e| warn!("Session expiry timer failed: {}", e))
+        .for_each({
+            let server = server.clone();
+            move |_instant| {
+                server.expire_sessions(session_timeout).or_else(|e| {
+                    warn!("Failed to expire sessions: {}", e);
+                    Ok(())
+                })
+            }
+        });
+    reactor.spawn(expiry_fut);
+
+    if let Some(websocket_socket) = config.websocket_socket() {
+        info!("Opening socket {} for incoming WebSocket clients", websocket_socket);
+        let socket = TcpListener::bind(websocket_socket).expect("Failed to bind socket");
+        let signer = signer.clone();
+        let server = server.clone();
+        let websocket_fut = socket
+            .incoming()
+            .map_err(|e| warn!("Failed to accept WebSocket client: {}", e))
+            .for_each(move |socket| {
+                info!("Accepted WebSocket client connection, serving requests");
+                let signer = signer.clone();
+                let server = server.clone();
+                let serve_fut = handshake::tcpstream_to_reader_writer(socket)
+                    .into_future()
+                    .map_err(|e| warn!("Failed to set up client connection: {}", e))
+                    .and_then(|(reader, writer)| {
+                        websocket::accept(reader, writer)
+                            .map_err(|e| warn!("WebSocket upgrade failed: {:?}", e))
+                    })
+                    .and_then(move |(reader, writer)| serve_client(reader, writer, signer, server));
+                reactor::spawn(serve_fut);
+                Ok(())
+            });
+        reactor.spawn(websocket_fut);
+    }
 
     info!("Opening socket {} for incoming TCP clients", config.listen_socket());
     let socket = TcpListener::bind(config.listen_socket()).expect("Failed to bind socket");
@@ -55,22 +149,15 @@ fn main() {
     let done = socket.incoming().for_each(move |socket| {
         info!("Accepted client connection, serving requests");
 
-        let server_clone = server.clone();
+        let serve_fut = match handshake::tcpstream_to_reader_writer(socket) {
+            Ok((reader, writer)) => serve_client(reader, writer, signer.clone(), server.clone()),
+            Err(e) => {
+                warn!("Failed to set up client connection: {}", e);
+                return Ok(());
+            }
+        };
 
-        // TODO fill this in properly for each connection based on TLS authentication info
-        let handshake_fut = handshake::temporary_unsafe_tcp_handshake_until_diffie_hellman_done(
-            socket,
-            signer.clone(),
-        )
-        .map_err(|e| warn!("Client handshake failed: {:?}", e))
-        .and_then(move |(reader, writer, client_context)| {
-            let home = HomeConnectionServer::new(Rc::new(client_context), server_clone.clone())
-                .map_err(|e| warn!("Failed to create server instance: {:?}", e))?;
-            HomeDispatcherCapnProto::dispatch(Rc::new(home), reader, writer);
-            Ok(())
-        });
-
-        reactor::spawn(handshake_fut);
+        reactor::spawn(serve_fut);
         Ok(())
     });
 
@@ -78,3 +165,24 @@ fn main() {
     debug!("Reactor finished with result: {:?}", res);
     info!("Server shutdown");
 }
+
+/// Authenticates the client and serves requests on the connection, regardless of its transport
+fn serve_client<R, W>(
+    reader: R,
+    writer: W,
+    signer: Rc<dyn Signer>,
+    server: Rc<HomeServer>,
+) -> impl Future<Item = (), Error = ()>
+where
+    R: std::io::Read + AsyncRead + 'static,
+    W: std::io::Write + AsyncWrite + 'static,
+{
+    handshake::ecdh_handshake(reader, writer, signer)
+        .map_err(|e| warn!("Client handshake failed: {:?}", e))
+        .and_then(move |(reader, writer, client_context)| {
+            let home = HomeConnectionServer::new(Rc::new(client_context), server)
+                .map_err(|e| warn!("Failed to create server instance: {:?}", e))?;
+            HomeDispatcherCapnProto::dispatch(Rc::new(home), reader, writer);
+            Ok(())
+        })
+}
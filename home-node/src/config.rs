@@ -2,10 +2,13 @@ use std::net::{SocketAddr, ToSocketAddrs};
 use std::path::PathBuf;
 use std::rc::Rc;
 use std::sync::Arc;
+use std::time::Duration;
 
 use log::*;
 use structopt::StructOpt;
 
+use crate::mailbox::MailboxLimits;
+use crate::registration::RegistrationPolicy;
 use did::vault::{HdProfileVault, ProfileVault};
 use did::*;
 use mercury_home_protocol::*;
@@ -44,6 +47,80 @@ struct CliConfig {
     /// Directory path to store hosted profiles in
     host_relations_path: PathBuf,
 
+    #[structopt(
+        long = "mailboxes",
+        default_value = "/tmp/mercury/home/mailboxes",
+        parse(from_os_str),
+        value_name = "PATH"
+    )]
+    /// Directory path to store events and missed calls of offline profiles
+    mailbox_path: PathBuf,
+
+    #[structopt(
+        long = "ban-lists",
+        default_value = "/tmp/mercury/home/ban-lists",
+        parse(from_os_str),
+        value_name = "PATH"
+    )]
+    /// Directory path to store profiles banned by hosted profiles
+    ban_list_path: PathBuf,
+
+    #[structopt(
+        long = "revocations",
+        default_value = "/tmp/mercury/home/revocations",
+        parse(from_os_str),
+        value_name = "PATH"
+    )]
+    /// Directory path to store relations revoked by or for hosted profiles
+    revocation_path: PathBuf,
+
+    #[structopt(
+        long = "redirects",
+        default_value = "/tmp/mercury/home/redirects",
+        parse(from_os_str),
+        value_name = "PATH"
+    )]
+    /// Directory path to store redirects of profiles moved to another home
+    redirect_path: PathBuf,
+
+    #[structopt(long = "redirect-period", default_value = "30", value_name = "DAYS")]
+    /// Lookups of a moved profile are answered with a redirect to its new home for this long
+    redirect_period_days: u64,
+
+    #[structopt(
+        long = "registration",
+        default_value = "open",
+        possible_values = &["open", "invite-only", "allowlist"],
+        value_name = "POLICY"
+    )]
+    /// Who can register: anyone, holders of invitations or profiles on the allowlist
+    registration_policy: String,
+
+    #[structopt(long = "allowlist", parse(from_os_str), value_name = "FILE")]
+    /// File listing a profile id on each line allowed to register with the allowlist policy
+    allowlist_path: Option<PathBuf>,
+
+    #[structopt(
+        long = "invitations",
+        default_value = "/tmp/mercury/home/invitations",
+        parse(from_os_str),
+        value_name = "PATH"
+    )]
+    /// Directory path to store invitations issued by this home
+    invitation_path: PathBuf,
+
+    #[structopt(long = "mailbox-max-items", default_value = "256", value_name = "COUNT")]
+    /// Maximum number of events and of missed calls kept for a single profile
+    mailbox_max_items: usize,
+
+    #[structopt(long = "mailbox-max-age", default_value = "30", value_name = "DAYS")]
+    /// Events and missed calls older than this are dropped from mailboxes
+    mailbox_max_age_days: u64,
+
+    #[structopt(long = "session-timeout", default_value = "90", value_name = "SECS")]
+    /// Sessions without a heartbeat for this long are expired and their profiles go offline
+    session_timeout_secs: u64,
+
     #[structopt(
         long = "distributed-storage",
         default_value = "127.0.0.1:6161",
@@ -55,6 +132,10 @@ struct CliConfig {
     #[structopt(long = "tcp", default_value = "0.0.0.0:2077", value_name = "IP:Port")]
     /// Listen on this socket to serve TCP clients
     socket_addr: String,
+
+    #[structopt(long = "websocket", value_name = "IP:Port")]
+    /// Also listen on this socket to serve WebSocket clients, e.g. browsers
+    websocket_addr: Option<String>,
 }
 
 impl CliConfig {
@@ -68,26 +149,27 @@ impl CliConfig {
 pub struct Config {
     private_storage_path: PathBuf,
     host_relations_path: PathBuf,
+    mailbox_path: PathBuf,
+    mailbox_limits: MailboxLimits,
+    ban_list_path: PathBuf,
+    revocation_path: PathBuf,
+    redirect_path: PathBuf,
+    redirect_period: Duration,
+    session_timeout: Duration,
+    registration_policy: RegistrationPolicy,
+    invitation_path: PathBuf,
     distributed_storage_address: SocketAddr,
     _vault: Arc<HdProfileVault>,
     signer: Rc<dyn Signer>,
     listen_socket: SocketAddr, // TODO consider using Vec if listening on several network devices is needed
+    websocket_socket: Option<SocketAddr>,
 }
 
 impl Config {
     pub fn new() -> Self {
         let cli = CliConfig::new();
 
-        let vault_path =
-            did::paths::vault_path(cli.keyvault_dir).expect("Failed to get keyvault path");
-        let vault = Arc::new(HdProfileVault::load(&vault_path).expect(&format!(
-            "Profile vault is required but failed to load from {}",
-            vault_path.to_string_lossy()
-        )));
-
-        let profile_id = cli.profile_id.or_else(|| vault.get_active().expect("Failed to get active profile") )
-            .expect("Profile id is needed for authenticating the node, but neither command line argument is specified, nor active profile is set in vault");
-        let signer = vault.clone().signer(&profile_id).unwrap();
+        let (vault, signer) = load_signer(cli.keyvault_dir, cli.profile_id);
 
         info!("homenode profile id: {}", signer.profile_id());
         info!("homenode public key: {}", signer.public_key());
@@ -99,6 +181,13 @@ impl Config {
             .next()
             .expect("Failed to parse socket address for private storage");
 
+        let websocket_socket = cli.websocket_addr.map(|addr| {
+            addr.to_socket_addrs()
+                .unwrap()
+                .next()
+                .expect("Failed to parse socket address for WebSocket clients")
+        });
+
         let distributed_storage_address = cli
             .distributed_storage_address
             .to_socket_addrs()
@@ -106,13 +195,37 @@ impl Config {
             .next()
             .expect("Failed to parse socket address for distributed storage");
 
+        let registration_policy = match cli.registration_policy.as_str() {
+            "open" => RegistrationPolicy::Open,
+            "invite-only" => RegistrationPolicy::InviteOnly,
+            "allowlist" => {
+                let path = cli.allowlist_path.expect("Allowlist policy requires an allowlist file");
+                RegistrationPolicy::load_allowlist(&path).expect("Failed to load allowlist")
+            }
+            policy => panic!("Unknown registration policy: {}", policy),
+        };
+        info!("Registration policy: {}", cli.registration_policy);
+
         Self {
             private_storage_path: cli.profile_backup_path,
             host_relations_path: cli.host_relations_path,
+            mailbox_path: cli.mailbox_path,
+            mailbox_limits: MailboxLimits {
+                max_items: cli.mailbox_max_items,
+                max_age: Duration::from_secs(cli.mailbox_max_age_days * 24 * 60 * 60),
+            },
+            ban_list_path: cli.ban_list_path,
+            revocation_path: cli.revocation_path,
+            redirect_path: cli.redirect_path,
+            redirect_period: Duration::from_secs(cli.redirect_period_days * 24 * 60 * 60),
+            session_timeout: Duration::from_secs(cli.session_timeout_secs),
+            registration_policy,
+            invitation_path: cli.invitation_path,
             distributed_storage_address,
             _vault: vault,
             signer,
             listen_socket,
+            websocket_socket,
         }
     }
 
@@ -122,6 +235,33 @@ impl Config {
     pub fn host_relations_path(&self) -> &PathBuf {
         &self.host_relations_path
     }
+    pub fn mailbox_path(&self) -> &PathBuf {
+        &self.mailbox_path
+    }
+    pub fn mailbox_limits(&self) -> MailboxLimits {
+        self.mailbox_limits
+    }
+    pub fn ban_list_path(&self) -> &PathBuf {
+        &self.ban_list_path
+    }
+    pub fn revocation_path(&self) -> &PathBuf {
+        &self.revocation_path
+    }
+    pub fn redirect_path(&self) -> &PathBuf {
+        &self.redirect_path
+    }
+    pub fn redirect_period(&self) -> Duration {
+        self.redirect_period
+    }
+    pub fn session_timeout(&self) -> Duration {
+        self.session_timeout
+    }
+    pub fn registration_policy(&self) -> &RegistrationPolicy {
+        &self.registration_policy
+    }
+    pub fn invitation_path(&self) -> &PathBuf {
+        &self.invitation_path
+    }
     pub fn distributed_storage_address(&self) -> &SocketAddr {
         &self.distributed_storage_address
     }
@@ -131,4 +271,24 @@ impl Config {
     pub fn listen_socket(&self) -> &SocketAddr {
         &self.listen_socket
     }
+    pub fn websocket_socket(&self) -> Option<&SocketAddr> {
+        self.websocket_socket.as_ref()
+    }
+}
+
+/// Loads the key of the home node from its vault, using the active profile if no id is given.
+pub fn load_signer(
+    keyvault_dir: Option<PathBuf>,
+    profile_id: Option<ProfileId>,
+) -> (Arc<HdProfileVault>, Rc<dyn Signer>) {
+    let vault_path = did::paths::vault_path(keyvault_dir).expect("Failed to get keyvault path");
+    let vault = Arc::new(HdProfileVault::load(&vault_path).expect(&format!(
+        "Profile vault is required but failed to load from {}",
+        vault_path.to_string_lossy()
+    )));
+
+    let profile_id = profile_id.or_else(|| vault.get_active().expect("Failed to get active profile") )
+        .expect("Profile id is needed for authenticating the node, but neither command line argument is specified, nor active profile is set in vault");
+    let signer = vault.clone().signer(&profile_id).unwrap();
+    (vault, signer)
 }
@@ -1,2 +1,4 @@
 pub mod config;
+pub mod mailbox;
+pub mod registration;
 pub mod server;
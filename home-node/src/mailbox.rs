@@ -0,0 +1,328 @@
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::*;
+use serde_derive::{Deserialize, Serialize};
+
+use mercury_home_protocol::*;
+
+/// Bounds of what a home keeps for a single profile while it is offline.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct MailboxLimits {
+    /// Maximum number of events, of missed calls and of messages per application kept,
+    /// the oldest ones are dropped first.
+    pub max_items: usize,
+    /// Items older than this are dropped.
+    pub max_age: Duration,
+}
+
+impl Default for MailboxLimits {
+    fn default() -> Self {
+        Self { max_items: 256, max_age: Duration::from_secs(30 * 24 * 60 * 60) }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+struct Envelope<T> {
+    received_at: u64, // seconds since the Unix epoch
+    content: T,
+}
+
+/// Persistent store-and-forward queue of a hosted profile. Events and application messages are
+/// kept until the profile acknowledges them, missed calls until they are delivered to the
+/// checked in application.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Mailbox {
+    #[serde(default)]
+    events: Vec<Envelope<ProfileEvent>>,
+    #[serde(default)]
+    calls: Vec<Envelope<MissedCall>>,
+    #[serde(default)]
+    inboxes: HashMap<ApplicationId, Vec<Envelope<AppMessage>>>,
+    #[serde(default)]
+    next_message_id: u64,
+}
+
+impl Mailbox {
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty() && self.calls.is_empty() && self.inboxes.is_empty()
+    }
+
+    pub fn events(&self) -> Vec<ProfileEvent> {
+        self.events.iter().map(|envelope| envelope.content.clone()).collect()
+    }
+
+    pub fn missed_calls(&self, app: &ApplicationId) -> Vec<MissedCall> {
+        self.calls
+            .iter()
+            .filter(|envelope| envelope.content.app == *app)
+            .map(|envelope| envelope.content.clone())
+            .collect()
+    }
+
+    pub fn messages(&self, app: &ApplicationId) -> Vec<AppMessage> {
+        self.inboxes
+            .get(app)
+            .map(|inbox| inbox.iter().map(|envelope| envelope.content.clone()).collect())
+            .unwrap_or_default()
+    }
+
+    pub fn push_message(
+        &mut self,
+        app: ApplicationId,
+        relation: RelationProof,
+        payload: AppMessageFrame,
+    ) -> u64 {
+        let id = self.next_message_id;
+        self.next_message_id += 1;
+        let message = AppMessage { id, relation, payload };
+        self.inboxes
+            .entry(app)
+            .or_default()
+            .push(Envelope { received_at: now_secs(), content: message });
+        id
+    }
+
+    pub fn ack_messages(&mut self, app: &ApplicationId, ids: &[u64]) {
+        if let Some(inbox) = self.inboxes.get_mut(app) {
+            inbox.retain(|envelope| !ids.contains(&envelope.content.id));
+            if inbox.is_empty() {
+                self.inboxes.remove(app);
+            }
+        }
+    }
+
+    pub fn push_event(&mut self, event: ProfileEvent) {
+        self.events.push(Envelope { received_at: now_secs(), content: event });
+    }
+
+    pub fn push_call(&mut self, call: MissedCall) {
+        self.calls.push(Envelope { received_at: now_secs(), content: call });
+    }
+
+    /// Returns false if the event was not found, e.g. it was already acknowledged.
+    pub fn ack_event(&mut self, event: &ProfileEvent) -> bool {
+        let count = self.events.len();
+        self.events.retain(|envelope| envelope.content != *event);
+        self.events.len() != count
+    }
+
+    pub fn remove_calls(&mut self, delivered: &[MissedCall]) {
+        self.calls.retain(|envelope| !delivered.contains(&envelope.content));
+    }
+
+    /// Drops all events, missed calls and messages sent by `peer_id`, e.g. after banning it.
+    pub fn remove_from(&mut self, peer_id: &ProfileId) {
+        let involves_peer =
+            |relation: &RelationProof| relation.a_id == *peer_id || relation.b_id == *peer_id;
+        self.events.retain(|envelope| match envelope.content {
+            ProfileEvent::PairingRequest(ref half_proof) => half_proof.signer_id != *peer_id,
+            ProfileEvent::PairingResponse(ref relation) => !involves_peer(relation),
+            ProfileEvent::RelationRevoked(ref revocation) => !involves_peer(&revocation.relation),
+            ProfileEvent::Unknown(_) => true,
+        });
+        self.calls.retain(|envelope| !involves_peer(&envelope.content.relation));
+        for inbox in self.inboxes.values_mut() {
+            inbox.retain(|envelope| !involves_peer(&envelope.content.relation));
+        }
+        self.inboxes.retain(|_app, inbox| !inbox.is_empty());
+    }
+
+    /// Drops missed calls and messages sent over a revoked relation.
+    pub fn remove_relation(&mut self, relation: &RelationProof) {
+        self.calls.retain(|envelope| envelope.content.relation != *relation);
+        for inbox in self.inboxes.values_mut() {
+            inbox.retain(|envelope| envelope.content.relation != *relation);
+        }
+        self.inboxes.retain(|_app, inbox| !inbox.is_empty());
+    }
+
+    /// Pending events, missed calls and messages, e.g. to move them to a new home.
+    pub fn export(&self) -> MailboxContent {
+        let calls = self.calls.iter().map(|envelope| envelope.content.clone()).collect();
+        let messages =
+            self.inboxes.keys().map(|app| (app.to_owned(), self.messages(app))).collect();
+        MailboxContent { events: self.events(), calls, messages }
+    }
+
+    /// Adds items exported from another mailbox. Events and missed calls already present are skipped,
+    /// messages get new ids from this mailbox.
+    pub fn import(&mut self, content: MailboxContent) {
+        for event in content.events {
+            if !self.events.iter().any(|envelope| envelope.content == event) {
+                self.push_event(event);
+            }
+        }
+        for call in content.calls {
+            if !self.calls.iter().any(|envelope| envelope.content == call) {
+                self.push_call(call);
+            }
+        }
+        for (app, messages) in content.messages {
+            for message in messages {
+                self.push_message(app.clone(), message.relation, message.payload);
+            }
+        }
+    }
+
+    pub fn enforce(&mut self, limits: &MailboxLimits) {
+        let oldest_allowed = now_secs().saturating_sub(limits.max_age.as_secs());
+        let count_before = self.item_count();
+        Self::enforce_on(&mut self.events, limits.max_items, oldest_allowed);
+        Self::enforce_on(&mut self.calls, limits.max_items, oldest_allowed);
+        for inbox in self.inboxes.values_mut() {
+            Self::enforce_on(inbox, limits.max_items, oldest_allowed);
+        }
+        self.inboxes.retain(|_app, inbox| !inbox.is_empty());
+        let dropped = count_before - self.item_count();
+        if dropped > 0 {
+            warn!("Dropped {} items from mailbox exceeding its limits", dropped);
+        }
+    }
+
+    fn item_count(&self) -> usize {
+        self.events.len()
+            + self.calls.len()
+            + self.inboxes.values().map(|inbox| inbox.len()).sum::<usize>()
+    }
+
+    fn enforce_on<T>(items: &mut Vec<Envelope<T>>, max_items: usize, oldest_allowed: u64) {
+        items.retain(|envelope| envelope.received_at >= oldest_allowed);
+        if items.len() > max_items {
+            items.drain(..items.len() - max_items);
+        }
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs()).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use did::vault::{HdProfileVault, ProfileVault};
+    use keyvault::Seed;
+
+    fn relation() -> RelationProof {
+        let seed = Seed::from_bip39(&Seed::generate_bip39()).unwrap();
+        let mut vault = HdProfileVault::create(seed);
+        let (key_a, key_b) = (vault.create_key(None).unwrap(), vault.create_key(None).unwrap());
+        let vault = Arc::new(vault);
+        let signer_a = vault.clone().signer(&key_a.key_id()).unwrap();
+        let signer_b = vault.signer(&key_b.key_id()).unwrap();
+        let half_proof =
+            RelationHalfProof::new("friend", &key_b.key_id(), signer_a.as_ref()).unwrap();
+        RelationProof::sign_remaining_half(&half_proof, signer_b.as_ref()).unwrap()
+    }
+
+    #[test]
+    fn ack_and_limits() {
+        let limits = MailboxLimits { max_items: 2, max_age: Duration::from_secs(60) };
+        let mut mailbox = Mailbox::default();
+        for idx in 0..3 {
+            mailbox.push_event(ProfileEvent::Unknown(vec![idx]));
+        }
+        mailbox.enforce(&limits);
+        assert_eq!(
+            mailbox.events(),
+            vec![ProfileEvent::Unknown(vec![1]), ProfileEvent::Unknown(vec![2])]
+        );
+
+        assert!(mailbox.ack_event(&ProfileEvent::Unknown(vec![1])));
+        assert!(!mailbox.ack_event(&ProfileEvent::Unknown(vec![1])));
+        assert_eq!(mailbox.events(), vec![ProfileEvent::Unknown(vec![2])]);
+
+        mailbox.events[0].received_at -= 61;
+        mailbox.enforce(&limits);
+        assert!(mailbox.is_empty());
+    }
+
+    #[test]
+    fn message_inboxes() {
+        let limits = MailboxLimits { max_items: 2, max_age: Duration::from_secs(60) };
+        let chat = ApplicationId::from("chat");
+        let relation = relation();
+        let mut mailbox = Mailbox::default();
+        for idx in 0..3 {
+            mailbox.push_message(chat.clone(), relation.clone(), AppMessageFrame(vec![idx]));
+        }
+        mailbox.enforce(&limits);
+        let ids = mailbox.messages(&chat).iter().map(|message| message.id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![1, 2]);
+        assert!(mailbox.messages(&ApplicationId::from("other")).is_empty());
+
+        mailbox.ack_messages(&chat, &[1]);
+        assert_eq!(mailbox.messages(&chat)[0].payload, AppMessageFrame(vec![2]));
+        mailbox.ack_messages(&chat, &[2]);
+        assert!(mailbox.is_empty());
+        assert_eq!(mailbox.push_message(chat, relation, AppMessageFrame(vec![])), 3);
+    }
+
+    #[test]
+    fn remove_from_peer() {
+        let chat = ApplicationId::from("chat");
+        let relation = relation();
+        let mut mailbox = Mailbox::default();
+        mailbox.push_event(ProfileEvent::Unknown(vec![]));
+        mailbox.push_event(ProfileEvent::PairingResponse(relation.clone()));
+        mailbox.push_message(chat.clone(), relation.clone(), AppMessageFrame(vec![]));
+        mailbox.push_call(MissedCall {
+            app: chat.clone(),
+            relation: relation.clone(),
+            init_payload: AppMessageFrame(vec![]),
+        });
+
+        mailbox.remove_from(&relation.a_id);
+        assert_eq!(mailbox.events(), vec![ProfileEvent::Unknown(vec![])]);
+        assert!(mailbox.missed_calls(&chat).is_empty());
+        assert!(mailbox.messages(&chat).is_empty());
+    }
+
+    #[test]
+    fn remove_revoked_relation() {
+        let chat = ApplicationId::from("chat");
+        let (revoked, kept) = (relation(), relation());
+        let mut mailbox = Mailbox::default();
+        mailbox.push_message(chat.clone(), revoked.clone(), AppMessageFrame(vec![1]));
+        mailbox.push_message(chat.clone(), kept.clone(), AppMessageFrame(vec![2]));
+        mailbox.push_call(MissedCall {
+            app: chat.clone(),
+            relation: revoked.clone(),
+            init_payload: AppMessageFrame(vec![]),
+        });
+
+        mailbox.remove_relation(&revoked);
+        let messages = mailbox.messages(&chat);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].relation, kept);
+        assert!(mailbox.missed_calls(&chat).is_empty());
+    }
+
+    #[test]
+    fn export_import() {
+        let chat = ApplicationId::from("chat");
+        let relation = relation();
+        let mut old_mailbox = Mailbox::default();
+        old_mailbox.push_event(ProfileEvent::PairingResponse(relation.clone()));
+        old_mailbox.push_message(chat.clone(), relation.clone(), AppMessageFrame(vec![1]));
+        old_mailbox.push_call(MissedCall {
+            app: chat.clone(),
+            relation: relation.clone(),
+            init_payload: AppMessageFrame(vec![]),
+        });
+
+        let mut new_mailbox = Mailbox::default();
+        new_mailbox.push_event(ProfileEvent::PairingResponse(relation.clone()));
+        new_mailbox.push_message(chat.clone(), relation.clone(), AppMessageFrame(vec![0]));
+        new_mailbox.import(old_mailbox.export());
+
+        assert_eq!(new_mailbox.events(), vec![ProfileEvent::PairingResponse(relation)]);
+        let messages = new_mailbox.messages(&chat);
+        assert_eq!(messages.iter().map(|message| message.id).collect::<Vec<_>>(), vec![0, 1]);
+        assert_eq!(messages[1].payload, AppMessageFrame(vec![1]));
+        assert_eq!(new_mailbox.missed_calls(&chat).len(), 1);
+    }
+}
@@ -0,0 +1,72 @@
+use std::collections::HashSet;
+use std::path::Path;
+use std::str::FromStr;
+
+use failure::{bail, ensure, format_err, Fallible};
+use serde_derive::{Deserialize, Serialize};
+
+use mercury_home_protocol::*;
+
+/// Decides which profiles are allowed to register on the home.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum RegistrationPolicy {
+    /// Anyone can register
+    Open,
+    /// Only profiles presenting a valid invitation issued by the home can register
+    InviteOnly,
+    /// Only the listed profiles can register, invitations are not accepted
+    Allowlist(HashSet<ProfileId>),
+}
+
+impl Default for RegistrationPolicy {
+    fn default() -> Self {
+        RegistrationPolicy::Open
+    }
+}
+
+impl RegistrationPolicy {
+    /// Reads the allowlist from a file with a profile id on each line, empty lines and
+    /// lines starting with # are skipped.
+    pub fn load_allowlist(path: &Path) -> Fallible<Self> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format_err!("Failed to read allowlist {:?}: {}", path, e))?;
+        let allowed = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(ProfileId::from_str)
+            .collect::<Fallible<HashSet<_>>>()?;
+        Ok(RegistrationPolicy::Allowlist(allowed))
+    }
+}
+
+/// Lifecycle of an invitation issued by the home, stored by its voucher.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum InvitationStatus {
+    Issued,
+    Used(ProfileId),
+    Revoked,
+}
+
+/// Vouchers are used as file names in the invitation store, so only a safe subset of characters is allowed.
+pub fn validate_voucher(voucher: &str) -> Fallible<()> {
+    ensure!(!voucher.is_empty(), "Voucher must not be empty");
+    if let Some(invalid) =
+        voucher.chars().find(|c| !(c.is_ascii_alphanumeric() || *c == '-' || *c == '_'))
+    {
+        bail!("Voucher contains invalid character '{}'", invalid);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn voucher_characters() {
+        assert!(validate_voucher("spring-2020_42").is_ok());
+        assert!(validate_voucher("").is_err());
+        assert!(validate_voucher("../host-relations").is_err());
+    }
+}
@@ -1,6 +1,6 @@
-use std::collections::HashMap;
-use std::time::Duration;
-use std::{cell::RefCell, rc::Rc, rc::Weak};
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant, SystemTime};
+use std::{cell::Cell, cell::RefCell, rc::Rc, rc::Weak};
 
 use failure::Fail;
 use futures::sync::{mpsc, oneshot};
@@ -9,11 +9,15 @@ use log::*;
 use tokio::prelude::*;
 use tokio_current_thread as reactor;
 
+use crate::mailbox::{Mailbox, MailboxLimits};
+use crate::registration::{validate_voucher, InvitationStatus, RegistrationPolicy};
 use claims::model::Link;
+use claims::repo::InMemoryProfileRepository;
 use mercury_home_protocol::api::AsyncSink; // TODO this should normally work with protocol::*, why is this needed?
 use mercury_home_protocol::error::*;
 use mercury_home_protocol::*;
-use mercury_storage::asynch::KeyValueStore;
+use mercury_storage::asynch::imp::InMemoryStore;
+use mercury_storage::asynch::{optional, KeyValueStore};
 
 // TODO this should come from user configuration with a reasonable default value close to this
 const CFG_CALL_ANSWER_TIMEOUT: Duration = Duration::from_secs(30);
@@ -21,26 +25,320 @@ const CFG_CALL_ANSWER_TIMEOUT: Duration = Duration::from_secs(30);
 pub struct HomeServer {
     validator: Rc<dyn Validator>,
     public_profile_dht: Rc<RefCell<dyn DistributedPublicProfileRepository>>,
+    public_profile_explorer: Rc<RefCell<dyn ProfileExplorer>>,
     private_backup_db: Rc<RefCell<dyn PrivateProfileRepository>>,
     host_relations_db: Rc<RefCell<dyn KeyValueStore<ProfileId, RelationProof>>>,
+    mailbox_db: Rc<RefCell<dyn KeyValueStore<ProfileId, Mailbox>>>,
+    mailbox_limits: MailboxLimits,
+    ban_list_db: Rc<RefCell<dyn KeyValueStore<ProfileId, HashSet<ProfileId>>>>,
+    registration_policy: RegistrationPolicy,
+    invitation_db: Rc<RefCell<dyn KeyValueStore<String, InvitationStatus>>>, // {voucher->status}
+    revocation_db: Rc<RefCell<dyn KeyValueStore<ProfileId, Vec<RelationRevocation>>>>, // {hosted profile->revocations}
+    redirect_db: Rc<RefCell<dyn KeyValueStore<ProfileId, HomeRedirect>>>, // {moved profile->redirect}
+    redirect_period: Duration,
     sessions: Rc<RefCell<HashMap<ProfileId, Weak<HomeSessionServer>>>>,
+    // NOTE presence is not persisted, all profiles are offline after a restart
+    presences: Rc<RefCell<HashMap<ProfileId, Presence>>>,
+    // {profile->[(subscriber, sink)]}
+    presence_subscribers:
+        Rc<RefCell<HashMap<ProfileId, Vec<(ProfileId, AsyncSink<Presence, String>)>>>>,
+}
+
+/// Storage of a home: public profiles, backups and everything kept for the hosted profiles.
+pub struct HomeStores {
+    pub public_dht: Rc<RefCell<dyn DistributedPublicProfileRepository>>,
+    pub public_explorer: Rc<RefCell<dyn ProfileExplorer>>,
+    pub private_db: Rc<RefCell<dyn PrivateProfileRepository>>,
+    pub host_relations_db: Rc<RefCell<dyn KeyValueStore<ProfileId, RelationProof>>>,
+    pub mailbox_db: Rc<RefCell<dyn KeyValueStore<ProfileId, Mailbox>>>,
+    pub ban_list_db: Rc<RefCell<dyn KeyValueStore<ProfileId, HashSet<ProfileId>>>>,
+    pub invitation_db: Rc<RefCell<dyn KeyValueStore<String, InvitationStatus>>>,
+    pub revocation_db: Rc<RefCell<dyn KeyValueStore<ProfileId, Vec<RelationRevocation>>>>,
+    pub redirect_db: Rc<RefCell<dyn KeyValueStore<ProfileId, HomeRedirect>>>,
+}
+
+impl HomeStores {
+    /// Stores losing all data when dropped, e.g. for tests.
+    pub fn in_memory() -> Self {
+        let public_dht = Rc::new(RefCell::new(InMemoryProfileRepository::new()));
+        Self {
+            public_dht: public_dht.clone(),
+            public_explorer: public_dht,
+            private_db: Rc::new(RefCell::new(InMemoryProfileRepository::new())),
+            host_relations_db: Rc::new(RefCell::new(InMemoryStore::new())),
+            mailbox_db: Rc::new(RefCell::new(InMemoryStore::new())),
+            ban_list_db: Rc::new(RefCell::new(InMemoryStore::new())),
+            invitation_db: Rc::new(RefCell::new(InMemoryStore::new())),
+            revocation_db: Rc::new(RefCell::new(InMemoryStore::new())),
+            redirect_db: Rc::new(RefCell::new(InMemoryStore::new())),
+        }
+    }
 }
 
 impl HomeServer {
     pub fn new(
         validator: Rc<dyn Validator>,
-        public_dht: Rc<RefCell<dyn DistributedPublicProfileRepository>>,
-        private_db: Rc<RefCell<dyn PrivateProfileRepository>>,
-        host_relations_db: Rc<RefCell<dyn KeyValueStore<ProfileId, RelationProof>>>,
+        stores: HomeStores,
+        mailbox_limits: MailboxLimits,
+        registration_policy: RegistrationPolicy,
+        redirect_period: Duration,
     ) -> Self {
         Self {
             validator,
-            public_profile_dht: public_dht,
-            private_backup_db: private_db,
-            host_relations_db,
+            public_profile_dht: stores.public_dht,
+            public_profile_explorer: stores.public_explorer,
+            private_backup_db: stores.private_db,
+            host_relations_db: stores.host_relations_db,
+            mailbox_db: stores.mailbox_db,
+            mailbox_limits,
+            ban_list_db: stores.ban_list_db,
+            registration_policy,
+            invitation_db: stores.invitation_db,
+            revocation_db: stores.revocation_db,
+            redirect_db: stores.redirect_db,
+            redirect_period,
             sessions: Rc::new(RefCell::new(HashMap::new())),
+            presences: Rc::new(RefCell::new(HashMap::new())),
+            presence_subscribers: Rc::new(RefCell::new(HashMap::new())),
+        }
+    }
+
+    fn presence_of(&self, profile_id: &ProfileId) -> Presence {
+        self.presences.borrow().get(profile_id).cloned().unwrap_or_default()
+    }
+
+    fn publish_presence(&self, profile_id: ProfileId, presence: Presence) {
+        debug!("Profile {} changed presence to {:?}", profile_id, presence);
+        if let Some(subscribers) = self.presence_subscribers.borrow_mut().get_mut(&profile_id) {
+            let mut live_subscribers = Vec::new();
+            for (subscriber, mut sink) in subscribers.drain(..) {
+                match sink.try_send(Ok(presence.clone())) {
+                    Err(ref e) if e.is_disconnected() => {}
+                    // NOTE a slow subscriber misses this change but gets the next one
+                    Err(_e) => live_subscribers.push((subscriber, sink)),
+                    Ok(()) => live_subscribers.push((subscriber, sink)),
+                }
+            }
+            *subscribers = live_subscribers;
+        }
+        self.presences.borrow_mut().insert(profile_id, presence);
+    }
+
+    /// Shows the profile offline to its subscribers, they stay subscribed until it is back.
+    fn clear_presence(&self, profile_id: &ProfileId) {
+        let was_published = self.presences.borrow().contains_key(profile_id);
+        if was_published {
+            self.publish_presence(profile_id.to_owned(), Presence::default());
+            self.presences.borrow_mut().remove(profile_id);
         }
     }
+
+    fn unsubscribe_presence(&self, profile_id: &ProfileId, subscriber_id: &ProfileId) {
+        if let Some(subscribers) = self.presence_subscribers.borrow_mut().get_mut(profile_id) {
+            subscribers.retain(|(subscriber, _sink)| subscriber != subscriber_id);
+        }
+    }
+
+    fn load_ban_list(
+        &self,
+        profile_id: &ProfileId,
+    ) -> Box<dyn Future<Item = HashSet<ProfileId>, Error = Error>> {
+        let ban_list_fut = optional(self.ban_list_db.borrow().get(profile_id.to_owned()))
+            .map(Option::unwrap_or_default)
+            .map_err(|e| e.context(ErrorKind::StorageFailed).into());
+        Box::new(ban_list_fut)
+    }
+
+    fn update_ban_list(
+        &self,
+        profile_id: ProfileId,
+        update: impl FnOnce(&mut HashSet<ProfileId>) + 'static,
+    ) -> Box<dyn Future<Item = (), Error = Error>> {
+        let ban_list_db = self.ban_list_db.clone();
+        let upd_fut = self.load_ban_list(&profile_id).and_then(move |mut banned| {
+            update(&mut banned);
+            ban_list_db
+                .borrow_mut()
+                .set(profile_id, banned)
+                .map_err(|e| e.context(ErrorKind::BanListUpdateFailed).into())
+        });
+        Box::new(upd_fut)
+    }
+
+    /// Fails with ProfileBanned if `peer_id` is on the ban list of the hosted `profile_id`
+    fn ensure_not_banned(
+        &self,
+        profile_id: &ProfileId,
+        peer_id: ProfileId,
+    ) -> Box<dyn Future<Item = (), Error = Error>> {
+        let check_fut = self.load_ban_list(profile_id).and_then(move |banned| {
+            if banned.contains(&peer_id) {
+                debug!("Refused request of banned profile {}", peer_id);
+                Err(ErrorKind::ProfileBanned.into())
+            } else {
+                Ok(())
+            }
+        });
+        Box::new(check_fut)
+    }
+
+    fn load_revocations(
+        &self,
+        profile_id: &ProfileId,
+    ) -> Box<dyn Future<Item = Vec<RelationRevocation>, Error = Error>> {
+        let revocations_fut = optional(self.revocation_db.borrow().get(profile_id.to_owned()))
+            .map(Option::unwrap_or_default)
+            .map_err(|e| e.context(ErrorKind::StorageFailed).into());
+        Box::new(revocations_fut)
+    }
+
+    fn add_revocation(
+        &self,
+        profile_id: ProfileId,
+        revocation: RelationRevocation,
+    ) -> Box<dyn Future<Item = (), Error = Error>> {
+        let revocation_db = self.revocation_db.clone();
+        let add_fut = self.load_revocations(&profile_id).and_then(move |mut revocations| {
+            if !revocations.contains(&revocation) {
+                revocations.push(revocation);
+            }
+            revocation_db
+                .borrow_mut()
+                .set(profile_id, revocations)
+                .map_err(|e| e.context(ErrorKind::RelationRevocationFailed).into())
+        });
+        Box::new(add_fut)
+    }
+
+    /// Fails with RelationRevoked if `relation` was revoked by or for the hosted `profile_id`
+    fn ensure_not_revoked(
+        &self,
+        profile_id: &ProfileId,
+        relation: RelationProof,
+    ) -> Box<dyn Future<Item = (), Error = Error>> {
+        let check_fut = self.load_revocations(profile_id).and_then(move |revocations| {
+            if revocations.iter().any(|revocation| revocation.revokes(&relation)) {
+                debug!("Refused request over revoked relation {:?}", relation);
+                Err(ErrorKind::RelationRevoked.into())
+            } else {
+                Ok(())
+            }
+        });
+        Box::new(check_fut)
+    }
+
+    /// Returns the redirect of a profile that moved to another home, expired redirects are dropped
+    fn load_redirect(
+        &self,
+        profile_id: &ProfileId,
+    ) -> Box<dyn Future<Item = Option<HomeRedirect>, Error = Error>> {
+        let redirect_db = self.redirect_db.clone();
+        let profile_id = profile_id.to_owned();
+        let redirect_fut = optional(self.redirect_db.borrow().get(profile_id.clone()))
+            .map_err(|e| e.context(ErrorKind::StorageFailed).into())
+            .and_then(move |redirect_opt| {
+                let expired = redirect_opt
+                    .as_ref()
+                    .map_or(false, |redirect| redirect.is_expired_at(SystemTime::now()));
+                if !expired {
+                    return Box::new(future::ok(redirect_opt))
+                        as Box<dyn Future<Item = Option<HomeRedirect>, Error = Error>>;
+                }
+                debug!("Redirect of profile {} expired, dropping it", profile_id);
+                let clear_fut =
+                    redirect_db.borrow_mut().clear_local(profile_id).then(|_res| Ok(None));
+                Box::new(clear_fut)
+            });
+        Box::new(redirect_fut)
+    }
+
+    /// Fails with ProfileMoved if `profile_id` left this home and its redirect is still valid
+    fn ensure_not_moved(
+        &self,
+        profile_id: &ProfileId,
+    ) -> Box<dyn Future<Item = (), Error = Error>> {
+        let check_fut =
+            self.load_redirect(profile_id).and_then(|redirect_opt| match redirect_opt {
+                Some(redirect) => {
+                    debug!("Refused request to profile moved to home {}", redirect.new_home_id);
+                    Err(ErrorKind::ProfileMoved.into())
+                }
+                None => Ok(()),
+            });
+        Box::new(check_fut)
+    }
+
+    fn load_mailbox(
+        &self,
+        profile_id: &ProfileId,
+    ) -> Box<dyn Future<Item = Mailbox, Error = Error>> {
+        let mailbox_fut = optional(self.mailbox_db.borrow().get(profile_id.to_owned()))
+            .map(Option::unwrap_or_default)
+            .map_err(|e| e.context(ErrorKind::StorageFailed).into());
+        Box::new(mailbox_fut)
+    }
+
+    fn update_mailbox(
+        &self,
+        profile_id: ProfileId,
+        update: impl FnOnce(&mut Mailbox) + 'static,
+    ) -> Box<dyn Future<Item = (), Error = Error>> {
+        let mailbox_db = self.mailbox_db.clone();
+        let limits = self.mailbox_limits;
+        let upd_fut = self.load_mailbox(&profile_id).and_then(move |mut mailbox| {
+            update(&mut mailbox);
+            mailbox.enforce(&limits);
+            mailbox_db
+                .borrow_mut()
+                .set(profile_id, mailbox)
+                .map_err(|e| e.context(ErrorKind::MailboxUpdateFailed).into())
+        });
+        Box::new(upd_fut)
+    }
+
+    /// Closes the channels of a session replaced or expired, calls buffered for its apps are
+    /// kept in the mailbox as missed calls. Events are in the mailbox already until acknowledged.
+    fn retire_session(
+        &self,
+        session: &HomeSessionServer,
+    ) -> Box<dyn Future<Item = (), Error = Error>> {
+        let missed_calls = session.expire();
+        if missed_calls.is_empty() {
+            return Box::new(future::ok(()));
+        }
+        self.update_mailbox(session.context.peer_id(), move |mailbox| {
+            for missed_call in missed_calls {
+                mailbox.push_call(missed_call)
+            }
+        })
+    }
+
+    /// Expires sessions without a heartbeat for longer than `max_idle`, their profiles go offline.
+    /// Connections lost without notice otherwise keep their sessions alive.
+    pub fn expire_sessions(&self, max_idle: Duration) -> Box<dyn Future<Item = (), Error = Error>> {
+        let now = Instant::now();
+        let idle_sessions = {
+            let mut sessions = self.sessions.borrow_mut();
+            sessions.retain(|_profile_id, weak| weak.upgrade().is_some());
+            sessions
+                .values()
+                .filter_map(|weak| weak.upgrade())
+                .filter(|session| now.duration_since(session.last_seen.get()) > max_idle)
+                .collect::<Vec<_>>()
+        };
+
+        let expire_futs = idle_sessions
+            .iter()
+            .map(|session| {
+                let profile_id = session.context.peer_id();
+                debug!("Session of profile {} expired without heartbeat", profile_id);
+                self.sessions.borrow_mut().remove(&profile_id);
+                self.clear_presence(&profile_id);
+                self.retire_session(session)
+            })
+            .collect::<Vec<_>>();
+        Box::new(future::join_all(expire_futs).map(|_| ()))
+    }
 }
 
 pub struct HomeConnectionServer {
@@ -66,10 +364,10 @@ impl HomeConnectionServer {
 
         // Check if this profile is hosted on this server
         let session_fut = server
-            .private_backup_db
+            .host_relations_db
             .borrow()
-            .get(&to_profile)
-            .and_then(move |_profile_data| {
+            .get(to_profile.clone())
+            .and_then(move |_host_proof| {
                 // Seperate variable needed, see https://stackoverflow.com/questions/50391668/running-asynchronous-mutable-operations-with-rust-futures
                 let sessions = sessions_clone.borrow();
                 // If hosted here, check if profile is in reach with an online session
@@ -81,21 +379,131 @@ impl HomeConnectionServer {
         Box::new(session_fut)
     }
 
+    /// Returns the voucher of the invitation to be marked used if the policy of the home
+    /// allows the profile to register
+    fn check_registration_policy(
+        &self,
+        profile_id: &ProfileId,
+        invite: Option<HomeInvitation>,
+    ) -> Box<dyn Future<Item = Option<String>, Error = Error>> {
+        let invite = match self.server.registration_policy {
+            RegistrationPolicy::Open => return Box::new(future::ok(None)),
+            RegistrationPolicy::Allowlist(ref allowed) => {
+                if !allowed.contains(profile_id) {
+                    return Box::new(future::err(ErrorKind::RegistrationNotAllowed.into()));
+                }
+                return Box::new(future::ok(None));
+            }
+            RegistrationPolicy::InviteOnly => match invite {
+                Some(invite) => invite,
+                None => return Box::new(future::err(ErrorKind::InvitationRequired.into())),
+            },
+        };
+
+        let my_signer = self.context.my_signer();
+        if invite.home_id != *my_signer.profile_id() || validate_voucher(&invite.voucher).is_err() {
+            return Box::new(future::err(ErrorKind::InvalidInvitation.into()));
+        }
+        if let Err(e) =
+            self.server.validator.validate_home_invitation(&invite, &my_signer.public_key())
+        {
+            return Box::new(future::err(e.context(ErrorKind::InvalidInvitation).into()));
+        }
+
+        let voucher = invite.voucher;
+        let status_fut =
+            self.server.invitation_db.borrow().get(voucher.clone()).then(move |res| match res {
+                Ok(InvitationStatus::Issued) => Ok(Some(voucher)),
+                Ok(status) => {
+                    debug!("Invitation {} cannot be used anymore: {:?}", voucher, status);
+                    Err(ErrorKind::InvalidInvitation.into())
+                }
+                Err(e) => {
+                    debug!("Invitation {} was not issued by this home: {}", voucher, e);
+                    Err(ErrorKind::InvalidInvitation.into())
+                }
+            });
+        Box::new(status_fut)
+    }
+
+    /// Returns the id of the profile hosted here if the relation is valid between
+    /// that profile and the connected peer, it was not revoked and the peer is not banned by that profile.
+    /// Fails with ProfileMoved if the profile moved to another home.
+    fn validate_relation_to_hosted(
+        &self,
+        relation: RelationProof,
+    ) -> Box<dyn Future<Item = ProfileId, Error = Error>> {
+        let to_profile = match relation.peer_id(&self.context.peer_id()) {
+            Ok(profile_id) => profile_id.to_owned(),
+            Err(e) => return Box::new(future::err(e.context(ErrorKind::ProfileMismatch).into())),
+        };
+
+        let server_clone = self.server.clone();
+        let server_clone2 = self.server.clone();
+        let server_clone3 = self.server.clone();
+        let relation_clone = relation.clone();
+        let peer_id_clone = self.context.peer_id().clone();
+        let peer_id_clone2 = self.context.peer_id().clone();
+        let peer_pubkey_clone = self.context.peer_pubkey().clone();
+        let valid_fut = self
+            .server
+            .ensure_not_moved(&to_profile)
+            .and_then(move |()| {
+                let profile_fut = server_clone
+                    .private_backup_db
+                    .borrow()
+                    .get(&to_profile)
+                    .map_err(|e| e.context(ErrorKind::PeerNotHostedHere).into());
+                profile_fut.and_then(move |profile_data| {
+                    server_clone
+                        .validator
+                        .validate_relation_proof(
+                            &relation,
+                            &peer_id_clone,
+                            &peer_pubkey_clone,
+                            &profile_data.id(),
+                            &profile_data.public_key(),
+                        )
+                        .map_err(|err| err.context(ErrorKind::InvalidRelationProof).into())
+                        .map(|()| to_profile)
+                })
+            })
+            .and_then(move |to_profile| {
+                server_clone2.ensure_not_revoked(&to_profile, relation_clone).map(|()| to_profile)
+            })
+            .and_then(move |to_profile| {
+                server_clone3.ensure_not_banned(&to_profile, peer_id_clone2).map(|()| to_profile)
+            });
+        Box::new(valid_fut)
+    }
+
     fn push_event(
         server: Rc<HomeServer>,
         to_profile: ProfileId,
         event: ProfileEvent,
     ) -> Box<dyn Future<Item = (), Error = Error>> {
         debug!("Dispatching event {:?} to session of profile {}", event, to_profile);
-        let push_fut = Self::get_live_session(server, to_profile).and_then(|session_rc_opt| {
-            match session_rc_opt {
-                // TODO if push to session fails, consider just dropping the session
-                //      (is anything manual needed using weak pointers?) and requiring a reconnect
-                Some(ref session) => session.push_event(event),
-                // TODO save event into persistent storage and delegate it when profile is online again
-                None => Box::new(future::ok(())),
-            }
-        });
+        let server_clone = server.clone();
+        let push_fut = Self::get_live_session(server, to_profile.clone())
+            .and_then({
+                // Events are kept in the mailbox until acknowledged, so they survive
+                // both the profile being offline and a restart of the home
+                let event = event.clone();
+                move |session_rc_opt| {
+                    server_clone
+                        .update_mailbox(to_profile, move |mailbox| mailbox.push_event(event))
+                        .map(|()| session_rc_opt)
+                }
+            })
+            .and_then(|session_rc_opt| {
+                match session_rc_opt {
+                    // TODO if push to session fails, consider just dropping the session
+                    //      (is anything manual needed using weak pointers?) and requiring a reconnect
+                    Some(ref session) => session.push_event(event),
+                    // Event is delivered from the mailbox when the profile is online again
+                    None => Box::new(future::ok(())),
+                }
+            });
 
         Box::new(push_fut)
     }
@@ -106,18 +514,26 @@ impl HomeConnectionServer {
         to_app: ApplicationId,
         call: Box<dyn IncomingCall>,
     ) -> Box<dyn Future<Item = (), Error = Error>> {
-        let push_fut = Self::get_live_session(server, to_profile).and_then(|session_rc_opt| {
-            match session_rc_opt {
-                Some(ref session) => {
-                    // TODO if push to session fails, consider just dropping the session
-                    //      (is anything manual needed using weak pointers?) and requiring a reconnect
-                    let push_fut = session.push_call(to_app, call);
-                    Box::new(push_fut) as Box<dyn Future<Item = (), Error = Error>>
+        let server_clone = server.clone();
+        let push_fut =
+            Self::get_live_session(server, to_profile.clone()).and_then(move |session_rc_opt| {
+                match session_rc_opt {
+                    Some(ref session) => {
+                        // TODO if push to session fails, consider just dropping the session
+                        //      (is anything manual needed using weak pointers?) and requiring a reconnect
+                        let push_fut = session.push_call(to_app, call);
+                        Box::new(push_fut) as Box<dyn Future<Item = (), Error = Error>>
+                    }
+                    // Nobody could answer the call, so dropping it fails the call on the caller side,
+                    // but the callee is notified about the missed call when the profile is online again
+                    None => {
+                        let missed_call = MissedCall::new(to_app, call.request_details());
+                        server_clone.update_mailbox(to_profile, move |mailbox| {
+                            mailbox.push_call(missed_call)
+                        })
+                    }
                 }
-                // TODO save event into persistent storage and delegate it when profile is online again
-                None => Box::new(future::ok(())),
-            }
-        });
+            });
 
         Box::new(push_fut)
     }
@@ -125,18 +541,29 @@ impl HomeConnectionServer {
 
 impl ProfileExplorer for HomeConnectionServer {
     fn fetch(&self, id: &ProfileId) -> AsyncFallible<Profile> {
-        let profile_fut = self
-            .server
-            .public_profile_dht
-            .borrow()
-            .get_public(id)
-            .map_err(|e| e.context(ErrorKind::DhtLookupFailed).into());
+        let requester_id = self.context.peer_id();
+        let profile_dht = self.server.public_profile_dht.clone();
+        let id = id.to_owned();
+        let profile_fut =
+            self.server.ensure_not_moved(&id).map_err(failure::Error::from).and_then(move |()| {
+                let get_fut = profile_dht
+                    .borrow()
+                    .get_public(&id)
+                    .map(move |profile| profile.view_for(Some(&requester_id)))
+                    .map_err(|e| e.context(ErrorKind::DhtLookupFailed).into());
+                get_fut
+            });
         Box::new(profile_fut)
     }
 
-    fn followers(&self, _id: &ProfileId) -> AsyncFallible<Vec<Link>> {
-        unimplemented!()
-        // Ok( Vec::new() ) // TODO implement this properly
+    fn followers(&self, id: &ProfileId, offset: usize, limit: usize) -> AsyncFallible<Vec<Link>> {
+        let followers_fut = self
+            .server
+            .public_profile_explorer
+            .borrow()
+            .followers(id, offset, limit)
+            .map_err(|e| e.context(ErrorKind::DhtLookupFailed).into());
+        Box::new(followers_fut)
     }
 }
 
@@ -146,19 +573,21 @@ impl Home for HomeConnectionServer {
             return Box::new(future::err(ErrorKind::FailedToClaimProfile.into()));
         }
 
-        let claim_fut = self
-            .server
-            .host_relations_db
-            .borrow()
-            .get(profile_id)
-            .map_err(|e| e.context(ErrorKind::FailedToClaimProfile).into());
+        let host_relations_db = self.server.host_relations_db.clone();
+        let claim_fut = self.server.ensure_not_moved(&profile_id).and_then(move |()| {
+            let get_fut = host_relations_db
+                .borrow()
+                .get(profile_id)
+                .map_err(|e| e.context(ErrorKind::FailedToClaimProfile).into());
+            get_fut
+        });
         Box::new(claim_fut)
     }
 
     fn register(
         &self,
         half_proof: RelationHalfProof,
-        //_invite: Option<HomeInvitation>,
+        invite: Option<HomeInvitation>,
     ) -> Box<dyn Future<Item = RelationProof, Error = Error>> {
         if half_proof.signer_id != self.context.peer_id() {
             return Box::new(future::err(ErrorKind::SignerMismatch.into()));
@@ -197,30 +626,60 @@ impl Home for HomeConnectionServer {
 
         let profile_id = half_proof.signer_id.to_owned();
         let host_relations_store = self.server.host_relations_db.clone();
-        let reg_fut = self
-            .server
-            .host_relations_db
-            .borrow()
-            .get(profile_id.clone())
-            .then(|get_res| {
-                match get_res {
-                    Ok(_stored_proof) => {
-                        debug!("Profile was already registered");
-                        Err(ErrorKind::AlreadyRegistered.into())
+        let redirect_store = self.server.redirect_db.clone();
+        let invitation_store = self.server.invitation_db.clone();
+        let policy_fut = self.check_registration_policy(&profile_id, invite);
+        let reg_fut = optional(self.server.host_relations_db.borrow().get(profile_id.clone()))
+            .map_err(|e| e.context(ErrorKind::StorageFailed).into())
+            .and_then(|stored_proof_opt| match stored_proof_opt {
+                Some(_stored_proof) => {
+                    debug!("Profile was already registered");
+                    Err(ErrorKind::AlreadyRegistered.into())
+                }
+                None => Ok(()),
+            })
+            .and_then(|()| policy_fut)
+            .and_then({
+                let profile_id = profile_id.clone();
+                let host_relations_store = host_relations_store.clone();
+                move |voucher_opt| {
+                    // Store private profile info in local storage only (e.g. SQL)
+                    debug!("Saving private profile info into local storage");
+                    host_relations_store
+                        .borrow_mut()
+                        .set(profile_id, host_proof.clone())
+                        .map(move |()| (host_proof, voucher_opt))
+                        .map_err(|e| e.context(ErrorKind::StorageFailed).into())
+                }
+            })
+            .and_then({
+                let profile_id = profile_id.clone();
+                move |(host_proof, voucher_opt)| match voucher_opt {
+                    // NOTE the registration is rolled back if the invitation cannot be used up
+                    Some(voucher) => {
+                        let used_fut = invitation_store
+                            .borrow_mut()
+                            .set(voucher, InvitationStatus::Used(profile_id.clone()))
+                            .or_else(move |e| {
+                                warn!("Failed to use up invitation of {}, unregistering", profile_id);
+                                host_relations_store
+                                    .borrow_mut()
+                                    .clear_local(profile_id)
+                                    .then(|_res| Err(e))
+                            })
+                            .map(|()| host_proof)
+                            .map_err(|e| e.context(ErrorKind::StorageFailed).into());
+                        Box::new(used_fut) as Box<dyn Future<Item = RelationProof, Error = Error>>
                     }
-                    // TODO only errors like NotFound should be accepted here but other (e.g. I/O) errors should be delegated
-                    Err(_e) => Ok(()),
+                    None => Box::new(future::ok(host_proof)),
                 }
             })
-            // NOTE Block with "return" is needed, see https://stackoverflow.com/questions/50391668/running-asynchronous-mutable-operations-with-rust-futures
-            .and_then(move |_| {
-                // Store private profile info in local storage only (e.g. SQL)
-                debug!("Saving private profile info into local storage");
-                return host_relations_store
+            // NOTE a profile moving back here is not redirected anymore
+            .and_then(move |host_proof| {
+                return redirect_store
                     .borrow_mut()
-                    .set(profile_id, host_proof.clone())
-                    .map(|_| host_proof)
-                    .map_err(|_e| ErrorKind::StorageFailed.into());
+                    .clear_local(profile_id)
+                    .then(|_res| Ok(host_proof));
             });
 
         Box::new(reg_fut)
@@ -239,21 +698,30 @@ impl Home for HomeConnectionServer {
             Err(e) => return Box::new(future::err(e.context(ErrorKind::ProfileMismatch).into())),
         };
 
+        // NOTE registration is checked instead of a backup being present, otherwise a profile
+        //      could neither make its first backup nor restore a lost one
         let val_fut = self
             .server
-            .private_backup_db
+            .host_relations_db
             .borrow()
-            .get(&profile_id)
+            .get(profile_id.clone())
             .map({
                 let context_clone = self.context.clone();
                 let server_clone = self.server.clone();
                 let sessions_clone = self.server.sessions.clone();
-                move |_own_profile| {
-                    let session = Rc::new(HomeSessionServer::new(context_clone, server_clone));
-                    sessions_clone
+                move |_host_proof| {
+                    let session =
+                        Rc::new(HomeSessionServer::new(context_clone, server_clone.clone()));
+                    let old_session = sessions_clone
                         .borrow_mut()
-                        .entry(profile_id)
-                        .or_insert(Rc::downgrade(&session));
+                        .insert(profile_id, Rc::downgrade(&session))
+                        .and_then(|weak| weak.upgrade());
+                    // NOTE a client reconnecting might login before its previous session expired
+                    if let Some(old_session) = old_session {
+                        reactor::spawn(server_clone.retire_session(&old_session).map_err(|e| {
+                            warn!("Failed to keep calls of replaced session in mailbox: {}", e)
+                        }));
+                    }
                     session as Rc<dyn HomeSession>
                 }
             })
@@ -280,50 +748,37 @@ impl Home for HomeConnectionServer {
         }
 
         let to_profile = half_proof.peer_id.clone();
-        Self::push_event(self.server.clone(), to_profile, ProfileEvent::PairingRequest(half_proof))
-    }
-
-    fn pair_response(&self, relation: RelationProof) -> Box<dyn Future<Item = (), Error = Error>> {
-        let to_profile = match relation.peer_id(&self.context.peer_id()) {
-            Ok(profile_id) => profile_id.to_owned(),
-            Err(err) => {
-                return Box::new(future::err(err.context(ErrorKind::ProfileMismatch).into()));
-            }
-        };
-
-        debug!("Got pairing response from {} to {}", self.context.peer_id(), to_profile);
         let server_clone = self.server.clone();
         let server_clone2 = self.server.clone();
-        let peer_id_clone = self.context.peer_id().clone();
-        let peer_pubkey_clone = self.context.peer_pubkey().clone();
-        let relation_clone = relation.clone();
-
-        // We need to look up the public key to be able to validate the proof
-        let fut = self
+        let peer_id = self.context.peer_id();
+        let pair_fut = self
             .server
-            .private_backup_db
-            .borrow()
-            .get(&to_profile)
-            .map_err(|err| err.context(ErrorKind::PeerNotHostedHere).into())
-            .and_then(move |profile_data| {
-                server_clone
-                    .validator
-                    .validate_relation_proof(
-                        &relation,
-                        &peer_id_clone,
-                        &peer_pubkey_clone,
-                        &profile_data.id(),
-                        &profile_data.public_key(),
-                    )
-                    .map_err(|err| err.context(ErrorKind::InvalidRelationProof).into())
+            .ensure_not_moved(&to_profile)
+            .and_then({
+                let to_profile = to_profile.clone();
+                move |()| server_clone.ensure_not_banned(&to_profile, peer_id)
             })
-            .and_then(|_| {
+            .and_then(move |()| {
                 Self::push_event(
                     server_clone2,
                     to_profile,
-                    ProfileEvent::PairingResponse(relation_clone),
+                    ProfileEvent::PairingRequest(half_proof),
                 )
             });
+        Box::new(pair_fut)
+    }
+
+    fn pair_response(&self, relation: RelationProof) -> Box<dyn Future<Item = (), Error = Error>> {
+        debug!("Got pairing response from {}", self.context.peer_id());
+        let server_clone = self.server.clone();
+        let relation_clone = relation.clone();
+        let fut = self.validate_relation_to_hosted(relation).and_then(move |to_profile| {
+            Self::push_event(
+                server_clone,
+                to_profile,
+                ProfileEvent::PairingResponse(relation_clone),
+            )
+        });
 
         Box::new(fut)
     }
@@ -334,39 +789,20 @@ impl Home for HomeConnectionServer {
         call_req: CallRequestDetails,
     ) -> Box<dyn Future<Item = Option<AppMsgSink>, Error = Error>> {
         // TODO add error case for calling self
-        let to_profile = match call_req.relation.peer_id(&self.context.peer_id()) {
-            Ok(profile_id) => profile_id.to_owned(),
-            Err(e) => return Box::new(future::err(e.context(ErrorKind::ProfileMismatch).into())),
-        };
+        if !call_req.relation.accessible_by(&app) {
+            debug!("Refused call to app {:?} outside the scope of the relation", app);
+            return Box::new(future::err(ErrorKind::RelationScopeMismatch.into()));
+        }
 
         let server_clone = self.server.clone();
-        let server_clone2 = self.server.clone();
-        let peer_id_clone = self.context.peer_id().clone();
-        let peer_pubkey_clone = self.context.peer_pubkey().clone();
         let relation = call_req.relation.clone();
         let (send, recv) = oneshot::channel();
         let call = Box::new(Call::new(call_req, send));
 
         let answer_fut = self
-            .server
-            .private_backup_db
-            .borrow()
-            .get(&to_profile)
-            .map_err(|e| e.context(ErrorKind::PeerNotHostedHere).into())
-            .and_then(move |profile_data| {
-                server_clone
-                    .validator
-                    .validate_relation_proof(
-                        &relation,
-                        &peer_id_clone,
-                        &peer_pubkey_clone,
-                        &profile_data.id(),
-                        &profile_data.public_key(),
-                    )
-                    .map_err(|err| err.context(ErrorKind::InvalidRelationProof).into())
-            })
-            .and_then(|_| {
-                Self::push_call(server_clone2, to_profile, app, call)
+            .validate_relation_to_hosted(relation)
+            .and_then(move |to_profile| {
+                Self::push_call(server_clone, to_profile, app, call)
                     .map_err(|err| err.context(ErrorKind::CallFailed).into())
             })
             .and_then(move |_void| {
@@ -383,6 +819,148 @@ impl Home for HomeConnectionServer {
             });
         Box::new(answer_fut)
     }
+
+    fn send_message(
+        &self,
+        relation: RelationProof,
+        app: ApplicationId,
+        message: AppMessageFrame,
+    ) -> Box<dyn Future<Item = (), Error = Error>> {
+        if !relation.accessible_by(&app) {
+            debug!("Refused message to app {:?} outside the scope of the relation", app);
+            return Box::new(future::err(ErrorKind::RelationScopeMismatch.into()));
+        }
+
+        let server_clone = self.server.clone();
+        let relation_clone = relation.clone();
+        let send_fut = self.validate_relation_to_hosted(relation).and_then(move |to_profile| {
+            debug!("Storing message for app {:?} of profile {}", app, to_profile);
+            server_clone
+                .update_mailbox(to_profile, move |mailbox| {
+                    mailbox.push_message(app, relation_clone, message);
+                })
+                .map_err(|err| err.context(ErrorKind::SendMessageFailed).into())
+        });
+        Box::new(send_fut)
+    }
+
+    fn presence(&self, relation: RelationProof) -> Box<dyn Future<Item = Presence, Error = Error>> {
+        let server = self.server.clone();
+        let presence_fut = self
+            .validate_relation_to_hosted(relation)
+            .map(move |profile_id| server.presence_of(&profile_id))
+            .map_err(|err| err.context(ErrorKind::FailedToGetPresence).into());
+        Box::new(presence_fut)
+    }
+
+    fn subscribe_presence(&self, relation: RelationProof) -> AsyncStream<Presence, String> {
+        let (sender, receiver) = mpsc::channel(CHANNEL_CAPACITY);
+
+        let server = self.server.clone();
+        let subscriber_id = self.context.peer_id();
+        let subscribe_fut = self.validate_relation_to_hosted(relation).then(move |res| {
+            let first_item = match res {
+                Ok(profile_id) => {
+                    let presence = server.presence_of(&profile_id);
+                    server
+                        .presence_subscribers
+                        .borrow_mut()
+                        .entry(profile_id)
+                        .or_insert_with(Vec::new)
+                        .push((subscriber_id, sender.clone()));
+                    Ok(presence)
+                }
+                Err(e) => Err(format!("Failed to subscribe to presence: {}", e)),
+            };
+            sender.send(first_item).map(|_sender| ()).map_err(|_e| ())
+        });
+        reactor::spawn(subscribe_fut);
+
+        receiver
+    }
+
+    fn revoke_relation(
+        &self,
+        revocation: RelationRevocation,
+    ) -> Box<dyn Future<Item = (), Error = Error>> {
+        if revocation.revoker_id != self.context.peer_id() {
+            return Box::new(future::err(ErrorKind::SignerMismatch.into()));
+        }
+        if let Err(e) = self.server.validator.validate_relation_revocation(&revocation) {
+            return Box::new(future::err(e.context(ErrorKind::RelationRevocationFailed).into()));
+        }
+        let revoker_id = revocation.revoker_id.to_owned();
+        let peer_id = match revocation.peer_id() {
+            Ok(peer_id) => peer_id.to_owned(),
+            Err(e) => return Box::new(future::err(e.context(ErrorKind::ProfileMismatch).into())),
+        };
+        debug!("Profile {} revokes its relation with {}", revoker_id, peer_id);
+        self.server.unsubscribe_presence(&revoker_id, &peer_id);
+        self.server.unsubscribe_presence(&peer_id, &revoker_id);
+
+        // NOTE both profiles might be hosted here, the revocation is kept for each of them
+        let hosted_fut = |profile_id: &ProfileId| {
+            self.server
+                .host_relations_db
+                .borrow()
+                .get(profile_id.to_owned())
+                .then(|res| Ok::<_, Error>(res.is_ok()))
+        };
+        let server = self.server.clone();
+        let revoke_fut = hosted_fut(&revoker_id).join(hosted_fut(&peer_id)).and_then(
+            move |(revoker_hosted, peer_hosted)| {
+                if !revoker_hosted && !peer_hosted {
+                    return Box::new(future::err(ErrorKind::PeerNotHostedHere.into()))
+                        as Box<dyn Future<Item = (), Error = Error>>;
+                }
+
+                let revoker_fut = if revoker_hosted {
+                    server.add_revocation(revoker_id, revocation.clone())
+                } else {
+                    Box::new(future::ok(()))
+                };
+                if !peer_hosted {
+                    return revoker_fut;
+                }
+
+                let relation = revocation.relation.clone();
+                let peer_fut = revoker_fut
+                    .and_then({
+                        let server = server.clone();
+                        let peer_id = peer_id.clone();
+                        move |()| {
+                            server.add_revocation(peer_id, revocation.clone()).map(|()| revocation)
+                        }
+                    })
+                    .and_then({
+                        let server = server.clone();
+                        let peer_id = peer_id.clone();
+                        move |revocation| {
+                            server
+                                .update_mailbox(peer_id, move |mailbox| {
+                                    mailbox.remove_relation(&relation)
+                                })
+                                .map(|()| revocation)
+                        }
+                    })
+                    .and_then(move |revocation| {
+                        Self::push_event(server, peer_id, ProfileEvent::RelationRevoked(revocation))
+                    });
+                Box::new(peer_fut)
+            },
+        );
+        Box::new(revoke_fut)
+    }
+
+    fn redirect(
+        &self,
+        profile_id: &ProfileId,
+    ) -> Box<dyn Future<Item = HomeRedirect, Error = Error>> {
+        let redirect_fut = self.server.load_redirect(profile_id).and_then(|redirect_opt| {
+            redirect_opt.ok_or_else(|| ErrorKind::FailedToGetRedirect.into())
+        });
+        Box::new(redirect_fut)
+    }
 }
 
 struct Call {
@@ -412,7 +990,7 @@ impl IncomingCall for Call {
 }
 
 enum ServerSink<T, E> {
-    // TODO message buffer should be persistent on the long run
+    // NOTE only live calls are buffered here, Mailbox keeps what must survive the session
     Buffer(Vec<Result<T, E>>), // Temporary buffer, sink is not initialized
     Sender(AsyncSink<T, E>), // Initialized sink end of channel, user is listening on the other half
 }
@@ -422,8 +1000,10 @@ pub struct HomeSessionServer {
     //      drop all related sessions automatically
     context: Rc<PeerContext>,
     server: Rc<HomeServer>,
-    events: RefCell<ServerSink<ProfileEvent, String>>,
+    events: RefCell<Option<AsyncSink<ProfileEvent, String>>>, // None until the client listens
     apps: RefCell<HashMap<ApplicationId, ServerSink<Box<dyn IncomingCall>, String>>>, // {appId->sender<call>}
+    last_seen: Cell<Instant>, // time of login or the last heartbeat
+    expired: Cell<bool>,
 }
 
 impl HomeSessionServer {
@@ -432,20 +1012,89 @@ impl HomeSessionServer {
         Self {
             context,
             server,
-            events: RefCell::new(ServerSink::Buffer(Vec::new())),
+            events: RefCell::new(None),
             apps: RefCell::new(HashMap::new()),
+            last_seen: Cell::new(Instant::now()),
+            expired: Cell::new(false),
+        }
+    }
+
+    /// Closes the event and call channels, returning the calls not yet delivered to the client
+    fn expire(&self) -> Vec<MissedCall> {
+        self.expired.set(true);
+        self.events.replace(None);
+        let apps = self.apps.replace(HashMap::new());
+        let mut missed_calls = Vec::new();
+        for (app, sink) in apps {
+            if let ServerSink::Buffer(calls) = sink {
+                missed_calls.extend(
+                    calls
+                        .into_iter()
+                        .filter_map(|call| call.ok())
+                        .map(|call| MissedCall::new(app.clone(), call.request_details())),
+                );
+            }
         }
+        missed_calls
+    }
+
+    /// Creates a redirect to the new home of the connected profile, `new_home` must be
+    /// its signed public profile hosted on a home other than this one
+    fn new_home_redirect(&self, new_home: &Profile) -> Result<HomeRedirect, Error> {
+        let profile_id = self.context.peer_id();
+        if new_home.id() != profile_id {
+            return Err(ErrorKind::ProfileMismatch.into());
+        }
+        if let Err(e) = new_home.validate() {
+            debug!("Rejected new home profile of {}: {}", profile_id, e);
+            return Err(ErrorKind::InvalidSignature.into());
+        }
+
+        let my_id = self.context.my_signer().profile_id().to_owned();
+        let host_proofs = new_home.to_hosted().map(|hosted| hosted.homes).unwrap_or_default();
+        let host_proof = host_proofs
+            .iter()
+            .find(|proof| proof.peer_id(&profile_id).map_or(false, |home_id| *home_id != my_id))
+            .ok_or_else(|| Error::from(ErrorKind::HomeIdMismatch))?;
+        let (new_home_id, new_home_pubkey) = if host_proof.a_id == profile_id {
+            (&host_proof.b_id, &host_proof.b_pub_key)
+        } else {
+            (&host_proof.a_id, &host_proof.a_pub_key)
+        };
+        self.server
+            .validator
+            .validate_relation_proof(
+                host_proof,
+                &profile_id,
+                &self.context.peer_pubkey(),
+                new_home_id,
+                new_home_pubkey,
+            )
+            .map_err(|e| e.context(ErrorKind::InvalidRelationProof))?;
+
+        let valid_until = SystemTime::now() + self.server.redirect_period;
+        HomeRedirect::new(&profile_id, new_home_id, valid_until, self.context.my_signer())
+            .map_err(|e| e.context(ErrorKind::UnregisterFailed).into())
+    }
+
+    // NOTE events of an expired session were already moved to the mailbox, it must log in again
+    fn ensure_active(&self) -> Result<(), Error> {
+        if self.expired.get() {
+            return Err(ErrorKind::SessionExpired.into());
+        }
+        Ok(())
     }
 
     fn push_event(&self, event: ProfileEvent) -> Box<dyn Future<Item = (), Error = Error>> {
         debug!("Session with {} got event dispatched: {:?}", self.context.peer_id(), event);
-        match *self.events.borrow_mut() {
-            ServerSink::Buffer(ref mut bufvec) => {
-                debug!("No event channel is available on the client side, buffering event");
-                bufvec.push(Ok(event)); // TODO consider size constraints
+        match *self.events.borrow() {
+            None => {
+                debug!(
+                    "No event channel is available on the client side, leaving event in mailbox"
+                );
                 Box::new(future::ok(()))
             }
-            ServerSink::Sender(ref mut sender) => {
+            Some(ref sender) => {
                 debug!("Event channel for active client was found, sending event there");
                 let fut = sender.clone()
                     .send(Ok(event))
@@ -471,7 +1120,11 @@ impl HomeSessionServer {
         let sink = apps.entry(app).or_insert(ServerSink::Buffer(Vec::new()));
         match *sink {
             ServerSink::Buffer(ref mut bufvec) => {
-                bufvec.push(Ok(call)); // TODO consider size constraints
+                if bufvec.len() >= self.server.mailbox_limits.max_items {
+                    warn!("Too many calls are waiting for app checkin, dropping the oldest one");
+                    bufvec.remove(0);
+                }
+                bufvec.push(Ok(call));
                 Box::new(future::ok(()))
             }
             ServerSink::Sender(ref mut sender) => Box::new(
@@ -490,12 +1143,33 @@ impl Drop for HomeSessionServer {
     fn drop(&mut self) {
         let peer_id = self.context.peer_id();
         debug!("dropping session {}", peer_id);
-        self.server.sessions.borrow_mut().remove(&peer_id);
+        // NOTE the profile might have logged in again meanwhile, that session must be kept
+        let is_current = {
+            let mut sessions = self.server.sessions.borrow_mut();
+            let is_current = sessions.get(&peer_id).map_or(false, |weak| weak.upgrade().is_none());
+            if is_current {
+                sessions.remove(&peer_id);
+            }
+            is_current
+        };
+        if is_current {
+            self.server.clear_presence(&peer_id);
+        }
     }
 }
 
+// A stream that fails right away, the channel is closed after the error
+fn failed_stream<T: 'static>(err: Error) -> AsyncStream<T, String> {
+    let (sender, receiver) = mpsc::channel(CHANNEL_CAPACITY);
+    reactor::spawn(sender.send(Err(err.to_string())).map(|_sender| ()).map_err(|_e| ()));
+    receiver
+}
+
 impl HomeSession for HomeSessionServer {
     fn backup(&self, own_prof: OwnProfile) -> Box<dyn Future<Item = (), Error = Error>> {
+        if let Err(e) = self.ensure_active() {
+            return Box::new(future::err(e));
+        }
         if own_prof.id() != self.context.peer_id() {
             return Box::new(future::err(ErrorKind::ProfileMismatch.into()));
         }
@@ -504,22 +1178,25 @@ impl HomeSession for HomeSessionServer {
             return Box::new(future::err(ErrorKind::PublicKeyMismatch.into()));
         }
 
+        // NOTE the public part is spread to untrusted storage nodes, it must be signed by the profile
+        if let Err(e) = own_prof.public_data().validate() {
+            debug!("Rejected backup of profile {}: {}", own_prof.id(), e);
+            return Box::new(future::err(ErrorKind::InvalidSignature.into()));
+        }
+        // NOTE the home must never learn private parts of the profile
+        if !own_prof.is_sealed() {
+            debug!("Rejected backup of profile {} with private parts in plaintext", own_prof.id());
+            return Box::new(future::err(ErrorKind::BackupNotSealed.into()));
+        }
+
+        // Update public profile parts in distributed storage (e.g. DHT).
+        // NOTE there might be no previous backup yet, e.g. right after registration
         let upd_fut = self
             .server
-            .private_backup_db
-            .borrow()
-            .get(&own_prof.id())
+            .public_profile_dht
+            .borrow_mut()
+            .set_public(own_prof.public_data())
             // NOTE Block with "return" is needed, see https://stackoverflow.com/questions/50391668/running-asynchronous-mutable-operations-with-rust-futures
-            .and_then({
-                let distributed_store = self.server.public_profile_dht.clone();
-                let pub_prof = own_prof.public_data();
-                move |_own_prof_orig| {
-                    // Update public profile parts in distributed storage (e.g. DHT)
-                    return distributed_store
-                        .borrow_mut()
-                        .set_public(pub_prof);
-                }
-            })
             .and_then({
                 let local_store = self.server.private_backup_db.clone();
                 move |_| {
@@ -536,39 +1213,89 @@ impl HomeSession for HomeSessionServer {
     }
 
     fn restore(&self) -> Box<dyn Future<Item = OwnProfile, Error = Error>> {
-        unimplemented!()
+        if let Err(e) = self.ensure_active() {
+            return Box::new(future::err(e));
+        }
+        let restore_fut = self
+            .server
+            .private_backup_db
+            .borrow()
+            .get(&self.context.peer_id())
+            .map_err(|e| e.context(ErrorKind::FailedToLoadProfile).into());
+        Box::new(restore_fut)
     }
 
-    // TODO is the ID of the new home enough here or do we need the whole profile?
-    // TODO newhome should be stored and some special redirect to new home should be sent when someone looking for the profile
-    fn unregister(&self, _newhome: Option<Profile>) -> Box<dyn Future<Item = (), Error = Error>> {
+    fn unregister(&self, new_home: Option<Profile>) -> Box<dyn Future<Item = (), Error = Error>> {
+        if let Err(e) = self.ensure_active() {
+            return Box::new(future::err(e));
+        }
         let profile_id = self.context.peer_id().to_owned();
         let profile_key = self.context.peer_pubkey();
 
+        let redirect_opt = match new_home {
+            None => None,
+            Some(ref new_home) => match self.new_home_redirect(new_home) {
+                Ok(redirect) => Some(redirect),
+                Err(e) => return Box::new(future::err(e)),
+            },
+        };
+
         // TODO is it the caller's responsibility to remove this home from the persona facet's homelist
         //      or should we do it here and save the results into the distributed public db?
-        // TODO how to delete profile from self.server.hosted_profiles_db? We'll probably need a remove operation
 
         // Drop session reference from server
         self.server.sessions.borrow_mut().remove(&profile_id);
+        self.server.clear_presence(&profile_id);
 
         // TODO force close/drop session connection after successful unregister().
         //      Ideally self would be consumed here, but that'd require binding to self: Box<Self> or Rc<Self> to compile within a trait.
 
+        // NOTE the public profile stays available when moving, it is maintained by the new home
+        let public_fut = match redirect_opt {
+            Some(_) => Box::new(future::ok(())) as AsyncFallible<()>,
+            None => self.server.public_profile_dht.borrow_mut().clear_public_local(&profile_key),
+        };
+        // NOTE previous versions are not retained for a profile that left, even when moving
+        let public_history_fut = {
+            let public_dht = self.server.public_profile_dht.clone();
+            let profile_id = profile_id.clone();
+            future::lazy(move || public_dht.borrow_mut().clear_history_local(&profile_id))
+        };
+        let redirect_fut = match redirect_opt {
+            Some(redirect) => {
+                debug!("Profile {} moved to home {}", profile_id, redirect.new_home_id);
+                self.server.redirect_db.borrow_mut().set(profile_id.clone(), redirect)
+            }
+            None => Box::new(future::ok(())),
+        };
         let local_fut = self.server.private_backup_db.borrow_mut().clear(&profile_key);
-        let unreg_fut = self
-            .server
-            .public_profile_dht
-            .borrow_mut()
-            .clear_public_local(&profile_key)
+        let local_history_fut = {
+            let private_db = self.server.private_backup_db.clone();
+            let profile_id = profile_id.clone();
+            future::lazy(move || private_db.borrow_mut().clear_history(&profile_id))
+        };
+        let host_fut = self.server.host_relations_db.borrow_mut().clear_local(profile_id.clone());
+        let mailbox_fut = self.server.mailbox_db.borrow_mut().clear_local(profile_id.clone());
+        let ban_list_fut = self.server.ban_list_db.borrow_mut().clear_local(profile_id.clone());
+        let revocations_fut = self.server.revocation_db.borrow_mut().clear_local(profile_id);
+        let unreg_fut = redirect_fut
+            .and_then(|_| public_fut)
+            .and_then(|_| public_history_fut)
             .and_then(|_| local_fut)
+            .and_then(|_| local_history_fut)
+            .and_then(|_| host_fut)
+            .and_then(|_| optional(mailbox_fut))
+            .and_then(|_| optional(ban_list_fut))
+            .and_then(|_| optional(revocations_fut))
             .map_err(|e| e.context(ErrorKind::UnregisterFailed).into());
 
         Box::new(unreg_fut)
     }
 
-    // TODO add argument in a later milestone, presence: Option<AppMessageFrame>) ->
     fn checkin_app(&self, app: &ApplicationId) -> AsyncStream<Box<dyn IncomingCall>, String> {
+        if let Err(e) = self.ensure_active() {
+            return failed_stream(e);
+        }
         let (sender, receiver) = mpsc::channel(CHANNEL_CAPACITY);
 
         match self.apps.borrow_mut().insert(app.to_owned(), ServerSink::Sender(sender.clone())) {
@@ -582,50 +1309,219 @@ impl HomeSession for HomeSessionServer {
             }
             Some(ServerSink::Buffer(call_vec)) => {
                 // Send all collected calls from buffer as we now finally have a channel to the app
-                // TODO use persistent storage for calls when profile is offline and delegate them here
                 reactor::spawn(
-                    sender.send_all(stream::iter_ok(call_vec)).map(|_sender| ()).map_err(|_e| ()),
+                    sender
+                        .clone()
+                        .send_all(stream::iter_ok(call_vec))
+                        .map(|_sender| ())
+                        .map_err(|_e| ()),
                 )
             }
             None => {}
         }
 
+        // Notify the app about calls missed while the profile was offline
+        let app = app.to_owned();
+        let peer_id = self.context.peer_id();
+        let server = self.server.clone();
+        let missed_fut = self
+            .server
+            .load_mailbox(&peer_id)
+            .map_err(|e| warn!("Failed to load mailbox of profile: {}", e))
+            .and_then(move |mailbox| {
+                let missed_calls = mailbox.missed_calls(&app);
+                let incoming_calls = missed_calls.iter().map(|missed| {
+                    let request = CallRequestDetails {
+                        relation: missed.relation.clone(),
+                        init_payload: missed.init_payload.clone(),
+                        to_caller: None,
+                    };
+                    // NOTE the caller is not waiting anymore, answering the call has no effect
+                    let (send, _recv) = oneshot::channel();
+                    Ok(Box::new(Call::new(request, send)) as Box<dyn IncomingCall>)
+                });
+                sender
+                    .send_all(stream::iter_ok(incoming_calls.collect::<Vec<_>>()))
+                    .map_err(|_e| ())
+                    .and_then(move |_sender| {
+                        server
+                            .update_mailbox(peer_id, move |mailbox| {
+                                mailbox.remove_calls(&missed_calls)
+                            })
+                            .map_err(|e| {
+                                warn!("Failed to remove delivered calls from mailbox: {}", e)
+                            })
+                    })
+            });
+        reactor::spawn(missed_fut);
+
         // TODO how to detect dropped stream and remove the sink from the session?
         receiver
     }
 
+    fn messages(
+        &self,
+        app: &ApplicationId,
+    ) -> Box<dyn Future<Item = Vec<AppMessage>, Error = Error>> {
+        if let Err(e) = self.ensure_active() {
+            return Box::new(future::err(e));
+        }
+        let app = app.to_owned();
+        let messages_fut = self
+            .server
+            .load_mailbox(&self.context.peer_id())
+            .map(move |mailbox| mailbox.messages(&app));
+        Box::new(messages_fut)
+    }
+
+    fn ack_messages(
+        &self,
+        app: &ApplicationId,
+        ids: &[u64],
+    ) -> Box<dyn Future<Item = (), Error = Error>> {
+        if let Err(e) = self.ensure_active() {
+            return Box::new(future::err(e));
+        }
+        let (app, ids) = (app.to_owned(), ids.to_owned());
+        self.server
+            .update_mailbox(self.context.peer_id(), move |mailbox| mailbox.ack_messages(&app, &ids))
+    }
+
+    fn set_presence(&self, presence: Presence) -> Box<dyn Future<Item = (), Error = Error>> {
+        if let Err(e) = self.ensure_active() {
+            return Box::new(future::err(e));
+        }
+        self.server.publish_presence(self.context.peer_id(), presence);
+        Box::new(future::ok(()))
+    }
+
     // TODO investigate if race condition is possible, e.g. an event was sent out to the old_sender,
     //      and a repeated events() call is received. In this case, can we be sure that the event
     //      has been processed via the old_sender?
     fn events(&self) -> AsyncStream<ProfileEvent, String> {
+        if let Err(e) = self.ensure_active() {
+            return failed_stream(e);
+        }
         let (sender, receiver) = mpsc::channel(CHANNEL_CAPACITY);
 
         // Set up events with the new channel and check the old event sink
-        match self.events.replace(ServerSink::Sender(sender.clone())) {
-            // We already had another channel properly set up
-            ServerSink::Sender(old_sender) => {
-                // NOTE consuming the events stream multiple times is likely a client implementation error
-                reactor::spawn(
-                    old_sender.send( Err( "WARNING: Repeated call of HomeSession::events() detected, this channel is dropped, using the new one".to_owned() ) )
-                        .map( |_sender| () )
-                        .map_err( |_e| () )
-                )
-            }
-            // The client was not listening to events so far, the channel is brand new
-            ServerSink::Buffer(msg_vec) => {
-                // Send all collected messages from buffer as we now finally have a channel to the user
-                // TODO use persistent storage for events when profile is offline and delegate them here
-                reactor::spawn(
-                    sender.send_all(stream::iter_ok(msg_vec)).map(|_sender| ()).map_err(|_e| ()),
-                )
-            }
+        // We already had another channel properly set up
+        if let Some(old_sender) = self.events.replace(Some(sender.clone())) {
+            // NOTE consuming the events stream multiple times is likely a client implementation error
+            reactor::spawn(
+                old_sender.send( Err( "WARNING: Repeated call of HomeSession::events() detected, this channel is dropped, using the new one".to_owned() ) )
+                    .map( |_sender| () )
+                    .map_err( |_e| () )
+            )
         }
 
+        // Send all unacknowledged events from the mailbox as we now have a channel to the user.
+        // NOTE an event arriving meanwhile might be delivered twice, acknowledging it once is enough
+        let mailbox_fut = self
+            .server
+            .load_mailbox(&self.context.peer_id())
+            .map_err(|e| warn!("Failed to load mailbox of profile: {}", e))
+            .and_then(move |mailbox| {
+                let events = mailbox.events().into_iter().map(Ok).collect::<Vec<_>>();
+                sender.send_all(stream::iter_ok(events)).map(|_sender| ()).map_err(|_e| ())
+            });
+        reactor::spawn(mailbox_fut);
+
         receiver
     }
 
+    fn ack_event(&self, event: &ProfileEvent) -> Box<dyn Future<Item = (), Error = Error>> {
+        if let Err(e) = self.ensure_active() {
+            return Box::new(future::err(e));
+        }
+        let event = event.to_owned();
+        self.server.update_mailbox(self.context.peer_id(), move |mailbox| {
+            if !mailbox.ack_event(&event) {
+                debug!("Acknowledged event was not found in mailbox: {:?}", event);
+            }
+        })
+    }
+
+    fn banned_profiles(&self) -> Box<dyn Future<Item = Vec<ProfileId>, Error = Error>> {
+        if let Err(e) = self.ensure_active() {
+            return Box::new(future::err(e));
+        }
+        let banned_fut = self
+            .server
+            .load_ban_list(&self.context.peer_id())
+            .map(|banned| banned.into_iter().collect());
+        Box::new(banned_fut)
+    }
+
+    fn ban(&self, profile: &ProfileId) -> Box<dyn Future<Item = (), Error = Error>> {
+        if let Err(e) = self.ensure_active() {
+            return Box::new(future::err(e));
+        }
+        let profile_id = self.context.peer_id();
+        debug!("Profile {} bans {}", profile_id, profile);
+        self.server.unsubscribe_presence(&profile_id, profile);
+
+        // Drop what the banned profile left in the mailbox before it could reach the user
+        let banned = profile.to_owned();
+        let server = self.server.clone();
+        let ban_fut = self
+            .server
+            .update_ban_list(profile_id.clone(), {
+                let banned = banned.clone();
+                move |ban_list| {
+                    ban_list.insert(banned);
+                }
+            })
+            .and_then(move |()| {
+                server.update_mailbox(profile_id, move |mailbox| mailbox.remove_from(&banned))
+            });
+        Box::new(ban_fut)
+    }
+
+    fn unban(&self, profile: &ProfileId) -> Box<dyn Future<Item = (), Error = Error>> {
+        if let Err(e) = self.ensure_active() {
+            return Box::new(future::err(e));
+        }
+        let unbanned = profile.to_owned();
+        self.server.update_ban_list(self.context.peer_id(), move |ban_list| {
+            ban_list.remove(&unbanned);
+        })
+    }
+
+    fn export_mailbox(&self) -> Box<dyn Future<Item = MailboxContent, Error = Error>> {
+        if let Err(e) = self.ensure_active() {
+            return Box::new(future::err(e));
+        }
+        let export_fut =
+            self.server.load_mailbox(&self.context.peer_id()).map(|mailbox| mailbox.export());
+        Box::new(export_fut)
+    }
+
+    fn import_mailbox(&self, content: MailboxContent) -> Box<dyn Future<Item = (), Error = Error>> {
+        if let Err(e) = self.ensure_active() {
+            return Box::new(future::err(e));
+        }
+        debug!("Importing mailbox of profile {} from its previous home", self.context.peer_id());
+        let import_fut = self
+            .server
+            .update_mailbox(self.context.peer_id(), move |mailbox| mailbox.import(content))
+            .map_err(|e| e.context(ErrorKind::MailboxTransferFailed).into());
+        Box::new(import_fut)
+    }
+
+    fn heartbeat(&self) -> Box<dyn Future<Item = (), Error = Error>> {
+        if let Err(e) = self.ensure_active() {
+            return Box::new(future::err(e));
+        }
+        self.last_seen.set(Instant::now());
+        Box::new(future::ok(()))
+    }
+
     // TODO consider removing this after testing
     fn ping(&self, txt: &str) -> Box<dyn Future<Item = String, Error = Error>> {
+        if let Err(e) = self.ensure_active() {
+            return Box::new(future::err(e));
+        }
         debug!("Ping received `{}`, sending it back", txt);
         Box::new(future::ok(txt.to_owned()))
     }
@@ -0,0 +1,66 @@
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::Future;
+
+use claims::model::AttributeVisibility;
+use did::vault::{HdProfileVault, ProfileVault};
+use mercury_home_node::mailbox::MailboxLimits;
+use mercury_home_node::registration::RegistrationPolicy;
+use mercury_home_node::server::{HomeConnectionServer, HomeServer, HomeStores};
+use mercury_home_protocol::crypto::CompositeValidator;
+use mercury_home_protocol::*;
+
+#[test]
+fn test_backup_and_restore() {
+    let phrase = keyvault::Seed::generate_bip39();
+    let mut vault = HdProfileVault::create(keyvault::Seed::from_bip39(&phrase).unwrap());
+    let home_key = vault.create_key(None).unwrap();
+    let my_key = vault.create_key(None).unwrap();
+    let vault = Arc::new(vault);
+    let home_signer = vault.clone().signer(&home_key.key_id()).unwrap();
+    let my_signer = vault.clone().signer(&my_key.key_id()).unwrap();
+
+    let server = Rc::new(HomeServer::new(
+        Rc::new(CompositeValidator::default()),
+        HomeStores::in_memory(),
+        MailboxLimits::default(),
+        RegistrationPolicy::Open,
+        Duration::from_secs(60),
+    ));
+    let connect = || {
+        let context = Rc::new(PeerContext::new(home_signer.clone(), my_key.clone()));
+        HomeConnectionServer::new(context, server.clone()).unwrap()
+    };
+
+    let home = connect();
+    let half_proof = RelationHalfProof::new(
+        RelationProof::RELATION_TYPE_HOSTED_ON_HOME,
+        &home_key.key_id(),
+        my_signer.as_ref(),
+    )
+    .unwrap();
+    let home_proof = home.register(half_proof, None).wait().unwrap();
+
+    let mut profile = OwnProfile::empty(&my_key);
+    profile.mut_public_data().add_hosted_on(&home_proof).unwrap();
+    profile.set_attribute("name".to_owned(), "Me".into(), AttributeVisibility::Public);
+    profile.set_attribute("phone".to_owned(), "+36123".into(), AttributeVisibility::Private);
+    profile.mut_public_data().sign(my_signer.as_ref()).unwrap();
+    let backup_key = vault.backup_key(&my_key.key_id()).unwrap();
+
+    let session = home.login(&home_proof).wait().unwrap();
+    let plaintext_res = session.backup(profile.clone()).wait();
+    assert_eq!(plaintext_res.unwrap_err().kind(), ErrorKind::BackupNotSealed);
+    session.backup(profile.seal_private_parts(&backup_key).unwrap()).wait().unwrap();
+    drop(session);
+    drop(home);
+
+    // Local state is lost, only the vault seed and the home are known
+    let session = connect().login(&home_proof).wait().unwrap();
+    let backup = session.restore().wait().unwrap();
+    assert!(backup.is_sealed());
+    assert_eq!(backup.public_data(), profile.public_data());
+    assert_eq!(backup.open_private_parts(&backup_key).unwrap(), profile);
+}
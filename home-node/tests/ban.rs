@@ -0,0 +1,80 @@
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::Future;
+
+use did::vault::{HdProfileVault, ProfileVault};
+use mercury_home_node::mailbox::MailboxLimits;
+use mercury_home_node::registration::RegistrationPolicy;
+use mercury_home_node::server::{HomeConnectionServer, HomeServer, HomeStores};
+use mercury_home_protocol::crypto::CompositeValidator;
+use mercury_home_protocol::error::ErrorKind;
+use mercury_home_protocol::*;
+
+#[test]
+fn test_banned_profile_refused() {
+    let phrase = keyvault::Seed::generate_bip39();
+    let mut vault = HdProfileVault::create(keyvault::Seed::from_bip39(&phrase).unwrap());
+    let home_key = vault.create_key(None).unwrap();
+    let my_key = vault.create_key(None).unwrap();
+    let contact_key = vault.create_key(None).unwrap();
+    let vault = Arc::new(vault);
+    let home_signer = vault.clone().signer(&home_key.key_id()).unwrap();
+    let my_signer = vault.clone().signer(&my_key.key_id()).unwrap();
+    let contact_signer = vault.clone().signer(&contact_key.key_id()).unwrap();
+
+    let server = Rc::new(HomeServer::new(
+        Rc::new(CompositeValidator::default()),
+        HomeStores::in_memory(),
+        MailboxLimits::default(),
+        RegistrationPolicy::Open,
+        Duration::from_secs(60),
+    ));
+    let connect = |peer_key: &PublicKey| {
+        let context = Rc::new(PeerContext::new(home_signer.clone(), peer_key.clone()));
+        HomeConnectionServer::new(context, server.clone()).unwrap()
+    };
+
+    let home = connect(&my_key);
+    let half_proof = RelationHalfProof::new(
+        RelationProof::RELATION_TYPE_HOSTED_ON_HOME,
+        &home_key.key_id(),
+        my_signer.as_ref(),
+    )
+    .unwrap();
+    let home_proof = home.register(half_proof, None).wait().unwrap();
+    let mut profile = OwnProfile::empty(&my_key);
+    profile.mut_public_data().add_hosted_on(&home_proof).unwrap();
+    profile.mut_public_data().sign(my_signer.as_ref()).unwrap();
+    let session = home.login(&home_proof).wait().unwrap();
+    let backup_key = vault.backup_key(&my_key.key_id()).unwrap();
+    session.backup(profile.seal_private_parts(&backup_key).unwrap()).wait().unwrap();
+
+    let chat = ApplicationId::from("chat");
+    let contact_half_proof =
+        RelationHalfProof::new("friend", &my_key.key_id(), contact_signer.as_ref()).unwrap();
+    let relation =
+        RelationProof::sign_remaining_half(&contact_half_proof, my_signer.as_ref()).unwrap();
+    let contact_home = connect(&contact_key);
+    contact_home
+        .send_message(relation.clone(), chat.clone(), AppMessageFrame(vec![1]))
+        .wait()
+        .unwrap();
+
+    session.ban(&contact_key.key_id()).wait().unwrap();
+    assert_eq!(session.banned_profiles().wait().unwrap(), vec![contact_key.key_id()]);
+    assert!(session.messages(&chat).wait().unwrap().is_empty());
+
+    let send_res =
+        contact_home.send_message(relation.clone(), chat.clone(), AppMessageFrame(vec![2]));
+    assert_eq!(send_res.wait().unwrap_err().kind(), ErrorKind::ProfileBanned);
+    let pair_res = contact_home.pair_request(contact_half_proof.clone());
+    assert_eq!(pair_res.wait().unwrap_err().kind(), ErrorKind::ProfileBanned);
+    assert!(contact_home.presence(relation.clone()).wait().is_err());
+
+    session.unban(&contact_key.key_id()).wait().unwrap();
+    assert!(session.banned_profiles().wait().unwrap().is_empty());
+    contact_home.send_message(relation, chat.clone(), AppMessageFrame(vec![3])).wait().unwrap();
+    assert_eq!(session.messages(&chat).wait().unwrap()[0].payload, AppMessageFrame(vec![3]));
+}
@@ -0,0 +1,75 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::Future;
+
+use did::vault::{HdProfileVault, ProfileVault};
+use mercury_home_node::mailbox::MailboxLimits;
+use mercury_home_node::registration::{InvitationStatus, RegistrationPolicy};
+use mercury_home_node::server::{HomeConnectionServer, HomeServer, HomeStores};
+use mercury_home_protocol::crypto::CompositeValidator;
+use mercury_home_protocol::error::ErrorKind;
+use mercury_home_protocol::*;
+use mercury_storage::asynch::imp::InMemoryStore;
+use mercury_storage::asynch::KeyValueStore;
+
+#[test]
+fn test_invite_only_registration() {
+    let phrase = keyvault::Seed::generate_bip39();
+    let mut vault = HdProfileVault::create(keyvault::Seed::from_bip39(&phrase).unwrap());
+    let home_key = vault.create_key(None).unwrap();
+    let first_key = vault.create_key(None).unwrap();
+    let second_key = vault.create_key(None).unwrap();
+    let vault = Arc::new(vault);
+    let home_signer = vault.clone().signer(&home_key.key_id()).unwrap();
+    let first_signer = vault.clone().signer(&first_key.key_id()).unwrap();
+    let second_signer = vault.clone().signer(&second_key.key_id()).unwrap();
+
+    let mut invitations = InMemoryStore::new();
+    invitations.set("welcome".to_owned(), InvitationStatus::Issued).wait().unwrap();
+    invitations.set("withdrawn".to_owned(), InvitationStatus::Revoked).wait().unwrap();
+    let invitation_db = Rc::new(RefCell::new(invitations));
+
+    let server = Rc::new(HomeServer::new(
+        Rc::new(CompositeValidator::default()),
+        HomeStores { invitation_db: invitation_db.clone(), ..HomeStores::in_memory() },
+        MailboxLimits::default(),
+        RegistrationPolicy::InviteOnly,
+        Duration::from_secs(60),
+    ));
+    let connect = |peer_key: &PublicKey| {
+        let context = Rc::new(PeerContext::new(home_signer.clone(), peer_key.clone()));
+        HomeConnectionServer::new(context, server.clone()).unwrap()
+    };
+    let half_proof = |signer: &dyn Signer| {
+        RelationHalfProof::new(
+            RelationProof::RELATION_TYPE_HOSTED_ON_HOME,
+            &home_key.key_id(),
+            signer,
+        )
+        .unwrap()
+    };
+
+    let first_home = connect(&first_key);
+    let missing_res = first_home.register(half_proof(first_signer.as_ref()), None);
+    assert_eq!(missing_res.wait().unwrap_err().kind(), ErrorKind::InvitationRequired);
+
+    let forged = HomeInvitation::new("welcome", first_signer.as_ref()).unwrap();
+    let forged_res = first_home.register(half_proof(first_signer.as_ref()), Some(forged));
+    assert_eq!(forged_res.wait().unwrap_err().kind(), ErrorKind::InvalidInvitation);
+
+    let revoked = HomeInvitation::new("withdrawn", home_signer.as_ref()).unwrap();
+    let revoked_res = first_home.register(half_proof(first_signer.as_ref()), Some(revoked));
+    assert_eq!(revoked_res.wait().unwrap_err().kind(), ErrorKind::InvalidInvitation);
+
+    let invite = HomeInvitation::new("welcome", home_signer.as_ref()).unwrap();
+    first_home.register(half_proof(first_signer.as_ref()), Some(invite.clone())).wait().unwrap();
+    let status = invitation_db.borrow().get("welcome".to_owned()).wait().unwrap();
+    assert_eq!(status, InvitationStatus::Used(first_key.key_id()));
+
+    let second_home = connect(&second_key);
+    let reused_res = second_home.register(half_proof(second_signer.as_ref()), Some(invite));
+    assert_eq!(reused_res.wait().unwrap_err().kind(), ErrorKind::InvalidInvitation);
+}
@@ -0,0 +1,61 @@
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::Future;
+
+use did::vault::{HdProfileVault, ProfileVault};
+use mercury_home_node::mailbox::MailboxLimits;
+use mercury_home_node::registration::RegistrationPolicy;
+use mercury_home_node::server::{HomeConnectionServer, HomeServer, HomeStores};
+use mercury_home_protocol::crypto::CompositeValidator;
+use mercury_home_protocol::error::ErrorKind;
+use mercury_home_protocol::*;
+
+#[test]
+fn test_sessions_expire_without_heartbeat() {
+    let phrase = keyvault::Seed::generate_bip39();
+    let mut vault = HdProfileVault::create(keyvault::Seed::from_bip39(&phrase).unwrap());
+    let home_key = vault.create_key(None).unwrap();
+    let my_key = vault.create_key(None).unwrap();
+    let vault = Arc::new(vault);
+    let home_signer = vault.clone().signer(&home_key.key_id()).unwrap();
+    let my_signer = vault.clone().signer(&my_key.key_id()).unwrap();
+
+    let server = Rc::new(HomeServer::new(
+        Rc::new(CompositeValidator::default()),
+        HomeStores::in_memory(),
+        MailboxLimits::default(),
+        RegistrationPolicy::Open,
+        Duration::from_secs(60),
+    ));
+    let context = Rc::new(PeerContext::new(home_signer, my_key.clone()));
+    let home = HomeConnectionServer::new(context, server.clone()).unwrap();
+
+    let half_proof = RelationHalfProof::new(
+        RelationProof::RELATION_TYPE_HOSTED_ON_HOME,
+        &home_key.key_id(),
+        my_signer.as_ref(),
+    )
+    .unwrap();
+    let home_proof = home.register(half_proof, None).wait().unwrap();
+    let session = home.login(&home_proof).wait().unwrap();
+    session.heartbeat().wait().unwrap();
+
+    server.expire_sessions(Duration::from_secs(3600)).wait().unwrap();
+    session.heartbeat().wait().unwrap();
+
+    std::thread::sleep(Duration::from_millis(10));
+    server.expire_sessions(Duration::from_millis(1)).wait().unwrap();
+    let heartbeat_res = session.heartbeat().wait();
+    assert_eq!(heartbeat_res.unwrap_err().kind(), ErrorKind::SessionExpired);
+    // Events of the expired session are already in the mailbox, it must not serve any requests
+    let presence_res = session.set_presence(Presence::new(PresenceStatus::Online)).wait();
+    assert_eq!(presence_res.unwrap_err().kind(), ErrorKind::SessionExpired);
+    let messages_res = session.messages(&ApplicationId("app".to_owned())).wait();
+    assert_eq!(messages_res.unwrap_err().kind(), ErrorKind::SessionExpired);
+
+    // The client logs in again to continue
+    let session = home.login(&home_proof).wait().unwrap();
+    session.heartbeat().wait().unwrap();
+}
@@ -0,0 +1,97 @@
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::Future;
+
+use did::vault::{HdProfileVault, ProfileVault};
+use mercury_home_node::mailbox::MailboxLimits;
+use mercury_home_node::registration::RegistrationPolicy;
+use mercury_home_node::server::{HomeConnectionServer, HomeServer, HomeStores};
+use mercury_home_protocol::crypto::CompositeValidator;
+use mercury_home_protocol::error::ErrorKind;
+use mercury_home_protocol::*;
+
+fn home_server() -> Rc<HomeServer> {
+    Rc::new(HomeServer::new(
+        Rc::new(CompositeValidator::default()),
+        HomeStores::in_memory(),
+        MailboxLimits::default(),
+        RegistrationPolicy::Open,
+        Duration::from_secs(60),
+    ))
+}
+
+#[test]
+fn test_move_to_new_home() {
+    let phrase = keyvault::Seed::generate_bip39();
+    let mut vault = HdProfileVault::create(keyvault::Seed::from_bip39(&phrase).unwrap());
+    let old_home_key = vault.create_key(None).unwrap();
+    let new_home_key = vault.create_key(None).unwrap();
+    let my_key = vault.create_key(None).unwrap();
+    let contact_key = vault.create_key(None).unwrap();
+    let vault = Arc::new(vault);
+    let old_home_signer = vault.clone().signer(&old_home_key.key_id()).unwrap();
+    let new_home_signer = vault.clone().signer(&new_home_key.key_id()).unwrap();
+    let my_signer = vault.clone().signer(&my_key.key_id()).unwrap();
+    let contact_signer = vault.clone().signer(&contact_key.key_id()).unwrap();
+
+    let (old_server, new_server) = (home_server(), home_server());
+    let connect = |home_signer: &Rc<dyn Signer>, server: &Rc<HomeServer>, peer_key: &PublicKey| {
+        let context = Rc::new(PeerContext::new(home_signer.clone(), peer_key.clone()));
+        HomeConnectionServer::new(context, server.clone()).unwrap()
+    };
+    let register = |home: &HomeConnectionServer, home_key: &PublicKey| {
+        let half_proof = RelationHalfProof::new(
+            RelationProof::RELATION_TYPE_HOSTED_ON_HOME,
+            &home_key.key_id(),
+            my_signer.as_ref(),
+        )
+        .unwrap();
+        home.register(half_proof, None).wait().unwrap()
+    };
+
+    let old_home = connect(&old_home_signer, &old_server, &my_key);
+    let old_proof = register(&old_home, &old_home_key);
+    let mut profile = OwnProfile::empty(&my_key);
+    profile.mut_public_data().add_hosted_on(&old_proof).unwrap();
+    profile.mut_public_data().sign(my_signer.as_ref()).unwrap();
+    let backup_key = vault.backup_key(&my_key.key_id()).unwrap();
+    let old_session = old_home.login(&old_proof).wait().unwrap();
+    old_session.backup(profile.seal_private_parts(&backup_key).unwrap()).wait().unwrap();
+
+    let chat = ApplicationId::from("chat");
+    let contact_half_proof =
+        RelationHalfProof::new("friend", &my_key.key_id(), contact_signer.as_ref()).unwrap();
+    let relation =
+        RelationProof::sign_remaining_half(&contact_half_proof, my_signer.as_ref()).unwrap();
+    let contact_old_home = connect(&old_home_signer, &old_server, &contact_key);
+    contact_old_home
+        .send_message(relation.clone(), chat.clone(), AppMessageFrame(vec![1]))
+        .wait()
+        .unwrap();
+
+    let new_home = connect(&new_home_signer, &new_server, &my_key);
+    let new_proof = register(&new_home, &new_home_key);
+    profile.mut_public_data().set_hosted(&HostedFacet::new(vec![new_proof.clone()], vec![]));
+    profile.mut_public_data().sign(my_signer.as_ref()).unwrap();
+    let new_session = new_home.login(&new_proof).wait().unwrap();
+    new_session.backup(profile.seal_private_parts(&backup_key).unwrap()).wait().unwrap();
+
+    let mailbox = old_session.export_mailbox().wait().unwrap();
+    new_session.import_mailbox(mailbox).wait().unwrap();
+    old_session.unregister(Some(profile.public_data())).wait().unwrap();
+
+    let messages = new_session.messages(&chat).wait().unwrap();
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].payload, AppMessageFrame(vec![1]));
+
+    // The old home refuses requests but tells where the profile moved
+    let send_res = contact_old_home.send_message(relation, chat, AppMessageFrame(vec![2]));
+    assert_eq!(send_res.wait().unwrap_err().kind(), ErrorKind::ProfileMoved);
+    assert!(old_home.login(&old_proof).wait().is_err());
+    let redirect = contact_old_home.redirect(&my_key.key_id()).wait().unwrap();
+    assert_eq!(redirect.new_home_id, new_home_key.key_id());
+    CompositeValidator::default().validate_home_redirect(&redirect, &old_home_key).unwrap();
+    assert!(contact_old_home.redirect(&contact_key.key_id()).wait().is_err());
+}
@@ -0,0 +1,91 @@
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::{future, Future, Stream};
+use tokio_current_thread as reactor;
+
+use did::vault::{HdProfileVault, ProfileVault};
+use mercury_home_node::mailbox::MailboxLimits;
+use mercury_home_node::registration::RegistrationPolicy;
+use mercury_home_node::server::{HomeConnectionServer, HomeServer, HomeStores};
+use mercury_home_protocol::crypto::CompositeValidator;
+use mercury_home_protocol::*;
+
+#[test]
+fn test_presence_shown_to_contacts() {
+    let phrase = keyvault::Seed::generate_bip39();
+    let mut vault = HdProfileVault::create(keyvault::Seed::from_bip39(&phrase).unwrap());
+    let home_key = vault.create_key(None).unwrap();
+    let my_key = vault.create_key(None).unwrap();
+    let contact_key = vault.create_key(None).unwrap();
+    let vault = Arc::new(vault);
+    let home_signer = vault.clone().signer(&home_key.key_id()).unwrap();
+    let my_signer = vault.clone().signer(&my_key.key_id()).unwrap();
+    let contact_signer = vault.clone().signer(&contact_key.key_id()).unwrap();
+
+    let server = Rc::new(HomeServer::new(
+        Rc::new(CompositeValidator::default()),
+        HomeStores::in_memory(),
+        MailboxLimits::default(),
+        RegistrationPolicy::Open,
+        Duration::from_secs(60),
+    ));
+    let connect = |peer_key: &PublicKey| {
+        let context = Rc::new(PeerContext::new(home_signer.clone(), peer_key.clone()));
+        HomeConnectionServer::new(context, server.clone()).unwrap()
+    };
+
+    let home = connect(&my_key);
+    let half_proof = RelationHalfProof::new(
+        RelationProof::RELATION_TYPE_HOSTED_ON_HOME,
+        &home_key.key_id(),
+        my_signer.as_ref(),
+    )
+    .unwrap();
+    let home_proof = home.register(half_proof, None).wait().unwrap();
+    let mut profile = OwnProfile::empty(&my_key);
+    profile.mut_public_data().add_hosted_on(&home_proof).unwrap();
+    profile.mut_public_data().sign(my_signer.as_ref()).unwrap();
+    let session = home.login(&home_proof).wait().unwrap();
+    let backup_key = vault.backup_key(&my_key.key_id()).unwrap();
+    session.backup(profile.seal_private_parts(&backup_key).unwrap()).wait().unwrap();
+
+    let contact_half_proof =
+        RelationHalfProof::new("friend", &my_key.key_id(), contact_signer.as_ref()).unwrap();
+    let relation =
+        RelationProof::sign_remaining_half(&contact_half_proof, my_signer.as_ref()).unwrap();
+    let contact_home = connect(&contact_key);
+    let presence = contact_home.presence(relation.clone()).wait().unwrap();
+    assert_eq!(presence.status, PresenceStatus::Offline);
+
+    let mut presence = Presence::new(PresenceStatus::Away);
+    presence.apps.insert(ApplicationId::from("chat"), AppMessageFrame(b"in a meeting".to_vec()));
+    session.set_presence(presence.clone()).wait().unwrap();
+    assert_eq!(contact_home.presence(relation.clone()).wait().unwrap(), presence);
+
+    // Subscribers see the profile going offline with its session and stay subscribed
+    let mut reactor = reactor::CurrentThread::new();
+    let updates = reactor
+        .block_on(future::lazy(|| {
+            future::ok::<_, ()>(contact_home.subscribe_presence(relation.clone()))
+        }))
+        .unwrap();
+    let (current, updates) = reactor.block_on(updates.into_future()).map_err(|_e| ()).unwrap();
+    assert_eq!(current, Some(Ok(presence)));
+
+    drop(session);
+    let (offline, updates) = reactor.block_on(updates.into_future()).map_err(|_e| ()).unwrap();
+    assert_eq!(offline, Some(Ok(Presence::default())));
+    let presence = contact_home.presence(relation.clone()).wait().unwrap();
+    assert_eq!(presence.status, PresenceStatus::Offline);
+
+    let session = home.login(&home_proof).wait().unwrap();
+    session.set_presence(Presence::new(PresenceStatus::Online)).wait().unwrap();
+    let (online, _updates) = reactor.block_on(updates.into_future()).map_err(|_e| ()).unwrap();
+    assert_eq!(online, Some(Ok(Presence::new(PresenceStatus::Online))));
+
+    // Others cannot use the relation of the contact
+    let stranger_home = connect(&home_key);
+    assert!(stranger_home.presence(relation).wait().is_err());
+}
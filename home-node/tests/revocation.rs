@@ -0,0 +1,93 @@
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use futures::{future, Future, Stream};
+
+use did::vault::{HdProfileVault, ProfileVault};
+use mercury_home_node::mailbox::MailboxLimits;
+use mercury_home_node::registration::RegistrationPolicy;
+use mercury_home_node::server::{HomeConnectionServer, HomeServer, HomeStores};
+use mercury_home_protocol::crypto::CompositeValidator;
+use mercury_home_protocol::error::ErrorKind;
+use mercury_home_protocol::*;
+
+#[test]
+fn test_revoked_and_expired_relations_refused() {
+    let phrase = keyvault::Seed::generate_bip39();
+    let mut vault = HdProfileVault::create(keyvault::Seed::from_bip39(&phrase).unwrap());
+    let home_key = vault.create_key(None).unwrap();
+    let my_key = vault.create_key(None).unwrap();
+    let contact_key = vault.create_key(None).unwrap();
+    let vault = Arc::new(vault);
+    let home_signer = vault.clone().signer(&home_key.key_id()).unwrap();
+    let my_signer = vault.clone().signer(&my_key.key_id()).unwrap();
+    let contact_signer = vault.clone().signer(&contact_key.key_id()).unwrap();
+
+    let server = Rc::new(HomeServer::new(
+        Rc::new(CompositeValidator::default()),
+        HomeStores::in_memory(),
+        MailboxLimits::default(),
+        RegistrationPolicy::Open,
+        Duration::from_secs(60),
+    ));
+    let connect = |peer_key: &PublicKey| {
+        let context = Rc::new(PeerContext::new(home_signer.clone(), peer_key.clone()));
+        HomeConnectionServer::new(context, server.clone()).unwrap()
+    };
+
+    let home = connect(&my_key);
+    let half_proof = RelationHalfProof::new(
+        RelationProof::RELATION_TYPE_HOSTED_ON_HOME,
+        &home_key.key_id(),
+        my_signer.as_ref(),
+    )
+    .unwrap();
+    let home_proof = home.register(half_proof, None).wait().unwrap();
+    let mut profile = OwnProfile::empty(&my_key);
+    profile.mut_public_data().add_hosted_on(&home_proof).unwrap();
+    profile.mut_public_data().sign(my_signer.as_ref()).unwrap();
+    let session = home.login(&home_proof).wait().unwrap();
+    let backup_key = vault.backup_key(&my_key.key_id()).unwrap();
+    session.backup(profile.seal_private_parts(&backup_key).unwrap()).wait().unwrap();
+
+    let chat = ApplicationId::from("chat");
+    let contact_home = connect(&contact_key);
+    let expired_half_proof = RelationHalfProof::new_expiring(
+        "friend",
+        &my_key.key_id(),
+        SystemTime::now() - Duration::from_secs(1),
+        contact_signer.as_ref(),
+    )
+    .unwrap();
+    let expired =
+        RelationProof::sign_remaining_half(&expired_half_proof, my_signer.as_ref()).unwrap();
+    let expired_res = contact_home.send_message(expired, chat.clone(), AppMessageFrame(vec![]));
+    assert!(expired_res.wait().is_err());
+
+    let contact_half_proof =
+        RelationHalfProof::new("friend", &my_key.key_id(), contact_signer.as_ref()).unwrap();
+    let relation =
+        RelationProof::sign_remaining_half(&contact_half_proof, my_signer.as_ref()).unwrap();
+    contact_home
+        .send_message(relation.clone(), chat.clone(), AppMessageFrame(vec![1]))
+        .wait()
+        .unwrap();
+
+    // Only the revoker itself can send its revocation
+    let revocation = RelationRevocation::new(&relation, contact_signer.as_ref()).unwrap();
+    let forwarded_res = home.revoke_relation(revocation.clone());
+    assert_eq!(forwarded_res.wait().unwrap_err().kind(), ErrorKind::SignerMismatch);
+
+    contact_home.revoke_relation(revocation.clone()).wait().unwrap();
+    assert!(session.messages(&chat).wait().unwrap().is_empty());
+    let send_res = contact_home.send_message(relation.clone(), chat, AppMessageFrame(vec![2]));
+    assert_eq!(send_res.wait().unwrap_err().kind(), ErrorKind::RelationRevoked);
+    assert!(contact_home.presence(relation).wait().is_err());
+
+    let events = tokio_current_thread::block_on_all(future::lazy(|| {
+        session.events().take(1).collect().map_err(|()| "Failed to read events")
+    }))
+    .unwrap();
+    assert_eq!(events, vec![Ok(ProfileEvent::RelationRevoked(revocation))]);
+}
@@ -0,0 +1,82 @@
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::Future;
+
+use did::vault::{HdProfileVault, ProfileVault};
+use mercury_home_node::mailbox::MailboxLimits;
+use mercury_home_node::registration::RegistrationPolicy;
+use mercury_home_node::server::{HomeConnectionServer, HomeServer, HomeStores};
+use mercury_home_protocol::crypto::CompositeValidator;
+use mercury_home_protocol::error::ErrorKind;
+use mercury_home_protocol::*;
+
+#[test]
+fn test_apps_outside_relation_scope_refused() {
+    let phrase = keyvault::Seed::generate_bip39();
+    let mut vault = HdProfileVault::create(keyvault::Seed::from_bip39(&phrase).unwrap());
+    let home_key = vault.create_key(None).unwrap();
+    let my_key = vault.create_key(None).unwrap();
+    let contact_key = vault.create_key(None).unwrap();
+    let vault = Arc::new(vault);
+    let home_signer = vault.clone().signer(&home_key.key_id()).unwrap();
+    let my_signer = vault.clone().signer(&my_key.key_id()).unwrap();
+    let contact_signer = vault.clone().signer(&contact_key.key_id()).unwrap();
+
+    let server = Rc::new(HomeServer::new(
+        Rc::new(CompositeValidator::default()),
+        HomeStores::in_memory(),
+        MailboxLimits::default(),
+        RegistrationPolicy::Open,
+        Duration::from_secs(60),
+    ));
+    let connect = |peer_key: &PublicKey| {
+        let context = Rc::new(PeerContext::new(home_signer.clone(), peer_key.clone()));
+        HomeConnectionServer::new(context, server.clone()).unwrap()
+    };
+
+    let home = connect(&my_key);
+    let half_proof = RelationHalfProof::new(
+        RelationProof::RELATION_TYPE_HOSTED_ON_HOME,
+        &home_key.key_id(),
+        my_signer.as_ref(),
+    )
+    .unwrap();
+    let home_proof = home.register(half_proof, None).wait().unwrap();
+    let mut profile = OwnProfile::empty(&my_key);
+    profile.mut_public_data().add_hosted_on(&home_proof).unwrap();
+    profile.mut_public_data().sign(my_signer.as_ref()).unwrap();
+    let session = home.login(&home_proof).wait().unwrap();
+    let backup_key = vault.backup_key(&my_key.key_id()).unwrap();
+    session.backup(profile.seal_private_parts(&backup_key).unwrap()).wait().unwrap();
+
+    let (chat, wallet) = (ApplicationId::from("chat"), ApplicationId::from("wallet"));
+    let scope = RelationScope::new(vec![chat.clone()], Default::default());
+    let contact_half_proof = RelationHalfProof::new_scoped(
+        "friend",
+        &my_key.key_id(),
+        scope,
+        None,
+        contact_signer.as_ref(),
+    )
+    .unwrap();
+    let relation =
+        RelationProof::sign_remaining_half(&contact_half_proof, my_signer.as_ref()).unwrap();
+
+    let contact_home = connect(&contact_key);
+    contact_home
+        .send_message(relation.clone(), chat.clone(), AppMessageFrame(vec![1]))
+        .wait()
+        .unwrap();
+    let send_res =
+        contact_home.send_message(relation.clone(), wallet.clone(), AppMessageFrame(vec![]));
+    assert_eq!(send_res.wait().unwrap_err().kind(), ErrorKind::RelationScopeMismatch);
+    assert_eq!(session.messages(&chat).wait().unwrap().len(), 1);
+    assert!(session.messages(&wallet).wait().unwrap().is_empty());
+
+    let call_req =
+        CallRequestDetails { relation, init_payload: AppMessageFrame(vec![]), to_caller: None };
+    let call_res = contact_home.call(wallet, call_req);
+    assert_eq!(call_res.wait().err().unwrap().kind(), ErrorKind::RelationScopeMismatch);
+}
@@ -1,3 +1,4 @@
+use std::collections::{HashMap, HashSet};
 use std::str;
 
 use crate::*;
@@ -5,16 +6,70 @@ use keyvault::PublicKey as KeyVaultPublicKey;
 
 pub const CHANNEL_CAPACITY: usize = 1;
 
+/// Revision of the home protocol, increased on every incompatible change.
+pub type ProtocolVersion = u32;
+
+/// Newest protocol version implemented by this crate.
+pub const PROTOCOL_VERSION: ProtocolVersion = 1;
+/// Oldest protocol version this crate is still able to talk.
+pub const MIN_PROTOCOL_VERSION: ProtocolVersion = 1;
+
+/// Optional features of the protocol a peer may or may not implement.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Capability {
+    /// Keeps events and missed calls of offline profiles
+    Mailbox,
+    /// Accepts asynchronous messages to applications
+    AppMessages,
+    /// Keeps private profile backups to restore from
+    Restore,
+    /// Tells contacts whether the profile is online
+    Presence,
+}
+
+impl Capability {
+    /// Capabilities implemented by this crate.
+    pub const ALL: &'static [Capability] =
+        &[Capability::Mailbox, Capability::AppMessages, Capability::Restore, Capability::Presence];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Capability::Mailbox => "mailbox",
+            Capability::AppMessages => "app_messages",
+            Capability::Restore => "restore",
+            Capability::Presence => "presence",
+        }
+    }
+
+    /// Returns None for capabilities unknown to this crate, e.g. sent by a newer peer.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().cloned().find(|capability| capability.name() == name)
+    }
+}
+
 /// Represents a connection to another Profile (Home <-> Persona), (Persona <-> Persona)
 #[derive(Clone)]
 pub struct PeerContext {
     my_signer: Rc<dyn Signer>,
     peer_pubkey: PublicKey,
+    protocol_version: ProtocolVersion,
+    capabilities: HashSet<Capability>,
 }
 
 impl PeerContext {
+    /// Context of a peer assumed to speak the same protocol version with the same capabilities.
     pub fn new(my_signer: Rc<dyn Signer>, peer_pubkey: PublicKey) -> Self {
-        Self { my_signer, peer_pubkey }
+        let capabilities = Capability::ALL.iter().cloned().collect();
+        Self::new_negotiated(my_signer, peer_pubkey, PROTOCOL_VERSION, capabilities)
+    }
+
+    pub fn new_negotiated(
+        my_signer: Rc<dyn Signer>,
+        peer_pubkey: PublicKey,
+        protocol_version: ProtocolVersion,
+        capabilities: HashSet<Capability>,
+    ) -> Self {
+        Self { my_signer, peer_pubkey, protocol_version, capabilities }
     }
 
     pub fn my_signer(&self) -> &dyn Signer {
@@ -26,6 +81,17 @@ impl PeerContext {
     pub fn peer_id(&self) -> ProfileId {
         self.peer_pubkey.key_id()
     }
+    /// Highest protocol version supported by both sides.
+    pub fn protocol_version(&self) -> ProtocolVersion {
+        self.protocol_version
+    }
+    /// Capabilities supported by both sides.
+    pub fn capabilities(&self) -> &HashSet<Capability> {
+        &self.capabilities
+    }
+    pub fn supports(&self, capability: Capability) -> bool {
+        self.capabilities.contains(&capability)
+    }
 
     pub fn validate(&self, validator: &dyn Validator) -> Result<(), Error> {
         validator.validate_profile_auth(&self.peer_pubkey(), &self.peer_id()).and_then(|valid| {
@@ -47,6 +113,17 @@ pub struct AppMessageFrame(pub Vec<u8>);
 pub type AppMsgStream = AsyncStream<AppMessageFrame, String>;
 pub type AppMsgSink = AsyncSink<AppMessageFrame, String>;
 
+/// A message left by a peer for an application of the profile, kept on the home until acknowledged.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct AppMessage {
+    /// Assigned by the home, unique within the inbox of the application.
+    pub id: u64,
+    /// Proves for the home that the sender is allowed to leave messages for the profile.
+    /// The receiver can find out who sent the message by looking at `relation`.
+    pub relation: RelationProof,
+    pub payload: AppMessageFrame,
+}
+
 /// A struct that is passed from the caller to the callee. The callee can examine this
 /// before answering the call.
 #[derive(Debug)]
@@ -74,10 +151,11 @@ pub trait Home: ProfileExplorer {
 
     // TODO this should return only the signed RelationProof of the home hosting the profile
     //      because in this form the home can return malicious changes in the profile
+    /// Depending on the policy of the home, registration might require an invitation issued by the home.
     fn register(
         &self,
         half_proof: RelationHalfProof,
-        // invite: Option<HomeInvitation>,
+        invite: Option<HomeInvitation>,
     ) -> AsyncResult<RelationProof, Error>;
 
     /// By calling this method, any active session of the same profile is closed.
@@ -93,15 +171,64 @@ pub trait Home: ProfileExplorer {
     // NOTE initiating a real P2P connection (vs a single frame push notification),
     //      the caller must fill in some message channel to itself.
     //      A successful call returns a channel to callee.
+    /// Fails with `RelationScopeMismatch` if the relation does not allow the application.
     fn call(
         &self,
         app: ApplicationId,
         call_req: CallRequestDetails,
     ) -> AsyncResult<Option<AppMsgSink>, Error>;
 
-    // TODO consider how to do this in a later milestone
-    //    fn presence(&self, rel: Relation, app: ApplicationId) ->
-    //        AsyncResult<Option<AppMessageFrame>, Error>;
+    /// Leaves a message for the peer in `relation` without requiring the peer to be online.
+    /// The peer must be hosted on this home server, it can retrieve the message
+    /// through `HomeSession::messages()` later. The relation must allow the application.
+    fn send_message(
+        &self,
+        relation: RelationProof,
+        app: ApplicationId,
+        message: AppMessageFrame,
+    ) -> AsyncResult<(), Error>;
+
+    /// Presence last published by the peer of the relation, called on the peer's home.
+    /// Profiles that did not publish their presence yet are offline.
+    fn presence(&self, relation: RelationProof) -> AsyncResult<Presence, Error>;
+
+    /// Current presence of the peer of the relation followed by all its changes.
+    fn subscribe_presence(&self, relation: RelationProof) -> AsyncStream<Presence, String>;
+
+    /// Called by the revoker on its own home and on the home of its peer. Calls, messages
+    /// and presence queries over the relation are refused afterwards, a peer hosted here
+    /// gets a `RelationRevoked` event.
+    fn revoke_relation(&self, revocation: RelationRevocation) -> AsyncResult<(), Error>;
+
+    /// Where a profile that left this home moved to. Requests to such a profile fail with
+    /// `ProfileMoved` as long as the redirect is valid.
+    fn redirect(&self, profile_id: &ProfileId) -> AsyncResult<HomeRedirect, Error>;
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum PresenceStatus {
+    Online,
+    Away,
+    Offline,
+}
+
+impl Default for PresenceStatus {
+    fn default() -> Self {
+        PresenceStatus::Offline
+    }
+}
+
+/// Availability of a profile shown to its contacts, with an optional status for each application.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Presence {
+    pub status: PresenceStatus,
+    pub apps: HashMap<ApplicationId, AppMessageFrame>,
+}
+
+impl Presence {
+    pub fn new(status: PresenceStatus) -> Self {
+        Self { status, apps: Default::default() }
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
@@ -110,12 +237,37 @@ pub enum ProfileEvent {
     PairingRequest(RelationHalfProof),
     // TODO do we want to distinguish "rejected" and "notYetApproved" states for pairing, i.e. need an explicit rejected response?
     PairingResponse(RelationProof),
+    RelationRevoked(RelationRevocation),
     // TODO are these events needed? What others?
     //    HomeBroadcast,
     //    HomeHostingExpiry,
     //    ProfileUpdated, // from a different client instance/session
 }
 
+/// Notification about a call that could not be delivered because the callee was offline.
+/// The caller is not waiting for an answer anymore, only the request can be examined.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct MissedCall {
+    pub app: ApplicationId,
+    pub relation: RelationProof,
+    pub init_payload: AppMessageFrame,
+}
+
+impl MissedCall {
+    pub fn new(app: ApplicationId, details: &CallRequestDetails) -> Self {
+        Self { app, relation: details.relation.clone(), init_payload: details.init_payload.clone() }
+    }
+}
+
+/// Items waiting for the profile on its home, transferred to the new home when the profile moves.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct MailboxContent {
+    pub events: Vec<ProfileEvent>,
+    #[serde(default)]
+    pub calls: Vec<MissedCall>,
+    pub messages: HashMap<ApplicationId, Vec<AppMessage>>,
+}
+
 pub trait IncomingCall {
     /// Get a reference to details of the call.
     /// It contains information about the caller party (`relation`), an initial message (`initial_payload`)
@@ -133,23 +285,53 @@ pub trait IncomingCall {
 }
 
 pub trait HomeSession {
+    /// The public part of the profile must be signed by the profile key, otherwise it is rejected.
+    /// Private parts must be sealed by the client using `OwnProfile::seal_private_parts()`,
+    /// the home stores the backup as it is and returns it unchanged on `restore()`.
     fn backup(&self, own_profile: OwnProfile) -> AsyncResult<(), Error>;
     fn restore(&self) -> AsyncResult<OwnProfile, Error>;
 
     // NOTE newhome is a profile that contains at least one HomeFacet different than this home
+    /// If the signed public profile hosted on a new home is given, the home keeps a `HomeRedirect`
+    /// to the new home for a grace period.
     fn unregister(&self, new_home: Option<Profile>) -> AsyncResult<(), Error>;
 
+    /// Events sent while the profile was offline are kept on the home and delivered on the next call.
+    /// Events are delivered again until they are acknowledged with `ack_event()`.
     fn events(&self) -> AsyncStream<ProfileEvent, String>;
 
+    /// Removes a processed event from the mailbox of the profile on the home.
+    fn ack_event(&self, event: &ProfileEvent) -> AsyncResult<(), Error>;
+
     // TODO some kind of proof might be needed that the AppId given really belongs to the caller
-    // TODO add argument in a later milestone, presence: Option<AppMessageFrame>) ->
+    /// Calls missed while the profile was offline are delivered first, they cannot be answered anymore.
     fn checkin_app(&self, app: &ApplicationId) -> AsyncStream<Box<dyn IncomingCall>, String>;
 
+    /// Messages left for the application by peers, in the order they were received.
+    /// Messages are returned again until they are acknowledged with `ack_messages()`.
+    fn messages(&self, app: &ApplicationId) -> AsyncResult<Vec<AppMessage>, Error>;
+
+    fn ack_messages(&self, app: &ApplicationId, ids: &[u64]) -> AsyncResult<(), Error>;
+
+    /// Pending events and messages of all applications, e.g. to move them to a new home.
+    fn export_mailbox(&self) -> AsyncResult<MailboxContent, Error>;
+    /// Adds the items to the mailbox, as if they were sent to this home.
+    fn import_mailbox(&self, content: MailboxContent) -> AsyncResult<(), Error>;
+
+    /// Publishes the presence of the profile to its contacts, replacing the previous one.
+    fn set_presence(&self, presence: Presence) -> AsyncResult<(), Error>;
+
+    /// Keeps the session alive, it has to be sent at least every `HEARTBEAT_INTERVAL`.
+    /// The home expires sessions without heartbeats, a client has to login again after
+    /// `ErrorKind::SessionExpired` is returned.
+    fn heartbeat(&self) -> AsyncResult<(), Error>;
+
     // TODO remove this after testing
     fn ping(&self, txt: &str) -> AsyncResult<String, Error>;
 
-    // TODO ban features are delayed to a later milestone
-    //    fn banned_profiles(&self) -> AsyncResult<Vec<ProfileId>, Error>;
-    //    fn ban(&self, profile: &ProfileId) -> AsyncResult<(), Error>;
-    //    fn unban(&self, profile: &ProfileId) -> AsyncResult<(), Error>;
+    fn banned_profiles(&self) -> AsyncResult<Vec<ProfileId>, Error>;
+    /// The home refuses pairing requests, calls, messages and presence queries of a banned
+    /// profile, items already waiting in the mailbox from that profile are dropped.
+    fn ban(&self, profile: &ProfileId) -> AsyncResult<(), Error>;
+    fn unban(&self, profile: &ProfileId) -> AsyncResult<(), Error>;
 }
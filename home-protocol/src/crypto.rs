@@ -1,3 +1,5 @@
+use std::time::SystemTime;
+
 use crate::*;
 use keyvault::PublicKey as KeyVaultPublicKey;
 
@@ -38,11 +40,25 @@ pub trait Validator: ProfileIdValidator + SignatureValidator {
         half_proof: &RelationHalfProof,
         signer_pubkey: &PublicKey,
     ) -> Result<(), Error> {
-        self.validate_signature(
-            signer_pubkey,
-            &RelationSignablePart::from(half_proof).serialized(),
-            &half_proof.signature,
-        )?;
+        if half_proof.is_expired_at(SystemTime::now()) {
+            Err(ErrorKind::RelationExpired)?
+        }
+        let signable = RelationSignablePart::from(half_proof).serialized();
+        if !self.validate_signature(signer_pubkey, &signable, &half_proof.signature)? {
+            Err(ErrorKind::InvalidSignature)?
+        }
+        Ok(())
+    }
+
+    fn validate_home_invitation(
+        &self,
+        invitation: &HomeInvitation,
+        home_pubkey: &PublicKey,
+    ) -> Result<(), Error> {
+        let signable = HomeInvitationSignablePart::new(&invitation.home_id, &invitation.voucher);
+        if !self.validate_signature(home_pubkey, &signable.serialized(), &invitation.signature)? {
+            Err(ErrorKind::InvalidInvitation)?
+        }
         Ok(())
     }
 
@@ -54,11 +70,17 @@ pub trait Validator: ProfileIdValidator + SignatureValidator {
         id_2: &ProfileId,
         public_key_2: &PublicKey,
     ) -> Result<(), Error> {
+        if relation_proof.is_expired_at(SystemTime::now()) {
+            Err(ErrorKind::RelationExpired)?
+        }
+
         // TODO consider inverting relation_type for different directions
         let signable_a = RelationSignablePart::new(
             &relation_proof.relation_type,
             &relation_proof.a_id,
             &relation_proof.b_id,
+            relation_proof.valid_until,
+            &relation_proof.scope,
         )
         .serialized();
 
@@ -66,6 +88,8 @@ pub trait Validator: ProfileIdValidator + SignatureValidator {
             &relation_proof.relation_type,
             &relation_proof.b_id,
             &relation_proof.a_id,
+            relation_proof.valid_until,
+            &relation_proof.scope,
         )
         .serialized();
 
@@ -74,18 +98,68 @@ pub trait Validator: ProfileIdValidator + SignatureValidator {
             Err(ErrorKind::RelationValidationFailed)?
         }
 
-        if *peer_of_id_1 == relation_proof.b_id {
+        let signatures_valid = if *peer_of_id_1 == relation_proof.b_id {
             // id_1 is 'proof.id_a'
-            self.validate_signature(&public_key_1, &signable_a, &relation_proof.a_signature)?;
-            self.validate_signature(&public_key_2, &signable_b, &relation_proof.b_signature)?;
+            self.validate_signature(&public_key_1, &signable_a, &relation_proof.a_signature)?
+                && self.validate_signature(
+                    &public_key_2,
+                    &signable_b,
+                    &relation_proof.b_signature,
+                )?
         } else {
             // id_1 is 'proof.id_b'
-            self.validate_signature(&public_key_1, &signable_b, &relation_proof.b_signature)?;
-            self.validate_signature(&public_key_2, &signable_a, &relation_proof.a_signature)?;
+            self.validate_signature(&public_key_1, &signable_b, &relation_proof.b_signature)?
+                && self.validate_signature(
+                    &public_key_2,
+                    &signable_a,
+                    &relation_proof.a_signature,
+                )?
+        };
+        if !signatures_valid {
+            Err(ErrorKind::InvalidSignature)?
         }
 
         Ok(())
     }
+
+    fn validate_home_redirect(
+        &self,
+        redirect: &HomeRedirect,
+        old_home_pubkey: &PublicKey,
+    ) -> Result<(), Error> {
+        let signable = HomeRedirectSignablePart::new(
+            &redirect.profile_id,
+            &redirect.old_home_id,
+            &redirect.new_home_id,
+            redirect.valid_until,
+        );
+        if !self.validate_signature(old_home_pubkey, &signable.serialized(), &redirect.signature)? {
+            Err(ErrorKind::InvalidSignature)?
+        }
+        Ok(())
+    }
+
+    /// Checks that the revocation is signed by one of the profiles of a valid relation.
+    fn validate_relation_revocation(&self, revocation: &RelationRevocation) -> Result<(), Error> {
+        let relation = &revocation.relation;
+        let peer_id = revocation.peer_id()?;
+        let revoker_pubkey = relation.peer_pubkey(peer_id)?;
+        let peer_pubkey = relation.peer_pubkey(&revocation.revoker_id)?;
+        self.validate_relation_proof(
+            relation,
+            &revocation.revoker_id,
+            revoker_pubkey,
+            peer_id,
+            peer_pubkey,
+        )?;
+
+        let signable =
+            RelationRevocationSignablePart::new(relation, &revocation.revoker_id)?.serialized();
+        if !self.validate_signature(revoker_pubkey, &signable, &revocation.signature)? {
+            Err(ErrorKind::InvalidSignature)?
+        }
+        Ok(())
+    }
 }
 
 pub struct MultiHashProfileValidator {}
@@ -162,3 +236,106 @@ impl SignatureValidator for CompositeValidator {
 }
 
 impl Validator for CompositeValidator {}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use did::vault::{HdProfileVault, ProfileVault};
+    use keyvault::Seed;
+
+    fn signers() -> (Rc<dyn Signer>, Rc<dyn Signer>) {
+        let seed = Seed::from_bip39(&Seed::generate_bip39()).unwrap();
+        let mut vault = HdProfileVault::create(seed);
+        let first_id = vault.create_key(None).unwrap().key_id();
+        let second_id = vault.create_key(None).unwrap().key_id();
+        let vault = std::sync::Arc::new(vault);
+        (vault.clone().signer(&first_id).unwrap(), vault.signer(&second_id).unwrap())
+    }
+
+    fn validate(
+        relation: &RelationProof,
+        first: &dyn Signer,
+        second: &dyn Signer,
+    ) -> Result<(), Error> {
+        CompositeValidator::default().validate_relation_proof(
+            relation,
+            first.profile_id(),
+            &first.public_key(),
+            second.profile_id(),
+            &second.public_key(),
+        )
+    }
+
+    #[test]
+    fn relation_expiry() {
+        let (first, second) = signers();
+
+        let valid_until = SystemTime::now() + Duration::from_secs(3600);
+        let half_proof =
+            RelationHalfProof::new_expiring("friend", second.profile_id(), valid_until, &*first)
+                .unwrap();
+        let relation = RelationProof::sign_remaining_half(&half_proof, &*second).unwrap();
+        assert_eq!(relation.valid_until, Some(valid_until));
+        validate(&relation, &*first, &*second).unwrap();
+
+        let mut extended = relation.clone();
+        extended.valid_until = Some(valid_until + Duration::from_secs(3600));
+        let err = validate(&extended, &*first, &*second).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidSignature);
+
+        let past = SystemTime::now() - Duration::from_secs(1);
+        let half_proof =
+            RelationHalfProof::new_expiring("friend", second.profile_id(), past, &*first).unwrap();
+        let half_res =
+            CompositeValidator::default().validate_half_proof(&half_proof, &first.public_key());
+        assert_eq!(half_res.unwrap_err().kind(), ErrorKind::RelationExpired);
+        let expired = RelationProof::sign_remaining_half(&half_proof, &*second).unwrap();
+        let err = validate(&expired, &*first, &*second).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::RelationExpired);
+    }
+
+    #[test]
+    fn relation_scope() {
+        let (first, second) = signers();
+        let chat = ApplicationId::from("chat");
+        let scope = RelationScope::new(
+            vec![chat.clone()],
+            vec![("nickname".to_owned(), "Bob".to_owned())].into_iter().collect(),
+        );
+        let half_proof =
+            RelationHalfProof::new_scoped("friend", second.profile_id(), scope, None, &*first)
+                .unwrap();
+        let relation = RelationProof::sign_remaining_half(&half_proof, &*second).unwrap();
+        validate(&relation, &*first, &*second).unwrap();
+        assert!(relation.accessible_by(&chat));
+        assert!(!relation.accessible_by(&ApplicationId::from("wallet")));
+
+        let mut widened = relation.clone();
+        widened.scope.apps.clear();
+        let err = validate(&widened, &*first, &*second).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidSignature);
+
+        let half_proof = RelationHalfProof::new("friend", second.profile_id(), &*first).unwrap();
+        let unscoped = RelationProof::sign_remaining_half(&half_proof, &*second).unwrap();
+        assert!(unscoped.accessible_by(&ApplicationId::from("wallet")));
+    }
+
+    #[test]
+    fn relation_revocation() {
+        let (first, second) = signers();
+        let validator = CompositeValidator::default();
+        let half_proof = RelationHalfProof::new("friend", second.profile_id(), &*first).unwrap();
+        let relation = RelationProof::sign_remaining_half(&half_proof, &*second).unwrap();
+
+        let revocation = RelationRevocation::new(&relation, &*second).unwrap();
+        assert_eq!(revocation.peer_id().unwrap(), first.profile_id());
+        assert!(revocation.revokes(&relation));
+        validator.validate_relation_revocation(&revocation).unwrap();
+
+        let mut forged = revocation.clone();
+        forged.revoker_id = first.profile_id().to_owned();
+        assert!(validator.validate_relation_revocation(&forged).is_err());
+    }
+}
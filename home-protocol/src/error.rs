@@ -1,13 +1,15 @@
 use std::fmt::Display;
 
 use failure::{Backtrace, Context, Fail};
+use serde_derive::{Deserialize, Serialize};
 
 #[derive(Debug)]
 pub struct Error {
     inner: Context<ErrorKind>,
 }
 
-#[derive(Copy, Clone, Eq, PartialEq, Debug, Fail)]
+// NOTE kinds are also sent to remote peers by their name, renaming them breaks compatibility
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Deserialize, Fail, Serialize)]
 pub enum ErrorKind {
     #[fail(display = "profile lookup failed")]
     ProfileLookupFailed,
@@ -23,6 +25,8 @@ pub enum ErrorKind {
     SignatureValidationFailed,
     #[fail(display = "handshake failed")]
     DiffieHellmanHandshakeFailed,
+    #[fail(display = "no common protocol version with peer")]
+    ProtocolVersionMismatch,
     #[fail(display = "relation signing failed")]
     RelationSigningFailed,
     #[fail(display = "relation validation failed")]
@@ -95,6 +99,48 @@ pub enum ErrorKind {
     PingFailed,
     #[fail(display = "login failed")]
     LoginFailed,
+    #[fail(display = "mailbox update failed")]
+    MailboxUpdateFailed,
+    #[fail(display = "failed to send message")]
+    SendMessageFailed,
+    #[fail(display = "failed to load messages")]
+    FailedToLoadMessages,
+    #[fail(display = "presence update failed")]
+    PresenceUpdateFailed,
+    #[fail(display = "failed to get presence")]
+    FailedToGetPresence,
+    #[fail(display = "banned by the profile")]
+    ProfileBanned,
+    #[fail(display = "ban list update failed")]
+    BanListUpdateFailed,
+    #[fail(display = "failed to load ban list")]
+    FailedToLoadBanList,
+    #[fail(display = "registration requires an invitation")]
+    InvitationRequired,
+    #[fail(display = "invalid, revoked or already used invitation")]
+    InvalidInvitation,
+    #[fail(display = "registration is not allowed by home policy")]
+    RegistrationNotAllowed,
+    #[fail(display = "relation expired")]
+    RelationExpired,
+    #[fail(display = "relation revoked")]
+    RelationRevoked,
+    #[fail(display = "relation revocation failed")]
+    RelationRevocationFailed,
+    #[fail(display = "profile moved to another home")]
+    ProfileMoved,
+    #[fail(display = "failed to get redirect")]
+    FailedToGetRedirect,
+    #[fail(display = "mailbox transfer failed")]
+    MailboxTransferFailed,
+    #[fail(display = "application is not allowed to use the relation")]
+    RelationScopeMismatch,
+    #[fail(display = "session expired")]
+    SessionExpired,
+    #[fail(display = "websocket handshake failed")]
+    WebSocketHandshakeFailed,
+    #[fail(display = "private parts of backup are not sealed")]
+    BackupNotSealed,
 }
 
 impl PartialEq for Error {
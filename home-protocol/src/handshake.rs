@@ -1,138 +1,271 @@
+use std::cmp::{max, min};
+use std::collections::HashSet;
+use std::io::{Read, Write};
 use std::mem;
 use std::rc::Rc;
 
-//bincode::{deserialize, serialize};
 use bytes::{Buf, BufMut, BytesMut, IntoBuf};
 use failure::Fail;
 use serde_json::{from_slice, to_vec};
 use tokio::io::{self, AsyncRead, AsyncWrite};
 use tokio::net::tcp::TcpStream;
-//use x25519_dalek::diffie_hellman;
 
 use crate::*;
+use keyvault::encryption::{EphemeralSecret, SymmetricKey, NONCE_SIZE};
+use keyvault::PublicKey as KeyVaultPublicKey;
+
+const HANDSHAKE_CONTEXT: &[u8] = b"mercury handshake v1";
+const SESSION_KEY_CONTEXT: &str = "mercury session";
+const MAX_HANDSHAKE_MESSAGE_SIZE: u32 = 8096;
+
+/// Maximum number of plaintext bytes sent in a single frame of a secured stream
+pub const MAX_FRAME_PAYLOAD_SIZE: usize = 64 * 1024;
+const FRAME_HEADER_SIZE: usize = mem::size_of::<u32>();
+const SEQUENCE_SIZE: usize = mem::size_of::<u64>();
+const AUTH_TAG_SIZE: usize = 16;
+const MIN_SEALED_FRAME_SIZE: usize = NONCE_SIZE + SEQUENCE_SIZE + AUTH_TAG_SIZE;
+const MAX_SEALED_FRAME_SIZE: usize = MIN_SEALED_FRAME_SIZE + MAX_FRAME_PAYLOAD_SIZE;
 
 #[derive(Deserialize, Clone, Debug, Eq, PartialEq, Serialize)]
 struct AuthenticationInfo {
     public_key: PublicKey,
     // TODO this might not be needed, can be deduced from public_key
     profile_id: ProfileId,
+    // X25519 public key used only for this connection
+    ephemeral_key: Vec<u8>,
+    min_version: ProtocolVersion,
+    max_version: ProtocolVersion,
+    // NOTE names are sent instead of an enum so peers can ignore capabilities they do not know
+    capabilities: Vec<String>,
 }
 
 impl AuthenticationInfo {
-    fn new(signer: &dyn Signer) -> Self {
-        Self { profile_id: signer.profile_id().to_owned(), public_key: signer.public_key() }
+    fn new(signer: &dyn Signer, ephemeral_key: Vec<u8>) -> Self {
+        Self {
+            profile_id: signer.profile_id().to_owned(),
+            public_key: signer.public_key(),
+            ephemeral_key,
+            min_version: MIN_PROTOCOL_VERSION,
+            max_version: PROTOCOL_VERSION,
+            capabilities: Capability::ALL.iter().map(|cap| cap.name().to_owned()).collect(),
+        }
     }
 }
 
+// Picks the highest version and the capabilities supported by both sides.
+// NOTE both infos are covered by the signatures, so an attacker cannot force a downgrade
+fn negotiate(
+    my_info: &AuthenticationInfo,
+    peer_info: &AuthenticationInfo,
+) -> Result<(ProtocolVersion, HashSet<Capability>), Error> {
+    let version = min(my_info.max_version, peer_info.max_version);
+    if version < max(my_info.min_version, peer_info.min_version) {
+        warn!(
+            "No common protocol version, supported: {}-{}, peer supports: {}-{}",
+            my_info.min_version, my_info.max_version, peer_info.min_version, peer_info.max_version
+        );
+        return Err(ErrorKind::ProtocolVersionMismatch.into());
+    }
+    let capabilities = peer_info
+        .capabilities
+        .iter()
+        .filter(|name| my_info.capabilities.contains(name))
+        .filter_map(|name| Capability::from_name(name))
+        .collect();
+    Ok((version, capabilities))
+}
+
+// Proves that the sender owns the key it claimed, binding it to the ephemeral keys of this connection
+#[derive(Deserialize, Clone, Debug, Eq, PartialEq, Serialize)]
+struct AuthenticationProof {
+    signature: Signature,
+}
+
+// NOTE raw bytes are signed instead of serializing the messages again to be independent of the format
+fn transcript(signer_info: &[u8], verifier_info: &[u8]) -> Vec<u8> {
+    let mut transcript = HANDSHAKE_CONTEXT.to_vec();
+    for info in &[signer_info, verifier_info] {
+        transcript.extend_from_slice(&(info.len() as u32).to_le_bytes());
+        transcript.extend_from_slice(info);
+    }
+    transcript
+}
+
+fn write_message<W>(writer: W, message: Vec<u8>) -> AsyncResult<W, std::io::Error>
+where
+    W: std::io::Write + AsyncWrite + 'static,
+{
+    let mut size_out_bytes = BytesMut::with_capacity(FRAME_HEADER_SIZE);
+    size_out_bytes.put_u32_le(message.len() as u32);
+    let write_fut = io::write_all(writer, size_out_bytes)
+        .and_then(move |(writer, _buf)| io::write_all(writer, message))
+        .map(|(writer, _buf)| writer);
+    Box::new(write_fut)
+}
+
+fn read_message<R>(reader: R) -> AsyncResult<(R, Vec<u8>), std::io::Error>
+where
+    R: std::io::Read + AsyncRead + 'static,
+{
+    let mut size_bytes = BytesMut::new();
+    size_bytes.resize(FRAME_HEADER_SIZE, 0);
+    let read_fut = io::read_exact(reader, size_bytes).and_then(|(reader, buf)| {
+        let size_in_bytes = buf.into_buf().get_u32_le();
+        if size_in_bytes > MAX_HANDSHAKE_MESSAGE_SIZE {
+            let err = Err(std::io::Error::from(std::io::ErrorKind::ConnectionAborted));
+            return Box::new(err.into_future()) as AsyncResult<_, _>;
+        }
+        let mut in_bytes = BytesMut::new();
+        in_bytes.resize(size_in_bytes as usize, 0);
+        let res_fut = io::read_exact(reader, in_bytes).map(|(reader, buf)| (reader, buf.to_vec()));
+        Box::new(res_fut)
+    });
+    Box::new(read_fut)
+}
+
+fn handshake_err(err: impl Fail) -> Error {
+    err.context(ErrorKind::DiffieHellmanHandshakeFailed).into()
+}
+
 fn exchange_identities<R, W>(
     reader: R,
     writer: W,
-    signer: Rc<dyn Signer>,
-) -> AsyncResult<(R, W, AuthenticationInfo), Error>
+    my_info: Vec<u8>,
+) -> AsyncResult<(R, W, Vec<u8>), Error>
 where
     R: std::io::Read + AsyncRead + 'static,
     W: std::io::Write + AsyncWrite + 'static,
 {
     debug!("Starting handshake with peer");
-    let auth_info = AuthenticationInfo::new(signer.as_ref());
-    let out_bytes = match to_vec(&auth_info) {
-        Ok(data) => data,
-        Err(e) => {
-            return Box::new(
-                Err(e.context(ErrorKind::DiffieHellmanHandshakeFailed).into()).into_future(),
-            );
-        }
-    };
-    let bufsize = out_bytes.len() as u32;
-
-    let mut size_out_bytes = BytesMut::with_capacity(mem::size_of_val(&bufsize));
-    size_out_bytes.put_u32_le(bufsize);
-    trace!("Sending auth info of myself: {:?}", auth_info);
-
-    let exch_fut = io::write_all(writer, size_out_bytes)
-        .and_then(move |(writer, _buf)| io::write_all(writer, out_bytes))
-        .and_then(move |(writer, _buf)| {
-            trace!("Reading buffer size for peer info");
-            let mut size_bytes = BytesMut::new();
-            size_bytes.resize(mem::size_of_val(&bufsize), 0);
-            io::read_exact(reader, size_bytes).map(|(reader, buf)| (reader, writer, buf))
-        })
-        .and_then(|(reader, writer, buf)| {
-            trace!("Reading peer info, size: {:?}", buf);
-            let size_in_bytes = buf.into_buf().get_u32_le();
-            if size_in_bytes > 8096 {
-                let err = Err(std::io::Error::from(std::io::ErrorKind::ConnectionAborted));
-                return Box::new(err.into_future()) as Box<dyn Future<Item = _, Error = _>>;
-            }
-            let mut in_bytes = BytesMut::new();
-            in_bytes.resize(size_in_bytes as usize, 0);
-            let res_fut =
-                io::read_exact(reader, in_bytes).map(|(reader, buf)| (reader, writer, buf));
-            Box::new(res_fut)
-        })
-        .and_then(|(reader, writer, buf)| {
-            trace!("Processing peer info received");
-            let peer_auth: AuthenticationInfo =
-                from_slice(&buf).map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-            trace!("Received peer identity: {:?}", peer_auth);
-            Ok((reader, writer, peer_auth))
-        })
-        .map_err(|e| e.context(ErrorKind::DiffieHellmanHandshakeFailed).into());
+    let exch_fut = write_message(writer, my_info)
+        .and_then(move |writer| read_message(reader).map(|(reader, buf)| (reader, writer, buf)))
+        .map_err(handshake_err);
     Box::new(exch_fut)
 }
 
-pub fn tcpstream_to_reader_writer(
-    socket: TcpStream,
-) -> Result<(impl std::io::Read + AsyncRead, impl std::io::Write + AsyncWrite), std::io::Error> {
-    socket.set_nodelay(true)?;
-    Ok(socket.split())
-}
-
-pub fn ecdh_handshake<R, W>(
+fn exchange_proofs<R, W>(
     reader: R,
     writer: W,
-    signer: Rc<dyn Signer>,
-) -> Box<dyn Future<Item = (R, W, PeerContext), Error = Error>>
+    my_proof: Vec<u8>,
+) -> AsyncResult<(R, W, Vec<u8>), Error>
 where
     R: std::io::Read + AsyncRead + 'static,
     W: std::io::Write + AsyncWrite + 'static,
 {
-    let ecdh_fut = exchange_identities(reader, writer, signer.clone()).and_then(
-        |(reader, writer, peer_auth)| {
-            //let _my_secret_key: [u8; 32] = Default::default(); // TODO how to get secret key as bytes, especially when HW wallet support is planned?
+    let exch_fut = write_message(writer, my_proof)
+        .and_then(move |writer| read_message(reader).map(|(reader, buf)| (reader, writer, buf)))
+        .map_err(handshake_err);
+    Box::new(exch_fut)
+}
 
-            //let mut peer_pubkey: [u8; 32] = Default::default();
-            //peer_pubkey.copy_from_slice(peer_auth.public_key.to_bytes());
+fn sign_transcript(
+    signer: &dyn Signer,
+    my_info: &[u8],
+    peer_info: &[u8],
+) -> Result<Vec<u8>, Error> {
+    let signature = signer
+        .sign(&transcript(my_info, peer_info))
+        .map_err(|e| e.context(ErrorKind::DiffieHellmanHandshakeFailed))?;
+    to_vec(&AuthenticationProof { signature }).map_err(handshake_err)
+}
 
-            // TODO shadow reader and writer with ones using the shared secret to do symmetric en/decryption
-            //      and return them with a proper peercontext
-            // let shared_secret = diffie_hellman(&my_secret_key, &peer_ed25519_pubkey);
+fn validate_peer(peer_info_bytes: &[u8]) -> Result<AuthenticationInfo, Error> {
+    let peer_info: AuthenticationInfo = from_slice(peer_info_bytes).map_err(handshake_err)?;
+    trace!("Received peer identity: {:?}", peer_info);
+    if peer_info.public_key.key_id() != peer_info.profile_id {
+        return Err(ErrorKind::PublicKeyMismatch.into());
+    }
+    Ok(peer_info)
+}
 
-            let peer_ctx = PeerContext::new(signer, peer_auth.public_key);
-            Ok((reader, writer, peer_ctx))
-        },
-    );
-    Box::new(ecdh_fut)
+fn validate_proof(
+    peer_info: &AuthenticationInfo,
+    peer_info_bytes: &[u8],
+    my_info_bytes: &[u8],
+    peer_proof_bytes: &[u8],
+) -> Result<(), Error> {
+    let peer_proof: AuthenticationProof = from_slice(peer_proof_bytes).map_err(handshake_err)?;
+    let transcript = transcript(peer_info_bytes, my_info_bytes);
+    if !peer_info.public_key.verify(&transcript, &peer_proof.signature) {
+        return Err(ErrorKind::SignatureValidationFailed.into());
+    }
+    Ok(())
 }
 
-pub fn temporary_unsafe_handshake_until_diffie_hellman_done<R, W>(
+pub fn tcpstream_to_reader_writer(
+    socket: TcpStream,
+) -> Result<(impl std::io::Read + AsyncRead, impl std::io::Write + AsyncWrite), std::io::Error> {
+    socket.set_nodelay(true)?;
+    Ok(socket.split())
+}
+
+/// Authenticates both parties of a connection and secures all further traffic on it.
+/// Both sides send their public key and a new ephemeral key, then sign the messages exchanged
+/// to prove they own the key they claimed. Session keys are derived from the ephemeral keys.
+pub fn ecdh_handshake<R, W>(
     reader: R,
     writer: W,
     signer: Rc<dyn Signer>,
-) -> Box<dyn Future<Item = (R, W, PeerContext), Error = Error>>
+) -> AsyncResult<(SecureReader<R>, SecureWriter<W>, PeerContext), Error>
 where
     R: std::io::Read + AsyncRead + 'static,
     W: std::io::Write + AsyncWrite + 'static,
 {
-    let handshake_fut = exchange_identities(reader, writer, signer.clone())
-        .map_err(|err| err.context(ErrorKind::DiffieHellmanHandshakeFailed).into())
-        .and_then(|(reader, writer, peer_auth)| {
-            warn!("No proper peer validation was performed, safety is ignored");
-            let peer_ctx = PeerContext::new(signer, peer_auth.public_key);
-            debug!("Handshake succeeded");
-            Ok((reader, writer, peer_ctx))
+    let ephemeral_secret = EphemeralSecret::generate();
+    let my_info = AuthenticationInfo::new(signer.as_ref(), ephemeral_secret.public_key().to_vec());
+    let my_info_bytes = match to_vec(&my_info) {
+        Ok(data) => data,
+        Err(e) => return Box::new(Err(handshake_err(e)).into_future()),
+    };
+    trace!("Sending auth info of myself: {:?}", my_info);
+
+    let ecdh_fut = exchange_identities(reader, writer, my_info_bytes.clone())
+        .and_then({
+            let signer = signer.clone();
+            let my_info = my_info.clone();
+            let my_info_bytes = my_info_bytes.clone();
+            move |(reader, writer, peer_info_bytes)| {
+                let peer_info = validate_peer(&peer_info_bytes)?;
+                let negotiated = negotiate(&my_info, &peer_info)?;
+                let my_proof = sign_transcript(signer.as_ref(), &my_info_bytes, &peer_info_bytes)?;
+                Ok((reader, writer, peer_info, peer_info_bytes, negotiated, my_proof))
+            }
+        })
+        .and_then(|(reader, writer, peer_info, peer_info_bytes, negotiated, my_proof)| {
+            exchange_proofs(reader, writer, my_proof).map(move |(reader, writer, peer_proof)| {
+                (reader, writer, peer_info, peer_info_bytes, negotiated, peer_proof)
+            })
+        })
+        .and_then(move |(reader, writer, peer_info, peer_info_bytes, negotiated, peer_proof)| {
+            validate_proof(&peer_info, &peer_info_bytes, &my_info_bytes, &peer_proof)?;
+
+            let shared_secret = ephemeral_secret
+                .diffie_hellman(&peer_info.ephemeral_key)
+                .map_err(|e| e.context(ErrorKind::DiffieHellmanHandshakeFailed))?;
+            // Each direction has its own key, so frames cannot be reflected back to their sender
+            let session_key = |ephemeral_key: &[u8]| {
+                SymmetricKey::derive(
+                    &[&shared_secret[..], ephemeral_key].concat(),
+                    SESSION_KEY_CONTEXT,
+                )
+            };
+            let send_key = session_key(&my_info.ephemeral_key);
+            let receive_key = session_key(&peer_info.ephemeral_key);
+
+            let (version, capabilities) = negotiated;
+            debug!(
+                "Handshake succeeded with peer {} using protocol version {}, capabilities {:?}",
+                peer_info.profile_id, version, capabilities
+            );
+            let peer_ctx =
+                PeerContext::new_negotiated(signer, peer_info.public_key, version, capabilities);
+            Ok((
+                SecureReader::new(reader, receive_key),
+                SecureWriter::new(writer, send_key),
+                peer_ctx,
+            ))
         });
-    Box::new(handshake_fut)
+    Box::new(ecdh_fut)
 }
 
 pub fn tcp_ecdh_handshake(
@@ -146,25 +279,203 @@ pub fn tcp_ecdh_handshake(
 > {
     let res_fut = tcpstream_to_reader_writer(socket)
         .into_future()
-        .map_err(|e| e.context(ErrorKind::DiffieHellmanHandshakeFailed).into())
+        .map_err(handshake_err)
         .and_then(|(reader, writer)| ecdh_handshake(reader, writer, signer));
     Box::new(res_fut)
 }
 
-pub fn temporary_unsafe_tcp_handshake_until_diffie_hellman_done(
-    socket: TcpStream,
-    signer: Rc<dyn Signer>,
-) -> Box<
-    dyn Future<
-        Item = (impl std::io::Read + AsyncRead, impl std::io::Write + AsyncWrite, PeerContext),
-        Error = Error,
-    >,
-> {
-    let res_fut = tcpstream_to_reader_writer(socket)
-        .into_future()
-        .map_err(|e| e.context(ErrorKind::DiffieHellmanHandshakeFailed).into())
-        .and_then(|(reader, writer)| {
-            temporary_unsafe_handshake_until_diffie_hellman_done(reader, writer, signer)
-        });
-    Box::new(res_fut)
+fn invalid_data(msg: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg)
+}
+
+/// Reading half of a connection secured by `ecdh_handshake()`. Frames sent by the peer are
+/// decrypted and authenticated, frames dropped, reordered or replayed by others are rejected.
+pub struct SecureReader<R> {
+    inner: R,
+    key: SymmetricKey,
+    sequence: u64,
+    frame: Vec<u8>,     // bytes of the frame being received
+    plaintext: Vec<u8>, // payload of the last frame
+    consumed: usize,    // bytes of plaintext already read
+}
+
+impl<R> SecureReader<R> {
+    fn new(inner: R, key: SymmetricKey) -> Self {
+        Self { inner, key, sequence: 0, frame: vec![], plaintext: vec![], consumed: 0 }
+    }
+}
+
+impl<R: Read> SecureReader<R> {
+    // Returns false if the connection was closed between frames
+    fn receive_frame(&mut self) -> std::io::Result<bool> {
+        let mut chunk = [0u8; 4096];
+        loop {
+            let frame_size = if self.frame.len() < FRAME_HEADER_SIZE {
+                FRAME_HEADER_SIZE
+            } else {
+                let mut header = [0u8; FRAME_HEADER_SIZE];
+                header.copy_from_slice(&self.frame[..FRAME_HEADER_SIZE]);
+                let sealed_size = u32::from_le_bytes(header) as usize;
+                if sealed_size < MIN_SEALED_FRAME_SIZE || sealed_size > MAX_SEALED_FRAME_SIZE {
+                    return Err(invalid_data("Invalid frame size"));
+                }
+                FRAME_HEADER_SIZE + sealed_size
+            };
+            if self.frame.len() == frame_size && frame_size > FRAME_HEADER_SIZE {
+                break;
+            }
+
+            // NOTE a WouldBlock error keeps the partial frame, reading continues when data arrives
+            let missing = min(frame_size - self.frame.len(), chunk.len());
+            let read = self.inner.read(&mut chunk[..missing])?;
+            if read == 0 {
+                if self.frame.is_empty() {
+                    return Ok(false);
+                }
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+            self.frame.extend_from_slice(&chunk[..read]);
+        }
+
+        let opened = self
+            .key
+            .open(&self.frame[FRAME_HEADER_SIZE..])
+            .map_err(|_e| invalid_data("Failed to authenticate frame"))?;
+        self.frame.clear();
+        let mut sequence = [0u8; SEQUENCE_SIZE];
+        sequence.copy_from_slice(&opened[..SEQUENCE_SIZE]);
+        if u64::from_le_bytes(sequence) != self.sequence {
+            return Err(invalid_data("Frame out of sequence"));
+        }
+        self.sequence += 1;
+        self.plaintext = opened[SEQUENCE_SIZE..].to_vec();
+        self.consumed = 0;
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for SecureReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.consumed == self.plaintext.len() {
+            if !self.receive_frame()? {
+                return Ok(0);
+            }
+        }
+        let size = min(buf.len(), self.plaintext.len() - self.consumed);
+        buf[..size].copy_from_slice(&self.plaintext[self.consumed..self.consumed + size]);
+        self.consumed += size;
+        Ok(size)
+    }
+}
+
+impl<R: AsyncRead> AsyncRead for SecureReader<R> {}
+
+/// Writing half of a connection secured by `ecdh_handshake()`.
+/// Data is sent in encrypted and authenticated frames of at most `MAX_FRAME_PAYLOAD_SIZE` bytes.
+pub struct SecureWriter<W> {
+    inner: W,
+    key: SymmetricKey,
+    sequence: u64,
+    pending: Vec<u8>, // frame not yet fully written to inner
+    written: usize,   // bytes of pending already written
+}
+
+impl<W> SecureWriter<W> {
+    fn new(inner: W, key: SymmetricKey) -> Self {
+        Self { inner, key, sequence: 0, pending: vec![], written: 0 }
+    }
+}
+
+impl<W: Write> SecureWriter<W> {
+    fn write_pending(&mut self) -> std::io::Result<()> {
+        while self.written < self.pending.len() {
+            let written = self.inner.write(&self.pending[self.written..])?;
+            if written == 0 {
+                return Err(std::io::ErrorKind::WriteZero.into());
+            }
+            self.written += written;
+        }
+        self.pending.clear();
+        self.written = 0;
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for SecureWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        // Only a single frame is buffered, the caller has to wait until it is sent
+        self.write_pending()?;
+
+        let payload = &buf[..min(buf.len(), MAX_FRAME_PAYLOAD_SIZE)];
+        let mut plaintext = self.sequence.to_le_bytes().to_vec();
+        plaintext.extend_from_slice(payload);
+        let sealed = self
+            .key
+            .seal(&plaintext)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))?;
+        self.sequence += 1;
+        self.pending = (sealed.len() as u32).to_le_bytes().to_vec();
+        self.pending.extend_from_slice(&sealed);
+
+        // The frame is accepted even if the inner writer is not ready, it is sent on the next call
+        match self.write_pending() {
+            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+            res => res?,
+        }
+        Ok(payload.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.write_pending()?;
+        self.inner.flush()
+    }
+}
+
+impl<W: AsyncWrite> AsyncWrite for SecureWriter<W> {
+    fn shutdown(&mut self) -> Poll<(), std::io::Error> {
+        match self.write_pending() {
+            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => Ok(Async::NotReady),
+            Err(e) => Err(e),
+            Ok(()) => self.inner.shutdown(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use did::vault::{HdProfileVault, ProfileVault};
+    use keyvault::Seed;
+
+    fn auth_info(min_version: ProtocolVersion, max_version: ProtocolVersion) -> AuthenticationInfo {
+        let seed = Seed::from_bip39(&Seed::generate_bip39()).unwrap();
+        let mut vault = HdProfileVault::create(seed);
+        let public_key = vault.create_key(None).unwrap();
+        AuthenticationInfo {
+            profile_id: public_key.key_id(),
+            public_key,
+            ephemeral_key: vec![],
+            min_version,
+            max_version,
+            capabilities: vec!["restore".to_owned(), "mailbox".to_owned()],
+        }
+    }
+
+    #[test]
+    fn version_negotiation() {
+        let mut peer = auth_info(1, 3);
+        peer.capabilities.push("teleport".to_owned());
+        let (version, capabilities) = negotiate(&auth_info(2, 5), &peer).unwrap();
+        assert_eq!(version, 3);
+        assert_eq!(
+            capabilities,
+            [Capability::Mailbox, Capability::Restore].iter().cloned().collect()
+        );
+
+        let err = negotiate(&auth_info(4, 5), &peer).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ProtocolVersionMismatch);
+    }
 }
@@ -6,6 +6,7 @@ pub mod handshake;
 pub mod mercury_capnp;
 pub mod primitives;
 pub mod util;
+pub mod websocket;
 
 use std::rc::Rc;
 use std::time::Duration;
@@ -27,3 +28,6 @@ pub use keyvault;
 pub use keyvault::ed25519;
 
 pub const CHANNEL_CAPACITY: usize = 1;
+
+/// Period of `HomeSession::heartbeat()` expected by homes to keep a session alive.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
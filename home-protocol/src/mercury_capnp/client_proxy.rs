@@ -2,14 +2,17 @@ use std::convert::TryFrom;
 
 use capnp::capability::Promise;
 use capnp_rpc::pry;
-use failure::Fail;
+use failure::bail;
 use tokio::io::AsyncRead;
 use tokio::net::tcp::TcpStream;
 use tokio::prelude::*;
 use tokio_current_thread as reactor;
 
 use super::*;
-use crate::mercury_capnp::{FillFrom, PromiseUtil};
+use crate::mercury_capnp::{
+    bytes_to_mailbox_content, invitation_to_bytes, local_err, mailbox_content_to_bytes, FillFrom,
+    PromiseUtil,
+};
 use claims::model::Link;
 use claims::repo::ProfileExplorer;
 
@@ -57,6 +60,7 @@ impl ProfileExplorer for HomeClientCapnProto {
         let mut request = self.repo.get_request();
         request.get().set_profile_id(&id.to_bytes());
 
+        let id = id.to_owned();
         let resp_fut = request
             .send()
             .promise
@@ -65,14 +69,45 @@ impl ProfileExplorer for HomeClientCapnProto {
                 let profile = bytes_to_profile(profile_capnp);
                 Promise::result(profile)
             })
-            .map_err(|e| e.context(ErrorKind::FailedToLoadProfile).into());
+            .map_err(|e| local_err(e, ErrorKind::FailedToLoadProfile).into())
+            .and_then(move |profile| {
+                // NOTE the home must not be able to forge or substitute the profile
+                let check_res = profile.validate().and_then(|()| {
+                    if profile.id() != id {
+                        bail!("Got profile {} instead of {}", profile.id(), id);
+                    }
+                    Ok(profile)
+                });
+                check_res.map_err(|e| Error::from(e.context(ErrorKind::FailedToLoadProfile)).into())
+            });
 
         Box::new(resp_fut)
     }
 
-    fn followers(&self, _id: &ProfileId) -> AsyncFallible<Vec<Link>> {
-        unimplemented!()
-        // Ok( Vec::new() ) // TODO implement this properly
+    fn followers(&self, id: &ProfileId, offset: usize, limit: usize) -> AsyncFallible<Vec<Link>> {
+        let mut request = self.repo.followers_request();
+        request.get().set_profile_id(&id.to_bytes());
+        request.get().set_offset(offset as u32);
+        request.get().set_limit(limit as u32);
+
+        let resp_fut = request
+            .send()
+            .promise
+            .and_then(|resp| {
+                let followers_capnp = pry!(pry!(resp.get()).get_followers());
+                let followers: Result<Vec<Link>, capnp::Error> = followers_capnp
+                    .iter()
+                    .map(|follower_capnp| {
+                        let peer_profile =
+                            ProfileId::from_bytes(follower_capnp?).map_err(|e| capnp_err(e))?;
+                        Ok(Link { peer_profile })
+                    })
+                    .collect();
+                Promise::result(followers)
+            })
+            .map_err(|e| local_err(e, ErrorKind::ProfileLookupFailed).into());
+
+        Box::new(resp_fut)
     }
 }
 
@@ -89,7 +124,7 @@ impl Home for HomeClientCapnProto {
                     .and_then(|res| res.get_hosting_proof())
                     .and_then(|host_proof_capnp| RelationProof::try_from(host_proof_capnp))
             })
-            .map_err(|e| e.context(ErrorKind::FailedToClaimProfile).into());
+            .map_err(|e| local_err(e, ErrorKind::FailedToClaimProfile).into());
 
         Box::new(resp_fut)
     }
@@ -97,13 +132,13 @@ impl Home for HomeClientCapnProto {
     fn register(
         &self,
         half_proof: RelationHalfProof,
-        //invite: Option<HomeInvitation>,
+        invite: Option<HomeInvitation>,
     ) -> AsyncResult<RelationProof, Error> {
         let mut request = self.home.register_request();
         request.get().init_half_proof().fill_from(&half_proof);
-        //if let Some(inv) = invite {
-        //    request.get().init_invite().fill_from(&inv);
-        //}
+        if let Some(inv) = invite {
+            request.get().set_invite(&invitation_to_bytes(&inv));
+        }
 
         let resp_fut = request
             .send()
@@ -113,7 +148,7 @@ impl Home for HomeClientCapnProto {
                     .and_then(|res| res.get_hosting_proof())
                     .and_then(|host_proof_capnp| RelationProof::try_from(host_proof_capnp))
             })
-            .map_err(move |e| e.context(ErrorKind::RegisterFailed).into());
+            .map_err(move |e| local_err(e, ErrorKind::RegisterFailed).into());
 
         Box::new(resp_fut)
     }
@@ -130,7 +165,7 @@ impl Home for HomeClientCapnProto {
                     Rc::new(HomeSessionClientCapnProto::new(session_client)) as Rc<dyn HomeSession>
                 })
             })
-            .map_err(|e| e.context(ErrorKind::FailedToCreateSession).into());
+            .map_err(|e| local_err(e, ErrorKind::FailedToCreateSession).into());
 
         Box::new(resp_fut)
     }
@@ -144,7 +179,7 @@ impl Home for HomeClientCapnProto {
             .send()
             .promise
             .map(|_resp| ())
-            .map_err(|e| e.context(ErrorKind::PairRequestFailed).into());
+            .map_err(|e| local_err(e, ErrorKind::PairRequestFailed).into());
 
         Box::new(resp_fut)
     }
@@ -158,7 +193,7 @@ impl Home for HomeClientCapnProto {
             .send()
             .promise
             .map(|_resp| ())
-            .map_err(|e| e.context(ErrorKind::PairResponseFailed).into());
+            .map_err(|e| local_err(e, ErrorKind::PairResponseFailed).into());
 
         Box::new(resp_fut)
     }
@@ -191,10 +226,133 @@ impl Home for HomeClientCapnProto {
                         .ok()
                 })
             })
-            .map_err(|e| e.context(ErrorKind::CallFailed).into());
+            .map_err(|e| local_err(e, ErrorKind::CallFailed).into());
+
+        Box::new(resp_fut)
+    }
+
+    // NOTE receiver must have this server as its home
+    fn send_message(
+        &self,
+        relation: RelationProof,
+        app: ApplicationId,
+        message: AppMessageFrame,
+    ) -> AsyncResult<(), Error> {
+        let mut request = self.home.send_message_request();
+        request.get().init_relation().fill_from(&relation);
+        request.get().set_app((&app).into());
+        request.get().set_message((&message).into());
+
+        let resp_fut = request
+            .send()
+            .promise
+            .map(|_resp| ())
+            .map_err(|e| local_err(e, ErrorKind::SendMessageFailed).into());
+
+        Box::new(resp_fut)
+    }
+
+    // NOTE peer of the relation must have this server as its home
+    fn presence(&self, relation: RelationProof) -> AsyncResult<Presence, Error> {
+        let mut request = self.home.presence_request();
+        request.get().init_relation().fill_from(&relation);
+
+        let resp_fut = request
+            .send()
+            .promise
+            .and_then(|resp| Presence::try_from(resp.get()?.get_presence()?))
+            .map_err(|e| local_err(e, ErrorKind::FailedToGetPresence).into());
+
+        Box::new(resp_fut)
+    }
+
+    fn subscribe_presence(&self, relation: RelationProof) -> AsyncStream<Presence, String> {
+        let (send, recv) = mpsc::channel(1);
+        let listener = PresenceDispatcherCapnProto::new(send.clone());
+        // TODO consider how to drop/unregister this object from capnp if the stream is dropped
+        let listener_capnp = mercury_capnp::presence_listener::ToClient::new(listener)
+            .into_client::<::capnp_rpc::Server>();
+
+        let mut request = self.home.subscribe_presence_request();
+        request.get().init_relation().fill_from(&relation);
+        request.get().set_listener(listener_capnp);
+
+        reactor::spawn(request.send().promise.map(|_resp| ()).or_else(move |e| {
+            send.send(Err(format!("Presence subscription failed: {}", e)))
+                    .map(|_sink| ())
+                    // TODO what to do if failed to send error?
+                    .map_err(|_err| ())
+        }));
+
+        recv
+    }
+
+    fn revoke_relation(&self, revocation: RelationRevocation) -> AsyncResult<(), Error> {
+        let mut request = self.home.revoke_relation_request();
+        request.get().init_revocation().fill_from(&revocation);
+
+        let resp_fut = request
+            .send()
+            .promise
+            .map(|_resp| ())
+            .map_err(|e| local_err(e, ErrorKind::RelationRevocationFailed).into());
 
         Box::new(resp_fut)
     }
+
+    fn redirect(&self, profile_id: &ProfileId) -> AsyncResult<HomeRedirect, Error> {
+        let mut request = self.home.redirect_request();
+        request.get().set_profile_id(&profile_id.to_bytes());
+
+        let resp_fut = request
+            .send()
+            .promise
+            .and_then(|resp| HomeRedirect::try_from(resp.get()?.get_redirect()?))
+            .map_err(|e| local_err(e, ErrorKind::FailedToGetRedirect).into());
+
+        Box::new(resp_fut)
+    }
+}
+
+struct PresenceDispatcherCapnProto {
+    sender: mpsc::Sender<Result<Presence, String>>,
+}
+
+impl PresenceDispatcherCapnProto {
+    fn new(sender: mpsc::Sender<Result<Presence, String>>) -> Self {
+        Self { sender }
+    }
+}
+
+impl mercury_capnp::presence_listener::Server for PresenceDispatcherCapnProto {
+    fn receive(
+        &mut self,
+        params: mercury_capnp::presence_listener::ReceiveParams,
+        _results: mercury_capnp::presence_listener::ReceiveResults,
+    ) -> Promise<(), capnp::Error> {
+        let presence_capnp = pry!(pry!(params.get()).get_presence());
+        let presence = pry!(Presence::try_from(presence_capnp));
+        let recv_fut = self
+            .sender
+            .clone()
+            .send(Ok(presence))
+            .map(|_sink| ())
+            .map_err(|e| capnp::Error::failed(format!("Failed to delegate presence: {}", e)));
+        Promise::from_future(recv_fut)
+    }
+
+    fn error(
+        &mut self,
+        params: mercury_capnp::presence_listener::ErrorParams,
+        _results: mercury_capnp::presence_listener::ErrorResults,
+    ) -> Promise<(), capnp::Error> {
+        let error = pry!(pry!(params.get()).get_error()).into();
+        let recv_fut =
+            self.sender.clone().send(Err(error)).map(|_sink| ()).map_err(|e| {
+                capnp::Error::failed(format!("Failed to delegate presence error: {}", e))
+            });
+        Promise::from_future(recv_fut)
+    }
 }
 
 struct ProfileEventDispatcherCapnProto {
@@ -258,7 +416,7 @@ impl HomeSession for HomeSessionClientCapnProto {
             .send()
             .promise
             .map(|_resp| ())
-            .map_err(|e| e.context(ErrorKind::ProfileUpdateFailed).into());
+            .map_err(|e| local_err(e, ErrorKind::ProfileUpdateFailed).into());
 
         Box::new(resp_fut)
     }
@@ -273,7 +431,7 @@ impl HomeSession for HomeSessionClientCapnProto {
                     res.get_own_profile().and_then(|own_bytes| bytes_to_own_profile(own_bytes))
                 })
             })
-            .map_err(|e| e.context(ErrorKind::ProfileUpdateFailed).into());
+            .map_err(|e| local_err(e, ErrorKind::ProfileUpdateFailed).into());
 
         Box::new(resp_fut)
     }
@@ -289,7 +447,7 @@ impl HomeSession for HomeSessionClientCapnProto {
             .send()
             .promise
             .map(|_resp| ())
-            .map_err(|e| e.context(ErrorKind::UnregisterFailed).into());
+            .map_err(|e| local_err(e, ErrorKind::UnregisterFailed).into());
 
         Box::new(resp_fut)
     }
@@ -314,6 +472,19 @@ impl HomeSession for HomeSessionClientCapnProto {
         recv
     }
 
+    fn ack_event(&self, event: &ProfileEvent) -> AsyncResult<(), Error> {
+        let mut request = self.session.ack_event_request();
+        request.get().init_event().fill_from(event);
+
+        let resp_fut = request
+            .send()
+            .promise
+            .map(|_resp| ())
+            .map_err(|e| local_err(e, ErrorKind::MailboxUpdateFailed).into());
+
+        Box::new(resp_fut)
+    }
+
     fn checkin_app(&self, app: &ApplicationId) -> AsyncStream<Box<dyn IncomingCall>, String> {
         // Send a call dispatcher proxy to remote home through which we'll accept incoming calls
         let (send, recv) = mpsc::channel(1);
@@ -338,6 +509,137 @@ impl HomeSession for HomeSessionClientCapnProto {
         recv
     }
 
+    fn messages(&self, app: &ApplicationId) -> AsyncResult<Vec<AppMessage>, Error> {
+        let mut request = self.session.messages_request();
+        request.get().set_app(app.into());
+
+        let resp_fut = request
+            .send()
+            .promise
+            .and_then(|resp| {
+                let messages_capnp = resp.get()?.get_messages()?;
+                messages_capnp
+                    .iter()
+                    .map(|message_capnp| AppMessage::try_from(message_capnp))
+                    .collect()
+            })
+            .map_err(|e| local_err(e, ErrorKind::FailedToLoadMessages).into());
+
+        Box::new(resp_fut)
+    }
+
+    fn ack_messages(&self, app: &ApplicationId, ids: &[u64]) -> AsyncResult<(), Error> {
+        let mut request = self.session.ack_messages_request();
+        request.get().set_app(app.into());
+        let mut ids_capnp = request.get().init_ids(ids.len() as u32);
+        for (idx, id) in ids.iter().enumerate() {
+            ids_capnp.set(idx as u32, *id);
+        }
+
+        let resp_fut = request
+            .send()
+            .promise
+            .map(|_resp| ())
+            .map_err(|e| local_err(e, ErrorKind::MailboxUpdateFailed).into());
+
+        Box::new(resp_fut)
+    }
+
+    fn set_presence(&self, presence: Presence) -> AsyncResult<(), Error> {
+        let mut request = self.session.set_presence_request();
+        request.get().init_presence().fill_from(&presence);
+
+        let resp_fut = request
+            .send()
+            .promise
+            .map(|_resp| ())
+            .map_err(|e| local_err(e, ErrorKind::PresenceUpdateFailed).into());
+
+        Box::new(resp_fut)
+    }
+
+    fn banned_profiles(&self) -> AsyncResult<Vec<ProfileId>, Error> {
+        let request = self.session.banned_profiles_request();
+
+        let resp_fut = request
+            .send()
+            .promise
+            .and_then(|resp| {
+                let profiles_capnp = pry!(pry!(resp.get()).get_profiles());
+                let profiles: Result<Vec<ProfileId>, capnp::Error> = profiles_capnp
+                    .iter()
+                    .map(|profile_capnp| {
+                        ProfileId::from_bytes(profile_capnp?).map_err(|e| capnp_err(e))
+                    })
+                    .collect();
+                Promise::result(profiles)
+            })
+            .map_err(|e| local_err(e, ErrorKind::FailedToLoadBanList).into());
+
+        Box::new(resp_fut)
+    }
+
+    fn ban(&self, profile: &ProfileId) -> AsyncResult<(), Error> {
+        let mut request = self.session.ban_request();
+        request.get().set_profile(&profile.to_bytes());
+
+        let resp_fut = request
+            .send()
+            .promise
+            .map(|_resp| ())
+            .map_err(|e| local_err(e, ErrorKind::BanListUpdateFailed).into());
+
+        Box::new(resp_fut)
+    }
+
+    fn unban(&self, profile: &ProfileId) -> AsyncResult<(), Error> {
+        let mut request = self.session.unban_request();
+        request.get().set_profile(&profile.to_bytes());
+
+        let resp_fut = request
+            .send()
+            .promise
+            .map(|_resp| ())
+            .map_err(|e| local_err(e, ErrorKind::BanListUpdateFailed).into());
+
+        Box::new(resp_fut)
+    }
+
+    fn export_mailbox(&self) -> AsyncResult<MailboxContent, Error> {
+        let request = self.session.export_mailbox_request();
+        let resp_fut = request
+            .send()
+            .promise
+            .and_then(|resp| bytes_to_mailbox_content(resp.get()?.get_content()?))
+            .map_err(|e| local_err(e, ErrorKind::MailboxTransferFailed).into());
+
+        Box::new(resp_fut)
+    }
+
+    fn import_mailbox(&self, content: MailboxContent) -> AsyncResult<(), Error> {
+        let mut request = self.session.import_mailbox_request();
+        request.get().set_content(&mailbox_content_to_bytes(&content));
+
+        let resp_fut = request
+            .send()
+            .promise
+            .map(|_resp| ())
+            .map_err(|e| local_err(e, ErrorKind::MailboxTransferFailed).into());
+
+        Box::new(resp_fut)
+    }
+
+    fn heartbeat(&self) -> AsyncResult<(), Error> {
+        let request = self.session.heartbeat_request();
+        let resp_fut = request
+            .send()
+            .promise
+            .map(|_resp| ())
+            .map_err(|e| local_err(e, ErrorKind::ConnectionToHomeFailed).into());
+
+        Box::new(resp_fut)
+    }
+
     fn ping(&self, txt: &str) -> AsyncResult<String, Error> {
         let mut request = self.session.ping_request();
         request.get().set_txt(txt);
@@ -346,7 +648,7 @@ impl HomeSession for HomeSessionClientCapnProto {
             .send()
             .promise
             .and_then(|resp| resp.get().and_then(|res| res.get_pong()).map(|pong| pong.to_owned()))
-            .map_err(|e| e.context(ErrorKind::PingFailed).into());
+            .map_err(|e| local_err(e, ErrorKind::PingFailed).into());
 
         Box::new(resp_fut)
     }
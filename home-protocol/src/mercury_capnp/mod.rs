@@ -3,11 +3,14 @@ include!(concat!(env!("OUT_DIR"), "/protocol/mercury_capnp.rs"));
 pub mod client_proxy;
 pub mod server_dispatcher;
 
+use std::collections::{BTreeMap, HashMap};
 use std::convert::TryFrom;
+use std::time::{Duration, UNIX_EPOCH};
 
 use capnp;
 use capnp::capability::Promise;
 use capnp_rpc::pry;
+use failure::Fail;
 use futures::prelude::*;
 use futures::{future, sync::mpsc, Sink};
 use tokio_current_thread as reactor;
@@ -58,6 +61,58 @@ fn capnp_err(err: failure::Error) -> capnp::Error {
     capnp::Error::failed(err.to_string())
 }
 
+// Marks capnp exceptions carrying an error of the remote Home implementation, see mercury.capnp
+const REMOTE_ERROR_PREFIX: &str = "mercury error: ";
+
+/// Error returned by the remote side of a call, serialized into the reason of a capnp exception.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+struct RemoteError {
+    kind: ErrorKind,
+    message: String,
+}
+
+/// Converts an error of the local Home implementation to be sent to the remote caller,
+/// keeping its `ErrorKind` if it has one.
+fn remote_err(err: impl Into<failure::Error>, summary: &str) -> capnp::Error {
+    let err = err.into();
+    let message = format!(
+        "{}: {}",
+        summary,
+        err.iter_chain().map(|fail| fail.to_string()).collect::<Vec<_>>().join(": ")
+    );
+    let kind = err.iter_chain().find_map(|fail| {
+        fail.downcast_ref::<Error>()
+            .map(|err| err.kind())
+            .or_else(|| {
+                fail.downcast_ref::<failure::Context<ErrorKind>>().map(|ctx| *ctx.get_context())
+            })
+            .or_else(|| fail.downcast_ref::<ErrorKind>().cloned())
+    });
+    match kind {
+        None => capnp::Error::failed(message),
+        Some(kind) => {
+            let remote = RemoteError { kind, message };
+            serde_json::to_string(&remote)
+                .map(|json| capnp::Error::failed(format!("{}{}", REMOTE_ERROR_PREFIX, json)))
+                .unwrap_or_else(|_e| capnp::Error::failed(remote.message))
+        }
+    }
+}
+
+/// Converts a failed remote call to an error of the same `ErrorKind` the remote side returned.
+/// Transport failures and errors without a kind get the `fallback` kind.
+fn local_err(err: capnp::Error, fallback: ErrorKind) -> Error {
+    // NOTE capnp prepends its own text to the reason of remote exceptions
+    let remote = err.description.find(REMOTE_ERROR_PREFIX).and_then(|pos| {
+        let json = &err.description[pos + REMOTE_ERROR_PREFIX.len()..];
+        serde_json::from_str::<RemoteError>(json).ok()
+    });
+    match remote {
+        Some(remote) => failure::err_msg(remote.message).context(remote.kind).into(),
+        None => err.context(fallback).into(),
+    }
+}
+
 fn bytes_to_profile(src: &[u8]) -> Result<Profile, capnp::Error> {
     serde_json::from_slice(&src).map_err(|e| capnp::Error::failed(e.to_string()))
 }
@@ -78,6 +133,71 @@ fn own_profile_to_bytes(src: &OwnProfile) -> Vec<u8> {
         .expect("Implementation error: serialization can fail only if Serialize implementation returns error or with non-string keys in the type")
 }
 
+fn bytes_to_invitation(src: &[u8]) -> Result<HomeInvitation, capnp::Error> {
+    serde_json::from_slice(&src).map_err(|e| capnp::Error::failed(e.to_string()))
+}
+
+fn invitation_to_bytes(src: &HomeInvitation) -> Vec<u8> {
+    // TODO how to return error here without changing the signature of fill_from()?
+    serde_json::to_vec(src)
+        .expect("Implementation error: serialization can fail only if Serialize implementation returns error or with non-string keys in the type")
+}
+
+// NOTE nanoseconds are needed to keep the exact timestamp that was signed
+fn timestamp_from_capnp(nanos: u64) -> Option<TimeStamp> {
+    if nanos == 0 {
+        None
+    } else {
+        Some(UNIX_EPOCH + Duration::from_nanos(nanos))
+    }
+}
+
+fn timestamp_to_capnp(src: Option<TimeStamp>) -> u64 {
+    src.and_then(|timestamp| timestamp.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |elapsed| elapsed.as_nanos() as u64)
+}
+
+fn bytes_to_mailbox_content(src: &[u8]) -> Result<MailboxContent, capnp::Error> {
+    serde_json::from_slice(&src).map_err(|e| capnp::Error::failed(e.to_string()))
+}
+
+fn mailbox_content_to_bytes(src: &MailboxContent) -> Vec<u8> {
+    // TODO how to return error here without changing the signature of fill_from()?
+    serde_json::to_vec(src)
+        .expect("Implementation error: serialization can fail only if Serialize implementation returns error or with non-string keys in the type")
+}
+
+impl<'a> TryFrom<relation_scope::Reader<'a>> for RelationScope {
+    type Error = capnp::Error;
+
+    fn try_from(src: relation_scope::Reader) -> Result<Self, Self::Error> {
+        let mut apps = Vec::new();
+        for app in src.get_apps()?.iter() {
+            apps.push(app?.into());
+        }
+        let mut metadata = BTreeMap::new();
+        for entry in src.get_metadata()?.iter() {
+            metadata.insert(entry.get_key()?.to_owned(), entry.get_value()?.to_owned());
+        }
+        Ok(RelationScope { apps, metadata })
+    }
+}
+
+impl<'a> FillFrom<RelationScope> for relation_scope::Builder<'a> {
+    fn fill_from(mut self, src: &RelationScope) {
+        let mut apps_capnp = self.reborrow().init_apps(src.apps.len() as u32);
+        for (idx, app) in src.apps.iter().enumerate() {
+            apps_capnp.set(idx as u32, app.into());
+        }
+        let mut metadata_capnp = self.init_metadata(src.metadata.len() as u32);
+        for (idx, (key, value)) in src.metadata.iter().enumerate() {
+            let mut entry_capnp = metadata_capnp.reborrow().get(idx as u32);
+            entry_capnp.set_key(key);
+            entry_capnp.set_value(value);
+        }
+    }
+}
+
 impl<'a> TryFrom<relation_half_proof::Reader<'a>> for RelationHalfProof {
     type Error = capnp::Error;
 
@@ -89,6 +209,8 @@ impl<'a> TryFrom<relation_half_proof::Reader<'a>> for RelationHalfProof {
                 .map_err(|e| capnp_err(e))?,
             peer_id: ProfileId::from_bytes(src.get_peer_id()?).map_err(|e| capnp_err(e))?,
             signature: Signature::from_bytes(src.get_signature()?).map_err(|e| capnp_err(e))?,
+            valid_until: timestamp_from_capnp(src.get_valid_until()),
+            scope: RelationScope::try_from(src.get_scope()?)?,
         })
     }
 }
@@ -100,6 +222,8 @@ impl<'a> FillFrom<RelationHalfProof> for relation_half_proof::Builder<'a> {
         self.set_signer_pub_key(&src.signer_pubkey.to_bytes());
         self.set_peer_id(&src.peer_id.to_bytes());
         self.set_signature(&src.signature.to_bytes());
+        self.set_valid_until(timestamp_to_capnp(src.valid_until));
+        self.init_scope().fill_from(&src.scope);
     }
 }
 
@@ -115,6 +239,8 @@ impl<'a> TryFrom<relation_proof::Reader<'a>> for RelationProof {
             b_id: ProfileId::from_bytes(src.get_b_id()?).map_err(|e| capnp_err(e))?,
             b_pub_key: PublicKey::from_bytes(src.get_b_pub_key()?).map_err(|e| capnp_err(e))?,
             b_signature: Signature::from_bytes(src.get_b_signature()?).map_err(|e| capnp_err(e))?,
+            valid_until: timestamp_from_capnp(src.get_valid_until()),
+            scope: RelationScope::try_from(src.get_scope()?)?,
         })
     }
 }
@@ -128,6 +254,52 @@ impl<'a> FillFrom<RelationProof> for relation_proof::Builder<'a> {
         self.set_b_id(&src.b_id.to_bytes());
         self.set_b_pub_key(&src.b_pub_key.to_bytes());
         self.set_b_signature(&src.b_signature.to_bytes());
+        self.set_valid_until(timestamp_to_capnp(src.valid_until));
+        self.init_scope().fill_from(&src.scope);
+    }
+}
+
+impl<'a> TryFrom<relation_revocation::Reader<'a>> for RelationRevocation {
+    type Error = capnp::Error;
+
+    fn try_from(src: relation_revocation::Reader) -> Result<Self, Self::Error> {
+        Ok(RelationRevocation {
+            relation: RelationProof::try_from(src.get_relation()?)?,
+            revoker_id: ProfileId::from_bytes(src.get_revoker_id()?).map_err(|e| capnp_err(e))?,
+            signature: Signature::from_bytes(src.get_signature()?).map_err(|e| capnp_err(e))?,
+        })
+    }
+}
+
+impl<'a> FillFrom<RelationRevocation> for relation_revocation::Builder<'a> {
+    fn fill_from(mut self, src: &RelationRevocation) {
+        self.reborrow().init_relation().fill_from(&src.relation);
+        self.set_revoker_id(&src.revoker_id.to_bytes());
+        self.set_signature(&src.signature.to_bytes());
+    }
+}
+
+impl<'a> TryFrom<home_redirect::Reader<'a>> for HomeRedirect {
+    type Error = capnp::Error;
+
+    fn try_from(src: home_redirect::Reader) -> Result<Self, Self::Error> {
+        Ok(HomeRedirect {
+            profile_id: ProfileId::from_bytes(src.get_profile_id()?).map_err(|e| capnp_err(e))?,
+            old_home_id: ProfileId::from_bytes(src.get_old_home_id()?).map_err(|e| capnp_err(e))?,
+            new_home_id: ProfileId::from_bytes(src.get_new_home_id()?).map_err(|e| capnp_err(e))?,
+            valid_until: UNIX_EPOCH + Duration::from_nanos(src.get_valid_until()),
+            signature: Signature::from_bytes(src.get_signature()?).map_err(|e| capnp_err(e))?,
+        })
+    }
+}
+
+impl<'a> FillFrom<HomeRedirect> for home_redirect::Builder<'a> {
+    fn fill_from(mut self, src: &HomeRedirect) {
+        self.set_profile_id(&src.profile_id.to_bytes());
+        self.set_old_home_id(&src.old_home_id.to_bytes());
+        self.set_new_home_id(&src.new_home_id.to_bytes());
+        self.set_valid_until(timestamp_to_capnp(Some(src.valid_until)));
+        self.set_signature(&src.signature.to_bytes());
     }
 }
 
@@ -143,12 +315,15 @@ impl<'a> TryFrom<profile_event::Reader<'a>> for ProfileEvent {
             profile_event::Which::PairingResponse(proof) => {
                 Ok(ProfileEvent::PairingResponse(RelationProof::try_from(proof?)?))
             }
+            profile_event::Which::RelationRevoked(revocation) => {
+                Ok(ProfileEvent::RelationRevoked(RelationRevocation::try_from(revocation?)?))
+            }
         }
     }
 }
 
 impl<'a> FillFrom<ProfileEvent> for profile_event::Builder<'a> {
-    fn fill_from(self, src: &ProfileEvent) {
+    fn fill_from(mut self, src: &ProfileEvent) {
         match src {
             ProfileEvent::PairingRequest(half_proof) => {
                 let mut builder = self.init_pairing_request();
@@ -158,11 +333,11 @@ impl<'a> FillFrom<ProfileEvent> for profile_event::Builder<'a> {
                 let mut builder = self.init_pairing_response();
                 builder.reborrow().fill_from(proof);
             }
-            ProfileEvent::Unknown(data) => {
-                let _builder = self.init_unknown(data.len() as u32);
-                // TODO fill with data
-                // builder.reborrow().fill_with(data);
+            ProfileEvent::RelationRevoked(revocation) => {
+                let mut builder = self.init_relation_revoked();
+                builder.reborrow().fill_from(revocation);
             }
+            ProfileEvent::Unknown(data) => self.set_unknown(data),
         };
     }
 }
@@ -187,6 +362,60 @@ impl<'a> FillFrom<CallRequestDetails> for call_request::Builder<'a> {
     }
 }
 
+impl<'a> TryFrom<app_message::Reader<'a>> for AppMessage {
+    type Error = capnp::Error;
+
+    fn try_from(src: app_message::Reader) -> Result<Self, Self::Error> {
+        Ok(AppMessage {
+            id: src.get_id(),
+            relation: RelationProof::try_from(src.get_relation()?)?,
+            payload: src.get_payload()?.into(),
+        })
+    }
+}
+
+impl<'a> FillFrom<AppMessage> for app_message::Builder<'a> {
+    fn fill_from(mut self, src: &AppMessage) {
+        self.set_id(src.id);
+        self.set_payload((&src.payload).into());
+        self.init_relation().fill_from(&src.relation);
+    }
+}
+
+// NOTE PresenceStatus here is the enum generated from the schema, not the one of the API
+impl<'a> TryFrom<presence::Reader<'a>> for Presence {
+    type Error = capnp::Error;
+
+    fn try_from(src: presence::Reader) -> Result<Self, Self::Error> {
+        let status = match src.get_status()? {
+            PresenceStatus::Offline => crate::PresenceStatus::Offline,
+            PresenceStatus::Online => crate::PresenceStatus::Online,
+            PresenceStatus::Away => crate::PresenceStatus::Away,
+        };
+        let mut apps = HashMap::new();
+        for app_status in src.get_apps()?.iter() {
+            apps.insert(app_status.get_app()?.into(), app_status.get_status()?.into());
+        }
+        Ok(Presence { status, apps })
+    }
+}
+
+impl<'a> FillFrom<Presence> for presence::Builder<'a> {
+    fn fill_from(mut self, src: &Presence) {
+        self.set_status(match src.status {
+            crate::PresenceStatus::Offline => PresenceStatus::Offline,
+            crate::PresenceStatus::Online => PresenceStatus::Online,
+            crate::PresenceStatus::Away => PresenceStatus::Away,
+        });
+        let mut apps_capnp = self.init_apps(src.apps.len() as u32);
+        for (idx, (app, status)) in src.apps.iter().enumerate() {
+            let mut app_capnp = apps_capnp.reborrow().get(idx as u32);
+            app_capnp.set_app(app.into());
+            app_capnp.set_status(status.into());
+        }
+    }
+}
+
 // TODO consider using a single generic imlementation for all kinds of Dispatchers
 pub struct AppMessageDispatcherCapnProto {
     sender: AppMsgSink,
@@ -255,4 +484,24 @@ pub fn fwd_appmsg(to_callee: app_message_listener::Client) -> AppMsgSink {
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remote_error_kind_kept() {
+        let err: Error = ErrorKind::AlreadyRegistered.into();
+        let capnp_err = remote_err(err, "Failed to register profile");
+        // capnp adds its own text to the reason when the exception is received
+        let received = capnp::Error::failed(format!("remote exception: {}", capnp_err.description));
+        assert_eq!(
+            local_err(received, ErrorKind::RegisterFailed).kind(),
+            ErrorKind::AlreadyRegistered
+        );
+
+        let transport_err = capnp::Error::disconnected("connection reset".to_owned());
+        assert_eq!(
+            local_err(transport_err, ErrorKind::RegisterFailed).kind(),
+            ErrorKind::RegisterFailed
+        );
+    }
+}
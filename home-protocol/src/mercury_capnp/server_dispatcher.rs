@@ -9,7 +9,10 @@ use tokio::net::tcp::TcpStream;
 use tokio_current_thread as reactor;
 
 use super::*;
-use crate::mercury_capnp::{capnp_err, FillFrom};
+use crate::mercury_capnp::{
+    bytes_to_invitation, bytes_to_mailbox_content, capnp_err, mailbox_content_to_bytes, remote_err,
+    FillFrom,
+};
 
 pub struct HomeDispatcherCapnProto {
     home: Rc<dyn Home>,
@@ -66,10 +69,34 @@ impl mercury_capnp::profile_repo::Server for HomeDispatcherCapnProto {
             .home
             .fetch(&profile_id)
             .map(move |profile| results.get().set_profile(&profile_to_bytes(&profile)))
-            .map_err(|e| capnp::Error::failed(format!("Failed to load profile id: {:?}", e)));
+            .map_err(|e| remote_err(e, "Failed to load profile id"));
 
         Promise::from_future(load_fut)
     }
+
+    fn followers(
+        &mut self,
+        params: mercury_capnp::profile_repo::FollowersParams,
+        mut results: mercury_capnp::profile_repo::FollowersResults,
+    ) -> Promise<(), capnp::Error> {
+        let params_capnp = pry!(params.get());
+        let profile_id_capnp = pry!(params_capnp.get_profile_id());
+        let profile_id = pry!(ProfileId::from_bytes(profile_id_capnp).map_err(|e| capnp_err(e)));
+        let offset = params_capnp.get_offset() as usize;
+        let limit = params_capnp.get_limit() as usize;
+        let followers_fut = self
+            .home
+            .followers(&profile_id, offset, limit)
+            .map(move |followers| {
+                let mut followers_capnp = results.get().init_followers(followers.len() as u32);
+                for (idx, link) in followers.iter().enumerate() {
+                    followers_capnp.set(idx as u32, &link.peer_profile.to_bytes());
+                }
+            })
+            .map_err(|e| remote_err(e, "Failed to list followers"));
+
+        Promise::from_future(followers_fut)
+    }
 }
 
 impl mercury_capnp::home::Server for HomeDispatcherCapnProto {
@@ -80,11 +107,10 @@ impl mercury_capnp::home::Server for HomeDispatcherCapnProto {
     ) -> Promise<(), capnp::Error> {
         let profile_id_capnp = pry!(pry!(params.get()).get_profile_id());
         let profile_id = pry!(ProfileId::from_bytes(profile_id_capnp).map_err(|e| capnp_err(e)));
-        let claim_fut = self
-            .home
-            .claim(profile_id)
-            .map_err(|e| capnp::Error::failed(format!("Failed to claim profile: {:?}", e)))
-            .map(move |hosting_proof| results.get().init_hosting_proof().fill_from(&hosting_proof));
+        let claim_fut =
+            self.home.claim(profile_id).map_err(|e| remote_err(e, "Failed to claim profile")).map(
+                move |hosting_proof| results.get().init_hosting_proof().fill_from(&hosting_proof),
+            );
 
         Promise::from_future(claim_fut)
     }
@@ -94,17 +120,18 @@ impl mercury_capnp::home::Server for HomeDispatcherCapnProto {
         params: mercury_capnp::home::RegisterParams,
         mut results: mercury_capnp::home::RegisterResults,
     ) -> Promise<(), capnp::Error> {
-        let half_proof_capnp = pry!(pry!(params.get()).get_half_proof());
-        let half_proof = pry!(RelationHalfProof::try_from(half_proof_capnp));
-
-        //let inv_capnp_res = pry!(params.get()).get_invite();
-        //let invite_opt =
-        //    inv_capnp_res.and_then(|inv_capnp| HomeInvitation::try_from(inv_capnp)).ok();
+        let params = pry!(params.get());
+        let half_proof = pry!(RelationHalfProof::try_from(pry!(params.get_half_proof())));
+        let invite_opt = if params.has_invite() {
+            Some(pry!(bytes_to_invitation(pry!(params.get_invite()))))
+        } else {
+            None
+        };
 
         let reg_fut = self
             .home
-            .register(half_proof) //, invite_opt)
-            .map_err(|e| capnp::Error::failed(format!("Failed to register profile: {:?}", e)))
+            .register(half_proof, invite_opt)
+            .map_err(|e| remote_err(e, "Failed to register profile"))
             .map(move |proof| results.get().init_hosting_proof().fill_from(&proof));
 
         Promise::from_future(reg_fut)
@@ -128,7 +155,7 @@ impl mercury_capnp::home::Server for HomeDispatcherCapnProto {
                 results.get().set_session(session);
                 ()
             })
-            .map_err(|e| capnp::Error::failed(format!("Failed to login: {:?}", e)));
+            .map_err(|e| remote_err(e, "Failed to login"));
 
         Promise::from_future(session_fut)
     }
@@ -144,7 +171,7 @@ impl mercury_capnp::home::Server for HomeDispatcherCapnProto {
         let pair_req_fut = self
             .home
             .pair_request(half_proof)
-            .map_err(|e| capnp::Error::failed(format!("Failed to request pairing {:?}", e)));
+            .map_err(|e| remote_err(e, "Failed to request pairing"));
 
         Promise::from_future(pair_req_fut)
     }
@@ -160,7 +187,7 @@ impl mercury_capnp::home::Server for HomeDispatcherCapnProto {
         let pair_resp_fut = self
             .home
             .pair_response(proof)
-            .map_err(|e| capnp::Error::failed(format!("Failed to send pairing response: {:?}", e)));
+            .map_err(|e| remote_err(e, "Failed to send pairing response"));
 
         Promise::from_future(pair_resp_fut)
     }
@@ -198,10 +225,110 @@ impl mercury_capnp::home::Server for HomeDispatcherCapnProto {
                     results.get().set_to_callee(to_callee_capnp);
                 });
             })
-            .map_err(|e| capnp::Error::failed(format!("Failed to call profile: {:?}", e)));
+            .map_err(|e| remote_err(e, "Failed to call profile"));
 
         Promise::from_future(call_fut)
     }
+
+    fn send_message(
+        &mut self,
+        params: mercury_capnp::home::SendMessageParams,
+        mut _results: mercury_capnp::home::SendMessageResults,
+    ) -> Promise<(), capnp::Error> {
+        let opts = pry!(params.get());
+        let relation = pry!(RelationProof::try_from(pry!(opts.get_relation())));
+        let app = ApplicationId::from(pry!(opts.get_app()));
+        let message = AppMessageFrame::from(pry!(opts.get_message()));
+
+        let send_fut = self
+            .home
+            .send_message(relation, app, message)
+            .map_err(|e| remote_err(e, "Failed to send message"));
+
+        Promise::from_future(send_fut)
+    }
+
+    fn presence(
+        &mut self,
+        params: mercury_capnp::home::PresenceParams,
+        mut results: mercury_capnp::home::PresenceResults,
+    ) -> Promise<(), capnp::Error> {
+        let relation = pry!(RelationProof::try_from(pry!(pry!(params.get()).get_relation())));
+
+        let presence_fut = self
+            .home
+            .presence(relation)
+            .map(move |presence| results.get().init_presence().fill_from(&presence))
+            .map_err(|e| remote_err(e, "Failed to get presence"));
+
+        Promise::from_future(presence_fut)
+    }
+
+    fn subscribe_presence(
+        &mut self,
+        params: mercury_capnp::home::SubscribePresenceParams,
+        mut _results: mercury_capnp::home::SubscribePresenceResults,
+    ) -> Promise<(), capnp::Error> {
+        let params = pry!(params.get());
+        let relation = pry!(RelationProof::try_from(pry!(params.get_relation())));
+        let callback = pry!(params.get_listener());
+
+        let presence_fut = self
+            .home
+            .subscribe_presence(relation)
+            .map_err(|()| {
+                remote_err(ErrorKind::FailedToGetPresence, "Failed to get presence updates")
+            })
+            .for_each(move |item| match item {
+                Ok(presence) => {
+                    let mut request = callback.receive_request();
+                    request.get().init_presence().fill_from(&presence);
+                    let fut = request.send().promise.map(|_resp| ());
+                    Box::new(fut) as AsyncResult<(), capnp::Error>
+                }
+                Err(err) => {
+                    let mut request = callback.error_request();
+                    request.get().set_error(&err);
+                    let fut = request.send().promise.map(|_resp| ());
+                    Box::new(fut)
+                }
+            });
+
+        Promise::from_future(presence_fut)
+    }
+
+    fn revoke_relation(
+        &mut self,
+        params: mercury_capnp::home::RevokeRelationParams,
+        mut _results: mercury_capnp::home::RevokeRelationResults,
+    ) -> Promise<(), capnp::Error> {
+        let revocation_capnp = pry!(pry!(params.get()).get_revocation());
+        let revocation = pry!(RelationRevocation::try_from(revocation_capnp));
+
+        let revoke_fut = self
+            .home
+            .revoke_relation(revocation)
+            .map_err(|e| remote_err(e, "Failed to revoke relation"));
+
+        Promise::from_future(revoke_fut)
+    }
+
+    fn redirect(
+        &mut self,
+        params: mercury_capnp::home::RedirectParams,
+        mut results: mercury_capnp::home::RedirectResults,
+    ) -> Promise<(), capnp::Error> {
+        let profile_id_capnp = pry!(pry!(params.get()).get_profile_id());
+        let profile_id = pry!(ProfileId::from_bytes(profile_id_capnp).map_err(|e| capnp_err(e)));
+
+        let redirect_fut = self
+            .home
+            .redirect(&profile_id)
+            .map(move |redirect| results.get().init_redirect().fill_from(&redirect))
+            .map_err(|e| remote_err(e, "Failed to get redirect"));
+
+        Promise::from_future(redirect_fut)
+    }
 }
 
 pub struct HomeSessionDispatcherCapnProto {
@@ -230,10 +357,8 @@ impl mercury_capnp::home_session::Server for HomeSessionDispatcherCapnProto {
         let own_profile_capnp = pry!(pry!(params.get()).get_own_profile());
         let own_profile = pry!(bytes_to_own_profile(own_profile_capnp));
 
-        let upd_fut = self
-            .session
-            .backup(own_profile)
-            .map_err(|e| capnp::Error::failed(format!("Failed to update profile: {:?}", e)));
+        let upd_fut =
+            self.session.backup(own_profile).map_err(|e| remote_err(e, "Failed to update profile"));
 
         Promise::from_future(upd_fut)
     }
@@ -248,7 +373,7 @@ impl mercury_capnp::home_session::Server for HomeSessionDispatcherCapnProto {
             .restore()
             .map(|own_prof| own_profile_to_bytes(&own_prof))
             .map(move |own_bytes| results.get().set_own_profile(&own_bytes))
-            .map_err(|e| capnp::Error::failed(format!("Failed to update profile: {:?}", e)));
+            .map_err(|e| remote_err(e, "Failed to update profile"));
 
         Promise::from_future(upd_fut)
     }
@@ -265,7 +390,7 @@ impl mercury_capnp::home_session::Server for HomeSessionDispatcherCapnProto {
         let upd_fut = self
             .session
             .unregister(new_home_opt)
-            .map_err(|e| capnp::Error::failed(format!("Failed to unregister profile: {:?}", e)));
+            .map_err(|e| remote_err(e, "Failed to unregister profile"));
 
         Promise::from_future(upd_fut)
     }
@@ -279,7 +404,7 @@ impl mercury_capnp::home_session::Server for HomeSessionDispatcherCapnProto {
         let ping_fut = self
             .session
             .ping(txt)
-            .map_err(|e| capnp::Error::failed(format!("Failed ping: {:?}", e)))
+            .map_err(|e| remote_err(e, "Failed ping"))
             .map(move |pong| results.get().set_pong(&pong));
         Promise::from_future(ping_fut)
     }
@@ -293,7 +418,7 @@ impl mercury_capnp::home_session::Server for HomeSessionDispatcherCapnProto {
         let events_fut = self
             .session
             .events()
-            .map_err(|e| capnp::Error::failed(format!("Failed to get profile events: {:?}", e)))
+            .map_err(|()| remote_err(ErrorKind::FailedToPushEvent, "Failed to get profile events"))
             .for_each(move |item| {
                 debug!("Capnp server is forwarding event to the client: {:?}", item);
                 match item {
@@ -317,6 +442,162 @@ impl mercury_capnp::home_session::Server for HomeSessionDispatcherCapnProto {
         Promise::from_future(events_fut)
     }
 
+    fn ack_event(
+        &mut self,
+        params: mercury_capnp::home_session::AckEventParams,
+        mut _results: mercury_capnp::home_session::AckEventResults,
+    ) -> Promise<(), capnp::Error> {
+        let event_capnp = pry!(pry!(params.get()).get_event());
+        let event = pry!(ProfileEvent::try_from(event_capnp));
+
+        let ack_fut = self
+            .session
+            .ack_event(&event)
+            .map_err(|e| remote_err(e, "Failed to acknowledge event"));
+
+        Promise::from_future(ack_fut)
+    }
+
+    fn messages(
+        &mut self,
+        params: mercury_capnp::home_session::MessagesParams,
+        mut results: mercury_capnp::home_session::MessagesResults,
+    ) -> Promise<(), capnp::Error> {
+        let app = ApplicationId::from(pry!(pry!(params.get()).get_app()));
+
+        let messages_fut = self
+            .session
+            .messages(&app)
+            .map(move |messages| {
+                let mut messages_capnp = results.get().init_messages(messages.len() as u32);
+                for (idx, message) in messages.iter().enumerate() {
+                    messages_capnp.reborrow().get(idx as u32).fill_from(message);
+                }
+            })
+            .map_err(|e| remote_err(e, "Failed to load messages"));
+
+        Promise::from_future(messages_fut)
+    }
+
+    fn ack_messages(
+        &mut self,
+        params: mercury_capnp::home_session::AckMessagesParams,
+        mut _results: mercury_capnp::home_session::AckMessagesResults,
+    ) -> Promise<(), capnp::Error> {
+        let params = pry!(params.get());
+        let app = ApplicationId::from(pry!(params.get_app()));
+        let ids = pry!(params.get_ids()).iter().collect::<Vec<_>>();
+
+        let ack_fut = self
+            .session
+            .ack_messages(&app, &ids)
+            .map_err(|e| remote_err(e, "Failed to acknowledge messages"));
+
+        Promise::from_future(ack_fut)
+    }
+
+    fn set_presence(
+        &mut self,
+        params: mercury_capnp::home_session::SetPresenceParams,
+        mut _results: mercury_capnp::home_session::SetPresenceResults,
+    ) -> Promise<(), capnp::Error> {
+        let presence = pry!(Presence::try_from(pry!(pry!(params.get()).get_presence())));
+
+        let set_fut = self
+            .session
+            .set_presence(presence)
+            .map_err(|e| remote_err(e, "Failed to set presence"));
+
+        Promise::from_future(set_fut)
+    }
+
+    fn banned_profiles(
+        &mut self,
+        _params: mercury_capnp::home_session::BannedProfilesParams,
+        mut results: mercury_capnp::home_session::BannedProfilesResults,
+    ) -> Promise<(), capnp::Error> {
+        let banned_fut = self
+            .session
+            .banned_profiles()
+            .map(move |profiles| {
+                let mut profiles_capnp = results.get().init_profiles(profiles.len() as u32);
+                for (idx, profile) in profiles.iter().enumerate() {
+                    profiles_capnp.set(idx as u32, &profile.to_bytes());
+                }
+            })
+            .map_err(|e| remote_err(e, "Failed to load ban list"));
+
+        Promise::from_future(banned_fut)
+    }
+
+    fn ban(
+        &mut self,
+        params: mercury_capnp::home_session::BanParams,
+        mut _results: mercury_capnp::home_session::BanResults,
+    ) -> Promise<(), capnp::Error> {
+        let profile_capnp = pry!(pry!(params.get()).get_profile());
+        let profile = pry!(ProfileId::from_bytes(profile_capnp).map_err(|e| capnp_err(e)));
+
+        let ban_fut =
+            self.session.ban(&profile).map_err(|e| remote_err(e, "Failed to ban profile"));
+
+        Promise::from_future(ban_fut)
+    }
+
+    fn unban(
+        &mut self,
+        params: mercury_capnp::home_session::UnbanParams,
+        mut _results: mercury_capnp::home_session::UnbanResults,
+    ) -> Promise<(), capnp::Error> {
+        let profile_capnp = pry!(pry!(params.get()).get_profile());
+        let profile = pry!(ProfileId::from_bytes(profile_capnp).map_err(|e| capnp_err(e)));
+
+        let unban_fut =
+            self.session.unban(&profile).map_err(|e| remote_err(e, "Failed to unban profile"));
+
+        Promise::from_future(unban_fut)
+    }
+
+    fn export_mailbox(
+        &mut self,
+        _params: mercury_capnp::home_session::ExportMailboxParams,
+        mut results: mercury_capnp::home_session::ExportMailboxResults,
+    ) -> Promise<(), capnp::Error> {
+        let export_fut = self
+            .session
+            .export_mailbox()
+            .map(move |content| results.get().set_content(&mailbox_content_to_bytes(&content)))
+            .map_err(|e| remote_err(e, "Failed to export mailbox"));
+
+        Promise::from_future(export_fut)
+    }
+
+    fn import_mailbox(
+        &mut self,
+        params: mercury_capnp::home_session::ImportMailboxParams,
+        mut _results: mercury_capnp::home_session::ImportMailboxResults,
+    ) -> Promise<(), capnp::Error> {
+        let content_capnp = pry!(pry!(params.get()).get_content());
+        let content = pry!(bytes_to_mailbox_content(content_capnp));
+
+        let import_fut = self
+            .session
+            .import_mailbox(content)
+            .map_err(|e| remote_err(e, "Failed to import mailbox"));
+
+        Promise::from_future(import_fut)
+    }
+
+    fn heartbeat(
+        &mut self,
+        _params: mercury_capnp::home_session::HeartbeatParams,
+        mut _results: mercury_capnp::home_session::HeartbeatResults,
+    ) -> Promise<(), capnp::Error> {
+        let heartbeat_fut =
+            self.session.heartbeat().map_err(|e| remote_err(e, "Failed to keep session alive"));
+        Promise::from_future(heartbeat_fut)
+    }
+
     fn checkin_app(
         &mut self,
         params: mercury_capnp::home_session::CheckinAppParams,
@@ -1,3 +1,5 @@
+use std::collections::BTreeMap;
+
 use serde::{de::Error as DeSerError, ser::SerializeSeq};
 use serde::{Deserialize as DeSer, Deserializer, Serializer};
 use serde_derive::{Deserialize, Serialize};
@@ -8,7 +10,10 @@ use multiaddr::{Multiaddr, ToMultiaddr};
 
 use crate::*;
 
-pub use claims::model::{AttributeId, AttributeMap, AttributeValue, ProfileId, Signature, Version};
+pub use claims::model::{
+    AttributeId, AttributeKind, AttributeMap, AttributeValue, GpsLocation, ProfileId, Signature,
+    TimeStamp, Version,
+};
 pub use did::model::{PrivateKey, PublicKey};
 
 pub type Profile = claims::model::PublicProfileData;
@@ -34,9 +39,9 @@ impl HostedFacet {
     }
 
     fn set_attribute(&self, attributes: &mut AttributeMap) {
-        let facet_str = serde_json::to_string(&self)
+        let facet = AttributeValue::from_serializable(&self)
             .expect("This can fail only with failing custom Serialize() or having non-string keys");
-        attributes.insert(Self::ATTRIBUTE_ID.to_string(), facet_str);
+        attributes.insert(Self::ATTRIBUTE_ID.to_string(), facet);
     }
 
     pub fn to_attribute_map(&self) -> AttributeMap {
@@ -46,9 +51,7 @@ impl HostedFacet {
     }
 
     fn to_hosted(attributes: &AttributeMap) -> Option<Self> {
-        attributes
-            .get(Self::ATTRIBUTE_ID)
-            .and_then(|facet_str| serde_json::from_str(facet_str).ok())
+        attributes.get(Self::ATTRIBUTE_ID).and_then(|facet| facet.to_deserializable().ok())
     }
 }
 
@@ -70,9 +73,9 @@ impl HomeFacet {
     }
 
     fn set_attributes(&self, attributes: &mut AttributeMap) {
-        let facet_str = serde_json::to_string(&self)
+        let facet = AttributeValue::from_serializable(&self)
             .expect("This can fail only with failing custom Serialize() or having non-string keys");
-        attributes.insert(Self::ATTRIBUTE_ID.to_string(), facet_str);
+        attributes.insert(Self::ATTRIBUTE_ID.to_string(), facet);
     }
 
     pub fn to_attribute_map(&self) -> AttributeMap {
@@ -82,9 +85,7 @@ impl HomeFacet {
     }
 
     fn to_home(attributes: &AttributeMap) -> Option<Self> {
-        attributes
-            .get(Self::ATTRIBUTE_ID)
-            .and_then(|facet_str| serde_json::from_str(facet_str).ok())
+        attributes.get(Self::ATTRIBUTE_ID).and_then(|facet| facet.to_deserializable().ok())
     }
 }
 
@@ -133,6 +134,30 @@ impl ProfileFacets for Profile {
     }
 }
 
+/// Structured details of a relation besides its type, signed by both peers.
+#[derive(Clone, Debug, Default, Deserialize, Eq, Hash, PartialEq, PartialOrd, Serialize)]
+pub struct RelationScope {
+    /// Applications allowed to use the relation, any application may use it if empty
+    #[serde(default)]
+    pub apps: Vec<ApplicationId>,
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl RelationScope {
+    pub fn new(apps: Vec<ApplicationId>, metadata: BTreeMap<String, String>) -> Self {
+        Self { apps, metadata }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.apps.is_empty() && self.metadata.is_empty()
+    }
+
+    pub fn allows(&self, app: &ApplicationId) -> bool {
+        self.apps.is_empty() || self.apps.contains(app)
+    }
+}
+
 // NOTE the binary blob to be signed is rust-specific: Strings are serialized to a u64 (size) and the encoded string itself.
 // TODO consider if this is platform-agnostic enough, especially when combined with capnproto
 #[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, PartialOrd, Serialize)]
@@ -140,6 +165,12 @@ pub struct RelationSignablePart {
     pub relation_type: String,
     pub signer_id: ProfileId,
     pub peer_id: ProfileId,
+    // NOTE skipped if missing to keep signatures of relations created without expiry valid
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub valid_until: Option<TimeStamp>,
+    // NOTE skipped if empty for the same reason as valid_until
+    #[serde(default, skip_serializing_if = "RelationScope::is_empty")]
+    pub scope: RelationScope,
     // TODO is a nonce needed?
 }
 
@@ -151,20 +182,64 @@ pub struct RelationHalfProof {
     pub signer_pubkey: PublicKey,
     pub peer_id: ProfileId,
     pub signature: Signature,
+    /// The relation cannot be used after this time, it never expires if not set
+    #[serde(default)]
+    pub valid_until: Option<TimeStamp>,
+    #[serde(default)]
+    pub scope: RelationScope,
     // TODO is a nonce needed?
 }
 
 impl RelationHalfProof {
     pub fn new(relation_type: &str, peer_id: &ProfileId, signer: &dyn Signer) -> Fallible<Self> {
-        let signable = RelationSignablePart::new(relation_type, signer.profile_id(), peer_id);
+        Self::new_scoped(relation_type, peer_id, RelationScope::default(), None, signer)
+    }
+
+    /// The peer signing the remaining half accepts the same expiry, it cannot be changed.
+    pub fn new_expiring(
+        relation_type: &str,
+        peer_id: &ProfileId,
+        valid_until: TimeStamp,
+        signer: &dyn Signer,
+    ) -> Fallible<Self> {
+        Self::new_scoped(
+            relation_type,
+            peer_id,
+            RelationScope::default(),
+            Some(valid_until),
+            signer,
+        )
+    }
+
+    /// The peer signing the remaining half accepts the same scope, it cannot be changed.
+    pub fn new_scoped(
+        relation_type: &str,
+        peer_id: &ProfileId,
+        scope: RelationScope,
+        valid_until: Option<TimeStamp>,
+        signer: &dyn Signer,
+    ) -> Fallible<Self> {
+        let signable = RelationSignablePart::new(
+            relation_type,
+            signer.profile_id(),
+            peer_id,
+            valid_until,
+            &scope,
+        );
         Ok(Self {
             relation_type: relation_type.to_owned(),
             signer_id: signer.profile_id().to_owned(),
             signer_pubkey: signer.public_key(),
             peer_id: peer_id.to_owned(),
             signature: signable.sign(signer)?,
+            valid_until,
+            scope,
         })
     }
+
+    pub fn is_expired_at(&self, time: TimeStamp) -> bool {
+        self.valid_until.map_or(false, |valid_until| valid_until <= time)
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
@@ -176,33 +251,194 @@ pub struct RelationProof {
     pub b_id: ProfileId,
     pub b_pub_key: PublicKey,
     pub b_signature: Signature,
+    #[serde(default)]
+    pub valid_until: Option<TimeStamp>,
+    #[serde(default)]
+    pub scope: RelationScope,
     // TODO is a nonce needed?
 }
 
-// TODO should we ignore this in early stages?
 /// This invitation allows a persona to register on the specified home.
-//#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, PartialOrd, Serialize)]
-//pub struct HomeInvitation {
-//    pub home_id: ProfileId,
-//
-//    /// A unique string that identifies the invitation
-//    pub voucher: String,
-//
-//    /// The signature of the home
-//    pub signature: Signature,
-//    // TODO is a nonce needed?
-//    // TODO is an expiration time needed?
-//}
-//
-//impl HomeInvitation {
-//    pub fn new(home_id: &ProfileId, voucher: &str, signature: &Signature) -> Self {
-//        Self {
-//            home_id: home_id.to_owned(),
-//            voucher: voucher.to_owned(),
-//            signature: signature.to_owned(),
-//        }
-//    }
-//}
+/// Vouchers are issued by the home operator, each of them can be used for a single registration.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct HomeInvitation {
+    pub home_id: ProfileId,
+
+    /// A unique string that identifies the invitation
+    pub voucher: String,
+
+    /// The signature of the home
+    pub signature: Signature,
+    // TODO is an expiration time needed?
+}
+
+impl HomeInvitation {
+    pub fn new(voucher: &str, home_signer: &dyn Signer) -> Fallible<Self> {
+        let signable = HomeInvitationSignablePart::new(home_signer.profile_id(), voucher);
+        Ok(Self {
+            home_id: home_signer.profile_id().to_owned(),
+            voucher: voucher.to_owned(),
+            signature: signable.sign(home_signer)?,
+        })
+    }
+}
+
+// NOTE serialized the same rust-specific way as RelationSignablePart
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, PartialOrd, Serialize)]
+pub struct HomeInvitationSignablePart {
+    pub home_id: ProfileId,
+    pub voucher: String,
+}
+
+impl HomeInvitationSignablePart {
+    pub(crate) fn new(home_id: &ProfileId, voucher: &str) -> Self {
+        Self { home_id: home_id.to_owned(), voucher: voucher.to_owned() }
+    }
+
+    pub(crate) fn serialized(&self) -> Vec<u8> {
+        // NOTE serializing a String and a ProfileId cannot fail, see RelationSignablePart::serialized()
+        serialize(self).unwrap()
+    }
+
+    fn sign(&self, signer: &dyn Signer) -> Fallible<Signature> {
+        signer.sign(&self.serialized())
+    }
+}
+
+/// Signed statement of a profile that a relation with its peer must not be used anymore.
+/// It is sent to the homes of both profiles, which refuse requests over the relation afterwards.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct RelationRevocation {
+    pub relation: RelationProof,
+    pub revoker_id: ProfileId,
+    pub signature: Signature,
+}
+
+impl RelationRevocation {
+    pub fn new(relation: &RelationProof, signer: &dyn Signer) -> Result<Self, Error> {
+        let signable = RelationRevocationSignablePart::new(relation, signer.profile_id())?;
+        Ok(Self {
+            relation: relation.to_owned(),
+            revoker_id: signer.profile_id().to_owned(),
+            signature: signable
+                .sign(signer)
+                .map_err(|e| e.context(ErrorKind::RelationSigningFailed))?,
+        })
+    }
+
+    pub fn peer_id(&self) -> Result<&ProfileId, Error> {
+        self.relation.peer_id(&self.revoker_id)
+    }
+
+    pub fn revokes(&self, relation: &RelationProof) -> bool {
+        self.relation == *relation
+    }
+}
+
+// NOTE serialized the same rust-specific way as RelationSignablePart,
+//      signatures of the relation identify the revoked proof
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct RelationRevocationSignablePart {
+    pub relation_type: String,
+    pub revoker_id: ProfileId,
+    pub peer_id: ProfileId,
+    pub a_signature: Signature,
+    pub b_signature: Signature,
+}
+
+impl RelationRevocationSignablePart {
+    pub(crate) fn new(relation: &RelationProof, revoker_id: &ProfileId) -> Result<Self, Error> {
+        Ok(Self {
+            relation_type: relation.relation_type.to_owned(),
+            revoker_id: revoker_id.to_owned(),
+            peer_id: relation.peer_id(revoker_id)?.to_owned(),
+            a_signature: relation.a_signature.to_owned(),
+            b_signature: relation.b_signature.to_owned(),
+        })
+    }
+
+    pub(crate) fn serialized(&self) -> Vec<u8> {
+        // NOTE serializing Strings, ProfileIds and Signatures cannot fail, see RelationSignablePart::serialized()
+        serialize(self).unwrap()
+    }
+
+    fn sign(&self, signer: &dyn Signer) -> Fallible<Signature> {
+        signer.sign(&self.serialized())
+    }
+}
+
+/// Signed by the old home of a profile that moved to a new home. The old home answers requests
+/// to the profile with ProfileMoved and serves this redirect until it expires.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct HomeRedirect {
+    pub profile_id: ProfileId,
+    pub old_home_id: ProfileId,
+    pub new_home_id: ProfileId,
+    pub valid_until: TimeStamp,
+    /// The signature of the old home
+    pub signature: Signature,
+}
+
+impl HomeRedirect {
+    pub fn new(
+        profile_id: &ProfileId,
+        new_home_id: &ProfileId,
+        valid_until: TimeStamp,
+        old_home_signer: &dyn Signer,
+    ) -> Fallible<Self> {
+        let signable = HomeRedirectSignablePart::new(
+            profile_id,
+            old_home_signer.profile_id(),
+            new_home_id,
+            valid_until,
+        );
+        Ok(Self {
+            profile_id: profile_id.to_owned(),
+            old_home_id: old_home_signer.profile_id().to_owned(),
+            new_home_id: new_home_id.to_owned(),
+            valid_until,
+            signature: signable.sign(old_home_signer)?,
+        })
+    }
+
+    pub fn is_expired_at(&self, time: TimeStamp) -> bool {
+        self.valid_until <= time
+    }
+}
+
+// NOTE serialized the same rust-specific way as RelationSignablePart
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, PartialOrd, Serialize)]
+pub struct HomeRedirectSignablePart {
+    pub profile_id: ProfileId,
+    pub old_home_id: ProfileId,
+    pub new_home_id: ProfileId,
+    pub valid_until: TimeStamp,
+}
+
+impl HomeRedirectSignablePart {
+    pub(crate) fn new(
+        profile_id: &ProfileId,
+        old_home_id: &ProfileId,
+        new_home_id: &ProfileId,
+        valid_until: TimeStamp,
+    ) -> Self {
+        Self {
+            profile_id: profile_id.to_owned(),
+            old_home_id: old_home_id.to_owned(),
+            new_home_id: new_home_id.to_owned(),
+            valid_until,
+        }
+    }
+
+    pub(crate) fn serialized(&self) -> Vec<u8> {
+        // NOTE serializing ProfileIds and a TimeStamp cannot fail, see RelationSignablePart::serialized()
+        serialize(self).unwrap()
+    }
+
+    fn sign(&self, signer: &dyn Signer) -> Fallible<Signature> {
+        signer.sign(&self.serialized())
+    }
+}
 
 pub fn serialize_multiaddr_vec<S>(x: &Vec<Multiaddr>, s: S) -> std::result::Result<S::Ok, S::Error>
 where
@@ -245,11 +481,19 @@ where
 
 // TODO use the same kind of ContentId/Hash as in mercury_claims::model::content_id()
 impl RelationSignablePart {
-    pub(crate) fn new(relation_type: &str, signer_id: &ProfileId, peer_id: &ProfileId) -> Self {
+    pub(crate) fn new(
+        relation_type: &str,
+        signer_id: &ProfileId,
+        peer_id: &ProfileId,
+        valid_until: Option<TimeStamp>,
+        scope: &RelationScope,
+    ) -> Self {
         Self {
             relation_type: relation_type.to_owned(),
             signer_id: signer_id.to_owned(),
             peer_id: peer_id.to_owned(),
+            valid_until,
+            scope: scope.to_owned(),
         }
     }
 
@@ -272,6 +516,8 @@ impl<'a> From<&'a RelationHalfProof> for RelationSignablePart {
             relation_type: src.relation_type.clone(),
             signer_id: src.signer_id.clone(),
             peer_id: src.peer_id.clone(),
+            valid_until: src.valid_until,
+            scope: src.scope.clone(),
         }
     }
 }
@@ -288,6 +534,8 @@ impl RelationProof {
         b_id: &ProfileId,
         b_pubkey: &PublicKey,
         b_signature: &Signature,
+        valid_until: Option<TimeStamp>,
+        scope: RelationScope,
     ) -> Self {
         if a_id < b_id {
             Self {
@@ -298,6 +546,8 @@ impl RelationProof {
                 b_id: b_id.to_owned(),
                 b_pub_key: b_pubkey.to_owned(),
                 b_signature: b_signature.to_owned(),
+                valid_until,
+                scope,
             }
         }
         // TODO decide on inverting relation_type if needed, e.g. `a_is_home_of_b` vs `b_is_home_of_a`
@@ -310,6 +560,8 @@ impl RelationProof {
                 b_id: a_id.to_owned(),
                 b_pub_key: a_pubkey.to_owned(),
                 b_signature: a_signature.to_owned(),
+                valid_until,
+                scope,
             }
         }
     }
@@ -327,6 +579,8 @@ impl RelationProof {
             &half_proof.relation_type,
             my_profile_id,
             &half_proof.signer_id,
+            half_proof.valid_until,
+            &half_proof.scope,
         );
         Ok(Self::new(
             &half_proof.relation_type,
@@ -338,12 +592,17 @@ impl RelationProof {
             &signable
                 .sign(signer)
                 .map_err(|e| e.context(ErrorKind::RelationSigningFailed.into()))?,
+            half_proof.valid_until,
+            half_proof.scope.clone(),
         ))
     }
 
-    // TODO relation-type should be more sophisticated once we have a proper metainfo schema there
+    pub fn is_expired_at(&self, time: TimeStamp) -> bool {
+        self.valid_until.map_or(false, |valid_until| valid_until <= time)
+    }
+
     pub fn accessible_by(&self, app: &ApplicationId) -> bool {
-        self.relation_type == app.0
+        self.scope.allows(app)
     }
 
     pub fn peer_id(&self, my_id: &ProfileId) -> Result<&ProfileId, Error> {
@@ -356,6 +615,16 @@ impl RelationProof {
         Err(ErrorKind::PeerIdRetreivalFailed)?
     }
 
+    pub fn peer_pubkey(&self, my_id: &ProfileId) -> Result<&PublicKey, Error> {
+        if self.a_id == *my_id {
+            return Ok(&self.b_pub_key);
+        }
+        if self.b_id == *my_id {
+            return Ok(&self.a_pub_key);
+        }
+        Err(ErrorKind::PeerIdRetreivalFailed)?
+    }
+
     pub fn peer_signature(&self, my_id: &ProfileId) -> Result<&Signature, Error> {
         if self.a_id == *my_id {
             return Ok(&self.b_signature);
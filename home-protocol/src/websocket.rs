@@ -0,0 +1,474 @@
+use std::cell::RefCell;
+use std::cmp::min;
+use std::io::{Read, Write};
+use std::rc::Rc;
+
+use failure::Fail;
+use futures::future::{loop_fn, Loop};
+use tokio::io::{self, AsyncRead, AsyncWrite};
+
+use crate::*;
+
+// Appended to the key of the client to prove that the server understood the upgrade request
+const ACCEPT_KEY_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const MAX_HTTP_HEAD_SIZE: usize = 8096;
+
+/// Maximum number of payload bytes accepted in a single WebSocket frame
+pub const MAX_FRAME_PAYLOAD_SIZE: usize = 1024 * 1024;
+const FIN_BIT: u8 = 0x80;
+const MASK_BIT: u8 = 0x80;
+const MASK_KEY_SIZE: usize = 4;
+const OPCODE_MASK: u8 = 0x0F;
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xA;
+
+fn websocket_err(err: impl Fail) -> Error {
+    err.context(ErrorKind::WebSocketHandshakeFailed).into()
+}
+
+fn invalid_data(msg: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg)
+}
+
+fn accept_key(client_key: &str) -> String {
+    let mut sha1 = sha1::Sha1::new();
+    sha1.update(client_key.as_bytes());
+    sha1.update(ACCEPT_KEY_GUID.as_bytes());
+    base64::encode(&sha1.digest().bytes())
+}
+
+// NOTE the peer sends nothing after its HTTP head until it gets an answer,
+//      so reading it byte by byte cannot consume the first frames
+fn read_http_head<R>(reader: R) -> AsyncResult<(R, String), std::io::Error>
+where
+    R: std::io::Read + AsyncRead + 'static,
+{
+    let read_fut = loop_fn((reader, Vec::new()), |(reader, mut head)| {
+        io::read_exact(reader, [0u8; 1]).and_then(move |(reader, byte)| {
+            head.push(byte[0]);
+            if head.ends_with(b"\r\n\r\n") {
+                let head =
+                    String::from_utf8(head).map_err(|_e| invalid_data("Invalid HTTP head"))?;
+                Ok(Loop::Break((reader, head)))
+            } else if head.len() > MAX_HTTP_HEAD_SIZE {
+                Err(invalid_data("HTTP head is too large"))
+            } else {
+                Ok(Loop::Continue((reader, head)))
+            }
+        })
+    });
+    Box::new(read_fut)
+}
+
+fn header_value<'a>(head: &'a str, name: &str) -> Option<&'a str> {
+    head.lines().skip(1).find_map(|line| {
+        let mut parts = line.splitn(2, ':');
+        let header = parts.next()?.trim();
+        let value = parts.next()?.trim();
+        if header.eq_ignore_ascii_case(name) {
+            Some(value)
+        } else {
+            None
+        }
+    })
+}
+
+/// Answers the WebSocket upgrade request of a client, e.g. a browser, on a connection accepted.
+/// All further traffic is sent in binary frames, e.g. to run `ecdh_handshake()` on them.
+pub fn accept<R, W>(
+    reader: R,
+    writer: W,
+) -> AsyncResult<(WebSocketReader<R>, WebSocketWriter<W>), Error>
+where
+    R: std::io::Read + AsyncRead + 'static,
+    W: std::io::Write + AsyncWrite + 'static,
+{
+    let accept_fut = read_http_head(reader)
+        .map_err(websocket_err)
+        .and_then(|(reader, head)| {
+            let upgrade = header_value(&head, "Upgrade").unwrap_or_default();
+            let is_upgrade = head.starts_with("GET ") && upgrade.eq_ignore_ascii_case("websocket");
+            let client_key = match header_value(&head, "Sec-WebSocket-Key") {
+                Some(key) if is_upgrade => key,
+                _ => return Err(ErrorKind::WebSocketHandshakeFailed.into()),
+            };
+            let response = format!(
+                "HTTP/1.1 101 Switching Protocols\r\n\
+                 Upgrade: websocket\r\n\
+                 Connection: Upgrade\r\n\
+                 Sec-WebSocket-Accept: {}\r\n\r\n",
+                accept_key(client_key)
+            );
+            Ok((reader, response))
+        })
+        .and_then(|(reader, response)| {
+            io::write_all(writer, response.into_bytes())
+                .map(move |(writer, _buf)| websocket(reader, writer, false))
+                .map_err(websocket_err)
+        });
+    Box::new(accept_fut)
+}
+
+/// Upgrades a connection opened to `host` to a WebSocket, the client side of `accept()`.
+pub fn connect<R, W>(
+    reader: R,
+    writer: W,
+    host: &str,
+    path: &str,
+) -> AsyncResult<(WebSocketReader<R>, WebSocketWriter<W>), Error>
+where
+    R: std::io::Read + AsyncRead + 'static,
+    W: std::io::Write + AsyncWrite + 'static,
+{
+    let client_key = base64::encode(&rand::random::<[u8; 16]>());
+    let expected_key = accept_key(&client_key);
+    let request = format!(
+        "GET {} HTTP/1.1\r\n\
+         Host: {}\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Key: {}\r\n\
+         Sec-WebSocket-Version: 13\r\n\r\n",
+        path, host, client_key
+    );
+
+    let connect_fut = io::write_all(writer, request.into_bytes())
+        .and_then(move |(writer, _buf)| {
+            read_http_head(reader).map(|(reader, head)| (reader, writer, head))
+        })
+        .map_err(websocket_err)
+        .and_then(move |(reader, writer, head)| {
+            let accepted = header_value(&head, "Sec-WebSocket-Accept") == Some(&expected_key[..]);
+            if !head.starts_with("HTTP/1.1 101 ") || !accepted {
+                debug!("Server refused WebSocket upgrade: {}", head);
+                return Err(ErrorKind::WebSocketHandshakeFailed.into());
+            }
+            Ok(websocket(reader, writer, true))
+        });
+    Box::new(connect_fut)
+}
+
+// Frames of clients are masked as required by RFC 6455, frames of servers are not
+fn websocket<R, W>(reader: R, writer: W, client: bool) -> (WebSocketReader<R>, WebSocketWriter<W>)
+where
+    W: Write + 'static,
+{
+    let sender = Rc::new(RefCell::new(FrameSender::new(writer, client)));
+    (WebSocketReader::new(reader, !client, sender.clone()), WebSocketWriter { sender })
+}
+
+fn apply_mask(data: &mut [u8], key: &[u8]) {
+    for (idx, byte) in data.iter_mut().enumerate() {
+        *byte ^= key[idx % MASK_KEY_SIZE];
+    }
+}
+
+// Returns the size of the frame header if enough bytes are received to know it
+fn header_size(frame: &[u8]) -> Option<usize> {
+    let second = *frame.get(1)?;
+    let length_size = match second & !MASK_BIT {
+        126 => 2,
+        127 => 8,
+        _ => 0,
+    };
+    let mask_size = if second & MASK_BIT != 0 { MASK_KEY_SIZE } else { 0 };
+    Some(2 + length_size + mask_size)
+}
+
+// Header of the frame must be already received
+fn payload_size(frame: &[u8]) -> u64 {
+    match frame[1] & !MASK_BIT {
+        126 => u64::from(u16::from_be_bytes([frame[2], frame[3]])),
+        127 => {
+            let mut size = [0u8; 8];
+            size.copy_from_slice(&frame[2..10]);
+            u64::from_be_bytes(size)
+        }
+        size => u64::from(size),
+    }
+}
+
+fn missing_bytes(frame: &[u8]) -> std::io::Result<usize> {
+    let header_size = match header_size(frame) {
+        Some(size) => size,
+        None => return Ok(2 - frame.len()),
+    };
+    if frame.len() < header_size {
+        return Ok(header_size - frame.len());
+    }
+    let payload_size = payload_size(frame);
+    if payload_size > MAX_FRAME_PAYLOAD_SIZE as u64 {
+        return Err(invalid_data("WebSocket frame is too large"));
+    }
+    Ok(header_size + payload_size as usize - frame.len())
+}
+
+/// Reading half of a WebSocket connection, payloads of binary frames are read as a byte stream.
+/// Pings are answered and a close frame is echoed through the writing half of the connection.
+pub struct WebSocketReader<R> {
+    inner: R,
+    masked: bool,     // frames of the peer must be masked iff it is a client
+    frame: Vec<u8>,   // bytes of the frame being received
+    payload: Vec<u8>, // payload of the last data frame
+    consumed: usize,  // bytes of payload already read
+    closed: bool,
+    replies: Rc<RefCell<dyn ControlSender>>,
+}
+
+impl<R> WebSocketReader<R> {
+    fn new(inner: R, masked: bool, replies: Rc<RefCell<dyn ControlSender>>) -> Self {
+        Self { inner, masked, frame: vec![], payload: vec![], consumed: 0, closed: false, replies }
+    }
+}
+
+impl<R: Read> WebSocketReader<R> {
+    // Returns false if the connection was closed between frames
+    fn receive_frame(&mut self) -> std::io::Result<bool> {
+        let mut chunk = [0u8; 4096];
+        while !self.closed {
+            loop {
+                let missing = missing_bytes(&self.frame)?;
+                if missing == 0 {
+                    break;
+                }
+                // NOTE WouldBlock keeps the partial frame, reading continues when data arrives
+                let missing = min(missing, chunk.len());
+                let read = self.inner.read(&mut chunk[..missing])?;
+                if read == 0 {
+                    if self.frame.is_empty() {
+                        return Ok(false);
+                    }
+                    return Err(std::io::ErrorKind::UnexpectedEof.into());
+                }
+                self.frame.extend_from_slice(&chunk[..read]);
+            }
+
+            if (self.frame[1] & MASK_BIT != 0) != self.masked {
+                return Err(invalid_data("WebSocket frame is not masked as required"));
+            }
+            let header_size = header_size(&self.frame).unwrap_or_default();
+            let mut payload = self.frame.split_off(header_size);
+            if self.masked {
+                apply_mask(&mut payload, &self.frame[header_size - MASK_KEY_SIZE..]);
+            }
+            let opcode = self.frame[0] & OPCODE_MASK;
+            self.frame.clear();
+            match opcode {
+                // NOTE message boundaries are not kept, so fragments need no special care
+                OPCODE_CONTINUATION | OPCODE_BINARY => {
+                    self.payload = payload;
+                    self.consumed = 0;
+                    return Ok(true);
+                }
+                OPCODE_CLOSE => {
+                    self.closed = true;
+                    // NOTE the status code of the peer is echoed
+                    self.replies.borrow_mut().send_control(OPCODE_CLOSE, &payload)?;
+                }
+                OPCODE_PING => self.replies.borrow_mut().send_control(OPCODE_PONG, &payload)?,
+                OPCODE_PONG => {}
+                _ => return Err(invalid_data("Unexpected WebSocket frame")),
+            }
+        }
+        Ok(false)
+    }
+}
+
+impl<R: Read> Read for WebSocketReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.consumed == self.payload.len() {
+            if !self.receive_frame()? {
+                return Ok(0);
+            }
+        }
+        let size = min(buf.len(), self.payload.len() - self.consumed);
+        buf[..size].copy_from_slice(&self.payload[self.consumed..self.consumed + size]);
+        self.consumed += size;
+        Ok(size)
+    }
+}
+
+impl<R: AsyncRead> AsyncRead for WebSocketReader<R> {}
+
+// Control frames are answered by the reading half, but sent on the writing half
+trait ControlSender {
+    fn send_control(&mut self, opcode: u8, payload: &[u8]) -> std::io::Result<()>;
+}
+
+/// Frames sent on a connection, shared by its reading and writing halves
+struct FrameSender<W> {
+    inner: W,
+    masked: bool,
+    pending: Vec<u8>, // frames not yet fully written to inner
+    written: usize,   // bytes of pending already written
+    closed: bool,     // a close frame was sent, no more frames may follow
+}
+
+impl<W> FrameSender<W> {
+    fn new(inner: W, masked: bool) -> Self {
+        Self { inner, masked, pending: vec![], written: 0, closed: false }
+    }
+
+    fn push_frame(&mut self, opcode: u8, payload: &[u8]) {
+        let mask_bit = if self.masked { MASK_BIT } else { 0 };
+        let mut frame = vec![FIN_BIT | opcode];
+        if payload.len() < 126 {
+            frame.push(mask_bit | payload.len() as u8);
+        } else if payload.len() <= usize::from(u16::MAX) {
+            frame.push(mask_bit | 126);
+            frame.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        } else {
+            frame.push(mask_bit | 127);
+            frame.extend_from_slice(&(payload.len() as u64).to_be_bytes());
+        }
+
+        let mut payload = payload.to_vec();
+        if self.masked {
+            let key = rand::random::<[u8; MASK_KEY_SIZE]>();
+            apply_mask(&mut payload, &key);
+            frame.extend_from_slice(&key);
+        }
+        frame.extend_from_slice(&payload);
+        self.pending.extend_from_slice(&frame);
+        self.closed |= opcode == OPCODE_CLOSE;
+    }
+}
+
+impl<W: Write> FrameSender<W> {
+    fn write_pending(&mut self) -> std::io::Result<()> {
+        while self.written < self.pending.len() {
+            let written = self.inner.write(&self.pending[self.written..])?;
+            if written == 0 {
+                return Err(std::io::ErrorKind::WriteZero.into());
+            }
+            self.written += written;
+        }
+        self.pending.clear();
+        self.written = 0;
+        Ok(())
+    }
+
+    // The frame is accepted even if the inner writer is not ready, it is sent on the next call
+    fn send(&mut self, opcode: u8, payload: &[u8]) -> std::io::Result<()> {
+        if self.closed {
+            return Err(std::io::ErrorKind::BrokenPipe.into());
+        }
+        self.push_frame(opcode, payload);
+        match self.write_pending() {
+            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => Ok(()),
+            res => res,
+        }
+    }
+}
+
+impl<W: Write> ControlSender for FrameSender<W> {
+    fn send_control(&mut self, opcode: u8, payload: &[u8]) -> std::io::Result<()> {
+        self.send(opcode, payload)
+    }
+}
+
+/// Writing half of a WebSocket connection, data is sent in binary frames.
+/// Frames of clients are masked as required by browsers.
+pub struct WebSocketWriter<W> {
+    sender: Rc<RefCell<FrameSender<W>>>,
+}
+
+impl<W: Write> Write for WebSocketWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let mut sender = self.sender.borrow_mut();
+        // Only a single frame is buffered, the caller has to wait until it is sent
+        sender.write_pending()?;
+
+        let payload = &buf[..min(buf.len(), MAX_FRAME_PAYLOAD_SIZE)];
+        sender.send(OPCODE_BINARY, payload)?;
+        Ok(payload.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        let mut sender = self.sender.borrow_mut();
+        sender.write_pending()?;
+        sender.inner.flush()
+    }
+}
+
+impl<W: AsyncWrite> AsyncWrite for WebSocketWriter<W> {
+    fn shutdown(&mut self) -> Poll<(), std::io::Error> {
+        let mut sender = self.sender.borrow_mut();
+        match sender.write_pending() {
+            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => Ok(Async::NotReady),
+            Err(e) => Err(e),
+            Ok(()) => sender.inner.shutdown(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accept_key_of_rfc_example() {
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    fn written(writer: &WebSocketWriter<Vec<u8>>) -> Vec<u8> {
+        writer.sender.borrow().inner.clone()
+    }
+
+    fn client_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mut sender = FrameSender::new(Vec::<u8>::new(), true);
+        sender.push_frame(opcode, payload);
+        sender.pending
+    }
+
+    #[test]
+    fn frames_roundtrip() {
+        let mut written_by_client = vec![];
+        for &(client, size) in &[(true, 5), (false, 300), (true, 70_000)] {
+            let data = (0..size).map(|idx| idx as u8).collect::<Vec<_>>();
+            let (_reader, mut writer) = websocket(std::io::empty(), vec![], client);
+            assert_eq!(writer.write(&data).unwrap(), size);
+            if client {
+                written_by_client.extend_from_slice(&written(&writer));
+            }
+
+            let (mut reader, _writer) =
+                websocket(std::io::Cursor::new(written(&writer)), vec![], !client);
+            let mut read = vec![];
+            reader.read_to_end(&mut read).unwrap();
+            assert_eq!(read, data);
+        }
+
+        let mut frames = client_frame(OPCODE_PING, b"ping");
+        frames.extend_from_slice(&written_by_client);
+        frames.extend_from_slice(&client_frame(OPCODE_CLOSE, &[0x03, 0xE8]));
+        frames.extend_from_slice(&client_frame(OPCODE_BINARY, &[42]));
+
+        let (mut reader, server_writer) = websocket(std::io::Cursor::new(frames), vec![], false);
+        let mut read = vec![];
+        reader.read_to_end(&mut read).unwrap();
+        assert_eq!(read.len(), 5 + 70_000);
+
+        let mut replies = vec![FIN_BIT | OPCODE_PONG, 4];
+        replies.extend_from_slice(b"ping");
+        replies.extend_from_slice(&[FIN_BIT | OPCODE_CLOSE, 2, 0x03, 0xE8]);
+        assert_eq!(written(&server_writer), replies);
+    }
+
+    #[test]
+    fn server_rejects_unmasked_frames() {
+        let (_reader, mut writer) = websocket(std::io::empty(), vec![], false);
+        writer.write_all(&[42]).unwrap();
+
+        let (mut reader, _writer) =
+            websocket(std::io::Cursor::new(written(&writer)), vec![], false);
+        let err = reader.read_to_end(&mut vec![]).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
@@ -1,3 +1,5 @@
+use curve25519_dalek::{edwards::CompressedEdwardsY, scalar::Scalar};
+use digest::Digest;
 use ed25519_dalek as ed;
 
 use super::*;
@@ -32,6 +34,30 @@ impl EdPrivateKey {
         let key_pair = ed::Keypair { secret, public };
         Ok(Self(key_pair))
     }
+
+    /// Calculates a secret shared with the owner of `peer`, who gets the same result calling this
+    /// with our public key. Both keys are mapped to X25519 (Curve25519 in Montgomery form), so the
+    /// same keypair can be used both for signing and for key agreement.
+    ///
+    /// # Error
+    /// If `peer` is not a valid point or is of small order
+    pub fn diffie_hellman(&self, peer: &EdPublicKey) -> Fallible<Vec<u8>> {
+        // The scalar of the expanded Ed25519 secret key, i.e. the one the public key was calculated with
+        let hash = sha2::Sha512::digest(self.0.secret.as_bytes());
+        let mut scalar_bytes = [0u8; 32];
+        scalar_bytes.copy_from_slice(&hash[..32]);
+        scalar_bytes[0] &= 248;
+        scalar_bytes[31] &= 127;
+        scalar_bytes[31] |= 64;
+        let scalar = Scalar::from_bits(scalar_bytes);
+
+        let peer_point = CompressedEdwardsY::from_slice(&peer.to_bytes())
+            .decompress()
+            .ok_or_else(|| format_err!("Peer public key is not a valid curve point"))?;
+        let shared = (peer_point.to_montgomery() * scalar).to_bytes();
+        ensure!(shared.iter().any(|byte| *byte != 0), "Peer public key is of small order");
+        Ok(shared.to_vec())
+    }
 }
 
 impl Clone for EdPrivateKey {
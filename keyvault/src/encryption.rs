@@ -0,0 +1,163 @@
+//! Authenticated symmetric encryption of data between parties knowing the same secret, e.g. one
+//! calculated with [`EdPrivateKey::diffie_hellman`] or derived from a [`Seed`].
+//!
+//! [`EdPrivateKey::diffie_hellman`]: ../ed25519/struct.EdPrivateKey.html#method.diffie_hellman
+//! [`Seed`]: ../struct.Seed.html
+
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use curve25519_dalek::constants::X25519_BASEPOINT;
+use curve25519_dalek::montgomery::MontgomeryPoint;
+use curve25519_dalek::scalar::Scalar;
+use failure::{ensure, err_msg, Fallible};
+use hmac::Mac;
+
+/// The size of the symmetric key in bytes
+pub const KEY_SIZE: usize = 32;
+/// The size of the random nonce prepended to each sealed message
+pub const NONCE_SIZE: usize = 24;
+
+type HmacSha256 = hmac::Hmac<sha2::Sha256>;
+
+/// A key of XChaCha20-Poly1305. Nonces are random, so the same key can seal any number of messages.
+#[derive(Clone)]
+pub struct SymmetricKey([u8; KEY_SIZE]);
+
+impl SymmetricKey {
+    /// Creates a key from exactly [`KEY_SIZE`] bytes.
+    ///
+    /// [`KEY_SIZE`]: constant.KEY_SIZE.html
+    pub fn from_bytes(bytes: &[u8]) -> Fallible<Self> {
+        ensure!(bytes.len() == KEY_SIZE, "Symmetric key must be {} bytes", KEY_SIZE);
+        let mut key = [0u8; KEY_SIZE];
+        key.copy_from_slice(bytes);
+        Ok(Self(key))
+    }
+
+    /// Derives a key from a secret for the purpose named by `context`. Different contexts give
+    /// independent keys, so the same secret can be reused safely for different purposes.
+    pub fn derive(secret: &[u8], context: &str) -> Self {
+        // This unwrap would only panic if HMAC rejected the key, but it accepts keys of any size
+        let mut hasher = HmacSha256::new_varkey(context.as_bytes()).unwrap();
+        hasher.input(secret);
+        let hash = hasher.result().code();
+        let mut key = [0u8; KEY_SIZE];
+        key.copy_from_slice(&hash[..KEY_SIZE]);
+        Self(key)
+    }
+
+    /// Encrypts and authenticates the plaintext. The result contains the nonce followed by the ciphertext.
+    pub fn seal(&self, plaintext: &[u8]) -> Fallible<Vec<u8>> {
+        let cipher = XChaCha20Poly1305::new((&self.0).into());
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext =
+            cipher.encrypt(&nonce, plaintext).map_err(|_| err_msg("Failed to encrypt message"))?;
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        Ok(sealed)
+    }
+
+    /// Decrypts a message sealed with the same key.
+    ///
+    /// # Error
+    /// If the message was sealed with another key or was tampered with
+    pub fn open(&self, sealed: &[u8]) -> Fallible<Vec<u8>> {
+        ensure!(sealed.len() >= NONCE_SIZE, "Sealed message is too short");
+        let (nonce, ciphertext) = sealed.split_at(NONCE_SIZE);
+        let cipher = XChaCha20Poly1305::new((&self.0).into());
+        cipher
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .map_err(|_| err_msg("Failed to decrypt message, wrong key or tampered content"))
+    }
+}
+
+/// A random X25519 key used for a single key agreement, e.g. in a session handshake. Secrets
+/// calculated with it cannot be recovered later even if the long-term keys of the parties leak.
+pub struct EphemeralSecret(Scalar);
+
+impl EphemeralSecret {
+    /// The size of the public key in bytes
+    pub const PUBLIC_KEY_SIZE: usize = 32;
+
+    /// Creates a new random key, clamped as specified for X25519.
+    pub fn generate() -> Self {
+        let mut scalar_bytes = [0u8; 32];
+        OsRng.fill_bytes(&mut scalar_bytes);
+        scalar_bytes[0] &= 248;
+        scalar_bytes[31] &= 127;
+        scalar_bytes[31] |= 64;
+        Self(Scalar::from_bits(scalar_bytes))
+    }
+
+    /// The public key to be sent to the peer.
+    pub fn public_key(&self) -> [u8; Self::PUBLIC_KEY_SIZE] {
+        (X25519_BASEPOINT * self.0).to_bytes()
+    }
+
+    /// Calculates the secret shared with the owner of the ephemeral `peer` public key.
+    /// Consumes the key so it cannot be reused.
+    ///
+    /// # Error
+    /// If `peer` is not [`PUBLIC_KEY_SIZE`] bytes or is of small order
+    ///
+    /// [`PUBLIC_KEY_SIZE`]: #associatedconstant.PUBLIC_KEY_SIZE
+    pub fn diffie_hellman(self, peer: &[u8]) -> Fallible<Vec<u8>> {
+        ensure!(
+            peer.len() == Self::PUBLIC_KEY_SIZE,
+            "Ephemeral public key must be {} bytes",
+            Self::PUBLIC_KEY_SIZE
+        );
+        let mut peer_bytes = [0u8; Self::PUBLIC_KEY_SIZE];
+        peer_bytes.copy_from_slice(peer);
+        let shared = (MontgomeryPoint(peer_bytes) * self.0).to_bytes();
+        ensure!(shared.iter().any(|byte| *byte != 0), "Peer public key is of small order");
+        Ok(shared.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ed25519::EdPrivateKey;
+    use crate::PrivateKey;
+
+    #[test]
+    fn seal_and_open() -> Fallible<()> {
+        let key = SymmetricKey::derive(b"shared secret", "test");
+        let sealed = key.seal(b"Hello World!")?;
+        assert_eq!(key.open(&sealed)?, b"Hello World!");
+        assert_ne!(key.seal(b"Hello World!")?, sealed);
+
+        let other_key = SymmetricKey::derive(b"shared secret", "other purpose");
+        assert!(other_key.open(&sealed).is_err());
+        let mut tampered = sealed.clone();
+        tampered[NONCE_SIZE] ^= 1;
+        assert!(key.open(&tampered).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn key_agreement() -> Fallible<()> {
+        let alice = EdPrivateKey::from_bytes([1u8; 32])?;
+        let bob = EdPrivateKey::from_bytes([2u8; 32])?;
+        let carol = EdPrivateKey::from_bytes([3u8; 32])?;
+        let alice_secret = alice.diffie_hellman(&bob.public_key())?;
+        assert_eq!(alice_secret, bob.diffie_hellman(&alice.public_key())?);
+        assert_ne!(alice_secret, carol.diffie_hellman(&bob.public_key())?);
+        Ok(())
+    }
+
+    #[test]
+    fn ephemeral_key_agreement() -> Fallible<()> {
+        let alice = EphemeralSecret::generate();
+        let bob = EphemeralSecret::generate();
+        let (alice_public, bob_public) = (alice.public_key(), bob.public_key());
+        assert_ne!(alice_public, bob_public);
+        assert_eq!(alice.diffie_hellman(&bob_public)?, bob.diffie_hellman(&alice_public)?);
+
+        assert!(EphemeralSecret::generate().diffie_hellman(&[0u8; 32]).is_err());
+        assert!(EphemeralSecret::generate().diffie_hellman(&alice_public[1..]).is_err());
+        Ok(())
+    }
+}
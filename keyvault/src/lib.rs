@@ -11,6 +11,7 @@ pub mod bip32;
 mod bip39;
 mod cc;
 pub mod ed25519;
+pub mod encryption;
 pub mod multicipher;
 pub mod secp256k1;
 #[cfg(test)]
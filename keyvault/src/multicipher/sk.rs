@@ -1,3 +1,5 @@
+use failure::{bail, ensure, Fallible};
+
 use super::*;
 
 erased_type! {
@@ -22,6 +24,29 @@ macro_rules! sign {
     }};
 }
 
+macro_rules! diffie_hellman {
+    ($suite:ident, $self_:tt, $peer:ident) => {
+        reify!($suite, sk, $self_).diffie_hellman(reify!($suite, pk, $peer))
+    };
+}
+
+impl MPrivateKey {
+    /// Calculates a secret shared with the owner of `peer`. See [`EdPrivateKey::diffie_hellman`]
+    ///
+    /// # Error
+    /// If the keys belong to different cipher suites or the suite does not support key agreement
+    ///
+    /// [`EdPrivateKey::diffie_hellman`]: ../ed25519/struct.EdPrivateKey.html#method.diffie_hellman
+    pub fn diffie_hellman(&self, peer: &MPublicKey) -> Fallible<Vec<u8>> {
+        ensure!(self.suite == peer.suite, "Key agreement needs keys of the same cipher suite");
+        match self.suite {
+            e!(variant) => diffie_hellman!(e, self, peer),
+            f!(variant) => diffie_hellman!(f, self, peer),
+            s!(variant) => bail!("Key agreement is not implemented for Secp256k1 keys"),
+        }
+    }
+}
+
 impl PrivateKey<MultiCipher> for MPrivateKey {
     fn public_key(&self) -> MPublicKey {
         visit!(public_key(self))
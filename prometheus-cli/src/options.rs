@@ -1,6 +1,8 @@
 use std::path::PathBuf;
+use std::time::Duration;
 
-use failure::Fallible;
+use failure::{format_err, Fallible};
+use futures::Future;
 use log::*;
 use structopt::StructOpt;
 
@@ -54,12 +56,12 @@ pub enum CommandVerb {
     Show(ShowCommand),
 
     #[structopt(name = "create")]
-    /// Create profile or link
+    /// Create profile, link or contact
     Create(CreateCommand),
 
     // TODO consider if removing profile is needed?
     #[structopt(name = "remove")]
-    /// Remove link
+    /// Remove link or contact
     Remove(RemoveCommand),
 
     #[structopt(name = "set")]
@@ -77,6 +79,10 @@ pub enum CommandVerb {
     #[structopt(name = "revert")]
     /// Revert unpublished profile to previous version
     Revert(RevertCommand),
+
+    #[structopt(name = "merge")]
+    /// Merge remote changes into local profile
+    Merge(MergeCommand),
 }
 
 impl Command for CommandVerb {
@@ -93,6 +99,7 @@ impl Command for CommandVerb {
             Clear(sub) => Box::new(sub),
             Publish(sub) => Box::new(sub),
             Revert(sub) => Box::new(sub),
+            Merge(sub) => Box::new(sub),
         };
         sub.execute(api)
     }
@@ -103,6 +110,42 @@ pub enum ListCommand {
     #[structopt(name = "profiles")]
     /// List profiles
     Profiles,
+    #[structopt(name = "claims")]
+    /// List claims of your profiles matching all given filters
+    Claims {
+        #[structopt(long)]
+        /// Only claims of this schema
+        schema: Option<String>,
+        #[structopt(long)]
+        /// Only claims about this profile of yours
+        subject: Option<ProfileId>,
+        #[structopt(long)]
+        /// Only claims witnessed by this profile
+        witness: Option<ProfileId>,
+        #[structopt(long)]
+        /// Only claims with this proof status (unproven, valid, expiring-soon or expired)
+        status: Option<ClaimExpiryStatus>,
+        #[structopt(long = "expires-within", value_name = "DAYS")]
+        /// Only claims whose latest proof expires within the given number of days
+        expires_within_days: Option<u64>,
+        #[structopt(long, value_name = "JSONPATH")]
+        /// Only claims whose content matches this JSONPath expression, e.g. '$[?(@.age > 18)]'
+        content: Option<String>,
+    },
+    #[structopt(name = "history")]
+    /// List retained versions of a profile
+    History {
+        #[structopt()]
+        /// List versions of this profile of yours if other than the active one
+        my_profile_id: Option<ProfileId>,
+    },
+    #[structopt(name = "bans")]
+    /// List profiles banned by your profile on its homes
+    Bans {
+        #[structopt()]
+        /// List profiles banned by this profile of yours if other than the active one
+        my_profile_id: Option<ProfileId>,
+    },
     // #[structopt(name = "followers")]
     // /// List followers
     // IncomingLinks {
@@ -133,6 +176,57 @@ impl Command for ListCommand {
                     };
                     info!("  {}: {}{}", profile_record.label(), profile_record.id(), status);
                 }
+            }
+            Claims { schema, subject, witness, status, expires_within_days, content } => {
+                let expires_before = expires_within_days
+                    .map(|days| {
+                        let period = days.checked_mul(24 * 60 * 60).map(Duration::from_secs);
+                        period
+                            .and_then(|period| TimeStamp::now().checked_add(period))
+                            .ok_or_else(|| format_err!("Expiry within {} days is too far", days))
+                    })
+                    .transpose()?;
+                let query = ClaimQuery {
+                    schema_id: schema,
+                    subject_id: subject,
+                    witness_id: witness,
+                    status,
+                    expires_after: None,
+                    expires_before,
+                    content,
+                };
+                let claims = api.query_claims(&query)?;
+                info!("Found {} claims", claims.len());
+                for claim in claims {
+                    let valid_until = claim
+                        .valid_until()
+                        .map(|time| format!(", valid until {:?}", time))
+                        .unwrap_or_default();
+                    info!(
+                        "  {}: {} about {}{}",
+                        claim.id(),
+                        claim.signable_part().typed_content.schema_id(),
+                        claim.signable_part().subject_id,
+                        valid_until
+                    );
+                }
+            }
+            History { my_profile_id } => {
+                let history = api.profile_history(my_profile_id)?;
+                info!("Your profile has {} versions", history.len());
+                for entry in history {
+                    info!(
+                        "  {}: version {} stored at {:?}",
+                        entry.revision, entry.version, entry.timestamp
+                    );
+                }
+            }
+            Bans { my_profile_id } => {
+                let banned = api.banned_profiles(my_profile_id).wait()?;
+                info!("You banned {} profiles", banned.len());
+                for profile_id in banned {
+                    info!("  {}", profile_id);
+                }
             } // IncomingLinks { my_profile_id } => {
               //     let followers = api.list_incoming_links(my_profile_id)?;
               //     info!("You have {} followers", followers.len());
@@ -159,6 +253,46 @@ pub enum ShowCommand {
         /// Possible values are: local, base, remote
         source: ProfileRepositoryKind,
     },
+
+    #[structopt(name = "attribute")]
+    /// Show typed value of an attribute of a local profile
+    Attribute {
+        #[structopt(long)]
+        /// Show attribute of this profile of yours if other than the active one
+        my_profile_id: Option<ProfileId>,
+
+        #[structopt()]
+        /// Attribute name
+        key: AttributeId,
+    },
+
+    #[structopt(name = "contact")]
+    /// Show contacts-only attributes a contact published for your profile
+    Contact {
+        #[structopt(long)]
+        /// Show attributes shared with this profile of yours if other than the active one
+        my_profile_id: Option<ProfileId>,
+
+        #[structopt()]
+        /// Profile id of the contact
+        contact_id: ProfileId,
+    },
+
+    #[structopt(name = "diff")]
+    /// Show changes between versions of a local profile
+    Diff {
+        #[structopt(long)]
+        /// Compare versions of this profile of yours if other than the active one
+        my_profile_id: Option<ProfileId>,
+
+        #[structopt()]
+        /// Revision listed in the history to compare from
+        from_revision: Revision,
+
+        #[structopt()]
+        /// Revision to compare to, the current local version if not given
+        to_revision: Option<Revision>,
+    },
 }
 
 impl Command for ShowCommand {
@@ -173,8 +307,17 @@ impl Command for ShowCommand {
                 info!("Details of profile id {}", public_profile.id());
                 info!("Profile version: {}", public_profile.version());
                 info!("  {} attributes:", attributes.len());
-                for (i, attribute) in attributes.iter().enumerate() {
-                    info!("    {}: {:?}", i, attribute);
+                for (i, (key, value)) in attributes.iter().enumerate() {
+                    info!("    {}: {} = {} ({})", i, key, value, value.kind());
+                }
+                for visibility in &[AttributeVisibility::Contacts, AttributeVisibility::Private] {
+                    let attributes = profile.attributes(*visibility);
+                    if !attributes.is_empty() {
+                        info!("  {} {} attributes:", attributes.len(), visibility);
+                        for (i, (key, value)) in attributes.iter().enumerate() {
+                            info!("    {}: {} = {} ({})", i, key, value, value.kind());
+                        }
+                    }
                 }
                 info!("  {} subscriptions:", links.len());
                 for (i, peer_id) in links.iter().enumerate() {
@@ -182,6 +325,53 @@ impl Command for ShowCommand {
                 }
                 Ok(())
             }
+            ShowCommand::Attribute { my_profile_id, key } => {
+                match api.get_attribute(my_profile_id, &key)? {
+                    Some(value) => info!("Attribute {} is {} value {}", key, value.kind(), value),
+                    None => info!("Attribute {} is not set", key),
+                }
+                Ok(())
+            }
+            ShowCommand::Contact { my_profile_id, contact_id } => {
+                let attributes = api.contact_attributes(my_profile_id, &contact_id)?;
+                info!("Contact {} shares {} attributes:", contact_id, attributes.len());
+                for (i, (key, value)) in attributes.iter().enumerate() {
+                    info!("    {}: {} = {} ({})", i, key, value, value.kind());
+                }
+                Ok(())
+            }
+            ShowCommand::Diff { my_profile_id, from_revision, to_revision } => {
+                let diff = api.profile_diff(my_profile_id, from_revision, to_revision)?;
+                info!("Changes from version {} to {}", diff.from_version, diff.to_version);
+                for change in &diff.attributes {
+                    info!(
+                        "  {} attribute {}: {:?} -> {:?}",
+                        change.visibility, change.attribute_id, change.old_value, change.new_value
+                    );
+                }
+                for peer_id in &diff.added_links {
+                    info!("  + link {}", peer_id);
+                }
+                for peer_id in &diff.removed_links {
+                    info!("  - link {}", peer_id);
+                }
+                for contact_id in &diff.added_contacts {
+                    info!("  + contact {}", contact_id);
+                }
+                for contact_id in &diff.removed_contacts {
+                    info!("  - contact {}", contact_id);
+                }
+                for claim_id in &diff.added_claims {
+                    info!("  + claim {}", claim_id);
+                }
+                for claim_id in &diff.removed_claims {
+                    info!("  - claim {}", claim_id);
+                }
+                for claim_id in &diff.changed_claims {
+                    info!("  ~ claim {}", claim_id);
+                }
+                Ok(())
+            }
         }
     }
 }
@@ -209,6 +399,30 @@ pub enum CreateCommand {
         peer_profile_id: ProfileId,
         // TODO is an optional "relation_type" needed here?
     },
+
+    #[structopt(name = "contact")]
+    /// Add the peer of a relation as contact to share contacts-only attributes with
+    Contact {
+        #[structopt(long)]
+        /// Add contact to this profile of yours if other than the active one
+        my_profile_id: Option<ProfileId>,
+
+        #[structopt(parse(from_os_str))]
+        /// JSON file containing the relation proof signed by both peers
+        proof_file: PathBuf,
+    },
+
+    #[structopt(name = "ban")]
+    /// Ban a profile, your homes refuse its pairing requests, calls and messages
+    Ban {
+        #[structopt(long)]
+        /// Ban profile for this profile of yours if other than the active one
+        my_profile_id: Option<ProfileId>,
+
+        #[structopt()]
+        /// Profile id to be banned
+        banned_id: ProfileId,
+    },
 }
 
 impl Command for CreateCommand {
@@ -228,6 +442,16 @@ impl Command for CreateCommand {
                 api.create_link(my_profile_id, &peer_profile_id)?;
                 info!("Created link to peer profile {}", peer_profile_id);
             }
+            Contact { my_profile_id, proof_file } => {
+                let proof: RelationProof =
+                    serde_json::from_reader(std::fs::File::open(proof_file)?)?;
+                let contact = api.add_contact(my_profile_id, proof)?;
+                info!("Added contact {} with relation {}", contact.id(), contact.relation_type);
+            }
+            Ban { my_profile_id, banned_id } => {
+                api.ban_profile(my_profile_id, &banned_id).wait()?;
+                info!("Banned profile {}", banned_id);
+            }
         };
         Ok(())
     }
@@ -246,6 +470,30 @@ pub enum RemoveCommand {
         /// Remove link with this remote profile
         peer_profile_id: ProfileId,
     },
+
+    #[structopt(name = "contact")]
+    /// Remove contact, contacts-only attributes are not sealed for them anymore
+    Contact {
+        #[structopt(long)]
+        /// Remove contact from this profile of yours if other than the active one
+        my_profile_id: Option<ProfileId>,
+
+        #[structopt()]
+        /// Profile id of the contact
+        contact_id: ProfileId,
+    },
+
+    #[structopt(name = "ban")]
+    /// Lift the ban of a profile
+    Ban {
+        #[structopt(long)]
+        /// Lift ban of this profile of yours if other than the active one
+        my_profile_id: Option<ProfileId>,
+
+        #[structopt()]
+        /// Profile id to be unbanned
+        banned_id: ProfileId,
+    },
 }
 
 impl Command for RemoveCommand {
@@ -255,6 +503,14 @@ impl Command for RemoveCommand {
                 api.remove_link(my_profile_id, &peer_profile_id)?;
                 info!("Removed link from profile {}", peer_profile_id);
             }
+            RemoveCommand::Contact { my_profile_id, contact_id } => {
+                api.remove_contact(my_profile_id, &contact_id)?;
+                info!("Removed contact {}", contact_id);
+            }
+            RemoveCommand::Ban { my_profile_id, banned_id } => {
+                api.unban_profile(my_profile_id, &banned_id).wait()?;
+                info!("Unbanned profile {}", banned_id);
+            }
         };
         Ok(())
     }
@@ -284,7 +540,16 @@ pub enum SetCommand {
 
         #[structopt()]
         /// Attribute value
-        value: AttributeValue,
+        value: String,
+
+        #[structopt(long = "type", default_value = "string", value_name = "KIND")]
+        /// Kind of the value: string, boolean, integer, float, timestamp (Unix seconds),
+        /// location (latitude,longitude), blob (multibase), link, array or object (JSON)
+        kind: AttributeKind,
+
+        #[structopt(long, default_value = "public")]
+        /// Who can read the attribute: public, contacts or private
+        visibility: AttributeVisibility,
     },
 }
 
@@ -296,9 +561,10 @@ impl Command for SetCommand {
                 api.set_active_profile(&my_profile_id)?;
                 info!("Active profile was set to {}", my_profile_id);
             }
-            Attribute { my_profile_id, key, value } => {
-                api.set_attribute(my_profile_id, &key, &value)?;
-                info!("Setting attribute {} to {}", key, value);
+            Attribute { my_profile_id, key, value, kind, visibility } => {
+                let value = AttributeValue::parse(kind, &value)?;
+                api.set_attribute(my_profile_id, &key, &value, visibility)?;
+                info!("Setting {} attribute {} to {} value {}", visibility, key, kind, value);
             }
         };
         Ok(())
@@ -445,13 +711,17 @@ pub enum RevertCommand {
         #[structopt()]
         /// Revert this specific local profile
         my_profile_id: Option<ProfileId>,
+
+        #[structopt(long = "to-revision", value_name = "REVISION")]
+        /// Roll back to this retained local revision instead of the last known remote version
+        revision: Option<Revision>,
     },
 }
 
 impl Command for RevertCommand {
     fn execute(self: Box<Self>, api: &mut dyn VaultApi) -> CmdRes {
         match *self {
-            RevertCommand::Profile { my_profile_id } => {
+            RevertCommand::Profile { my_profile_id, revision: None } => {
                 let profile = api.revert_profile(my_profile_id)?;
                 info!(
                     "Reverted profile {} to last known remote version {}",
@@ -459,6 +729,86 @@ impl Command for RevertCommand {
                     profile.version()
                 );
             }
+            RevertCommand::Profile { my_profile_id, revision: Some(revision) } => {
+                let profile = api.rollback_profile(my_profile_id, revision)?;
+                info!(
+                    "Rolled back profile {} to the content of revision {} as new version {}",
+                    profile.id(),
+                    revision,
+                    profile.version()
+                );
+            }
+        };
+        Ok(())
+    }
+}
+
+#[derive(Debug, StructOpt)]
+pub enum MergeCommand {
+    #[structopt(name = "profile")]
+    /// Three-way merge of local and remote profile versions, reporting conflicting changes
+    Profile {
+        #[structopt()]
+        /// Merge this specific local profile
+        my_profile_id: Option<ProfileId>,
+
+        #[structopt(long = "take-local", value_name = "ATTRIBUTE")]
+        /// Resolve conflict of this attribute using the local value
+        take_local: Vec<AttributeId>,
+
+        #[structopt(long = "take-remote", value_name = "ATTRIBUTE")]
+        /// Resolve conflict of this attribute using the remote value
+        take_remote: Vec<AttributeId>,
+
+        #[structopt(long = "private-data", value_name = "local|remote")]
+        /// Resolve conflict of private data using this side
+        private_data: Option<MergeSide>,
+    },
+}
+
+impl Command for MergeCommand {
+    fn execute(self: Box<Self>, api: &mut dyn VaultApi) -> CmdRes {
+        match *self {
+            MergeCommand::Profile { my_profile_id, take_local, take_remote, private_data } => {
+                let mut resolutions = MergeResolutions { private_data, ..Default::default() };
+                if !take_local.is_empty() || !take_remote.is_empty() {
+                    // NOTE values of conflicting attributes are known only after a merge attempt
+                    let attempt = api.merge_profile(my_profile_id.clone(), resolutions.clone())?;
+                    for conflict in attempt.conflicts {
+                        if let ProfileConflict::Attribute { attribute_id, local, remote, .. } =
+                            conflict
+                        {
+                            if take_local.contains(&attribute_id) {
+                                resolutions.attributes.insert(attribute_id, local);
+                            } else if take_remote.contains(&attribute_id) {
+                                resolutions.attributes.insert(attribute_id, remote);
+                            }
+                        }
+                    }
+                }
+
+                let merge = api.merge_profile(my_profile_id, resolutions)?;
+                if merge.conflicts.is_empty() {
+                    info!(
+                        "Merged profile {} as version {}",
+                        merge.profile.id(),
+                        merge.profile.version()
+                    );
+                } else {
+                    for conflict in &merge.conflicts {
+                        match conflict {
+                            ProfileConflict::Attribute { attribute_id, base, local, remote } => {
+                                info!(
+                                    "Conflicting attribute {}: base {:?}, local {:?}, remote {:?}",
+                                    attribute_id, base, local, remote
+                                )
+                            }
+                            ProfileConflict::PrivateData => info!("Conflicting private data"),
+                        }
+                    }
+                    info!("Nothing was stored, resolve all conflicts to complete the merge");
+                }
+            }
         };
         Ok(())
     }
@@ -9,6 +9,8 @@ use crate::test::FakeUserInteractor;
 use crate::vault::api_impl::VaultState;
 use crate::*;
 
+const CLAIM_EXPIRY_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
 pub struct Daemon {
     handle: reactor::Handle,
     http_server: Server,
@@ -104,6 +106,8 @@ fn start_daemon(options: Options) -> Fallible<Server> {
     let vault_path = did::paths::vault_path(options.config_dir.clone())?;
     let repo_path = did::paths::profile_repo_path(options.config_dir.clone())?;
     let base_path = did::paths::base_repo_path(options.config_dir.clone())?;
+    let repo_db_path = did::paths::profile_db_path(options.config_dir.clone())?;
+    let base_db_path = did::paths::base_db_path(options.config_dir.clone())?;
     let schema_path = did::paths::schemas_path(options.schemas_dir.clone())?;
 
     let mut interactor = FakeUserInteractor::new();
@@ -119,36 +123,69 @@ fn start_daemon(options: Options) -> Fallible<Server> {
         info!("No profile vault found in {}, restore it first", vault_path.to_string_lossy());
     }
 
-    let local_repo = FileProfileRepository::new(&repo_path)?;
-    let base_repo = FileProfileRepository::new(&base_path)?;
+    // NOTE profiles.dat and bases.dat are imported only once, when the databases are created
+    let local_repo =
+        Arc::new(RwLock::new(SqliteProfileRepository::open_or_import(&repo_db_path, &repo_path)?));
+    let base_repo = SqliteProfileRepository::open_or_import(&base_db_path, &base_path)?;
     let timeout = Duration::from_secs(options.network_timeout_secs);
+    let claim_expiry_warning_secs =
+        options.claim_expiry_warning_days.checked_mul(24 * 3600).ok_or_else(|| {
+            format_err!(
+                "Claim expiry warning period of {} days is too long",
+                options.claim_expiry_warning_days
+            )
+        })?;
+    let claim_expiry_warning = Duration::from_secs(claim_expiry_warning_secs);
     // TODO use some kind of real storage here on the long run
-    let remote_repo =
-        FileProfileRepository::new(&std::path::PathBuf::from("/tmp/mercury/home/profile-backups"))?;
-    let home_node_crawler = Default::default();
+    let remote_path = std::path::PathBuf::from("/tmp/mercury/home/profile-backups");
+    let remote_repo = FileProfileRepository::new(&remote_path)?;
+    let explorer = FileProfileRepository::new(&remote_path)?;
+    let home_node_crawler: Arc<RwLock<HomeNodeCrawler>> = Default::default();
+
+    // TODO make file path configurable, check config parameters for potential outdated repo path
+    // TODO use crawler and connected home nodes for distributed storage on the long run
+    let profile_repo = Arc::new(RwLock::new(FileProfileRepository::new(
+        &std::path::PathBuf::from("/tmp/cuccos"),
+    )?));
+    let connector = Arc::new(TcpHomeConnector::new(profile_repo.clone()));
 
     let vault_state = VaultState::new(
         vault_path.clone(),
         schema_path.clone(),
         vault,
-        Arc::new(RwLock::new(local_repo)),
+        local_repo.clone(),
         Box::new(base_repo),
         Box::new(remote_repo),
+        Box::new(explorer),
+        claim_expiry_warning,
+        connector.clone(),
+        home_node_crawler.clone(),
     );
 
-    // TODO make file path configurable, check config parameters for potential outdated repo path
-    // TODO use crawler and connected home nodes for distributed storage on the long run
-    let profile_repo = Arc::new(RwLock::new(FileProfileRepository::new(
-        &std::path::PathBuf::from("/tmp/cuccos"),
-    )?));
-    let connector = Arc::new(TcpHomeConnector::new(profile_repo.clone()));
-    let dapp_state = DAppSessionServiceImpl::new(Arc::new(RwLock::new(interactor)));
+    let dapp_state = DAppSessionServiceImpl::new(
+        Arc::new(RwLock::new(interactor)),
+        vault_path,
+        local_repo,
+        connector.clone(),
+    );
 
     let network_state = NetworkState::new(connector, home_node_crawler);
 
     let daemon_state =
         web::Data::new(Mutex::new(DaemonState::new(vault_state, dapp_state, network_state)));
 
+    let watched_state = daemon_state.clone();
+    std::thread::Builder::new().name("claim-expiry-watcher".to_owned()).spawn(move || loop {
+        match watched_state.lock() {
+            Ok(mut state) => state.vault.notify_claim_expiries(),
+            Err(e) => {
+                error!("Failed to lock state, stopped watching claim expiries: {}", e);
+                return;
+            }
+        }
+        std::thread::sleep(CLAIM_EXPIRY_CHECK_INTERVAL);
+    })?;
+
     // TODO The current implementation is not known to ever panic. However,
     //      if it was then the Arbiter thread would stop but not the whole server.
     //      The server should not be in an inconsistent half-stopped state after any panic.
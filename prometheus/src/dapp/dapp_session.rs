@@ -1,12 +1,25 @@
+use std::cell::Cell;
+use std::path::PathBuf;
+use std::rc::Rc;
 use std::sync::{Arc, RwLock};
+use std::time::Instant;
 
-use futures::{Future, Stream};
+use failure::{err_msg, format_err, Fallible};
+use futures::sync::mpsc;
+use futures::{future, stream, Future, Stream};
+use tokio::timer::Interval;
 
 use crate::dapp::user_interactor::UserInteractor;
+use crate::home::net::HomeConnector;
+use crate::home::session::ReconnectingSession;
 use crate::*;
 use claims::model::*;
+use claims::repo::{PrivateProfileRepository, SqliteProfileRepository};
+use did::vault::{HdProfileVault, ProfileVault};
 use mercury_home_protocol::{
-    AppMessageFrame, AppMsgSink, AppMsgStream, ApplicationId, IncomingCall, RelationProof,
+    AppMessage, AppMessageFrame, AppMsgSink, AppMsgStream, ApplicationId, AsyncStream,
+    CallRequestDetails, Home, HomeSession, IncomingCall, Presence, ProfileEvent, ProfileFacets,
+    RelationProof, Signer, CHANNEL_CAPACITY, HEARTBEAT_INTERVAL,
 };
 
 pub struct DAppCall {
@@ -21,13 +34,72 @@ pub struct DAppCall {
 // - send message via client to target profile
 pub trait Relation {
     fn proof(&self) -> &RelationProof;
-    // TODO fn send(&self, message: &MessageContent) -> AsyncFallible<()>;
+    /// Leaves a message on the home of the peer, the peer does not have to be online.
+    fn send(&self, message: AppMessageFrame) -> AsyncFallible<()>;
     fn call(&self, init_payload: AppMessageFrame) -> AsyncFallible<DAppCall>;
+    /// Presence last published by the peer.
+    fn presence(&self) -> AsyncFallible<Presence>;
+    /// Current presence of the peer followed by all its changes.
+    fn presence_updates(&self) -> AsyncStream<Presence, String>;
+}
+
+/// Relation reaching the peer through the home hosting the peer.
+pub struct HomeRelation {
+    proof: RelationProof,
+    dapp_id: ApplicationId,
+    peer_home: Rc<dyn Home>,
+}
+
+impl HomeRelation {
+    pub fn new(proof: RelationProof, dapp_id: ApplicationId, peer_home: Rc<dyn Home>) -> Self {
+        Self { proof, dapp_id, peer_home }
+    }
+}
+
+impl Relation for HomeRelation {
+    fn proof(&self) -> &RelationProof {
+        &self.proof
+    }
+
+    fn send(&self, message: AppMessageFrame) -> AsyncFallible<()> {
+        let send_fut = self
+            .peer_home
+            .send_message(self.proof.clone(), self.dapp_id.clone(), message)
+            .map_err(|e| e.into());
+        Box::new(send_fut)
+    }
+
+    fn call(&self, init_payload: AppMessageFrame) -> AsyncFallible<DAppCall> {
+        let (to_caller, incoming) = mpsc::channel(CHANNEL_CAPACITY);
+        let call_req = CallRequestDetails {
+            relation: self.proof.clone(),
+            init_payload,
+            to_caller: Some(to_caller),
+        };
+        let call_fut =
+            self.peer_home.call(self.dapp_id.clone(), call_req).map_err(|e| e.into()).and_then(
+                |to_callee_opt| {
+                    to_callee_opt
+                        .map(|outgoing| DAppCall { outgoing, incoming })
+                        .ok_or_else(|| err_msg("Call was not answered by the peer"))
+                },
+            );
+        Box::new(call_fut)
+    }
+
+    fn presence(&self) -> AsyncFallible<Presence> {
+        let presence_fut = self.peer_home.presence(self.proof.clone()).map_err(|e| e.into());
+        Box::new(presence_fut)
+    }
+
+    fn presence_updates(&self) -> AsyncStream<Presence, String> {
+        self.peer_home.subscribe_presence(self.proof.clone())
+    }
 }
 
 pub enum DAppEvent {
     PairingResponse(Box<dyn Relation>),
-    //TODO Message(...)
+    Message(AppMessage), // NOTE only for live notification, messages must be acknowledged on the home
     Call(Box<dyn IncomingCall>), // TODO wrap IncomingCall so as call.answer() could return a DAppCall directly
 }
 
@@ -49,11 +121,87 @@ pub trait DAppSession {
 pub struct DAppSessionImpl {
     dapp_id: ApplicationId,
     profile_id: ProfileId,
+    connector: Arc<dyn HomeConnector + Send + Sync>,
+    signer: Rc<dyn Signer>,
+    home_id: ProfileId,
+    session: Rc<dyn HomeSession>,
 }
 
 impl DAppSessionImpl {
-    pub fn new(dapp_id: ApplicationId, profile_id: ProfileId) -> Self {
-        Self { profile_id, dapp_id }
+    pub fn new(
+        dapp_id: ApplicationId,
+        connector: Arc<dyn HomeConnector + Send + Sync>,
+        signer: Rc<dyn Signer>,
+        home_id: ProfileId,
+        session: Rc<dyn HomeSession>,
+    ) -> Self {
+        let profile_id = signer.profile_id().to_owned();
+        Self { dapp_id, profile_id, connector, signer, home_id, session }
+    }
+
+    /// Relation reaching the peer of `proof` through the first home listed in its public profile.
+    fn home_relation(
+        connector: &Arc<dyn HomeConnector + Send + Sync>,
+        signer: &Rc<dyn Signer>,
+        home_id: &ProfileId,
+        dapp_id: &ApplicationId,
+        proof: RelationProof,
+    ) -> AsyncFallible<HomeRelation> {
+        let peer_id = match proof.peer_id(signer.profile_id()) {
+            Ok(peer_id) => peer_id.to_owned(),
+            Err(e) => return Box::new(future::err(e.into())),
+        };
+
+        let connector = connector.clone();
+        let signer = signer.clone();
+        let dapp_id = dapp_id.to_owned();
+        let relation_fut = connector
+            .clone()
+            .connect(home_id, &[], signer.clone())
+            .and_then({
+                let peer_id = peer_id.clone();
+                move |home| home.fetch(&peer_id)
+            })
+            .and_then(move |peer_profile| {
+                let home_proof = peer_profile
+                    .to_hosted()
+                    .and_then(|hosted| hosted.homes.into_iter().next())
+                    .ok_or_else(|| format_err!("Profile {} is not hosted on any home", peer_id))?;
+                Ok(home_proof.peer_id(&peer_id)?.to_owned())
+            })
+            .and_then(move |peer_home_id| connector.connect(&peer_home_id, &[], signer))
+            .map(move |peer_home| HomeRelation::new(proof, dapp_id, peer_home));
+        Box::new(relation_fut)
+    }
+
+    /// The home does not push messages to the session, so the inbox of the application is
+    /// polled and messages not seen yet are emitted until they are acknowledged.
+    fn message_events(&self) -> impl Stream<Item = DAppEvent, Error = ()> {
+        let session = self.session.clone();
+        let dapp_id = self.dapp_id.clone();
+        let last_seen_id = Cell::new(None);
+        Interval::new(Instant::now(), HEARTBEAT_INTERVAL)
+            .map_err(|e| warn!("Message polling timer failed: {}", e))
+            .and_then(move |_instant| {
+                session.messages(&dapp_id).then(|res| {
+                    Ok(res.unwrap_or_else(|e| {
+                        warn!("Failed to fetch messages from home: {}", e);
+                        vec![]
+                    }))
+                })
+            })
+            .map(move |messages: Vec<AppMessage>| {
+                let unseen = messages
+                    .into_iter()
+                    .filter(|message| Some(message.id) > last_seen_id.get())
+                    .collect::<Vec<_>>();
+                if let Some(max_id) = unseen.iter().map(|message| message.id).max() {
+                    last_seen_id.set(Some(max_id));
+                }
+                stream::iter_ok(unseen)
+            })
+            .flatten()
+            .map(DAppEvent::Message)
     }
 }
 
@@ -83,7 +231,38 @@ impl DAppSession for DAppSessionImpl {
     }
 
     fn checkin(&self) -> AsyncFallible<Box<dyn Stream<Item = DAppEvent, Error = ()>>> {
-        unimplemented!()
+        let calls = self
+            .session
+            .checkin_app(&self.dapp_id)
+            .map(DAppEvent::Call)
+            .map_err(|e| warn!("Failed to receive calls from home: {}", e));
+
+        let (connector, signer, home_id) =
+            (self.connector.clone(), self.signer.clone(), self.home_id.clone());
+        let dapp_id = self.dapp_id.clone();
+        let pairings = self
+            .session
+            .events()
+            .map_err(|e| warn!("Failed to receive events from home: {}", e))
+            .filter_map(|event| match event {
+                ProfileEvent::PairingResponse(proof) => Some(proof),
+                _ => None,
+            })
+            .and_then(move |proof| {
+                Self::home_relation(&connector, &signer, &home_id, &dapp_id, proof).then(|res| {
+                    match res {
+                        Ok(relation) => Ok(Some(DAppEvent::PairingResponse(Box::new(relation)))),
+                        Err(e) => {
+                            warn!("Failed to reach the home of the paired profile: {}", e);
+                            Ok(None)
+                        }
+                    }
+                })
+            })
+            .filter_map(|event| event);
+
+        let events = calls.select(pairings).select(self.message_events());
+        Box::new(future::ok(Box::new(events) as Box<dyn Stream<Item = DAppEvent, Error = ()>>))
     }
 }
 
@@ -94,11 +273,40 @@ pub trait DAppSessionService {
 
 pub struct DAppSessionServiceImpl {
     interactor: Arc<RwLock<dyn UserInteractor + Send + Sync>>,
+    vault_path: PathBuf,
+    local_repo: Arc<RwLock<SqliteProfileRepository>>,
+    connector: Arc<dyn HomeConnector + Send + Sync>,
 }
 
 impl DAppSessionServiceImpl {
-    pub fn new(interactor: Arc<RwLock<dyn UserInteractor + Send + Sync>>) -> Self {
-        Self { interactor }
+    pub fn new(
+        interactor: Arc<RwLock<dyn UserInteractor + Send + Sync>>,
+        vault_path: PathBuf,
+        local_repo: Arc<RwLock<SqliteProfileRepository>>,
+        connector: Arc<dyn HomeConnector + Send + Sync>,
+    ) -> Self {
+        Self { interactor, vault_path, local_repo, connector }
+    }
+
+    /// Signer of the profile and the proof of the home it uses to reach its peers.
+    fn home_login(
+        vault_path: &PathBuf,
+        local_repo: &RwLock<SqliteProfileRepository>,
+        profile_id: &ProfileId,
+    ) -> Fallible<(Rc<dyn Signer>, RelationProof)> {
+        // NOTE the vault of the daemon state is not shared, it must be uniquely owned to be changed
+        let signer = Arc::new(HdProfileVault::load(vault_path)?).signer(profile_id)?;
+        let profile = local_repo
+            .try_read()
+            .map_err(|e| format_err!("Failed to lock profile repository: {}", e))?
+            .get(profile_id)
+            .wait()?;
+        let host_proof = profile
+            .public_data()
+            .to_hosted()
+            .and_then(|hosted| hosted.homes.into_iter().next())
+            .ok_or_else(|| format_err!("Profile {} is not hosted on any home", profile_id))?;
+        Ok((signer, host_proof))
     }
 }
 
@@ -111,9 +319,31 @@ impl DAppSessionService for DAppSessionServiceImpl {
                 unreachable!()
             }
         };
-        let session_fut = interactor.select_profile().map(move |profile| {
-            Arc::new(DAppSessionImpl::new(app, profile)) as Arc<dyn DAppSession>
-        });
+        let vault_path = self.vault_path.clone();
+        let local_repo = self.local_repo.clone();
+        let connector = self.connector.clone();
+        let session_fut = interactor
+            .select_profile()
+            .and_then(move |profile_id| {
+                let (signer, host_proof) = Self::home_login(&vault_path, &local_repo, &profile_id)?;
+                let home_id = host_proof.peer_id(&profile_id)?.to_owned();
+                Ok((signer, host_proof, home_id))
+            })
+            .and_then(move |(signer, host_proof, home_id)| {
+                ReconnectingSession::login(
+                    connector.clone(),
+                    home_id.clone(),
+                    vec![],
+                    signer.clone(),
+                    host_proof,
+                )
+                .map_err(|e| e.into())
+                .map(move |session| {
+                    let session = Rc::new(session) as Rc<dyn HomeSession>;
+                    Arc::new(DAppSessionImpl::new(app, connector, signer, home_id, session))
+                        as Arc<dyn DAppSession>
+                })
+            });
         Box::new(session_fut)
     }
 }
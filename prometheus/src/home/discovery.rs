@@ -31,24 +31,29 @@ impl Default for HomeNodeCrawler {
         let mut result = Self { home_profiles: Default::default() };
         let facet = HomeFacet::new(vec!["/ip4/127.0.0.1/tcp/2077".parse().unwrap()], vec![]);
         let attributes = facet.to_attribute_map();
-        result
-            .add(&Profile::new(
-                "pez7aYuvoDPM5i7xedjwjsWaFVzL3qRKPv4sBLv3E3pAGi6".parse().unwrap(),
-                1,
-                vec![],
-                attributes,
-            ))
-            .unwrap();
+        // NOTE bootstrap nodes are configured locally, so they are trusted without a signature
+        result.insert(&Profile::new(
+            "pez7aYuvoDPM5i7xedjwjsWaFVzL3qRKPv4sBLv3E3pAGi6".parse().unwrap(),
+            1,
+            vec![],
+            attributes,
+        ));
         result
     }
 }
 
 impl HomeNodeCrawler {
+    /// Adds a home node profile discovered on the network, it must be signed by the node itself.
     pub fn add(&mut self, home: &Profile) -> Fallible<()> {
-        let home_facet = match home.to_home() {
-            None => bail!("Not a profile of a home node"),
-            Some(facet) => facet,
-        };
+        if home.to_home().is_none() {
+            bail!("Not a profile of a home node");
+        }
+        home.validate()?;
+        self.insert(home);
+        Ok(())
+    }
+
+    fn insert(&mut self, home: &Profile) {
         self.home_profiles
             .entry(home.id())
             .and_modify(|p| {
@@ -59,7 +64,6 @@ impl HomeNodeCrawler {
                 }
             })
             .or_insert(KnownHomeNode { profile: home.to_owned(), latency: None });
-        Ok(())
     }
 
     pub fn iter(&self) -> impl Iterator<Item = &KnownHomeNode> {
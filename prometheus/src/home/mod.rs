@@ -1,3 +1,4 @@
 pub mod connection;
 pub mod discovery;
 pub mod net;
+pub mod session;
@@ -3,7 +3,7 @@ use std::net::{IpAddr, SocketAddr};
 use std::rc::Rc;
 use std::sync::{Arc, RwLock};
 
-use failure::{bail, format_err, Fail};
+use failure::{bail, format_err, Fail, Fallible};
 use futures::{future, Future, IntoFuture};
 use log::*;
 use multiaddr::{AddrComponent, Multiaddr};
@@ -11,7 +11,7 @@ use tokio::net::tcp::TcpStream;
 
 use crate::*;
 use mercury_home_protocol::primitives::ProfileFacets;
-use mercury_home_protocol::{AsyncFallible, Home, Profile, ProfileId, Signer};
+use mercury_home_protocol::{AsyncFallible, Home, PeerContext, Profile, ProfileId, Signer};
 use std::collections::HashMap;
 
 pub trait HomeConnector {
@@ -21,6 +21,10 @@ pub trait HomeConnector {
         addr_hints: &[Multiaddr],
         signer: Rc<dyn Signer>,
     ) -> AsyncFallible<Rc<dyn Home>>;
+
+    /// Forgets the connection of the client to the home, e.g. after it was found broken,
+    /// so the next `connect()` opens a new one.
+    fn evict(&self, home_profile_id: &ProfileId, client_id: &ProfileId);
 }
 
 /// Convert a TCP/IP multiaddr to a SocketAddr. For multiaddr instances that are not TCP or IP, error is returned.
@@ -45,7 +49,12 @@ pub fn multiaddr_to_socketaddr(multiaddr: &Multiaddr) -> Fallible<SocketAddr> {
     Ok(SocketAddr::new(ip_address, ip_port))
 }
 
-// TODO consider tearing down and rebuilding the whole connection in case of a network error
+/// Returns true if the multiaddr is served over WebSocket, e.g. `/ip4/127.0.0.1/tcp/2078/ws`.
+pub fn is_websocket(multiaddr: &Multiaddr) -> bool {
+    multiaddr.iter().any(|component| matches!(component, AddrComponent::WS))
+}
+
+// NOTE a broken connection stays here until it is evicted, see ReconnectingSession
 // Map of pair<client_profile_id, home_profile_id> => pair<multiaddr, Home instance>
 thread_local!(static HOME_CACHE: RefCell<HashMap<(ProfileId, ProfileId), (Multiaddr, Rc<dyn Home>)>> = Default::default());
 
@@ -61,7 +70,7 @@ impl TcpHomeConnector {
     }
 
     pub fn connect_addr(addr: &Multiaddr) -> AsyncFallible<TcpStream> {
-        // TODO handle other multiaddresses, not only TCP
+        // TODO handle other multiaddresses, not only TCP and WebSocket over TCP
         let tcp_addr = match multiaddr_to_socketaddr(addr) {
             Ok(res) => res,
             Err(err) => return Box::new(future::err(err)),
@@ -90,35 +99,82 @@ impl TcpHomeConnector {
         }
 
         debug!("Home address {:?} not found in cache, connecting", addresses);
-        let tcp_conns = addresses.iter().map(move |addr| {
+        let home_conns = addresses.iter().map(|addr| {
             let addr_clone = addr.to_owned();
-            TcpHomeConnector::connect_addr(&addr).map(move |tcp_stream| (addr_clone, tcp_stream))
+            let home_profile_id = home_profile_id.to_owned();
+            let signer = signer.clone();
+            TcpHomeConnector::connect_addr(&addr).and_then(move |tcp_stream| {
+                Self::open_home(&addr_clone, tcp_stream, &home_profile_id, signer)
+                    .map(move |home| (addr_clone, home))
+            })
         });
 
-        let home_profile_id = home_profile_id.to_owned();
-        // TODO We have to find the first successful connection **that could authenticate itself as a home node
-        //      that has the given ProfileId**. At the moment the first successful TCP connection already wins.
-        let capnp_home = future::select_ok(tcp_conns)
-            .and_then( move |((addr, tcp_stream), _pending_futs)|
-            {
-                use mercury_home_protocol::handshake::temporary_unsafe_tcp_handshake_until_diffie_hellman_done;
-                temporary_unsafe_tcp_handshake_until_diffie_hellman_done(tcp_stream, signer.clone())
-                    .map_err(|err| err.context(mercury_home_protocol::error::ErrorKind::DiffieHellmanHandshakeFailed).into())
-                    .map( move |(reader, writer, _peer_ctx)| {
-                        use mercury_home_protocol::mercury_capnp::client_proxy::HomeClientCapnProto;
-                        let home = Rc::new( HomeClientCapnProto::new(reader, writer) ) as Rc<dyn Home>;
-
-                        debug!("Save home {:?} client into cache for reuse", addr);
-                        HOME_CACHE.with(|cache| {
-                            cache.borrow_mut().insert((signer.profile_id().to_owned(), home_profile_id), (addr, home.clone()));
-                        });
-                        home
-                    })
+        // NOTE the first connection authenticated as the home with the given ProfileId wins
+        let capnp_home = future::select_ok(home_conns).map(move |((addr, home), _pending_futs)| {
+            debug!("Save home {:?} client into cache for reuse", addr);
+            HOME_CACHE.with(|cache| {
+                cache.borrow_mut().insert(key, (addr, home.clone()));
             });
+            home
+        });
 
         Box::new(capnp_home)
     }
 
+    /// Authenticates the home on a connection opened to the address and sets up a client to it,
+    /// using WebSocket framing on addresses ending with `/ws`. Fails if the peer is not the home
+    /// with the expected id.
+    fn open_home(
+        addr: &Multiaddr,
+        tcp_stream: TcpStream,
+        home_profile_id: &ProfileId,
+        signer: Rc<dyn Signer>,
+    ) -> AsyncFallible<Rc<dyn Home>> {
+        use mercury_home_protocol::error::ErrorKind;
+        use mercury_home_protocol::handshake::{ecdh_handshake, tcpstream_to_reader_writer};
+        use mercury_home_protocol::mercury_capnp::client_proxy::HomeClientCapnProto;
+        use mercury_home_protocol::websocket;
+
+        let (reader, writer) = match tcpstream_to_reader_writer(tcp_stream) {
+            Ok(halves) => halves,
+            Err(e) => return Box::new(future::err(e.into())),
+        };
+
+        let home_profile_id = home_profile_id.to_owned();
+        if !is_websocket(addr) {
+            let home_fut = ecdh_handshake(reader, writer, signer)
+                .map_err(|err| err.context(ErrorKind::DiffieHellmanHandshakeFailed).into())
+                .and_then(move |(reader, writer, peer_ctx)| {
+                    Self::check_home_id(&peer_ctx, &home_profile_id)?;
+                    Ok(Rc::new(HomeClientCapnProto::new(reader, writer)) as Rc<dyn Home>)
+                });
+            return Box::new(home_fut);
+        }
+
+        let host = match multiaddr_to_socketaddr(addr) {
+            Ok(socket_addr) => socket_addr.to_string(),
+            Err(e) => return Box::new(future::err(e)),
+        };
+        let home_fut = websocket::connect(reader, writer, &host, "/")
+            .and_then(move |(reader, writer)| ecdh_handshake(reader, writer, signer))
+            .map_err(|err| err.context(ErrorKind::DiffieHellmanHandshakeFailed).into())
+            .and_then(move |(reader, writer, peer_ctx)| {
+                Self::check_home_id(&peer_ctx, &home_profile_id)?;
+                Ok(Rc::new(HomeClientCapnProto::new(reader, writer)) as Rc<dyn Home>)
+            });
+        Box::new(home_fut)
+    }
+
+    fn check_home_id(peer_ctx: &PeerContext, home_profile_id: &ProfileId) -> Fallible<()> {
+        use mercury_home_protocol::error::ErrorKind;
+
+        if peer_ctx.peer_id() != *home_profile_id {
+            warn!("Expected home {}, but peer {} answered", home_profile_id, peer_ctx.peer_id());
+            return Err(ErrorKind::HomeIdMismatch.into());
+        }
+        Ok(())
+    }
+
     fn connect_to_home_profile(
         &self,
         home_profile: &Profile,
@@ -164,6 +220,13 @@ impl HomeConnector for TcpHomeConnector {
             .and_then(move |home_profile| this.connect_to_home_profile(&home_profile, signer));
         Box::new(home_conn_fut)
     }
+
+    fn evict(&self, home_profile_id: &ProfileId, client_id: &ProfileId) {
+        let key = (client_id.to_owned(), home_profile_id.to_owned());
+        if let Some((addr, _home)) = HOME_CACHE.with(|cache| cache.borrow_mut().remove(&key)) {
+            debug!("Evicted connection to home {} with address {:?}", home_profile_id, addr);
+        }
+    }
 }
 
 #[cfg(test)]
@@ -181,4 +244,15 @@ mod tests {
         let socketaddr = multiaddr_to_socketaddr(&multiaddr);
         assert!(socketaddr.is_err());
     }
+
+    #[test]
+    fn test_websocket_multiaddr() {
+        let multiaddr = "/ip4/127.0.0.1/tcp/2078/ws".parse::<Multiaddr>().unwrap();
+        assert!(is_websocket(&multiaddr));
+        let socketaddr = multiaddr_to_socketaddr(&multiaddr).unwrap();
+        assert_eq!(socketaddr, SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 2078));
+
+        let multiaddr = "/ip4/127.0.0.1/tcp/2077".parse::<Multiaddr>().unwrap();
+        assert!(!is_websocket(&multiaddr));
+    }
 }
@@ -0,0 +1,252 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+use std::sync::Arc;
+
+use futures::future::Shared;
+use futures::sync::mpsc;
+use futures::{future, Future, Sink, Stream};
+use log::*;
+use multiaddr::Multiaddr;
+use tokio::timer::Interval;
+use tokio_current_thread as reactor;
+
+use crate::home::net::HomeConnector;
+use mercury_home_protocol::error::{Error, ErrorKind};
+use mercury_home_protocol::*;
+
+/// A session on a home kept alive with heartbeats. When a heartbeat fails, e.g. the connection
+/// was lost or the home expired the session, it connects again and logs in with a new session.
+/// Streams returned by `events()` and `checkin_app()` survive reconnects,
+/// they are subscribed again on each new session.
+pub struct ReconnectingSession {
+    state: Rc<SessionState>,
+}
+
+struct SessionState {
+    connector: Arc<dyn HomeConnector + Send + Sync>,
+    home_id: ProfileId,
+    addr_hints: Vec<Multiaddr>,
+    signer: Rc<dyn Signer>,
+    host_proof: RelationProof,
+    session: RefCell<Option<Rc<dyn HomeSession>>>, // None while reconnecting
+    reconnecting: RefCell<Option<Shared<AsyncResult<Rc<dyn HomeSession>, Error>>>>,
+    presence: RefCell<Option<Presence>>, // published again on reconnect
+    events: RefCell<Option<AsyncSink<ProfileEvent, String>>>,
+    apps: RefCell<HashMap<ApplicationId, AsyncSink<Box<dyn IncomingCall>, String>>>,
+}
+
+impl ReconnectingSession {
+    /// Logs in to the home of the profile proven by `host_proof` and keeps the session alive
+    /// until the returned instance is dropped.
+    pub fn login(
+        connector: Arc<dyn HomeConnector + Send + Sync>,
+        home_id: ProfileId,
+        addr_hints: Vec<Multiaddr>,
+        signer: Rc<dyn Signer>,
+        host_proof: RelationProof,
+    ) -> AsyncResult<Self, Error> {
+        let state = Rc::new(SessionState {
+            connector,
+            home_id,
+            addr_hints,
+            signer,
+            host_proof,
+            session: Default::default(),
+            reconnecting: Default::default(),
+            presence: Default::default(),
+            events: Default::default(),
+            apps: Default::default(),
+        });
+
+        let login_fut = SessionState::reconnect(&state).map(move |_session| {
+            reactor::spawn(SessionState::keep_alive(Rc::downgrade(&state)));
+            Self { state }
+        });
+        Box::new(login_fut)
+    }
+
+    fn session(&self) -> AsyncResult<Rc<dyn HomeSession>, Error> {
+        let current = self.state.session.borrow().clone();
+        match current {
+            Some(session) => Box::new(future::ok(session)),
+            None => SessionState::reconnect(&self.state),
+        }
+    }
+}
+
+impl SessionState {
+    fn keep_alive(state: Weak<SessionState>) -> impl Future<Item = (), Error = ()> {
+        Interval::new_interval(HEARTBEAT_INTERVAL)
+            .map_err(|e| warn!("Heartbeat timer failed: {}", e))
+            // NOTE the timer stops with an error after the session was dropped
+            .for_each(move |_instant| match state.upgrade() {
+                Some(state) => future::Either::A(SessionState::heartbeat(state)),
+                None => future::Either::B(future::err(())),
+            })
+    }
+
+    fn heartbeat(state: Rc<SessionState>) -> impl Future<Item = (), Error = ()> {
+        let current = state.session.borrow().clone();
+        let heartbeat_fut: AsyncResult<(), Error> = match current {
+            Some(session) => session.heartbeat(),
+            None => Box::new(future::err(ErrorKind::SessionExpired.into())),
+        };
+        heartbeat_fut.or_else(move |e| {
+            info!("Session on home {} was lost: {}, reconnecting", state.home_id, e);
+            SessionState::reconnect(&state).map(|_session| ()).or_else(|e| {
+                // NOTE reconnecting is retried with the next heartbeat
+                warn!("Failed to reconnect to home: {}", e);
+                Ok(())
+            })
+        })
+    }
+
+    /// Logs in with a new session. Callers arriving while a reconnect is already in progress
+    /// wait for that one instead of starting another, so the streams are forwarded only once.
+    fn reconnect(state: &Rc<SessionState>) -> AsyncResult<Rc<dyn HomeSession>, Error> {
+        let pending = state.reconnecting.borrow().clone();
+        let shared_fut = match pending {
+            Some(shared_fut) => shared_fut,
+            None => {
+                let shared_fut = SessionState::login(state).shared();
+                state.reconnecting.replace(Some(shared_fut.clone()));
+                shared_fut
+            }
+        };
+        Box::new(shared_fut.map(|session| (*session).clone()).map_err(|e| Error::from(e.kind())))
+    }
+
+    fn login(state: &Rc<SessionState>) -> AsyncResult<Rc<dyn HomeSession>, Error> {
+        state.session.replace(None);
+        state.connector.evict(&state.home_id, state.signer.profile_id());
+
+        let host_proof = state.host_proof.clone();
+        // NOTE the pending login is owned by the state, a strong reference would leak both
+        let weak_state = Rc::downgrade(state);
+        let login_fut = state
+            .connector
+            .clone()
+            .connect(&state.home_id, &state.addr_hints, state.signer.clone())
+            .map_err(|e| e.context(ErrorKind::ConnectionToHomeFailed).into())
+            .and_then(move |home| home.login(&host_proof))
+            .then(move |res| {
+                let state = match weak_state.upgrade() {
+                    Some(state) => state,
+                    None => return Err(ErrorKind::SessionExpired.into()),
+                };
+                state.reconnecting.replace(None);
+                let session = res?;
+                debug!("Logged in to home {}", state.home_id);
+                state.subscribe(&session);
+                state.session.replace(Some(session.clone()));
+                Ok(session)
+            });
+        Box::new(login_fut)
+    }
+
+    /// Forwards the streams of the new session into the ones already returned to the client
+    fn subscribe(&self, session: &Rc<dyn HomeSession>) {
+        if let Some(ref sink) = *self.events.borrow() {
+            forward(session.events(), sink.clone());
+        }
+        for (app, sink) in self.apps.borrow().iter() {
+            forward(session.checkin_app(app), sink.clone());
+        }
+        if let Some(presence) = self.presence.borrow().clone() {
+            reactor::spawn(
+                session
+                    .set_presence(presence)
+                    .map_err(|e| warn!("Failed to restore presence on home: {}", e)),
+            );
+        }
+    }
+}
+
+fn forward<T: 'static>(stream: AsyncStream<T, String>, sink: AsyncSink<T, String>) {
+    // NOTE the stream ends with the session, the sink is still open for the next one
+    reactor::spawn(sink.sink_map_err(|_e| ()).send_all(stream).map(|_| ()));
+}
+
+impl HomeSession for ReconnectingSession {
+    fn backup(&self, own_profile: OwnProfile) -> AsyncResult<(), Error> {
+        Box::new(self.session().and_then(move |session| session.backup(own_profile)))
+    }
+
+    fn restore(&self) -> AsyncResult<OwnProfile, Error> {
+        Box::new(self.session().and_then(|session| session.restore()))
+    }
+
+    fn unregister(&self, new_home: Option<Profile>) -> AsyncResult<(), Error> {
+        Box::new(self.session().and_then(move |session| session.unregister(new_home)))
+    }
+
+    fn events(&self) -> AsyncStream<ProfileEvent, String> {
+        let (sender, receiver) = mpsc::channel(CHANNEL_CAPACITY);
+        if let Some(ref session) = *self.state.session.borrow() {
+            forward(session.events(), sender.clone());
+        }
+        self.state.events.replace(Some(sender));
+        receiver
+    }
+
+    fn ack_event(&self, event: &ProfileEvent) -> AsyncResult<(), Error> {
+        let event = event.to_owned();
+        Box::new(self.session().and_then(move |session| session.ack_event(&event)))
+    }
+
+    fn checkin_app(&self, app: &ApplicationId) -> AsyncStream<Box<dyn IncomingCall>, String> {
+        let (sender, receiver) = mpsc::channel(CHANNEL_CAPACITY);
+        if let Some(ref session) = *self.state.session.borrow() {
+            forward(session.checkin_app(app), sender.clone());
+        }
+        self.state.apps.borrow_mut().insert(app.to_owned(), sender);
+        receiver
+    }
+
+    fn messages(&self, app: &ApplicationId) -> AsyncResult<Vec<AppMessage>, Error> {
+        let app = app.to_owned();
+        Box::new(self.session().and_then(move |session| session.messages(&app)))
+    }
+
+    fn ack_messages(&self, app: &ApplicationId, ids: &[u64]) -> AsyncResult<(), Error> {
+        let (app, ids) = (app.to_owned(), ids.to_owned());
+        Box::new(self.session().and_then(move |session| session.ack_messages(&app, &ids)))
+    }
+
+    fn export_mailbox(&self) -> AsyncResult<MailboxContent, Error> {
+        Box::new(self.session().and_then(|session| session.export_mailbox()))
+    }
+
+    fn import_mailbox(&self, content: MailboxContent) -> AsyncResult<(), Error> {
+        Box::new(self.session().and_then(move |session| session.import_mailbox(content)))
+    }
+
+    fn set_presence(&self, presence: Presence) -> AsyncResult<(), Error> {
+        self.state.presence.replace(Some(presence.clone()));
+        Box::new(self.session().and_then(move |session| session.set_presence(presence)))
+    }
+
+    fn heartbeat(&self) -> AsyncResult<(), Error> {
+        Box::new(self.session().and_then(|session| session.heartbeat()))
+    }
+
+    fn ping(&self, txt: &str) -> AsyncResult<String, Error> {
+        let txt = txt.to_owned();
+        Box::new(self.session().and_then(move |session| session.ping(&txt)))
+    }
+
+    fn banned_profiles(&self) -> AsyncResult<Vec<ProfileId>, Error> {
+        Box::new(self.session().and_then(|session| session.banned_profiles()))
+    }
+
+    fn ban(&self, profile: &ProfileId) -> AsyncResult<(), Error> {
+        let profile = profile.to_owned();
+        Box::new(self.session().and_then(move |session| session.ban(&profile)))
+    }
+
+    fn unban(&self, profile: &ProfileId) -> AsyncResult<(), Error> {
+        let profile = profile.to_owned();
+        Box::new(self.session().and_then(move |session| session.unban(&profile)))
+    }
+}
@@ -32,6 +32,10 @@ pub struct Options {
     /// Number of seconds used for network timeouts
     pub network_timeout_secs: u64,
 
+    #[structopt(long = "claim-expiry-warning", default_value = "30", value_name = "DAYS")]
+    /// Number of days before the expiry of claim proofs to start warning about renewal
+    pub claim_expiry_warning_days: u64,
+
     #[structopt(long, default_value = "log4rs.yml", value_name = "FILE", parse(from_os_str))]
     /// Config file for log4rs (YAML).
     pub logger_config: PathBuf,
@@ -1,12 +1,17 @@
 use std::rc::Rc;
 use std::str::FromStr;
+use std::time::Duration;
 
 use failure::{err_msg, Fallible};
 use serde_derive::{Deserialize, Serialize};
 
 use crate::daemon::NetworkState;
+pub use crate::vault::claim_index::ClaimQuery;
 use crate::*;
+pub use claims::history::{ProfileDiff, ProfileVersionInfo, Revision};
+pub use claims::merge::{MergeResolutions, MergeSide, ProfileConflict, ProfileMerge};
 use claims::model::*;
+pub use mercury_home_protocol::{HomeInvitation, RelationProof};
 use multiaddr::Multiaddr;
 
 #[derive(Clone, Debug, Eq, Hash, PartialEq, PartialOrd)]
@@ -34,10 +39,22 @@ pub struct RestoreCounts {
     pub restore_count: u32,
 }
 
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ClaimRenewal {
+    pub claim_id: ClaimId,
+    /// Serialized SignableClaimPart to be sent to witnesses, same as served for a new claim
+    pub witness_request: String,
+    /// Witnesses managed in this vault, their proofs were already renewed
+    pub renewed_by: Vec<ProfileId>,
+    /// Remote witnesses that still have to sign the witness request
+    pub pending_witnesses: Vec<ProfileId>,
+}
+
 // TODO expose repository synced/unsynced state of profile here
 // TODO error handling better suited for HTTP status codes (analogue to checked/unchecked exceptions)
 pub trait VaultApi {
     fn restore_vault(&mut self, phrase: String) -> Fallible<()>;
+    /// Restores profiles of the vault from their remote versions or from backups on known homes.
     fn restore_all_profiles(&mut self) -> Fallible<RestoreCounts>;
 
     fn set_active_profile(&mut self, my_profile_id: &ProfileId) -> Fallible<()>;
@@ -67,22 +84,55 @@ pub trait VaultApi {
     ) -> Fallible<PrivateProfileData>;
 
     fn revert_profile(&mut self, my_profile_id: Option<ProfileId>) -> Fallible<PrivateProfileData>;
+    /// Versions of the local profile retained so far, oldest first.
+    fn profile_history(
+        &self,
+        my_profile_id: Option<ProfileId>,
+    ) -> Fallible<Vec<ProfileVersionInfo>>;
+    /// Changes from a retained revision of the local profile to another one, or to the current one.
+    fn profile_diff(
+        &self,
+        my_profile_id: Option<ProfileId>,
+        from_revision: Revision,
+        to_revision: Option<Revision>,
+    ) -> Fallible<ProfileDiff>;
+    /// Stores the content of a retained revision as a new local profile version.
+    fn rollback_profile(
+        &mut self,
+        my_profile_id: Option<ProfileId>,
+        revision: Revision,
+    ) -> Fallible<PrivateProfileData>;
+    /// Merges remote changes into the local profile. Conflicting changes are reported and nothing
+    /// is stored until all of them are decided by the given resolutions.
+    fn merge_profile(
+        &mut self,
+        my_profile_id: Option<ProfileId>,
+        resolutions: MergeResolutions,
+    ) -> Fallible<ProfileMerge>;
     fn publish_profile(
         &mut self,
         my_profile_id: Option<ProfileId>,
         force: bool,
     ) -> Fallible<ProfileId>;
+    /// Restores the profile from its remote version or its backup on known homes, whichever is newer.
     fn restore_profile(
         &mut self,
         my_profile_id: Option<ProfileId>,
         force: bool,
     ) -> Fallible<PrivateProfileData>;
 
+    /// Value of an attribute of the local profile with any visibility, None if it is not set.
+    fn get_attribute(
+        &self,
+        my_profile_id: Option<ProfileId>,
+        key: &AttributeId,
+    ) -> Fallible<Option<AttributeValue>>;
     fn set_attribute(
         &mut self,
         my_profile_id: Option<ProfileId>,
         key: &AttributeId,
         value: &AttributeValue,
+        visibility: AttributeVisibility,
     ) -> Fallible<()>;
     fn clear_attribute(
         &mut self,
@@ -91,6 +141,8 @@ pub trait VaultApi {
     ) -> Fallible<()>;
 
     fn claims(&self, my_profile_id: Option<ProfileId>) -> Fallible<Vec<Claim>>;
+    /// Claims of all vault profiles matching all filters of the query.
+    fn query_claims(&self, query: &ClaimQuery) -> Fallible<Vec<Claim>>;
     fn add_claim(&mut self, my_profile_id: Option<ProfileId>, claim: Claim) -> Fallible<()>;
     fn remove_claim(&mut self, my_profile_id: Option<ProfileId>, claim: ClaimId) -> Fallible<()>;
     fn sign_claim(
@@ -104,6 +156,14 @@ pub trait VaultApi {
         claim: &ClaimId,
         proof: ClaimProof,
     ) -> Fallible<()>;
+    /// Claims of all vault profiles that still have a valid proof, but will expire within `period`.
+    fn expiring_claims(&self, period: Duration) -> Fallible<Vec<Claim>>;
+    fn expired_claims(&self) -> Fallible<Vec<Claim>>;
+    fn renew_claim(
+        &mut self,
+        my_profile_id: Option<ProfileId>,
+        claim: &ClaimId,
+    ) -> Fallible<ClaimRenewal>;
     fn license_claim(
         &mut self,
         my_profile_id: Option<ProfileId>,
@@ -123,15 +183,74 @@ pub trait VaultApi {
         peer_profile_id: &ProfileId,
     ) -> Fallible<()>;
 
+    /// Adds the peer of a relation as a contact, contacts-only attributes of the profile
+    /// are sealed for them on next publishing.
+    fn add_contact(
+        &mut self,
+        my_profile_id: Option<ProfileId>,
+        proof: RelationProof,
+    ) -> Fallible<Contact>;
+    fn remove_contact(
+        &mut self,
+        my_profile_id: Option<ProfileId>,
+        contact_id: &ProfileId,
+    ) -> Fallible<()>;
+    /// Contacts-only attributes that a contact published sealed for the profile.
+    fn contact_attributes(
+        &self,
+        my_profile_id: Option<ProfileId>,
+        contact_id: &ProfileId,
+    ) -> Fallible<AttributeMap>;
+
     fn did_homes(&self, my_profile_id: Option<ProfileId>) -> Fallible<Vec<DidHomeStatus>>;
+    /// Homes that do not accept registrations from anyone require an invitation issued by them.
     fn register_home(
         &mut self,
         my_id: Option<ProfileId>,
         home_id: &ProfileId,
         addr_hints: &[Multiaddr],
+        invite: Option<HomeInvitation>,
+        network: &NetworkState,
+    ) -> AsyncFallible<()>;
+    /// Registers the profile on the new home and moves its backup and pending mailbox there.
+    /// The old home redirects contacts looking for the profile to the new home for a while.
+    fn move_home(
+        &mut self,
+        my_id: Option<ProfileId>,
+        old_home_id: &ProfileId,
+        new_home_id: &ProfileId,
+        addr_hints: &[Multiaddr],
+        invite: Option<HomeInvitation>,
         network: &NetworkState,
     ) -> AsyncFallible<()>;
+    /// Publishes the profile to be online or offline for its contacts on the given home.
+    fn set_home_online(
+        &mut self,
+        my_id: Option<ProfileId>,
+        home_id: &ProfileId,
+        online: bool,
+        network: &NetworkState,
+    ) -> AsyncFallible<()>;
+    /// Profiles banned by the profile on any of its homes.
+    fn banned_profiles(&self, my_id: Option<ProfileId>) -> AsyncFallible<Vec<ProfileId>>;
+    /// Bans the profile on all homes of my profile, so they refuse its requests.
+    fn ban_profile(&mut self, my_id: Option<ProfileId>, banned_id: &ProfileId)
+        -> AsyncFallible<()>;
+    fn unban_profile(
+        &mut self,
+        my_id: Option<ProfileId>,
+        banned_id: &ProfileId,
+    ) -> AsyncFallible<()>;
 
     // TODO: This is related to add_claim and other calls, but does not conceptually belong here.
     fn claim_schemas(&self) -> Fallible<Rc<dyn ClaimSchemas>>;
+    /// Signs a new version of the named schema authored by the profile and adds it to its public
+    /// profile data, so it becomes available for others after the profile is published.
+    fn publish_claim_schema(
+        &mut self,
+        my_profile_id: Option<ProfileId>,
+        name: String,
+        content: serde_json::Value,
+        ordering: Option<Vec<String>>,
+    ) -> Fallible<SchemaVersion>;
 }
@@ -7,10 +7,13 @@ use serde::{Deserializer, Serializer};
 use serde_derive::{Deserialize, Serialize};
 
 use crate::home::discovery::KnownHomeNode;
+use crate::vault::claim_index::ClaimQuery;
 pub use claims::claim_schema::{ClaimSchemas, SchemaId, SchemaVersion};
+use claims::history::Revision;
 use claims::model::*;
 use did::vault::*;
 use mercury_home_protocol::primitives::{deserialize_multiaddr_vec, serialize_multiaddr_vec};
+use mercury_home_protocol::HomeInvitation;
 
 pub type MessageContent = Vec<u8>;
 
@@ -187,6 +190,67 @@ impl TryInto<Claim> for &ApiClaim {
     }
 }
 
+/// HTTP query parameters of ClaimQuery, times are in seconds since the Unix epoch
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ClaimQueryParams {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schema: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub witness: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<ClaimExpiryStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_after: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_before: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+}
+
+fn to_unix_secs(time: &TimeStamp) -> u64 {
+    time.duration_since(std::time::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
+
+fn from_unix_secs(secs: u64) -> TimeStamp {
+    std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs)
+}
+
+impl From<&ClaimQuery> for ClaimQueryParams {
+    fn from(src: &ClaimQuery) -> Self {
+        Self {
+            schema: src.schema_id.to_owned(),
+            subject: src.subject_id.as_ref().map(|id| id.to_string()),
+            witness: src.witness_id.as_ref().map(|id| id.to_string()),
+            status: src.status,
+            expires_after: src.expires_after.as_ref().map(to_unix_secs),
+            expires_before: src.expires_before.as_ref().map(to_unix_secs),
+            content: src.content.to_owned(),
+        }
+    }
+}
+
+impl TryFrom<&ClaimQueryParams> for ClaimQuery {
+    type Error = failure::Error;
+    fn try_from(src: &ClaimQueryParams) -> Fallible<Self> {
+        Ok(Self {
+            schema_id: src.schema.to_owned(),
+            subject_id: src.subject.as_ref().map(|id| id.parse()).transpose()?,
+            witness_id: src.witness.as_ref().map(|id| id.parse()).transpose()?,
+            status: src.status,
+            expires_after: src.expires_after.map(from_unix_secs),
+            expires_before: src.expires_before.map(from_unix_secs),
+            content: src.content.to_owned(),
+        })
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ClaimExpiryQuery {
+    pub days: Option<u64>,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct ApiClaimProof {
     pub signer_id: String,
@@ -245,12 +309,51 @@ impl TryFrom<&ApiSignedMessage> for SignedMessage {
     }
 }
 
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ProfileDiffQuery {
+    pub from: Revision,
+    /// The current local version if missing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<Revision>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct AttributeVisibilityQuery {
+    /// Public if missing
+    #[serde(default)]
+    pub visibility: AttributeVisibility,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ContactPath {
+    pub did: String,
+    pub contact_did: String,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ProfileRevisionPath {
+    pub did: String,
+    pub revision: Revision,
+}
+
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct ClaimPath {
     pub did: String,
     pub claim_id: String,
 }
 
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct HomePath {
+    pub did: String,
+    pub home_did: String,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct BanPath {
+    pub did: String,
+    pub banned_did: String,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct ClaimSchema {
     id: String,
@@ -261,56 +364,43 @@ pub struct ClaimSchema {
     ordering: Vec<String>,
 }
 
-impl ClaimSchema {
-    fn new(
-        id: impl ToString,
-        label: impl ToString,
-        content: serde_json::Value,
-        ordering: Vec<String>,
-    ) -> Self {
-        Self {
-            id: id.to_string(),
-            label: label.to_string(),
-            content,
-            ordering,
-            // TODO these should be received and filled from request values
-            author: Default::default(),
-            version: Default::default(),
-        }
-    }
-}
-
 impl From<&SchemaVersion> for ClaimSchema {
     fn from(model: &SchemaVersion) -> Self {
-        Self::new(model.id(), model.name(), model.content().clone(), model.ordering().to_vec())
+        Self {
+            id: model.id().to_owned(),
+            label: model.name().to_owned(),
+            author: model.author().to_owned(),
+            version: model.version(),
+            content: model.content().clone(),
+            ordering: model.ordering().to_vec(),
+        }
     }
 }
 
+// NOTE the id is derived from the content, so it is not transferred. The author's signature is not
+//      needed either, the schema is validated by the daemon when it is published or fetched.
 impl Into<SchemaVersion> for ClaimSchema {
     fn into(self) -> SchemaVersion {
-        SchemaVersion::new_with_order(
-            self.id,
-            self.author,
-            self.label,
-            self.version,
-            self.content,
-            self.ordering,
-        )
+        if self.ordering.is_empty() {
+            SchemaVersion::new(self.author, self.label, self.version, self.content)
+        } else {
+            SchemaVersion::new_with_order(
+                self.author,
+                self.label,
+                self.version,
+                self.content,
+                self.ordering,
+            )
+        }
     }
 }
 
-//impl Into<SchemaVersion> for &ClaimSchema {
-//    fn into(self) -> SchemaVersion {
-//        SchemaVersion::new_with_order(
-//            &self.id,
-//            &self.author,
-//            &self.label,
-//            self.version,
-//            self.content.clone(),
-//            self.ordering.clone(),
-//        )
-//    }
-//}
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct PublishClaimSchema {
+    pub label: String,
+    pub content: serde_json::Value,
+    pub ordering: Option<Vec<String>>,
+}
 
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct CreateClaim {
@@ -361,4 +451,7 @@ impl From<&KnownHomeNode> for HomeNode {
 pub struct HomeRegistration {
     pub home_did: String,
     pub addr_hints: Option<Vec<String>>,
+    /// Required by homes not open for registration by anyone
+    #[serde(default)]
+    pub invite: Option<HomeInvitation>,
 }
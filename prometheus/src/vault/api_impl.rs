@@ -1,15 +1,23 @@
+use std::collections::HashSet;
 use std::path::PathBuf;
 use std::rc::Rc;
 use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
 use std::time::Duration;
 
 use failure::{bail, ensure, err_msg, format_err, Fallible};
-use futures::{future::Either, prelude::*};
+use futures::sync::mpsc;
+use futures::{future, future::Either, prelude::*};
 use log::*;
 use multiaddr::Multiaddr;
+use tokio::timer::Interval;
+use tokio_current_thread as reactor;
 
 use crate::daemon::NetworkState;
+use crate::home::discovery::HomeNodeCrawler;
+use crate::home::net::HomeConnector;
+use crate::home::session::ReconnectingSession;
 use crate::vault::api::*;
+use crate::vault::claim_index::{ClaimExpiryEvent, ClaimIndex};
 use crate::{DidHomeStatus, HomeNode};
 use claims::claim_schema::ClaimSchemaRegistry;
 pub use claims::claim_schema::{ClaimSchemas, SchemaId, SchemaVersion};
@@ -17,7 +25,11 @@ use claims::model::*;
 use claims::repo::*;
 use did::vault::{self, ProfileLabel, ProfileMetadata, ProfileVault, ProfileVaultRecord};
 use keyvault::PublicKey as KeyVaultPublicKey;
-use mercury_home_protocol::{ProfileFacets, RelationHalfProof, RelationProof};
+use mercury_home_protocol::{
+    crypto::{CompositeValidator, Validator},
+    HomeSession, Presence, PresenceStatus, ProfileFacets, RelationHalfProof, RelationProof,
+    HEARTBEAT_INTERVAL,
+};
 
 const ERR_MSG_VAULT_UNINITIALIZED: &str = "Vault is uninitialized, `restore vault` first";
 
@@ -33,9 +45,16 @@ pub struct VaultState {
     vault_path: PathBuf,
     schema_path: PathBuf, // TODO Re-reading all schemas each time might be expensive
     vault: Option<Arc<dyn ProfileVault + Send + Sync>>,
-    local_repo: Arc<RwLock<FileProfileRepository>>, // NOTE match arms of get_profile() conflicts with Box<LocalProfileRepository>
+    local_repo: Arc<RwLock<SqliteProfileRepository>>, // NOTE match arms of get_profile() conflicts with Box<LocalProfileRepository>
     base_repo: Box<dyn PrivateProfileRepository + Send>,
     remote_repo: Box<dyn PrivateProfileRepository + Send>,
+    explorer: Box<dyn ProfileExplorer + Send>,
+    claim_index: ClaimIndex,
+    claim_expiry_warning: Duration,
+    claim_expiry_subscribers: Vec<mpsc::UnboundedSender<ClaimExpiryEvent>>,
+    online_homes: Arc<RwLock<HashSet<(ProfileId, ProfileId)>>>, // {(profileId, homeId)}
+    home_connector: Arc<dyn HomeConnector + Send + Sync>,
+    home_node_crawler: Arc<RwLock<HomeNodeCrawler>>,
 }
 
 // TODO !!! The current implementation assumes that though the ProfileRepository
@@ -48,11 +67,117 @@ impl VaultState {
         vault_path: PathBuf,
         schema_path: PathBuf,
         vault: Option<Arc<dyn ProfileVault + Send + Sync>>,
-        local_repo: Arc<RwLock<FileProfileRepository>>,
+        local_repo: Arc<RwLock<SqliteProfileRepository>>,
         base_repo: Box<dyn PrivateProfileRepository + Send>,
         remote_repo: Box<dyn PrivateProfileRepository + Send>,
+        explorer: Box<dyn ProfileExplorer + Send>,
+        claim_expiry_warning: Duration,
+        home_connector: Arc<dyn HomeConnector + Send + Sync>,
+        home_node_crawler: Arc<RwLock<HomeNodeCrawler>>,
     ) -> Self {
-        Self { vault_path, schema_path, vault, local_repo, base_repo, remote_repo }
+        let mut this = Self {
+            vault_path,
+            schema_path,
+            vault,
+            local_repo,
+            base_repo,
+            remote_repo,
+            explorer,
+            claim_index: Default::default(),
+            claim_expiry_warning,
+            claim_expiry_subscribers: Default::default(),
+            online_homes: Default::default(),
+            home_connector,
+            home_node_crawler,
+        };
+        if let Err(e) = this.reindex_claims() {
+            warn!("Failed to index claims of vault profiles: {}", e);
+        }
+        this
+    }
+
+    pub fn claim_expiry_warning(&self) -> Duration {
+        self.claim_expiry_warning
+    }
+
+    /// Subscribers first get an event for each claim already expiring or expired, then changes.
+    pub fn subscribe_claim_expiries(&mut self) -> mpsc::UnboundedReceiver<ClaimExpiryEvent> {
+        let (tx, rx) = mpsc::unbounded();
+        let events = self.claim_index.expiry_events(TimeStamp::now(), self.claim_expiry_warning);
+        for event in events {
+            // NOTE the receiver is still owned here, sending cannot fail
+            let _ = tx.unbounded_send(event);
+        }
+        self.claim_expiry_subscribers.push(tx);
+        rx
+    }
+
+    /// Pushes an event to all subscribers for claims that started to expire since the last check.
+    pub fn notify_claim_expiries(&mut self) {
+        let events =
+            self.claim_index.take_expiry_events(TimeStamp::now(), self.claim_expiry_warning);
+        for event in events {
+            info!("Claim {} of profile {} is {:?}", event.claim_id, event.subject_id, event.status);
+            self.claim_expiry_subscribers
+                .retain(|subscriber| subscriber.unbounded_send(event.clone()).is_ok());
+        }
+    }
+
+    fn schema_registry(&self) -> Fallible<ClaimSchemaRegistry> {
+        let p = &self.schema_path;
+        if !p.exists() {
+            std::fs::create_dir_all(p)?;
+            ClaimSchemaRegistry::populate_folder(p)?;
+        }
+        ClaimSchemaRegistry::import_folder(p)
+    }
+
+    /// Looks up a schema in the local registry. Unknown schemas are searched for in the public
+    /// profiles of the candidate authors and saved to the registry if found.
+    fn resolve_claim_schema(
+        &self,
+        schema_id: &SchemaId,
+        candidate_authors: &[ProfileId],
+    ) -> Fallible<SchemaVersion> {
+        if let Ok(schema) = self.schema_registry()?.get(schema_id) {
+            return Ok(schema);
+        }
+
+        for author_id in candidate_authors {
+            let profile = match self.explorer.fetch(author_id).wait() {
+                Ok(profile) => profile,
+                Err(e) => {
+                    debug!("Failed to fetch profile {} for schema lookup: {}", author_id, e);
+                    continue;
+                }
+            };
+            match SchemaVersion::published_by(&profile, schema_id) {
+                Ok(Some(schema)) => {
+                    info!("Fetched claim schema {} published by {}", schema_id, author_id);
+                    ClaimSchemaRegistry::export_file(&self.schema_path, &schema)?;
+                    return Ok(schema);
+                }
+                Ok(None) => {}
+                Err(e) => warn!("Invalid schema {} published by {}: {}", schema_id, author_id, e),
+            }
+        }
+        bail!("Claim schema {} not found", schema_id)
+    }
+
+    fn reindex_claims(&mut self) -> Fallible<()> {
+        self.claim_index.clear();
+        let vault = match self.vault.as_ref() {
+            Some(vault) => vault.clone(),
+            None => return Ok(()),
+        };
+        let local_repo = lock_r(self.local_repo.as_ref())?;
+        for record in vault.profiles()? {
+            match local_repo.get(&record.id()).wait() {
+                Ok(profile) => self.claim_index.update_profile(&profile),
+                Err(e) => debug!("Profile {} is not indexed: {}", record.id(), e),
+            }
+        }
+        Ok(())
     }
 
     fn vault(&self) -> Fallible<Arc<dyn ProfileVault>> {
@@ -107,21 +232,234 @@ impl VaultState {
         self.mut_vault()?.restore_id(&profile_id)?;
         let profile = self.base_repo.get(&profile_id).wait()?;
         lock_w(self.local_repo.as_ref())?.restore(profile.clone())?;
+        self.claim_index.update_profile(&profile);
         Ok(profile)
     }
 
     fn pull_base_profile(&mut self, profile_id: &ProfileId) -> Fallible<()> {
         debug!("Fetching remote version of profile {} to base cache", profile_id);
-        let remote_profile = self.remote_repo.get(&profile_id).wait()?;
+        let remote_profile = self.get_remote_profile(&profile_id)?;
         self.base_repo.set(remote_profile).wait()
     }
 
-    //         | none | some  (base)
-    // --------+------+-----------------------------
-    //    none | ok   | ok (but server impl error)
-    //    some | err  | err if local.ver > base.ver
+    /// The remote repository is a backup that must not learn private parts of our profiles,
+    /// so they are sealed with a key derived from the vault seed before leaving the device.
+    fn get_remote_profile(&self, profile_id: &ProfileId) -> Fallible<PrivateProfileData> {
+        let backup = self.remote_repo.get(&profile_id).wait()?;
+        backup.open_private_parts(&self.vault()?.backup_key(profile_id)?)
+    }
+
+    fn set_remote_profile(&mut self, profile: PrivateProfileData) -> Fallible<()> {
+        let backup = profile.seal_private_parts(&self.vault()?.backup_key(&profile.id())?)?;
+        self.remote_repo.set(backup).wait()
+    }
+
+    /// Fetches backups of profiles of the vault from their homes. The public profile listing
+    /// its homes might be lost with the device, so known home nodes are asked for them one by one.
+    // NOTE the API is blocking, but it is called from tasks of the running reactor of the daemon,
+    //      so the network is driven by another reactor on a separate thread until backups arrive
+    fn fetch_home_backups(&self, profile_ids: Vec<ProfileId>) -> Fallible<Vec<PrivateProfileData>> {
+        let vault = self.vault.clone().ok_or_else(|| err_msg(ERR_MSG_VAULT_UNINITIALIZED))?;
+        let connector = self.home_connector.clone();
+        let known_homes = lock_r(self.home_node_crawler.as_ref())?
+            .iter()
+            .map(|home| (home.profile.id(), home.addrs()))
+            .collect::<Vec<_>>();
+        if known_homes.is_empty() || profile_ids.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let fetch_thread = std::thread::Builder::new()
+            .name("home-backup-fetcher".to_owned())
+            .spawn(move || -> Fallible<_> {
+                let backup_futs = profile_ids.into_iter().map(|profile_id| {
+                    let signer = vault.clone().lookahead_signer(&profile_id)?;
+                    Ok(Self::fetch_backup_from_any(&connector, &known_homes, signer, profile_id))
+                });
+                let fetch_fut = future::join_all(backup_futs.collect::<Fallible<Vec<_>>>()?);
+                reactor::CurrentThread::new()
+                    .block_on(fetch_fut)
+                    .map_err(|e| format_err!("Failed to fetch backups from homes: {:?}", e))
+            })?;
+        let backups = fetch_thread
+            .join()
+            .map_err(|_e| err_msg("Thread fetching backups from homes panicked"))??;
+        Ok(backups.into_iter().filter_map(|backup| backup).collect())
+    }
+
+    /// Asks the homes one by one for the backup of the profile, None if none of them has it.
+    fn fetch_backup_from_any(
+        connector: &Arc<dyn HomeConnector + Send + Sync>,
+        homes: &[(ProfileId, Vec<Multiaddr>)],
+        signer: Rc<dyn Signer>,
+        profile_id: ProfileId,
+    ) -> AsyncFallible<Option<PrivateProfileData>> {
+        let mut backup_fut: AsyncFallible<PrivateProfileData> = Box::new(future::err(format_err!(
+            "No known home has a backup of profile {}",
+            profile_id
+        )));
+        for (home_id, addrs) in homes.iter().cloned() {
+            let connector = connector.clone();
+            let signer = signer.clone();
+            let profile_id = profile_id.to_owned();
+            backup_fut = Box::new(backup_fut.or_else(move |_e| {
+                Self::fetch_backup_from(connector, home_id, addrs, signer, profile_id)
+            }));
+        }
+        let fut = backup_fut.then(move |res| {
+            if let Err(e) = &res {
+                debug!("  No backup found on homes for profile {}: {}", profile_id, e);
+            }
+            Ok(res.ok())
+        });
+        Box::new(fut)
+    }
+
+    fn fetch_backup_from(
+        connector: Arc<dyn HomeConnector + Send + Sync>,
+        home_id: ProfileId,
+        addrs: Vec<Multiaddr>,
+        signer: Rc<dyn Signer>,
+        profile_id: ProfileId,
+    ) -> AsyncFallible<PrivateProfileData> {
+        let fut = connector
+            .clone()
+            .connect(&home_id, &addrs, signer.clone())
+            .and_then({
+                let profile_id = profile_id.clone();
+                move |home| home.fetch(&profile_id)
+            })
+            .and_then({
+                let profile_id = profile_id.clone();
+                move |profile| {
+                    Self::check_profile(&profile, &profile_id)?;
+                    Ok(profile)
+                }
+            })
+            .and_then(move |profile| {
+                let host_proof = profile
+                    .to_hosted()
+                    .and_then(|hosted| {
+                        hosted
+                            .homes
+                            .into_iter()
+                            .find(|proof| proof.peer_id(&profile.id()).ok() == Some(&home_id))
+                    })
+                    .ok_or_else(|| {
+                        format_err!("Profile {} is not hosted on home {}", profile.id(), home_id)
+                    })?;
+                Ok((home_id, host_proof))
+            })
+            .and_then(move |(home_id, host_proof)| {
+                ReconnectingSession::login(connector, home_id, addrs, signer, host_proof)
+                    .map_err(|e| e.into())
+            })
+            .and_then(|session| session.restore().map_err(|e| e.into()))
+            .and_then(move |backup| {
+                Self::check_profile(&backup.public_data(), &profile_id)?;
+                Ok(backup)
+            });
+        Box::new(fut)
+    }
+
+    /// Homes are not trusted, profiles got from them must be signed by the one requested.
+    fn check_profile(profile: &PublicProfileData, profile_id: &ProfileId) -> Fallible<()> {
+        profile.validate()?;
+        ensure!(
+            profile.id() == *profile_id,
+            "Got profile {} instead of {}",
+            profile.id(),
+            profile_id
+        );
+        Ok(())
+    }
+
+    /// Sessions on all homes of the profile, logging in to each of them.
+    fn home_sessions(&self, my_id: Option<ProfileId>) -> AsyncFallible<Vec<Rc<dyn HomeSession>>> {
+        let init_fn = || -> Fallible<_> {
+            let profile = self.selected_profile(my_id)?;
+            let host_proofs =
+                profile.public_data().to_hosted().map(|hosted| hosted.homes).unwrap_or_default();
+            ensure!(!host_proofs.is_empty(), "Profile {} is not hosted on any home", profile.id());
+            let signer = self.vault()?.signer(&profile.id())?;
+            Ok((profile.id(), host_proofs, signer))
+        };
+        let (profile_id, host_proofs, signer) = match init_fn() {
+            Ok(v) => v,
+            Err(e) => return Box::new(Err(e).into_future()),
+        };
+
+        let session_futs = host_proofs.into_iter().filter_map(|host_proof| {
+            let home_id = host_proof.peer_id(&profile_id).ok()?.to_owned();
+            let session_fut = ReconnectingSession::login(
+                self.home_connector.clone(),
+                home_id,
+                vec![],
+                signer.clone(),
+                host_proof,
+            )
+            .map(|session| Rc::new(session) as Rc<dyn HomeSession>)
+            .map_err(|e| e.into());
+            Some(session_fut)
+        });
+        Box::new(future::join_all(session_futs.collect::<Vec<_>>()))
+    }
+
+    /// Keeps the session alive with heartbeats while the home is set online for the profile.
+    fn keep_online(
+        online_homes: Arc<RwLock<HashSet<(ProfileId, ProfileId)>>>,
+        key: (ProfileId, ProfileId),
+        session: ReconnectingSession,
+    ) -> impl Future<Item = (), Error = ()> {
+        Interval::new_interval(HEARTBEAT_INTERVAL)
+            .map_err(|e| warn!("Online status timer failed: {}", e))
+            .take_while(move |_instant| {
+                Ok(lock_r(online_homes.as_ref()).map(|homes| homes.contains(&key)).unwrap_or(false))
+            })
+            .for_each(|_instant| Ok(()))
+            .then(move |_res| {
+                drop(session);
+                Ok(())
+            })
+    }
+
+    /// Keeps backups found on homes as the remote versions of the profiles if they are newer
+    /// than the ones already there, so restoring the profiles uses them.
+    fn import_home_backups(&mut self, profile_ids: Vec<ProfileId>) {
+        // Homes are just one source of profiles, failing to reach them does not prevent restoring
+        let backups = self.fetch_home_backups(profile_ids).unwrap_or_else(|e| {
+            warn!("Failed to fetch profile backups from homes: {}", e);
+            vec![]
+        });
+        for backup in backups {
+            let profile_id = backup.id();
+            if let Err(e) = self.import_home_backup(backup) {
+                warn!("Failed to import backup of profile {} from home: {}", profile_id, e);
+            }
+        }
+    }
+
+    fn import_home_backup(&mut self, backup: PrivateProfileData) -> Fallible<()> {
+        // NOTE the profile id was already checked when the backup was fetched
+        backup.public_data().validate()?;
+        let remote_res = self.remote_repo.get(&backup.id()).wait();
+        if remote_res.map_or(true, |remote| remote.version() < backup.version()) {
+            debug!(
+                "Using backup version {} of profile {} from home",
+                backup.version(),
+                backup.id()
+            );
+            self.remote_repo.set(backup).wait()?;
+        }
+        Ok(())
+    }
+
+    //         | none  | some  (base)
+    // --------+-------+-----------------------------
+    //    none | false | false (but server impl error)
+    //    some | true  | true if local.ver > base.ver
     // (local)
-    fn ensure_no_local_changes(&self, profile_id: &ProfileId) -> Fallible<()> {
+    fn has_local_changes(&self, profile_id: &ProfileId) -> Fallible<bool> {
         let base_profile_res = self.base_repo.get(&profile_id).wait();
         let local_profile_res = lock_r(self.local_repo.as_ref())?.get(&profile_id).wait();
 
@@ -130,23 +468,18 @@ impl VaultState {
             return Err(local_profile_res.unwrap_err());
         }
 
-        let profile_has_local_changes = local_profile_res.is_ok()
+        Ok(local_profile_res.is_ok()
             && (base_profile_res.is_err()
-                || local_profile_res.unwrap().version() > base_profile_res.unwrap().version());
-        if profile_has_local_changes {
-            // TODO do we really need an error here or just log some message and return success?
-            bail!("Conflict detected: local profile was modified since last known remote version");
-        }
-        Ok(())
+                || local_profile_res.unwrap().version() > base_profile_res.unwrap().version()))
     }
 
     // NOTE remote server should detect version conflict updating the entry
-    //         | none | some  (base)
-    // --------+------+-----------------------------
-    //    none | ok   | ok (but server impl error?)
-    //    some | err  | err if remote.ver > base.ver
+    //         | none  | some  (base)
+    // --------+-------+-----------------------------
+    //    none | false | false (but server impl error?)
+    //    some | true  | true if remote.ver > base.ver
     // (remote)
-    fn ensure_no_remote_changes(&self, profile_id: &ProfileId) -> Fallible<()> {
+    fn has_remote_changes(&self, profile_id: &ProfileId) -> Fallible<bool> {
         let remote_profile_res = self.remote_repo.get(&profile_id).wait();
         let base_profile_res = self.base_repo.get(&profile_id).wait();
 
@@ -155,12 +488,47 @@ impl VaultState {
             return Err(remote_profile_res.unwrap_err());
         }
 
-        let profile_has_remote_changes = remote_profile_res.is_ok()
+        Ok(remote_profile_res.is_ok()
             && (base_profile_res.is_err()
-                || remote_profile_res.unwrap().version() > base_profile_res.unwrap().version());
-        if profile_has_remote_changes {
-            // TODO do we really need an error here or just log some message and return success?
-            bail!("Conflict detected: remote profile was modified since last known version");
+                || remote_profile_res.unwrap().version() > base_profile_res.unwrap().version()))
+    }
+
+    /// Three-way merge of the local and remote versions of a profile using the base cache as
+    /// their common ancestor. The merged result is stored locally only if there are no conflicts left,
+    /// the remote version becoming the new base.
+    fn merge_remote_profile(
+        &mut self,
+        profile_id: &ProfileId,
+        resolutions: &MergeResolutions,
+    ) -> Fallible<ProfileMerge> {
+        let remote_profile = self.get_remote_profile(&profile_id)?;
+        let base_profile = self.base_repo.get(&profile_id).wait().ok();
+        let local_profile = lock_r(self.local_repo.as_ref())?.get(&profile_id).wait()?;
+
+        let merge = ProfileMerge::merge(
+            base_profile.as_ref(),
+            &local_profile,
+            &remote_profile,
+            resolutions,
+        )?;
+        if merge.conflicts.is_empty() {
+            debug!("Merged local and remote versions of profile {}", profile_id);
+            lock_w(self.local_repo.as_ref())?.set(merge.profile.clone()).wait()?;
+            self.base_repo.set(remote_profile).wait()?;
+            self.claim_index.update_profile(&merge.profile);
+        } else {
+            debug!("Merging profile {} has {} conflicts", profile_id, merge.conflicts.len());
+        }
+        Ok(merge)
+    }
+
+    fn ensure_no_conflicts(merge: &ProfileMerge) -> Fallible<()> {
+        if !merge.conflicts.is_empty() {
+            let keys = merge.conflicts.iter().map(|c| c.key()).collect::<Vec<_>>();
+            bail!(
+                "Conflict detected: both local and remote profile changed {}. Resolve them by merging",
+                keys.join(", ")
+            );
         }
         Ok(())
     }
@@ -174,7 +542,12 @@ impl VaultState {
             debug!("Applying remote profile version, overwriting any local changes if present");
         } else {
             debug!("Applying remote profile version with conflict detection");
-            self.ensure_no_local_changes(profile_id)?;
+            if self.has_local_changes(profile_id)? {
+                info!("Local profile was modified since last known remote version, merging");
+                let merge = self.merge_remote_profile(profile_id, &Default::default())?;
+                Self::ensure_no_conflicts(&merge)?;
+                return Ok(merge.profile);
+            }
         }
 
         self.pull_base_profile(profile_id)?;
@@ -208,12 +581,19 @@ before trying to restore another vault."#,
         }?;
         let new_vault = vault::HdProfileVault::create(seed);
         self.vault.replace(Arc::new(new_vault));
-        self.save_vault()
+        self.save_vault()?;
+        // NOTE profiles of the restored vault might be left in the local repository
+        self.reindex_claims()
     }
 
     fn restore_all_profiles(&mut self) -> Fallible<RestoreCounts> {
         let keys = self.vault()?.keys()?;
         let len = self.vault()?.len() as u32;
+        let key_ids = |range: std::ops::Range<u32>| -> Fallible<Vec<ProfileId>> {
+            range.map(|idx| keys.id(idx as i32)).collect()
+        };
+        let mut fetched_end = len + vault::GAP;
+        self.import_home_backups(key_ids(0..fetched_end)?);
 
         let mut try_count = 0;
         let mut restore_count = 0;
@@ -230,6 +610,10 @@ before trying to restore another vault."#,
         let mut idx = len;
         let mut end = len + vault::GAP;
         while idx < end {
+            if fetched_end < end {
+                self.import_home_backups(key_ids(fetched_end..end)?);
+                fetched_end = end;
+            }
             try_count += 1;
             let profile_id = keys.id(idx as i32)?;
             if let Err(e) = self.restore_one_profile(&profile_id, true) {
@@ -266,7 +650,8 @@ before trying to restore another vault."#,
         // TODO label should not be parameter of create_key()
         let new_profile_key = self.mut_vault()?.create_key(label)?;
         let empty_profile = PrivateProfileData::empty(&new_profile_key);
-        lock_w(self.local_repo.as_ref())?.set(empty_profile).wait()?;
+        lock_w(self.local_repo.as_ref())?.set(empty_profile.clone()).wait()?;
+        self.claim_index.update_profile(&empty_profile);
         self.vault()?.profile(&new_profile_key.key_id())
     }
 
@@ -313,7 +698,14 @@ before trying to restore another vault."#,
         let profile = match kind {
             Local => lock_r(self.local_repo.as_ref())?.get(&profile_id),
             Base => self.base_repo.get(&profile_id),
-            Remote => self.remote_repo.get(&profile_id),
+            Remote => {
+                let backup = self.remote_repo.get(&profile_id).wait()?;
+                let key_res = self.vault().and_then(|vault| vault.backup_key(&profile_id));
+                // NOTE private parts of profiles sealed by others cannot be opened, only their public data is shown
+                return Ok(key_res
+                    .and_then(|key| backup.open_private_parts(&key))
+                    .unwrap_or_else(|_e| PrivateProfileData::from_public(backup.public_data())));
+            }
         };
         profile.wait()
     }
@@ -325,6 +717,58 @@ before trying to restore another vault."#,
         Ok(profile)
     }
 
+    fn profile_history(
+        &self,
+        my_profile_id: Option<ProfileId>,
+    ) -> Fallible<Vec<ProfileVersionInfo>> {
+        let profile_id = self.selected_profile_id(my_profile_id)?;
+        lock_r(self.local_repo.as_ref())?.history(&profile_id)
+    }
+
+    fn profile_diff(
+        &self,
+        my_profile_id: Option<ProfileId>,
+        from_revision: Revision,
+        to_revision: Option<Revision>,
+    ) -> Fallible<ProfileDiff> {
+        let profile_id = self.selected_profile_id(my_profile_id)?;
+        let local_repo = lock_r(self.local_repo.as_ref())?;
+        let from_profile = local_repo.get_revision(&profile_id, from_revision)?;
+        let to_profile = match to_revision {
+            Some(revision) => local_repo.get_revision(&profile_id, revision)?,
+            None => local_repo.get(&profile_id).wait()?,
+        };
+        Ok(ProfileDiff::between(&from_profile, &to_profile))
+    }
+
+    fn rollback_profile(
+        &mut self,
+        my_profile_id: Option<ProfileId>,
+        revision: Revision,
+    ) -> Fallible<PrivateProfileData> {
+        let current_profile = self.selected_profile(my_profile_id)?;
+        let profile_id = current_profile.id();
+        let old_profile = lock_r(self.local_repo.as_ref())?.get_revision(&profile_id, revision)?;
+
+        // NOTE versions must keep increasing to be publishable, so the old content becomes a new version
+        let mut profile = old_profile;
+        profile.mut_public_data().set_version(current_profile.version() + 1);
+        lock_w(self.local_repo.as_ref())?.set(profile.clone()).wait()?;
+        self.claim_index.update_profile(&profile);
+        Ok(profile)
+    }
+
+    fn merge_profile(
+        &mut self,
+        my_profile_id: Option<ProfileId>,
+        resolutions: MergeResolutions,
+    ) -> Fallible<ProfileMerge> {
+        let profile_id = self.selected_profile_id(my_profile_id)?;
+        let merge = self.merge_remote_profile(&profile_id, &resolutions)?;
+        self.save_vault()?;
+        Ok(merge)
+    }
+
     fn publish_profile(
         &mut self,
         my_profile_id: Option<ProfileId>,
@@ -343,10 +787,19 @@ before trying to restore another vault."#,
             }
         } else {
             debug!("Publishing local profile version with conflict detection");
-            self.ensure_no_remote_changes(&profile_id)?;
+            if self.has_remote_changes(&profile_id)? {
+                info!("Remote profile was modified since last known version, merging");
+                let merge = self.merge_remote_profile(&profile_id, &Default::default())?;
+                Self::ensure_no_conflicts(&merge)?;
+                profile = merge.profile;
+            }
         }
 
-        self.remote_repo.set(profile).wait()?;
+        let vault = self.vault()?;
+        profile.seal_contact_attributes(|peer_key| vault.shared_secret(&profile_id, peer_key))?;
+        let signer = vault.signer(&profile_id)?;
+        profile.mut_public_data().sign(&*signer)?;
+        self.set_remote_profile(profile)?;
         self.pull_base_profile(&profile_id)?;
         self.save_vault()?;
         Ok(profile_id)
@@ -358,19 +811,30 @@ before trying to restore another vault."#,
         force: bool,
     ) -> Fallible<PrivateProfileData> {
         let profile_id = self.selected_profile_id(my_profile_id)?;
+        self.import_home_backups(vec![profile_id.clone()]);
         let profile = self.restore_one_profile(&profile_id, force)?;
         self.save_vault()?;
         Ok(profile)
     }
 
+    fn get_attribute(
+        &self,
+        my_profile_id: Option<ProfileId>,
+        key: &AttributeId,
+    ) -> Fallible<Option<AttributeValue>> {
+        let profile = self.selected_profile(my_profile_id)?;
+        Ok(profile.attribute(key).map(|(_visibility, value)| value.to_owned()))
+    }
+
     fn set_attribute(
         &mut self,
         my_profile_id: Option<ProfileId>,
         key: &AttributeId,
         value: &AttributeValue,
+        visibility: AttributeVisibility,
     ) -> Fallible<()> {
         let mut profile = self.selected_profile(my_profile_id)?;
-        profile.mut_public_data().set_attribute(key.to_owned(), value.to_owned());
+        profile.set_attribute(key.to_owned(), value.to_owned(), visibility);
         profile.mut_public_data().increase_version();
         lock_w(self.local_repo.as_ref())?.set(profile).wait()?;
         self.save_vault()
@@ -382,32 +846,62 @@ before trying to restore another vault."#,
         key: &AttributeId,
     ) -> Fallible<()> {
         let mut profile = self.selected_profile(my_profile_id)?;
-        profile.mut_public_data().clear_attribute(key);
+        profile.clear_attribute(key);
         profile.mut_public_data().increase_version();
         lock_w(self.local_repo.as_ref())?.set(profile).wait()?;
         self.save_vault()
     }
 
     fn claim_schemas(&self) -> Fallible<Rc<dyn ClaimSchemas>> {
-        let p = &self.schema_path;
-        if !p.exists() {
-            std::fs::create_dir_all(p)?;
-            ClaimSchemaRegistry::populate_folder(p)?;
-        }
-        let registry = ClaimSchemaRegistry::import_folder(p)?;
+        let registry = self.schema_registry()?;
         Ok(Rc::new(registry))
     }
 
+    fn publish_claim_schema(
+        &mut self,
+        my_profile_id: Option<ProfileId>,
+        name: String,
+        content: serde_json::Value,
+        ordering: Option<Vec<String>>,
+    ) -> Fallible<SchemaVersion> {
+        let mut profile = self.selected_profile(my_profile_id)?;
+        let author = profile.id().to_string();
+        let version = self.schema_registry()?.next_version(&author, &name);
+        let mut schema = match ordering {
+            Some(ordering) => {
+                SchemaVersion::new_with_order(author, name, version, content, ordering)
+            }
+            None => SchemaVersion::new(author, name, version, content),
+        };
+        let signer = self.vault()?.signer(&profile.id())?;
+        schema.sign(signer.as_ref())?;
+        ClaimSchemaRegistry::export_file(&self.schema_path, &schema)?;
+
+        schema.publish_to(profile.mut_public_data())?;
+        profile.mut_public_data().increase_version();
+        lock_w(self.local_repo.as_ref())?.set(profile).wait()?;
+        debug!("Published claim schema {} version {}", schema.name(), schema.version());
+        self.save_vault()?;
+        Ok(schema)
+    }
+
     fn claims(&self, my_profile_id: Option<ProfileId>) -> Fallible<Vec<Claim>> {
         let profile = self.selected_profile(my_profile_id)?;
         Ok(profile.claims())
     }
 
+    fn query_claims(&self, query: &ClaimQuery) -> Fallible<Vec<Claim>> {
+        self.claim_index.query(query, TimeStamp::now(), self.claim_expiry_warning)
+    }
+
     fn add_claim(&mut self, my_profile_id: Option<ProfileId>, claim: Claim) -> Fallible<()> {
         let claim_id = claim.id();
         let mut profile = self.selected_profile(my_profile_id)?;
 
-        // TODO check if schema_id is valid and related schema contents are available
+        let signable = claim.signable_part();
+        let mut candidate_authors = vec![signable.subject_id.to_owned()];
+        candidate_authors.extend(claim.witnesses());
+        self.resolve_claim_schema(signable.typed_content.schema_id(), &candidate_authors)?;
         // TODO validate contents against schema details
         let present_claims = profile.claims();
         let conflicts = present_claims.iter().filter(|old_claim| old_claim.id() == claim_id);
@@ -418,7 +912,8 @@ before trying to restore another vault."#,
         profile.mut_claims().push(claim);
         // TODO this is not public data, should not affect public version
         // profile.mut_public_data().increase_version();
-        lock_w(self.local_repo.as_ref())?.set(profile).wait()?;
+        lock_w(self.local_repo.as_ref())?.set(profile.clone()).wait()?;
+        self.claim_index.update_profile(&profile);
         debug!("Added claim: {:?}", claim_id);
         self.save_vault()
     }
@@ -433,7 +928,8 @@ before trying to restore another vault."#,
             bail!("Claim {} not found", id);
         }
 
-        lock_w(self.local_repo.as_ref())?.set(profile).wait()?;
+        lock_w(self.local_repo.as_ref())?.set(profile.clone()).wait()?;
+        self.claim_index.update_profile(&profile);
         debug!("Removed claim: {:?}", id);
         self.save_vault()
     }
@@ -444,6 +940,7 @@ before trying to restore another vault."#,
         claim: &SignableClaimPart,
     ) -> Fallible<ClaimProof> {
         let profile = self.selected_profile(my_profile_id)?;
+        self.resolve_claim_schema(claim.typed_content.schema_id(), &[claim.subject_id.to_owned()])?;
         let claim_bin = serde_json::to_vec(claim)?;
         let signed_message = self.vault()?.sign(&profile.id(), &claim_bin)?;
         let now = TimeStamp::now();
@@ -463,11 +960,53 @@ before trying to restore another vault."#,
             .mut_claim(claim_id)
             .ok_or_else(|| format_err!("Claim {} not found", claim_id))?;
         claim.add_proof(proof);
-        lock_w(self.local_repo.as_ref())?.set(profile).wait()?;
+        lock_w(self.local_repo.as_ref())?.set(profile.clone()).wait()?;
+        self.claim_index.update_profile(&profile);
         debug!("Added proof to claim: {:?}", claim_id);
         self.save_vault()
     }
 
+    fn expiring_claims(&self, period: Duration) -> Fallible<Vec<Claim>> {
+        Ok(self.claim_index.expiring(TimeStamp::now(), period))
+    }
+
+    fn expired_claims(&self) -> Fallible<Vec<Claim>> {
+        Ok(self.claim_index.expired(TimeStamp::now()))
+    }
+
+    fn renew_claim(
+        &mut self,
+        my_profile_id: Option<ProfileId>,
+        claim_id: &ClaimId,
+    ) -> Fallible<ClaimRenewal> {
+        let profile = self.selected_profile(my_profile_id)?;
+        let claim = profile
+            .claim(claim_id)
+            .ok_or_else(|| format_err!("Claim {} not found", claim_id))?
+            .to_owned();
+        let own_ids = self.vault()?.profiles()?.iter().map(|rec| rec.id()).collect::<HashSet<_>>();
+
+        let mut renewed_by = Vec::new();
+        let mut pending_witnesses = Vec::new();
+        for witness_id in claim.witnesses() {
+            if !own_ids.contains(&witness_id) {
+                pending_witnesses.push(witness_id);
+                continue;
+            }
+            let proof = self.sign_claim(Some(witness_id.clone()), claim.signable_part())?;
+            self.add_claim_proof(Some(profile.id()), claim_id, proof)?;
+            renewed_by.push(witness_id);
+        }
+        debug!("Renewed claim {}, waiting for {} witnesses", claim_id, pending_witnesses.len());
+
+        Ok(ClaimRenewal {
+            claim_id: claim_id.to_owned(),
+            witness_request: claim.signable_part().to_string(),
+            renewed_by,
+            pending_witnesses,
+        })
+    }
+
     fn license_claim(
         &mut self,
         _my_profile_id: Option<ProfileId>,
@@ -503,11 +1042,69 @@ before trying to restore another vault."#,
         self.save_vault()
     }
 
+    fn add_contact(
+        &mut self,
+        my_profile_id: Option<ProfileId>,
+        proof: RelationProof,
+    ) -> Fallible<Contact> {
+        let mut profile = self.selected_profile(my_profile_id)?;
+        let my_id = profile.id();
+        let peer_id = proof.peer_id(&my_id)?.to_owned();
+        let peer_pubkey = proof.peer_pubkey(&my_id)?.to_owned();
+        CompositeValidator::default().validate_relation_proof(
+            &proof,
+            &my_id,
+            &profile.public_key(),
+            &peer_id,
+            &peer_pubkey,
+        )?;
+
+        let contact = Contact::new(peer_pubkey, &proof.relation_type);
+        profile.add_contact(contact.clone());
+        profile.mut_public_data().increase_version();
+        lock_w(self.local_repo.as_ref())?.set(profile).wait()?;
+        debug!("Added contact: {:?}", contact);
+        self.save_vault()?;
+        Ok(contact)
+    }
+
+    fn remove_contact(
+        &mut self,
+        my_profile_id: Option<ProfileId>,
+        contact_id: &ProfileId,
+    ) -> Fallible<()> {
+        let mut profile = self.selected_profile(my_profile_id)?;
+        ensure!(
+            profile.contacts().iter().any(|contact| contact.id() == *contact_id),
+            "Profile {} has no contact {}",
+            profile.id(),
+            contact_id
+        );
+        profile.remove_contact(contact_id);
+        profile.mut_public_data().increase_version();
+        lock_w(self.local_repo.as_ref())?.set(profile).wait()?;
+        self.save_vault()
+    }
+
+    fn contact_attributes(
+        &self,
+        my_profile_id: Option<ProfileId>,
+        contact_id: &ProfileId,
+    ) -> Fallible<AttributeMap> {
+        let my_id = self.selected_profile_id(my_profile_id)?;
+        let contact_profile = self.remote_repo.get(contact_id).wait()?.public_data();
+        contact_profile.validate()?;
+        let shared_secret = self.vault()?.shared_secret(&my_id, &contact_profile.public_key())?;
+        let attributes = contact_profile.open_attributes_for(&shared_secret)?;
+        Ok(attributes.unwrap_or_default())
+    }
+
     fn did_homes(&self, my_profile_id: Option<ProfileId>) -> Fallible<Vec<DidHomeStatus>> {
         let profile = self.selected_profile(my_profile_id)?;
         let hosted_facet = profile.public_data().to_hosted().ok_or_else(|| {
             format_err!("Profile {} lacks hosting details (like homes) filled", profile.id())
         })?;
+        let online_homes = lock_r(self.online_homes.as_ref())?;
 
         Ok(hosted_facet
             .homes
@@ -519,8 +1116,10 @@ before trying to restore another vault."#,
                 }
                 home_id_opt
             })
-            // TODO online status is not gathered yet
-            .map(|home_did| DidHomeStatus { home_did: home_did.to_string(), online: false })
+            .map(|home_did| {
+                let online = online_homes.contains(&(profile.id(), home_did.to_owned()));
+                DidHomeStatus { home_did: home_did.to_string(), online }
+            })
             .collect())
     }
 
@@ -530,6 +1129,7 @@ before trying to restore another vault."#,
         my_id: Option<ProfileId>,
         home_id: &ProfileId,
         addr_hints: &[Multiaddr],
+        invite: Option<HomeInvitation>,
         network: &NetworkState,
     ) -> AsyncFallible<()> {
         let init_fn = || {
@@ -567,7 +1167,7 @@ before trying to restore another vault."#,
                     .map_err(|e| format_err!("Failed to lock crawler: {}", e))?;
                 crawler_lock.add(&prof).map(|()| home)
             } )
-            .and_then(move |home| home.register(host_half_proof).map_err(|e| e.into()))
+            .and_then(move |home| home.register(host_half_proof, invite).map_err(|e| e.into()))
             .and_then(move |host_proof| {
                 profile.mut_public_data().add_hosted_on(&host_proof)?;
                 profile.mut_public_data().increase_version();
@@ -585,4 +1185,240 @@ before trying to restore another vault."#,
 
         Box::new(fut)
     }
+
+    fn move_home(
+        &mut self,
+        my_id: Option<ProfileId>,
+        old_home_id: &ProfileId,
+        new_home_id: &ProfileId,
+        addr_hints: &[Multiaddr],
+        invite: Option<HomeInvitation>,
+        network: &NetworkState,
+    ) -> AsyncFallible<()> {
+        let init_fn = || -> Fallible<_> {
+            let profile = self.selected_profile(my_id)?;
+            let old_proof = profile
+                .public_data()
+                .to_hosted()
+                .and_then(|hosted| {
+                    hosted
+                        .homes
+                        .into_iter()
+                        .find(|proof| proof.peer_id(&profile.id()).ok() == Some(old_home_id))
+                })
+                .ok_or_else(|| {
+                    format_err!("Profile {} is not hosted on home {}", profile.id(), old_home_id)
+                })?;
+            ensure!(
+                !profile.public_data().is_hosted_on(new_home_id),
+                "Profile {} is already registered to home {}",
+                profile.id(),
+                new_home_id
+            );
+            let vault = self.vault()?;
+            let signer = vault.signer(&profile.id())?;
+            let backup_key = vault.backup_key(&profile.id())?;
+            let host_half = RelationHalfProof::new(
+                RelationProof::RELATION_TYPE_HOSTED_ON_HOME,
+                new_home_id,
+                signer.as_ref(),
+            )?;
+            Ok((profile, old_proof, signer, backup_key, host_half))
+        };
+
+        let (mut profile, old_proof, signer, backup_key, host_half_proof) = match init_fn() {
+            Ok(v) => v,
+            Err(e) => return Box::new(Err(e).into_future()),
+        };
+
+        let crawler = network.home_node_crawler.clone();
+        let connector = network.home_connector.to_owned();
+        let local_repo = self.local_repo.clone();
+        let online_homes = self.online_homes.clone();
+        let old_home_id = old_home_id.to_owned();
+        let new_home_id = new_home_id.to_owned();
+        let fut = connector
+            .clone()
+            .connect(&new_home_id, addr_hints, signer.clone())
+            .and_then({
+                let new_home_id = new_home_id.clone();
+                move |home| home.fetch(&new_home_id).map(|prof| (home, prof))
+            })
+            .and_then(move |(home, prof)| {
+                let mut crawler_lock = crawler
+                    .try_write()
+                    .map_err(|e| format_err!("Failed to lock crawler: {}", e))?;
+                crawler_lock.add(&prof).map(|()| home)
+            })
+            .and_then(move |home| home.register(host_half_proof, invite).map_err(|e| e.into()))
+            .and_then({
+                let old_proof = old_proof.clone();
+                let signer = signer.clone();
+                move |new_proof: RelationProof| {
+                    // NOTE the old home is replaced, contacts find the profile on the new home
+                    let mut hosted = profile.public_data().to_hosted().unwrap_or_default();
+                    hosted.homes.retain(|proof| *proof != old_proof);
+                    hosted.homes.push(new_proof.clone());
+                    profile.mut_public_data().set_hosted(&hosted);
+                    profile.mut_public_data().increase_version();
+                    profile.mut_public_data().sign(&*signer)?;
+                    let backup = profile.seal_private_parts(&backup_key)?;
+                    Ok((new_proof, profile, backup))
+                }
+            })
+            .and_then({
+                let connector = connector.clone();
+                let new_home_id = new_home_id.clone();
+                let addr_hints = addr_hints.to_owned();
+                let signer = signer.clone();
+                move |(new_proof, profile, backup)| {
+                    ReconnectingSession::login(
+                        connector,
+                        new_home_id,
+                        addr_hints,
+                        signer,
+                        new_proof,
+                    )
+                    .and_then(move |session| session.backup(backup).map(|()| session))
+                    .map(|session| (session, profile))
+                    .map_err(|e| e.into())
+                }
+            })
+            .and_then({
+                let old_home_id = old_home_id.clone();
+                move |(new_session, profile)| {
+                    ReconnectingSession::login(connector, old_home_id, vec![], signer, old_proof)
+                        .map_err(|e| e.into())
+                        .and_then(move |old_session| {
+                            old_session
+                                .export_mailbox()
+                                .and_then(move |content| new_session.import_mailbox(content))
+                                .and_then(move |()| {
+                                    old_session.unregister(Some(profile.public_data()))
+                                })
+                                .map(|()| profile)
+                                .map_err(|e| e.into())
+                        })
+                }
+            })
+            .and_then(move |profile| {
+                let profile_id = profile.id();
+                let save_fut = match lock_w(local_repo.as_ref()) {
+                    Ok(mut repo) => Either::A(repo.set(profile)),
+                    Err(e) => Either::B(Err(e).into_future()),
+                };
+                save_fut.map(move |()| profile_id)
+            })
+            .and_then(move |profile_id| {
+                lock_w(online_homes.as_ref())?.remove(&(profile_id, old_home_id));
+                Ok(())
+            });
+
+        Box::new(fut)
+    }
+
+    fn set_home_online(
+        &mut self,
+        my_id: Option<ProfileId>,
+        home_id: &ProfileId,
+        online: bool,
+        network: &NetworkState,
+    ) -> AsyncFallible<()> {
+        let init_fn = || {
+            let profile = self.selected_profile(my_id)?;
+            let host_proof = profile
+                .public_data()
+                .to_hosted()
+                .and_then(|hosted| {
+                    hosted
+                        .homes
+                        .into_iter()
+                        .find(|proof| proof.peer_id(&profile.id()).ok() == Some(home_id))
+                })
+                .ok_or_else(|| {
+                    format_err!("Profile {} is not hosted on home {}", profile.id(), home_id)
+                })?;
+            let signer = self.vault()?.signer(&profile.id())?;
+            Ok((profile.id(), host_proof, signer))
+        };
+
+        let (profile_id, host_proof, signer) = match init_fn() {
+            Ok(v) => v,
+            Err(e) => return Box::new(Err(e).into_future()),
+        };
+
+        let status = if online { PresenceStatus::Online } else { PresenceStatus::Offline };
+        let online_homes = self.online_homes.clone();
+        let home_id = home_id.to_owned();
+        let connector = network.home_connector.clone();
+        let fut =
+            ReconnectingSession::login(connector, home_id.clone(), vec![], signer, host_proof)
+                .and_then(move |session| {
+                    session.set_presence(Presence::new(status)).map(|()| session)
+                })
+                .map_err(|e| e.into())
+                .and_then(move |session| {
+                    let key = (profile_id, home_id);
+                    let mut online_homes_lock = lock_w(online_homes.as_ref())?;
+                    if online {
+                        online_homes_lock.insert(key.clone());
+                        reactor::spawn(Self::keep_online(online_homes.clone(), key, session));
+                    } else {
+                        online_homes_lock.remove(&key);
+                    }
+                    Ok(())
+                });
+        Box::new(fut)
+    }
+
+    fn banned_profiles(&self, my_id: Option<ProfileId>) -> AsyncFallible<Vec<ProfileId>> {
+        let fut = self
+            .home_sessions(my_id)
+            .and_then(|sessions| {
+                let banned_futs = sessions
+                    .iter()
+                    .map(|session| session.banned_profiles().map_err(|e| e.into()))
+                    .collect::<Vec<_>>();
+                future::join_all(banned_futs)
+            })
+            .map(|banned_lists| {
+                let mut banned = banned_lists.into_iter().flatten().collect::<Vec<_>>();
+                banned.sort_by_key(|profile_id| profile_id.to_string());
+                banned.dedup();
+                banned
+            });
+        Box::new(fut)
+    }
+
+    fn ban_profile(
+        &mut self,
+        my_id: Option<ProfileId>,
+        banned_id: &ProfileId,
+    ) -> AsyncFallible<()> {
+        let banned_id = banned_id.to_owned();
+        let fut = self.home_sessions(my_id).and_then(move |sessions| {
+            let ban_futs = sessions
+                .iter()
+                .map(|session| session.ban(&banned_id).map_err(|e| e.into()))
+                .collect::<Vec<_>>();
+            future::join_all(ban_futs).map(|_| ())
+        });
+        Box::new(fut)
+    }
+
+    fn unban_profile(
+        &mut self,
+        my_id: Option<ProfileId>,
+        banned_id: &ProfileId,
+    ) -> AsyncFallible<()> {
+        let banned_id = banned_id.to_owned();
+        let fut = self.home_sessions(my_id).and_then(move |sessions| {
+            let unban_futs = sessions
+                .iter()
+                .map(|session| session.unban(&banned_id).map_err(|e| e.into()))
+                .collect::<Vec<_>>();
+            future::join_all(unban_futs).map(|_| ())
+        });
+        Box::new(fut)
+    }
 }
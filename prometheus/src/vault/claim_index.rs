@@ -0,0 +1,340 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::Duration;
+
+use failure::{format_err, Fallible};
+use serde_derive::{Deserialize, Serialize};
+
+use claims::claim_schema::SchemaId;
+use claims::model::*;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ClaimExpiryEvent {
+    pub claim_id: ClaimId,
+    pub subject_id: ProfileId,
+    pub status: ClaimExpiryStatus,
+    pub valid_until: TimeStamp,
+}
+
+/// Filters for claims, unset fields match all claims.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ClaimQuery {
+    pub schema_id: Option<SchemaId>,
+    pub subject_id: Option<ProfileId>,
+    pub witness_id: Option<ProfileId>,
+    pub status: Option<ClaimExpiryStatus>,
+    /// Expiry window: the last proof of the claim expires after this time
+    pub expires_after: Option<TimeStamp>,
+    /// Expiry window: the last proof of the claim expires before this time
+    pub expires_before: Option<TimeStamp>,
+    /// JSONPath expression that must select at least one node of the claim content,
+    /// e.g. `$.email` or `$[?(@.age >= 18)]`
+    pub content: Option<String>,
+}
+
+impl ClaimQuery {
+    fn matches(&self, claim: &Claim, now: TimeStamp, warning_period: Duration) -> Fallible<bool> {
+        let signable = claim.signable_part();
+        if let Some(schema_id) = &self.schema_id {
+            if signable.typed_content.schema_id() != schema_id {
+                return Ok(false);
+            }
+        }
+        if let Some(subject_id) = &self.subject_id {
+            if signable.subject_id != *subject_id {
+                return Ok(false);
+            }
+        }
+        if let Some(witness_id) = &self.witness_id {
+            if !claim.witnesses().contains(witness_id) {
+                return Ok(false);
+            }
+        }
+        if let Some(status) = self.status {
+            if claim.expiry_status(now, warning_period) != status {
+                return Ok(false);
+            }
+        }
+        if self.expires_after.is_some() || self.expires_before.is_some() {
+            let valid_until = match claim.valid_until() {
+                Some(valid_until) => valid_until,
+                None => return Ok(false),
+            };
+            if self.expires_after.map_or(false, |after| valid_until <= after)
+                || self.expires_before.map_or(false, |before| valid_until > before)
+            {
+                return Ok(false);
+            }
+        }
+        if let Some(path) = &self.content {
+            let selected = jsonpath_lib::select(signable.typed_content.content(), path)
+                .map_err(|e| format_err!("Invalid JSONPath expression {}: {:?}", path, e))?;
+            if selected.is_empty() {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// Keeps claims of all profiles of the vault in memory, so they can be looked up without
+/// loading and scanning each profile from the repository.
+#[derive(Default)]
+pub struct ClaimIndex {
+    claims: HashMap<ClaimId, Claim>,
+    profile_claims: HashMap<ProfileId, HashSet<ClaimId>>,
+    schema_claims: HashMap<SchemaId, HashSet<ClaimId>>,
+    subject_claims: HashMap<ProfileId, HashSet<ClaimId>>,
+    witness_claims: HashMap<ProfileId, HashSet<ClaimId>>,
+    expiries: BTreeMap<TimeStamp, HashSet<ClaimId>>,
+    // Last status reported for each claim, used to notify only about changes.
+    // NOTE a renewed claim has a new expiry, so it has to be reported again.
+    notified: HashMap<ClaimId, (ClaimExpiryStatus, TimeStamp)>,
+}
+
+impl ClaimIndex {
+    pub fn update_profile(&mut self, profile: &PrivateProfileData) {
+        let old_claim_ids = self.remove_claims(&profile.id());
+        let claim_ids: HashSet<ClaimId> =
+            profile.claims().into_iter().map(|claim| self.insert(claim)).collect();
+        // NOTE claims kept by the update must not be reported again, removed ones are forgotten
+        for claim_id in old_claim_ids.difference(&claim_ids) {
+            self.notified.remove(claim_id);
+        }
+        self.profile_claims.insert(profile.id(), claim_ids);
+    }
+
+    pub fn remove_profile(&mut self, profile_id: &ProfileId) {
+        for claim_id in self.remove_claims(profile_id) {
+            self.notified.remove(&claim_id);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.claims.clear();
+        self.profile_claims.clear();
+        self.schema_claims.clear();
+        self.subject_claims.clear();
+        self.witness_claims.clear();
+        self.expiries.clear();
+        self.notified.clear();
+    }
+
+    pub fn get(&self, claim_id: &ClaimId) -> Option<&Claim> {
+        self.claims.get(claim_id)
+    }
+
+    pub fn query(
+        &self,
+        query: &ClaimQuery,
+        now: TimeStamp,
+        warning_period: Duration,
+    ) -> Fallible<Vec<Claim>> {
+        // Start from the smallest candidate set the indexed fields of the query allow
+        let mut candidate_sets = Vec::new();
+        if let Some(schema_id) = &query.schema_id {
+            candidate_sets.push(self.schema_claims.get(schema_id));
+        }
+        if let Some(subject_id) = &query.subject_id {
+            candidate_sets.push(self.subject_claims.get(subject_id));
+        }
+        if let Some(witness_id) = &query.witness_id {
+            candidate_sets.push(self.witness_claims.get(witness_id));
+        }
+        let candidates: Box<dyn Iterator<Item = &Claim>> =
+            match candidate_sets.into_iter().min_by_key(|set| set.map_or(0, |ids| ids.len())) {
+                None => Box::new(self.claims.values()),
+                Some(None) => return Ok(vec![]),
+                Some(Some(claim_ids)) => Box::new(claim_ids.iter().map(|id| &self.claims[id])),
+            };
+
+        let mut claims = Vec::new();
+        for claim in candidates {
+            if query.matches(claim, now, warning_period)? {
+                claims.push(claim.to_owned());
+            }
+        }
+        Ok(claims)
+    }
+
+    /// Claims that still have a valid proof, but all their proofs expire until `now + period`.
+    pub fn expiring(&self, now: TimeStamp, period: Duration) -> Vec<Claim> {
+        use std::ops::Bound::{Excluded, Included};
+        self.claims_in(self.expiries.range((Excluded(now), Included(now + period))))
+    }
+
+    pub fn expired(&self, now: TimeStamp) -> Vec<Claim> {
+        self.claims_in(self.expiries.range(..=now))
+    }
+
+    /// Collects claims that entered the expiring soon or expired state since the last call.
+    /// Events describing all claims that are expiring soon or already expired.
+    pub fn expiry_events(&self, now: TimeStamp, warning_period: Duration) -> Vec<ClaimExpiryEvent> {
+        let mut events = Vec::new();
+        for claim_ids in self.expiries.range(..=now + warning_period).map(|(_time, ids)| ids) {
+            for claim_id in claim_ids {
+                let claim = &self.claims[claim_id];
+                let valid_until = match claim.valid_until() {
+                    Some(valid_until) => valid_until,
+                    None => continue,
+                };
+                events.push(ClaimExpiryEvent {
+                    claim_id: claim_id.to_owned(),
+                    subject_id: claim.signable_part().subject_id.to_owned(),
+                    status: claim.expiry_status(now, warning_period),
+                    valid_until,
+                });
+            }
+        }
+        events
+    }
+
+    /// Like `expiry_events()`, but only for claims whose status changed since the last call.
+    pub fn take_expiry_events(
+        &mut self,
+        now: TimeStamp,
+        warning_period: Duration,
+    ) -> Vec<ClaimExpiryEvent> {
+        let mut events = self.expiry_events(now, warning_period);
+        events.retain(|event| {
+            let reported = (event.status, event.valid_until);
+            self.notified.insert(event.claim_id.to_owned(), reported) != Some(reported)
+        });
+        events
+    }
+
+    fn insert(&mut self, claim: Claim) -> ClaimId {
+        let claim_id = claim.id();
+        let signable = claim.signable_part();
+        let schema_id = signable.typed_content.schema_id().to_owned();
+        self.schema_claims.entry(schema_id).or_default().insert(claim_id.clone());
+        let subject_id = signable.subject_id.to_owned();
+        self.subject_claims.entry(subject_id).or_default().insert(claim_id.clone());
+        for witness_id in claim.witnesses() {
+            self.witness_claims.entry(witness_id).or_default().insert(claim_id.clone());
+        }
+        if let Some(valid_until) = claim.valid_until() {
+            self.expiries.entry(valid_until).or_default().insert(claim_id.clone());
+        }
+        self.claims.insert(claim_id.clone(), claim);
+        claim_id
+    }
+
+    fn remove_claims(&mut self, profile_id: &ProfileId) -> HashSet<ClaimId> {
+        let claim_ids = self.profile_claims.remove(profile_id).unwrap_or_default();
+        for claim_id in &claim_ids {
+            self.remove(claim_id);
+        }
+        claim_ids
+    }
+
+    fn remove(&mut self, claim_id: &ClaimId) {
+        let claim = match self.claims.remove(claim_id) {
+            Some(claim) => claim,
+            None => return,
+        };
+        let signable = claim.signable_part();
+        Self::unindex(&mut self.schema_claims, signable.typed_content.schema_id(), claim_id);
+        Self::unindex(&mut self.subject_claims, &signable.subject_id, claim_id);
+        for witness_id in claim.witnesses() {
+            Self::unindex(&mut self.witness_claims, &witness_id, claim_id);
+        }
+        if let Some(valid_until) = claim.valid_until() {
+            if let Some(ids) = self.expiries.get_mut(&valid_until) {
+                ids.remove(claim_id);
+                if ids.is_empty() {
+                    self.expiries.remove(&valid_until);
+                }
+            }
+        }
+    }
+
+    fn unindex<K: Eq + std::hash::Hash>(
+        index: &mut HashMap<K, HashSet<ClaimId>>,
+        key: &K,
+        claim_id: &ClaimId,
+    ) {
+        if let Some(ids) = index.get_mut(key) {
+            ids.remove(claim_id);
+            if ids.is_empty() {
+                index.remove(key);
+            }
+        }
+    }
+
+    fn claims_in<'a>(
+        &'a self,
+        range: impl Iterator<Item = (&'a TimeStamp, &'a HashSet<ClaimId>)>,
+    ) -> Vec<Claim> {
+        range
+            .flat_map(|(_time, claim_ids)| claim_ids.iter())
+            .filter_map(|claim_id| self.claims.get(claim_id).cloned())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+    use did::vault::{HdProfileVault, ProfileVault};
+    use keyvault::PublicKey as KeyVaultPublicKey;
+
+    #[test]
+    fn query_and_expiry() -> Fallible<()> {
+        let phrase = keyvault::Seed::generate_bip39();
+        let mut vault = HdProfileVault::create(keyvault::Seed::from_bip39(&phrase)?);
+        let subject_key = vault.create_key(None)?;
+        let witness_id = vault.create_key(None)?.key_id();
+
+        let now = TimeStamp::now();
+        let day = Duration::from_secs(24 * 60 * 60);
+        let mut adult = Claim::unproven(subject_key.key_id(), "age", json!({ "age": 42 }));
+        let signed_message = vault.sign(&witness_id, b"not validated by the index")?;
+        adult.add_proof(ClaimProof::new(witness_id.clone(), signed_message, now, now + 10 * day));
+        let child = Claim::unproven(subject_key.key_id(), "age", json!({ "age": 12 }));
+        let email = Claim::unproven(subject_key.key_id(), "email", json!({ "email": "a@b.c" }));
+
+        let mut profile = PrivateProfileData::empty(&subject_key);
+        profile.mut_claims().extend(vec![adult.clone(), child, email]);
+        let mut index = ClaimIndex::default();
+        index.update_profile(&profile);
+
+        let query = |q: ClaimQuery| index.query(&q, now, 30 * day);
+        assert_eq!(query(Default::default())?.len(), 3);
+        assert_eq!(
+            query(ClaimQuery { schema_id: Some("age".into()), ..Default::default() })?.len(),
+            2
+        );
+        let adults = ClaimQuery { content: Some("$[?(@.age >= 18)]".into()), ..Default::default() };
+        assert_eq!(query(adults)?, vec![adult.clone()]);
+        let witnessed = ClaimQuery { witness_id: Some(witness_id.clone()), ..Default::default() };
+        assert_eq!(query(witnessed)?, vec![adult.clone()]);
+        let expiring =
+            ClaimQuery { status: Some(ClaimExpiryStatus::ExpiringSoon), ..Default::default() };
+        assert_eq!(query(expiring)?, vec![adult.clone()]);
+        let window = ClaimQuery { expires_before: Some(now + 5 * day), ..Default::default() };
+        assert!(query(window)?.is_empty());
+        assert!(query(ClaimQuery { content: Some("$[".into()), ..Default::default() }).is_err());
+
+        assert_eq!(index.expiring(now, 30 * day), vec![adult.clone()]);
+        assert!(index.expired(now).is_empty());
+        assert_eq!(index.expired(now + 11 * day), vec![adult.clone()]);
+
+        let events = index.take_expiry_events(now, 30 * day);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].status, ClaimExpiryStatus::ExpiringSoon);
+        assert!(index.take_expiry_events(now, 30 * day).is_empty());
+        assert_eq!(index.expiry_events(now, 30 * day).len(), 1);
+        assert_eq!(index.take_expiry_events(now + 11 * day, 30 * day).len(), 1);
+
+        index.update_profile(&profile);
+        assert!(index.take_expiry_events(now + 11 * day, 30 * day).is_empty());
+
+        index.remove_profile(&subject_key.key_id());
+        assert!(index.query(&Default::default(), now, 30 * day)?.is_empty());
+        index.update_profile(&profile);
+        assert_eq!(index.take_expiry_events(now + 11 * day, 30 * day).len(), 1);
+        Ok(())
+    }
+}
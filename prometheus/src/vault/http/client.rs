@@ -2,10 +2,11 @@ use std::cell::RefCell;
 use std::collections::HashMap;
 use std::convert::{TryFrom, TryInto};
 use std::rc::Rc;
+use std::time::Duration;
 
 use actix_http::http::StatusCode;
 use actix_web::{
-    client::{Client as HttpClient, ClientResponse, SendRequestError},
+    client::{Client as HttpClient, ClientRequest, ClientResponse, SendRequestError},
     error::ParseError,
 };
 use failure::{format_err, Fallible};
@@ -17,6 +18,7 @@ use crate::*;
 use actix_http::error::PayloadError;
 use claims::model::*;
 use did::vault::{ProfileLabel, ProfileMetadata, ProfileVaultRecord};
+use mercury_home_protocol::{HomeInvitation, RelationProof};
 use multiaddr::Multiaddr;
 
 pub struct VaultClient {
@@ -32,6 +34,27 @@ impl VaultClient {
         }
     }
 
+    fn fetch_claims(&self, url: String) -> Fallible<Vec<Claim>> {
+        self.fetch_claims_request(HttpClient::new().get(url))
+    }
+
+    fn fetch_claims_request(&self, request: ClientRequest) -> Fallible<Vec<Claim>> {
+        let req_fut = request.send();
+        let fut = req_fut
+            .and_then(|response| validate_response_status(response, StatusCode::OK))
+            .and_then(|mut response| response.json().map_err(|e| SendRequestError::Body(e.into())))
+            .map(|claim_items: Vec<ApiClaim>| {
+                claim_items
+                    .iter()
+                    .filter_map(|item| {
+                        // TODO we should at least log errors here
+                        item.try_into().ok()
+                    })
+                    .collect()
+            });
+        self.await_fut(fut)
+    }
+
     // Note could we try using actix_web::error::Error instead?
     fn await_fut<T, E: actix_http::error::ResponseError>(
         &self,
@@ -216,6 +239,60 @@ impl VaultApi for VaultClient {
         self.await_fut(fut)
     }
 
+    fn profile_history(&self, id: Option<ProfileId>) -> Fallible<Vec<ProfileVersionInfo>> {
+        let did = did_str(id);
+        let url = format!("{}/vault/dids/{}/history", self.root_url, did);
+        let req_fut = HttpClient::new().get(url).send();
+        let fut = req_fut
+            .and_then(|response| validate_response_status(response, StatusCode::OK))
+            .and_then(|mut response| response.json().map_err(|e| SendRequestError::Body(e.into())));
+        self.await_fut(fut)
+    }
+
+    fn profile_diff(
+        &self,
+        id: Option<ProfileId>,
+        from_revision: Revision,
+        to_revision: Option<Revision>,
+    ) -> Fallible<ProfileDiff> {
+        let did = did_str(id);
+        let url = format!("{}/vault/dids/{}/history/diff", self.root_url, did);
+        let query = ProfileDiffQuery { from: from_revision, to: to_revision };
+        let req_fut = HttpClient::new().get(url).query(&query)?.send();
+        let fut = req_fut
+            .and_then(|response| validate_response_status(response, StatusCode::OK))
+            .and_then(|mut response| response.json().map_err(|e| SendRequestError::Body(e.into())));
+        self.await_fut(fut)
+    }
+
+    fn rollback_profile(
+        &mut self,
+        id: Option<ProfileId>,
+        revision: Revision,
+    ) -> Fallible<PrivateProfileData> {
+        let did = did_str(id);
+        let url = format!("{}/vault/dids/{}/history/{}/rollback", self.root_url, did, revision);
+        let req_fut = HttpClient::new().post(url).send();
+        let fut = req_fut
+            .and_then(|response| validate_response_status(response, StatusCode::OK))
+            .and_then(|mut response| response.json().map_err(|e| SendRequestError::Body(e.into())));
+        self.await_fut(fut)
+    }
+
+    fn merge_profile(
+        &mut self,
+        id: Option<ProfileId>,
+        resolutions: MergeResolutions,
+    ) -> Fallible<ProfileMerge> {
+        let did = did_str(id);
+        let url = format!("{}/vault/dids/{}/merge", self.root_url, did);
+        let req_fut = HttpClient::new().post(url).send_json(&resolutions);
+        let fut = req_fut
+            .and_then(|response| validate_response_status(response, StatusCode::OK))
+            .and_then(|mut response| response.json().map_err(|e| SendRequestError::Body(e.into())));
+        self.await_fut(fut)
+    }
+
     fn publish_profile(&mut self, id: Option<ProfileId>, force: bool) -> Fallible<ProfileId> {
         let did = did_str(id);
         let url = format!("{}/vault/dids/{}/publish", self.root_url, did);
@@ -240,15 +317,31 @@ impl VaultApi for VaultClient {
         self.await_fut(fut)
     }
 
+    fn get_attribute(
+        &self,
+        id: Option<ProfileId>,
+        key: &AttributeId,
+    ) -> Fallible<Option<AttributeValue>> {
+        let did = did_str(id);
+        let url = format!("{}/vault/dids/{}/attributes/{}", self.root_url, did, key);
+        let req_fut = HttpClient::new().get(url).send();
+        let fut = req_fut
+            .and_then(|response| validate_response_status(response, StatusCode::OK))
+            .and_then(|mut response| response.json().map_err(|e| SendRequestError::Body(e.into())));
+        self.await_fut(fut)
+    }
+
     fn set_attribute(
         &mut self,
         id: Option<ProfileId>,
         key: &AttributeId,
         value: &AttributeValue,
+        visibility: AttributeVisibility,
     ) -> Fallible<()> {
         let did = did_str(id);
         let url = format!("{}/vault/dids/{}/attributes/{}", self.root_url, did, key);
-        let req_fut = HttpClient::new().post(url).send_json(value);
+        let query = AttributeVisibilityQuery { visibility };
+        let req_fut = HttpClient::new().post(url).query(&query)?.send_json(value);
         let fut = req_fut
             .and_then(|response| validate_response_status(response, StatusCode::OK))
             .map(|_response| ());
@@ -265,6 +358,41 @@ impl VaultApi for VaultClient {
         self.await_fut(fut)
     }
 
+    fn add_contact(&mut self, id: Option<ProfileId>, proof: RelationProof) -> Fallible<Contact> {
+        let did = did_str(id);
+        let url = format!("{}/vault/dids/{}/contacts", self.root_url, did);
+        let req_fut = HttpClient::new().post(url).send_json(&proof);
+        let fut = req_fut
+            .and_then(|response| validate_response_status(response, StatusCode::CREATED))
+            .and_then(|mut response| response.json().map_err(|e| SendRequestError::Body(e.into())));
+        self.await_fut(fut)
+    }
+
+    fn remove_contact(&mut self, id: Option<ProfileId>, contact_id: &ProfileId) -> Fallible<()> {
+        let did = did_str(id);
+        let url = format!("{}/vault/dids/{}/contacts/{}", self.root_url, did, contact_id);
+        let req_fut = HttpClient::new().delete(url).send();
+        let fut = req_fut
+            .and_then(|response| validate_response_status(response, StatusCode::OK))
+            .map(|_response| ());
+        self.await_fut(fut)
+    }
+
+    fn contact_attributes(
+        &self,
+        id: Option<ProfileId>,
+        contact_id: &ProfileId,
+    ) -> Fallible<AttributeMap> {
+        let did = did_str(id);
+        let url =
+            format!("{}/vault/dids/{}/contacts/{}/attributes", self.root_url, did, contact_id);
+        let req_fut = HttpClient::new().get(url).send();
+        let fut = req_fut
+            .and_then(|response| validate_response_status(response, StatusCode::OK))
+            .and_then(|mut response| response.json().map_err(|e| SendRequestError::Body(e.into())));
+        self.await_fut(fut)
+    }
+
     fn claim_schemas(&self) -> Fallible<Rc<dyn ClaimSchemas>> {
         let url = format!("{}/claim-schemas", self.root_url);
         let req_fut = HttpClient::new().get(url).send();
@@ -277,25 +405,36 @@ impl VaultApi for VaultClient {
         self.await_fut(fut)
     }
 
-    fn claims(&self, id: Option<ProfileId>) -> Fallible<Vec<Claim>> {
+    fn publish_claim_schema(
+        &mut self,
+        id: Option<ProfileId>,
+        name: String,
+        content: serde_json::Value,
+        ordering: Option<Vec<String>>,
+    ) -> Fallible<SchemaVersion> {
         let did = did_str(id);
-        let url = format!("{}/vault/dids/{}/claims", self.root_url, did);
-        let req_fut = HttpClient::new().get(url).send();
+        let url = format!("{}/vault/dids/{}/claim-schemas", self.root_url, did);
+        let schema = PublishClaimSchema { label: name, content, ordering };
+        let req_fut = HttpClient::new().post(url).send_json(&schema);
         let fut = req_fut
-            .and_then(|response| validate_response_status(response, StatusCode::OK))
+            .and_then(|response| validate_response_status(response, StatusCode::CREATED))
             .and_then(|mut response| response.json().map_err(|e| SendRequestError::Body(e.into())))
-            .map(|claim_items: Vec<ApiClaim>| {
-                claim_items
-                    .iter()
-                    .filter_map(|item| {
-                        // TODO we should at least log errors here
-                        item.try_into().ok()
-                    })
-                    .collect()
-            });
+            .map(|schema: ClaimSchema| schema.into());
         self.await_fut(fut)
     }
 
+    fn claims(&self, id: Option<ProfileId>) -> Fallible<Vec<Claim>> {
+        let did = did_str(id);
+        let url = format!("{}/vault/dids/{}/claims", self.root_url, did);
+        self.fetch_claims(url)
+    }
+
+    fn query_claims(&self, query: &ClaimQuery) -> Fallible<Vec<Claim>> {
+        let url = format!("{}/vault/claims", self.root_url);
+        let request = HttpClient::new().get(url).query(&ClaimQueryParams::from(query))?;
+        self.fetch_claims_request(request)
+    }
+
     fn add_claim(&mut self, id: Option<ProfileId>, claim: Claim) -> Fallible<()> {
         let did = did_str(id);
         let url = format!("{}/vault/dids/{}/claims", self.root_url, did);
@@ -354,6 +493,27 @@ impl VaultApi for VaultClient {
         self.await_fut(fut)
     }
 
+    fn expiring_claims(&self, period: Duration) -> Fallible<Vec<Claim>> {
+        let days = period.as_secs() / (24 * 60 * 60);
+        let url = format!("{}/vault/claims/expiring?days={}", self.root_url, days);
+        self.fetch_claims(url)
+    }
+
+    fn expired_claims(&self) -> Fallible<Vec<Claim>> {
+        let url = format!("{}/vault/claims/expired", self.root_url);
+        self.fetch_claims(url)
+    }
+
+    fn renew_claim(&mut self, id: Option<ProfileId>, claim: &ClaimId) -> Fallible<ClaimRenewal> {
+        let did = did_str(id);
+        let url = format!("{}/vault/dids/{}/claims/{}/renew", self.root_url, did, claim);
+        let req_fut = HttpClient::new().post(url).send();
+        let fut = req_fut
+            .and_then(|response| validate_response_status(response, StatusCode::OK))
+            .and_then(|mut response| response.json().map_err(|e| SendRequestError::Body(e.into())));
+        self.await_fut(fut)
+    }
+
     fn license_claim(
         &mut self,
         _my_profile_id: Option<ProfileId>,
@@ -395,10 +555,66 @@ impl VaultApi for VaultClient {
         _my_id: Option<ProfileId>,
         _home_id: &ProfileId,
         _addr_hints: &[Multiaddr],
+        _invite: Option<HomeInvitation>,
+        _network: &NetworkState,
+    ) -> AsyncFallible<()> {
+        unimplemented!()
+    }
+
+    fn move_home(
+        &mut self,
+        _my_id: Option<ProfileId>,
+        _old_home_id: &ProfileId,
+        _new_home_id: &ProfileId,
+        _addr_hints: &[Multiaddr],
+        _invite: Option<HomeInvitation>,
+        _network: &NetworkState,
+    ) -> AsyncFallible<()> {
+        unimplemented!()
+    }
+
+    fn set_home_online(
+        &mut self,
+        _my_id: Option<ProfileId>,
+        _home_id: &ProfileId,
+        _online: bool,
         _network: &NetworkState,
     ) -> AsyncFallible<()> {
         unimplemented!()
     }
+
+    fn banned_profiles(&self, id: Option<ProfileId>) -> AsyncFallible<Vec<ProfileId>> {
+        let did = did_str(id);
+        let url = format!("{}/vault/dids/{}/bans", self.root_url, did);
+        let req_fut = HttpClient::new().get(url).send();
+        let fut = req_fut
+            .and_then(|response| validate_response_status(response, StatusCode::OK))
+            .and_then(|mut response| response.json().map_err(|e| SendRequestError::Body(e.into())));
+        let banned_res = self.await_fut(fut).and_then(|banned_dids: Vec<String>| {
+            banned_dids.iter().map(|did| did.parse()).collect::<Fallible<Vec<ProfileId>>>()
+        });
+        Box::new(banned_res.into_future())
+    }
+
+    fn ban_profile(&mut self, id: Option<ProfileId>, banned_id: &ProfileId) -> AsyncFallible<()> {
+        let did = did_str(id);
+        let url = format!("{}/vault/dids/{}/bans", self.root_url, did);
+        let req_fut = HttpClient::new().post(url).send_json(&banned_id.to_string());
+        let fut = req_fut
+            .and_then(|response| validate_response_status(response, StatusCode::CREATED))
+            .map(|_response| ());
+        Box::new(self.await_fut(fut).into_future())
+    }
+
+    fn unban_profile(&mut self, id: Option<ProfileId>, banned_id: &ProfileId) -> AsyncFallible<()> {
+        let did = did_str(id);
+        let url = format!("{}/vault/dids/{}/bans/{}", self.root_url, did, banned_id);
+        let req_fut = HttpClient::new().delete(url).send();
+        let fut = req_fut
+            .and_then(|response| validate_response_status(response, StatusCode::OK))
+            .map(|_response| ());
+        Box::new(self.await_fut(fut).into_future())
+    }
 }
 
 struct InMemoryClaimSchemas {
@@ -1,4 +1,6 @@
+use std::collections::HashMap;
 use std::convert::{TryFrom, TryInto};
+use std::time::Duration;
 
 use actix_web::{web, HttpResponse, Responder};
 use failure::{err_msg, format_err, Fallible};
@@ -10,6 +12,7 @@ use crate::vault::api_impl::VaultState;
 use crate::*;
 use claims::model::*;
 use keyvault::Seed;
+use mercury_home_protocol::RelationProof;
 use multiaddr::Multiaddr;
 
 pub fn generate_bip39_phrase() -> impl Responder {
@@ -257,6 +260,104 @@ pub fn revert(state: web::Data<Mutex<DaemonState>>, did_path: web::Path<String>)
     }
 }
 
+pub fn list_profile_history(
+    state: web::Data<Mutex<DaemonState>>,
+    did_path: web::Path<String>,
+) -> impl Responder {
+    let did = match did_opt(&did_path) {
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+        Ok(did) => did,
+    };
+    let state = match lock_state(&state) {
+        Err(e) => return HttpResponse::Conflict().body(e.to_string()),
+        Ok(state) => state,
+    };
+    match state.vault.profile_history(did) {
+        Ok(history) => {
+            debug!("Fetched {} versions of profile {}", history.len(), &did_path);
+            HttpResponse::Ok().json(history)
+        }
+        Err(e) => {
+            error!("Failed to fetch profile history: {}", e);
+            HttpResponse::Conflict().body(e.to_string())
+        }
+    }
+}
+
+pub fn diff_profile_versions(
+    state: web::Data<Mutex<DaemonState>>,
+    did_path: web::Path<String>,
+    query: web::Query<ProfileDiffQuery>,
+) -> impl Responder {
+    let did = match did_opt(&did_path) {
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+        Ok(did) => did,
+    };
+    let state = match lock_state(&state) {
+        Err(e) => return HttpResponse::Conflict().body(e.to_string()),
+        Ok(state) => state,
+    };
+    match state.vault.profile_diff(did, query.from, query.to) {
+        Ok(diff) => {
+            debug!("Compared revisions {:?} of profile {}", query, &did_path);
+            HttpResponse::Ok().json(diff)
+        }
+        Err(e) => {
+            error!("Failed to compare profile versions: {}", e);
+            HttpResponse::Conflict().body(e.to_string())
+        }
+    }
+}
+
+pub fn rollback_profile(
+    state: web::Data<Mutex<DaemonState>>,
+    revision_path: web::Path<ProfileRevisionPath>,
+) -> impl Responder {
+    let did = match did_opt(&revision_path.did) {
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+        Ok(did) => did,
+    };
+    let mut state = match lock_state(&state) {
+        Err(e) => return HttpResponse::Conflict().body(e.to_string()),
+        Ok(state) => state,
+    };
+    match state.vault.rollback_profile(did, revision_path.revision) {
+        Ok(priv_data) => {
+            debug!("Rolled back profile {:?}", &revision_path);
+            HttpResponse::Ok().json(priv_data) // TODO consider security here
+        }
+        Err(e) => {
+            error!("Failed to roll back profile: {}", e);
+            HttpResponse::Conflict().body(e.to_string())
+        }
+    }
+}
+
+pub fn merge_profile(
+    state: web::Data<Mutex<DaemonState>>,
+    did_path: web::Path<String>,
+    resolutions: web::Json<MergeResolutions>,
+) -> impl Responder {
+    let did = match did_opt(&did_path) {
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+        Ok(did) => did,
+    };
+    let mut state = match lock_state(&state) {
+        Err(e) => return HttpResponse::Conflict().body(e.to_string()),
+        Ok(state) => state,
+    };
+    match state.vault.merge_profile(did, resolutions.into_inner()) {
+        Ok(merge) => {
+            debug!("Merged profile {} with {} conflicts", &did_path, merge.conflicts.len());
+            HttpResponse::Ok().json(merge) // TODO consider security here
+        }
+        Err(e) => {
+            error!("Failed to merge profile: {}", e);
+            HttpResponse::Conflict().body(e.to_string())
+        }
+    }
+}
+
 pub fn publish(
     state: web::Data<Mutex<DaemonState>>,
     did_path: web::Path<String>,
@@ -368,10 +469,35 @@ fn set_avatar_in_state(
     state.set_profile_metadata(did, metadata.try_into()?)
 }
 
+pub fn get_did_attribute(
+    state: web::Data<Mutex<DaemonState>>,
+    attr_path: web::Path<AttributePath>,
+) -> impl Responder {
+    let did = match did_opt(&attr_path.did) {
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+        Ok(did) => did,
+    };
+    let state = match lock_state(&state) {
+        Err(e) => return HttpResponse::Conflict().body(e.to_string()),
+        Ok(state) => state,
+    };
+    match state.vault.get_attribute(did, &attr_path.attribute_id) {
+        Ok(attr_val) => {
+            debug!("Fetched attribute {:?}", &attr_path);
+            HttpResponse::Ok().json(attr_val)
+        }
+        Err(e) => {
+            error!("Failed to fetch attribute: {}", e);
+            HttpResponse::Conflict().body(e.to_string())
+        }
+    }
+}
+
 pub fn set_did_attribute(
     state: web::Data<Mutex<DaemonState>>,
     attr_path: web::Path<AttributePath>,
-    attr_val: web::Json<String>,
+    attr_val: web::Json<AttributeValue>,
+    query: web::Query<AttributeVisibilityQuery>,
 ) -> impl Responder {
     let did = match did_opt(&attr_path.did) {
         Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
@@ -381,9 +507,9 @@ pub fn set_did_attribute(
         Err(e) => return HttpResponse::Conflict().body(e.to_string()),
         Ok(state) => state,
     };
-    match state.vault.set_attribute(did, &attr_path.attribute_id, &attr_val) {
+    match state.vault.set_attribute(did, &attr_path.attribute_id, &attr_val, query.visibility) {
         Ok(()) => {
-            debug!("Set attribute {:?} to {}", &attr_path, &attr_val);
+            debug!("Set {} attribute {:?} to {}", query.visibility, &attr_path, &attr_val);
             HttpResponse::Ok().body("")
         }
         Err(e) => {
@@ -417,6 +543,87 @@ pub fn clear_did_attribute(
     }
 }
 
+pub fn add_did_contact(
+    state: web::Data<Mutex<DaemonState>>,
+    did_path: web::Path<String>,
+    proof: web::Json<RelationProof>,
+) -> impl Responder {
+    let did = match did_opt(&did_path) {
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+        Ok(did) => did,
+    };
+    let mut state = match lock_state(&state) {
+        Err(e) => return HttpResponse::Conflict().body(e.to_string()),
+        Ok(state) => state,
+    };
+    match state.vault.add_contact(did, proof.into_inner()) {
+        Ok(contact) => {
+            debug!("Added contact {} to profile {}", contact.id(), did_path);
+            HttpResponse::Created().json(contact)
+        }
+        Err(e) => {
+            error!("Failed to add contact: {}", e);
+            HttpResponse::Conflict().body(e.to_string())
+        }
+    }
+}
+
+pub fn remove_did_contact(
+    state: web::Data<Mutex<DaemonState>>,
+    contact_path: web::Path<ContactPath>,
+) -> impl Responder {
+    let did = match did_opt(&contact_path.did) {
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+        Ok(did) => did,
+    };
+    let contact_id: ProfileId = match contact_path.contact_did.parse() {
+        Ok(id) => id,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    let mut state = match lock_state(&state) {
+        Err(e) => return HttpResponse::Conflict().body(e.to_string()),
+        Ok(state) => state,
+    };
+    match state.vault.remove_contact(did, &contact_id) {
+        Ok(()) => {
+            debug!("Removed contact {:?}", contact_path);
+            HttpResponse::Ok().body("")
+        }
+        Err(e) => {
+            error!("Failed to remove contact: {}", e);
+            HttpResponse::Conflict().body(e.to_string())
+        }
+    }
+}
+
+pub fn get_contact_attributes(
+    state: web::Data<Mutex<DaemonState>>,
+    contact_path: web::Path<ContactPath>,
+) -> impl Responder {
+    let did = match did_opt(&contact_path.did) {
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+        Ok(did) => did,
+    };
+    let contact_id: ProfileId = match contact_path.contact_did.parse() {
+        Ok(id) => id,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    let state = match lock_state(&state) {
+        Err(e) => return HttpResponse::Conflict().body(e.to_string()),
+        Ok(state) => state,
+    };
+    match state.vault.contact_attributes(did, &contact_id) {
+        Ok(attributes) => {
+            debug!("Fetched attributes of contact {:?}", contact_path);
+            HttpResponse::Ok().json(attributes)
+        }
+        Err(e) => {
+            error!("Failed to fetch contact attributes: {}", e);
+            HttpResponse::Conflict().body(e.to_string())
+        }
+    }
+}
+
 pub fn sign_claim(
     state: web::Data<Mutex<DaemonState>>,
     did_path: web::Path<String>,
@@ -488,14 +695,21 @@ fn list_did_claims_impl(state: &VaultState, did: Option<ProfileId>) -> Fallible<
     Ok(claims)
 }
 
-pub fn list_vault_claims(state: web::Data<Mutex<DaemonState>>) -> impl Responder {
+pub fn list_vault_claims(
+    state: web::Data<Mutex<DaemonState>>,
+    params: web::Query<ClaimQueryParams>,
+) -> impl Responder {
+    let query = match ClaimQuery::try_from(&*params) {
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+        Ok(query) => query,
+    };
     let state = match lock_state(&state) {
         Err(e) => return HttpResponse::Conflict().body(e.to_string()),
         Ok(state) => state,
     };
-    match list_vault_claims_from_state(&state.vault) {
+    match state.vault.query_claims(&query).and_then(|claims| api_claims(&state.vault, claims)) {
         Ok(claims) => {
-            debug!("Fetched list of claims");
+            debug!("Fetched list of {} claims for query {:?}", claims.len(), query);
             HttpResponse::Ok().json(claims)
         }
         Err(e) => {
@@ -505,18 +719,82 @@ pub fn list_vault_claims(state: web::Data<Mutex<DaemonState>>) -> impl Responder
     }
 }
 
-fn list_vault_claims_from_state(state: &VaultState) -> Fallible<Vec<ApiClaim>> {
-    let schema_registry = state.claim_schemas()?;
+pub fn list_expiring_claims(
+    state: web::Data<Mutex<DaemonState>>,
+    query: web::Query<ClaimExpiryQuery>,
+) -> impl Responder {
+    let state = match lock_state(&state) {
+        Err(e) => return HttpResponse::Conflict().body(e.to_string()),
+        Ok(state) => state,
+    };
+    let period = match query.days {
+        Some(days) => Duration::from_secs(days * 24 * 60 * 60),
+        None => state.vault.claim_expiry_warning(),
+    };
+    match state.vault.expiring_claims(period).and_then(|claims| api_claims(&state.vault, claims)) {
+        Ok(claims) => {
+            debug!("Fetched list of {} expiring claims", claims.len());
+            HttpResponse::Ok().json(claims)
+        }
+        Err(e) => {
+            error!("Failed to fetch list of expiring claims: {}", e);
+            HttpResponse::Conflict().body(e.to_string())
+        }
+    }
+}
 
-    let mut claims = Vec::new();
-    for rec in state.list_vault_records()? {
-        let did_claims = state.claims(Some(rec.id()))?;
-        for claim in did_claims {
-            claims.push(ApiClaim::try_from(&claim, rec.label(), &*schema_registry)?);
+pub fn list_expired_claims(state: web::Data<Mutex<DaemonState>>) -> impl Responder {
+    let state = match lock_state(&state) {
+        Err(e) => return HttpResponse::Conflict().body(e.to_string()),
+        Ok(state) => state,
+    };
+    match state.vault.expired_claims().and_then(|claims| api_claims(&state.vault, claims)) {
+        Ok(claims) => {
+            debug!("Fetched list of {} expired claims", claims.len());
+            HttpResponse::Ok().json(claims)
+        }
+        Err(e) => {
+            error!("Failed to fetch list of expired claims: {}", e);
+            HttpResponse::Conflict().body(e.to_string())
         }
     }
+}
 
-    Ok(claims)
+fn api_claims(state: &VaultState, claims: Vec<Claim>) -> Fallible<Vec<ApiClaim>> {
+    let schema_registry = state.claim_schemas()?;
+    let labels = state
+        .list_vault_records()?
+        .iter()
+        .map(|rec| (rec.id(), rec.label()))
+        .collect::<HashMap<_, _>>();
+
+    let mut api_claims = Vec::new();
+    for claim in claims {
+        let label = labels.get(&claim.signable_part().subject_id).cloned().unwrap_or_default();
+        api_claims.push(ApiClaim::try_from(&claim, label, &*schema_registry)?);
+    }
+    Ok(api_claims)
+}
+
+/// Pushes claim expiry events to the client as server-sent events
+pub fn claim_expiry_events(state: web::Data<Mutex<DaemonState>>) -> impl Responder {
+    let mut state = match lock_state(&state) {
+        Err(e) => return HttpResponse::Conflict().body(e.to_string()),
+        Ok(state) => state,
+    };
+    let events = state.vault.subscribe_claim_expiries().filter_map(|event| {
+        match serde_json::to_string(&event) {
+            Ok(event_json) => Some(bytes::Bytes::from(format!("data: {}\n\n", event_json))),
+            Err(e) => {
+                error!("Failed to serialize claim expiry event {:?}: {}", event, e);
+                None
+            }
+        }
+    });
+    debug!("Subscribed to claim expiry events");
+    HttpResponse::Ok().content_type("text/event-stream").streaming(
+        events.map_err(|()| actix_web::error::ErrorInternalServerError("Event channel closed")),
+    )
 }
 
 pub fn create_did_claim(
@@ -611,6 +889,31 @@ pub fn request_claim_signature(
     }
 }
 
+pub fn renew_claim(
+    state: web::Data<Mutex<DaemonState>>,
+    claim_path: web::Path<ClaimPath>,
+) -> impl Responder {
+    let did = match did_opt(&claim_path.did) {
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+        Ok(did) => did,
+    };
+    let mut state = match lock_state(&state) {
+        Err(e) => return HttpResponse::Conflict().body(e.to_string()),
+        Ok(state) => state,
+    };
+
+    match state.vault.renew_claim(did, &claim_path.claim_id) {
+        Ok(renewal) => {
+            debug!("Renewed claim {:?}", &claim_path);
+            HttpResponse::Ok().json(renewal)
+        }
+        Err(e) => {
+            error!("Failed to renew claim {:?}: {}", &claim_path, e);
+            HttpResponse::Conflict().body(e.to_string())
+        }
+    }
+}
+
 pub fn add_claim_proof(
     state: web::Data<Mutex<DaemonState>>,
     claim_path: web::Path<ClaimPath>,
@@ -667,6 +970,33 @@ pub fn list_schemas(state: web::Data<Mutex<DaemonState>>) -> impl Responder {
     }
 }
 
+pub fn publish_schema(
+    state: web::Data<Mutex<DaemonState>>,
+    did_path: web::Path<String>,
+    schema: web::Json<PublishClaimSchema>,
+) -> impl Responder {
+    let did = match did_opt(&did_path) {
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+        Ok(did) => did,
+    };
+    let mut state = match lock_state(&state) {
+        Err(e) => return HttpResponse::Conflict().body(e.to_string()),
+        Ok(state) => state,
+    };
+
+    let schema = schema.into_inner();
+    match state.vault.publish_claim_schema(did, schema.label, schema.content, schema.ordering) {
+        Ok(schema) => {
+            debug!("Published claim schema {} by profile {}", schema.id(), &did_path);
+            HttpResponse::Created().json(ClaimSchema::from(&schema))
+        }
+        Err(e) => {
+            error!("Failed to publish claim schema: {}", e);
+            HttpResponse::Conflict().body(e.to_string())
+        }
+    }
+}
+
 pub fn list_homes(state: web::Data<Mutex<DaemonState>>) -> impl Responder {
     let state = match lock_state(&state) {
         Err(e) => return HttpResponse::Conflict().body(e.to_string()),
@@ -732,9 +1062,13 @@ pub fn register_did_home(
         Some(v) => v.iter().filter_map(|s| s.parse().ok()).collect(),
     };
 
+    let invite = reg_data.invite.clone();
+
     let state = &mut *state;
-    let fut = state.vault.register_home(did.clone(), &home_id, &addr_hints, &state.network).then(
-        move |res| match res {
+    let fut = state
+        .vault
+        .register_home(did.clone(), &home_id, &addr_hints, invite, &state.network)
+        .then(move |res| match res {
             Ok(homes) => {
                 debug!("Registered new home {} to profile {:?}", reg_data.home_did, did);
                 HttpResponse::Created().json(homes)
@@ -743,8 +1077,7 @@ pub fn register_did_home(
                 error!("Failed to fetch list of home nodes: {}", e);
                 HttpResponse::Conflict().body(e.to_string())
             }
-        },
-    );
+        });
     let fut = Box::new(fut) as AsyncResult<actix_http::Response, actix_http::Error>;
     actix_web::Either::B(fut)
 }
@@ -765,18 +1098,176 @@ pub fn leave_did_home(
 }
 
 pub fn set_did_home_online(
+    state: web::Data<Mutex<DaemonState>>,
+    home_path: web::Path<HomePath>,
+    online: web::Json<bool>,
+) -> impl Responder {
+    let did = match did_opt(&home_path.did) {
+        Err(e) => return actix_web::Either::A(HttpResponse::BadRequest().body(e.to_string())),
+        Ok(did) => did,
+    };
+    let home_id: ProfileId = match home_path.home_did.parse() {
+        Ok(id) => id,
+        Err(e) => return actix_web::Either::A(HttpResponse::BadRequest().body(e.to_string())),
+    };
+    let mut state = match lock_state(&state) {
+        Err(e) => return actix_web::Either::A(HttpResponse::Conflict().body(e.to_string())),
+        Ok(state) => state,
+    };
+
+    let state = &mut *state;
+    let online = *online;
+    let fut =
+        state.vault.set_home_online(did, &home_id, online, &state.network).then(
+            move |res| match res {
+                Ok(()) => {
+                    debug!("Set online status on home {} to {}", home_id, online);
+                    HttpResponse::Ok().body("")
+                }
+                Err(e) => {
+                    error!("Failed to set online status on home: {}", e);
+                    HttpResponse::Conflict().body(e.to_string())
+                }
+            },
+        );
+    let fut = Box::new(fut) as AsyncResult<actix_http::Response, actix_http::Error>;
+    actix_web::Either::B(fut)
+}
+
+pub fn move_did_home(
+    state: web::Data<Mutex<DaemonState>>,
+    home_path: web::Path<HomePath>,
+    reg_data: web::Json<HomeRegistration>,
+) -> impl Responder {
+    let did = match did_opt(&home_path.did) {
+        Err(e) => return actix_web::Either::A(HttpResponse::BadRequest().body(e.to_string())),
+        Ok(did) => did,
+    };
+    let old_home_id: ProfileId = match home_path.home_did.parse() {
+        Ok(id) => id,
+        Err(e) => return actix_web::Either::A(HttpResponse::BadRequest().body(e.to_string())),
+    };
+    let new_home_id: ProfileId = match reg_data.home_did.parse() {
+        Ok(id) => id,
+        Err(e) => return actix_web::Either::A(HttpResponse::BadRequest().body(e.to_string())),
+    };
+    let mut state = match lock_state(&state) {
+        Err(e) => return actix_web::Either::A(HttpResponse::Conflict().body(e.to_string())),
+        Ok(state) => state,
+    };
+    let addr_hints: Vec<Multiaddr> = match &reg_data.addr_hints {
+        None => vec![],
+        Some(v) => v.iter().filter_map(|s| s.parse().ok()).collect(),
+    };
+
+    let invite = reg_data.invite.clone();
+
+    let state = &mut *state;
+    let fut = state
+        .vault
+        .move_home(did, &old_home_id, &new_home_id, &addr_hints, invite, &state.network)
+        .then(move |res| match res {
+            Ok(()) => {
+                debug!("Moved profile from home {} to {}", old_home_id, new_home_id);
+                HttpResponse::Ok().body("")
+            }
+            Err(e) => {
+                error!("Failed to move profile to new home: {}", e);
+                HttpResponse::Conflict().body(e.to_string())
+            }
+        });
+    let fut = Box::new(fut) as AsyncResult<actix_http::Response, actix_http::Error>;
+    actix_web::Either::B(fut)
+}
+
+pub fn list_did_bans(
     state: web::Data<Mutex<DaemonState>>,
     did_path: web::Path<String>,
 ) -> impl Responder {
     let did = match did_opt(&did_path) {
-        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+        Err(e) => return actix_web::Either::A(HttpResponse::BadRequest().body(e.to_string())),
+        Ok(did) => did,
+    };
+    let state = match lock_state(&state) {
+        Err(e) => return actix_web::Either::A(HttpResponse::Conflict().body(e.to_string())),
+        Ok(state) => state,
+    };
+
+    let fut = state.vault.banned_profiles(did).then(|res| match res {
+        Ok(banned) => {
+            let banned_dids = banned.iter().map(|id| id.to_string()).collect::<Vec<_>>();
+            HttpResponse::Ok().json(banned_dids)
+        }
+        Err(e) => {
+            error!("Failed to list banned profiles: {}", e);
+            HttpResponse::Conflict().body(e.to_string())
+        }
+    });
+    let fut = Box::new(fut) as AsyncResult<actix_http::Response, actix_http::Error>;
+    actix_web::Either::B(fut)
+}
+
+pub fn ban_did_profile(
+    state: web::Data<Mutex<DaemonState>>,
+    did_path: web::Path<String>,
+    banned_did: web::Json<String>,
+) -> impl Responder {
+    let did = match did_opt(&did_path) {
+        Err(e) => return actix_web::Either::A(HttpResponse::BadRequest().body(e.to_string())),
         Ok(did) => did,
     };
+    let banned_id: ProfileId = match banned_did.parse() {
+        Ok(id) => id,
+        Err(e) => return actix_web::Either::A(HttpResponse::BadRequest().body(e.to_string())),
+    };
     let mut state = match lock_state(&state) {
-        Err(e) => return HttpResponse::Conflict().body(e.to_string()),
+        Err(e) => return actix_web::Either::A(HttpResponse::Conflict().body(e.to_string())),
         Ok(state) => state,
     };
-    unimplemented!()
+
+    let fut = state.vault.ban_profile(did, &banned_id).then(move |res| match res {
+        Ok(()) => {
+            debug!("Banned profile {}", banned_id);
+            HttpResponse::Created().body("")
+        }
+        Err(e) => {
+            error!("Failed to ban profile: {}", e);
+            HttpResponse::Conflict().body(e.to_string())
+        }
+    });
+    let fut = Box::new(fut) as AsyncResult<actix_http::Response, actix_http::Error>;
+    actix_web::Either::B(fut)
+}
+
+pub fn unban_did_profile(
+    state: web::Data<Mutex<DaemonState>>,
+    ban_path: web::Path<BanPath>,
+) -> impl Responder {
+    let did = match did_opt(&ban_path.did) {
+        Err(e) => return actix_web::Either::A(HttpResponse::BadRequest().body(e.to_string())),
+        Ok(did) => did,
+    };
+    let banned_id: ProfileId = match ban_path.banned_did.parse() {
+        Ok(id) => id,
+        Err(e) => return actix_web::Either::A(HttpResponse::BadRequest().body(e.to_string())),
+    };
+    let mut state = match lock_state(&state) {
+        Err(e) => return actix_web::Either::A(HttpResponse::Conflict().body(e.to_string())),
+        Ok(state) => state,
+    };
+
+    let fut = state.vault.unban_profile(did, &banned_id).then(move |res| match res {
+        Ok(()) => {
+            debug!("Unbanned profile {}", banned_id);
+            HttpResponse::Ok().body("")
+        }
+        Err(e) => {
+            error!("Failed to unban profile: {}", e);
+            HttpResponse::Conflict().body(e.to_string())
+        }
+    });
+    let fut = Box::new(fut) as AsyncResult<actix_http::Response, actix_http::Error>;
+    actix_web::Either::B(fut)
 }
 
 fn lock_state(state: &Mutex<DaemonState>) -> Fallible<MutexGuard<DaemonState>> {
@@ -47,12 +47,36 @@ pub fn init_url_mapping(service: &mut web::ServiceConfig) {
                                 .service(web::resource("/restore").route(web::post().to(restore)))
                                 .service(web::resource("/revert").route(web::post().to(revert)))
                                 .service(web::resource("/publish").route(web::post().to(publish)))
+                                .service(web::resource("/merge").route(web::post().to(merge_profile)))
+                                .service(
+                                    web::scope("/history")
+                                        .service(web::resource("").route(web::get().to(list_profile_history)))
+                                        .service(web::resource("/diff").route(web::get().to(diff_profile_versions)))
+                                        .service(
+                                            web::resource("/{revision}/rollback")
+                                                .route(web::post().to(rollback_profile)),
+                                        ),
+                                )
                                 .service(
                                 web::resource("/attributes/{attribute_id}")
+                                    .route(web::get().to(get_did_attribute))
                                     .route(web::post().to(set_did_attribute))
                                     .route(web::delete().to(clear_did_attribute)),
                                 )
+                                .service(
+                                    web::scope("/contacts")
+                                        .service(web::resource("").route(web::post().to(add_did_contact)))
+                                        .service(
+                                            web::scope("/{contact_did}")
+                                                .service(web::resource("").route(web::delete().to(remove_did_contact)))
+                                                .service(
+                                                    web::resource("/attributes")
+                                                        .route(web::get().to(get_contact_attributes)),
+                                                ),
+                                        ),
+                                )
                                 .service( web::resource("/sign-claim").route(web::post().to(sign_claim)))
+                                .service(web::resource("/claim-schemas").route(web::post().to(publish_schema)))
                                 .service(
                                     web::scope("/claims")
                                         .service(
@@ -71,6 +95,9 @@ pub fn init_url_mapping(service: &mut web::ServiceConfig) {
                                                 .service(web::resource("/witness-signature")
                                                     .route(web::put().to(add_claim_proof))
                                                 )
+                                                .service(web::resource("/renew")
+                                                    .route(web::post().to(renew_claim))
+                                                )
                                         ),
                                 )
                                 .service(web::scope("/homes")
@@ -85,11 +112,36 @@ pub fn init_url_mapping(service: &mut web::ServiceConfig) {
                                         .service(web::resource("online")
                                             .route(web::put().to(set_did_home_online))
                                         )
+                                        .service(web::resource("move")
+                                            .route(web::post().to(move_did_home))
+                                        )
+                                    )
+                                )
+                                .service(web::scope("/bans")
+                                    .service(web::resource("")
+                                        .route(web::get().to(list_did_bans))
+                                        .route(web::post().to(ban_did_profile))
+                                    )
+                                    .service(web::resource("{banned_did}")
+                                        .route(web::delete().to(unban_did_profile))
                                     )
                                 ),
                         ),
                 )
-                .service(web::resource("/claims").route(web::get().to(list_vault_claims))),
+                .service(
+                    web::scope("/claims")
+                        .service(web::resource("").route(web::get().to(list_vault_claims)))
+                        .service(
+                            web::resource("/expiring").route(web::get().to(list_expiring_claims)),
+                        )
+                        .service(
+                            web::resource("/expired").route(web::get().to(list_expired_claims)),
+                        )
+                        .service(
+                            web::resource("/expiry-events")
+                                .route(web::get().to(claim_expiry_events)),
+                        ),
+                ),
         )
         .service(web::resource("/homes").route(web::get().to(list_homes)))
         .service(web::resource("/claim-schemas").route(web::get().to(list_schemas)));
@@ -1,4 +1,5 @@
 pub mod api;
 pub mod api_data;
 pub mod api_impl;
+pub mod claim_index;
 pub mod http;
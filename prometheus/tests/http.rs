@@ -71,20 +71,43 @@ fn test_http_api() {
     }
 
     let first_attrid = "my_attrid".to_string();
-    let first_attrval = "my_attrval".to_string();
+    let first_attrval = AttributeValue::from("my_attrval");
     {
         let first_profile = api.get_profile_data(Some(first_id.clone()), RepoKind::Local).unwrap();
         assert!(first_profile.public_data().attributes().is_empty());
     }
-    api.set_attribute(Some(first_id.clone()), &first_attrid, &first_attrval).unwrap();
+    api.set_attribute(
+        Some(first_id.clone()),
+        &first_attrid,
+        &first_attrval,
+        AttributeVisibility::Public,
+    )
+    .unwrap();
     {
         let first_profile = api.get_profile_data(Some(first_id.clone()), RepoKind::Local).unwrap();
         assert_eq!(first_profile.public_data().attributes().len(), 1);
         assert_eq!(
             first_profile.public_data().attributes().get(&first_attrid).unwrap(),
-            "my_attrval"
+            &first_attrval
         );
     }
+    let typed_attrid = "my_typed_attrid".to_string();
+    let typed_attrval = AttributeValue::Integer(42);
+    api.set_attribute(
+        Some(first_id.clone()),
+        &typed_attrid,
+        &typed_attrval,
+        AttributeVisibility::Contacts,
+    )
+    .unwrap();
+    {
+        let attrval = api.get_attribute(Some(first_id.clone()), &typed_attrid).unwrap();
+        assert_eq!(attrval, Some(typed_attrval));
+        let first_profile = api.get_profile_data(Some(first_id.clone()), RepoKind::Local).unwrap();
+        assert_eq!(first_profile.public_data().attributes().len(), 1);
+        assert_eq!(first_profile.attributes(AttributeVisibility::Contacts).len(), 1);
+    }
+    api.clear_attribute(Some(first_id.clone()), &typed_attrid).unwrap();
     api.clear_attribute(Some(first_id.clone()), &first_attrid).unwrap();
     {
         let first_profile = api.get_profile_data(Some(first_id.clone()), RepoKind::Local).unwrap();
@@ -13,6 +13,13 @@ use tokio_current_thread as reactor;
 
 use crate::asynch::*;
 
+fn io_err(e: std::io::Error) -> failure::Error {
+    match e.kind() {
+        std::io::ErrorKind::NotFound => KeyNotFound.into(),
+        _ => e.into(),
+    }
+}
+
 //pub type FileStore = AsyncFileStore;
 pub type FileStore = BlockingFileStore;
 
@@ -66,7 +73,7 @@ where
     fn get(&self, key: String) -> StorageResult<V> {
         let bytes = match self.get_bytes(key) {
             Ok(bytes) => bytes,
-            Err(e) => return Box::new(Err(e.into()).into_future()),
+            Err(e) => return Box::new(Err(io_err(e)).into_future()),
         };
 
         let res = serde_json::from_slice(&bytes).map_err(|e| e.into());
@@ -74,7 +81,7 @@ where
     }
 
     fn clear_local(&mut self, key: String) -> StorageResult<()> {
-        let res = std::fs::remove_file(self.base_path.join(key)).map_err(|e| e.into());
+        let res = std::fs::remove_file(self.base_path.join(key)).map_err(io_err);
         Box::new(res.into_future())
     }
 }
@@ -146,7 +153,7 @@ where
             .inspect(|_| trace!("File opened for read"))
             .and_then(|file| tokio::io::read_to_end(file, Vec::new()))
             .inspect(|(_file, bytes)| trace!("Read {} bytes from file", bytes.len()))
-            .map_err(|e| format_err!("{}", e))
+            .map_err(io_err)
             .and_then(|(_file, bytes)| {
                 serde_json::from_slice(&bytes).map_err(|e| {
                     debug!("Failed to read file: {:?}", e);
@@ -157,8 +164,7 @@ where
     }
 
     fn clear_local(&mut self, key: String) -> StorageResult<()> {
-        let fut =
-            tokio::fs::remove_file(self.base_path.join(key)).map_err(|e| format_err!("{}", e));
+        let fut = tokio::fs::remove_file(self.base_path.join(key)).map_err(io_err);
         Box::new(self.schedule(fut))
     }
 }
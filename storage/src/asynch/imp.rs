@@ -224,13 +224,13 @@ where
     fn get(&self, key: KeyType) -> StorageResult<ValueType> {
         let result = match self.map.get(&key) {
             Some(val) => Ok(val.to_owned()),
-            None => Err(err_msg("invalid key")),
+            None => Err(KeyNotFound.into()),
         };
         Box::new(result.into_future())
     }
 
     fn clear_local(&mut self, key: KeyType) -> StorageResult<()> {
-        let result = self.map.remove(&key).map(|_| ()).ok_or(err_msg("invalid key"));
+        let result = self.map.remove(&key).map(|_| ()).ok_or_else(|| KeyNotFound.into());
         Box::new(result.into_future())
     }
 }
@@ -263,6 +263,11 @@ mod tests {
         let lookup_res = storage.get(hash).wait();
         assert!(lookup_res.is_ok());
         assert_eq!(lookup_res.unwrap(), object);
+
+        let missing_res = optional(storage.get("missing".to_string())).wait();
+        assert!(missing_res.unwrap().is_none());
+        let failing: StorageResult<Person> = Box::new(future::err(err_msg("disk failure")));
+        assert!(optional(failing).wait().is_err());
     }
 
     #[test]
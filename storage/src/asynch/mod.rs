@@ -15,6 +15,28 @@ pub mod router_service;
 
 type StorageResult<T> = AsyncFallible<T>;
 
+/// Reported by stores when nothing is stored for the key, as opposed to failing to access it.
+#[derive(Debug)]
+pub struct KeyNotFound;
+
+impl std::fmt::Display for KeyNotFound {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "key not found")
+    }
+}
+
+impl std::error::Error for KeyNotFound {}
+
+/// Turns a missing key into `None` but keeps all other errors, e.g. I/O failures.
+pub fn optional<T: 'static>(result: StorageResult<T>) -> StorageResult<Option<T>> {
+    let fut = result.then(|res| match res {
+        Ok(value) => Ok(Some(value)),
+        Err(ref e) if e.downcast_ref::<KeyNotFound>().is_some() => Ok(None),
+        Err(e) => Err(e),
+    });
+    Box::new(fut)
+}
+
 // TODO probably we should have references (e.g. maybe use AsRef) to keys whenever possible
 // NOTE this interface can be potentially implemented using a simple local in-memory storage
 //      or something as complex as a distributed hashtable (DHT).
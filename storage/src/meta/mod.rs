@@ -2,6 +2,10 @@ use std::time::SystemTime;
 
 use serde_derive::{Deserialize, Serialize};
 
+use mercury_home_protocol::{
+    AttributeValue as ProfileAttributeValue, GpsLocation as ProfileGpsLocation,
+};
+
 pub trait Attribute {
     fn name(&self) -> &str;
     fn value(&self) -> AttributeValue<'_>;
@@ -28,6 +32,29 @@ pub struct GpsLocation {
     longitude: f64,
 }
 
+/// Profile attributes hold an owned copy of the same typed value model.
+impl<'a> From<AttributeValue<'a>> for ProfileAttributeValue {
+    fn from(value: AttributeValue<'a>) -> Self {
+        match value {
+            AttributeValue::Boolean(v) => ProfileAttributeValue::Boolean(v),
+            AttributeValue::Integer(v) => ProfileAttributeValue::Integer(v),
+            AttributeValue::Float(v) => ProfileAttributeValue::Float(v),
+            AttributeValue::Timestamp(v) => ProfileAttributeValue::Timestamp(v),
+            AttributeValue::Location(v) => ProfileAttributeValue::Location(ProfileGpsLocation {
+                latitude: v.latitude,
+                longitude: v.longitude,
+            }),
+            AttributeValue::String(v) => ProfileAttributeValue::String(v.to_owned()),
+            AttributeValue::Blob(v) => ProfileAttributeValue::Blob(v.to_owned()),
+            AttributeValue::Link(v) => ProfileAttributeValue::Link(v.to_owned()),
+            AttributeValue::Array(v) => ProfileAttributeValue::Array(v.map(Into::into).collect()),
+            AttributeValue::Object(v) => ProfileAttributeValue::Object(
+                v.map(|attr| (attr.name().to_owned(), attr.value().into())).collect(),
+            ),
+        }
+    }
+}
+
 pub fn iter_first_attrval_by_name<'a, 'n>(
     iter: Box<dyn 'a + Iterator<Item = &'a dyn Attribute>>,
     name: &'n str,
@@ -219,5 +246,17 @@ pub mod tests {
             let color_purple_attrval = metadata.first_attrval_by_path(&["color", "purple"]);
             assert!(color_purple_attrval.is_none());
         }
+
+        {
+            // Test conversion to an owned profile attribute
+            let color_attrval = metadata.first_attrval_by_name("color").unwrap();
+            match ProfileAttributeValue::from(color_attrval) {
+                ProfileAttributeValue::Object(fields) => {
+                    assert_eq!(fields.len(), 3);
+                    assert_eq!(fields["green"], ProfileAttributeValue::Integer(60));
+                }
+                _ => panic!("Unexpected attribute type"),
+            };
+        }
     }
 }